@@ -1,4 +1,5 @@
 use crate::port::auth::repo as auth_repo;
+use crate::port::room::repo::RoomId;
 
 use chrono::NaiveDateTime;
 use uuid::Uuid;
@@ -9,6 +10,8 @@ pub type RefreshTokenSalt = Uuid;
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct Client {
     pub id: ClientId,
+    pub room_id: RoomId,
+    pub owner: bool,
     pub refresh_token_salt: RefreshTokenSalt,
     pub refresh_token_exp: NaiveDateTime,
     pub fingerprint: String,
@@ -18,6 +21,8 @@ impl From<auth_repo::Client> for Client {
     fn from(f: auth_repo::Client) -> Self {
         Self {
             id: f.id,
+            room_id: f.room_id,
+            owner: f.owner,
             refresh_token_salt: f.refresh_token_salt,
             refresh_token_exp: f.refresh_token_exp,
             fingerprint: f.fingerprint,
@@ -29,9 +34,26 @@ impl From<Client> for auth_repo::Client {
     fn from(f: Client) -> Self {
         Self {
             id: f.id,
+            room_id: f.room_id,
+            owner: f.owner,
             refresh_token_salt: f.refresh_token_salt,
             refresh_token_exp: f.refresh_token_exp,
             fingerprint: f.fingerprint,
         }
     }
 }
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct LoginAttempts {
+    pub failures: u32,
+    pub last_failure_at: NaiveDateTime,
+}
+
+impl From<LoginAttempts> for auth_repo::LoginAttempts {
+    fn from(f: LoginAttempts) -> Self {
+        Self {
+            failures: f.failures,
+            last_failure_at: f.last_failure_at,
+        }
+    }
+}
@@ -1,28 +1,119 @@
 use crate::adapter::auth::repo::models_sled;
+use crate::infra::crypto::{value_aad, Cipher, KeyStore};
 use crate::port::auth::repo::*;
 use crate::port::room::repo as room_repo;
-use crate::port::room::repo::RoomRepo;
+use crate::port::room::repo::{RoomId, RoomRepo};
 use crate::port::{RepoError, RepoResult};
 
+use std::convert::TryFrom;
+use std::net::IpAddr;
 use std::sync::Arc;
 
 pub struct AuthRepoSled<R: RoomRepo> {
     clients_tree: sled::Tree,
+    /// `room_id | client_id` of every client, to revoke them with the room
+    room_clients_tree: sled::Tree,
+    /// `room_id | tag(device_id)` of devices which logged in to the room,
+    /// to timestamp of the last login
+    known_devices_tree: sled::Tree,
+    /// `room/room_id` or `ip/tag(address)` to failed attempts
+    login_attempts_tree: sled::Tree,
+    /// `tag(invite_id)` to `uses | exp`, to limit logins with the invite
+    invite_uses_tree: sled::Tree,
     room_repo: Arc<R>,
+    key_store: Arc<KeyStore>,
 }
 
 impl<R> AuthRepoSled<R>
 where
     R: RoomRepo,
 {
-    pub fn new(sled_db: sled::Db, room_repo: Arc<R>) -> RepoResult<Self> {
+    pub fn new(sled_db: sled::Db, key_store: Arc<KeyStore>, room_repo: Arc<R>) -> RepoResult<Self> {
         let clients_tree = sled_db.open_tree("auth-clients")?;
+        let room_clients_tree = sled_db.open_tree("auth-room-clients")?;
+        let known_devices_tree = sled_db.open_tree("auth-known-devices")?;
+        let login_attempts_tree = sled_db.open_tree("auth-login-attempts")?;
+        let invite_uses_tree = sled_db.open_tree("auth-invite-uses")?;
+
+        key_store
+            .get_or_create_key(CIPHER_SCOPE)
+            .map_err(RepoError::CommonError)?;
 
         Ok(Self {
             clients_tree,
+            room_clients_tree,
+            known_devices_tree,
+            login_attempts_tree,
+            invite_uses_tree,
             room_repo,
+            key_store,
         })
     }
+
+    fn serialize(&self, client_id: ClientId, client: &models_sled::Client) -> RepoResult<Vec<u8>> {
+        let serialized =
+            bincode::serialize(client).map_err(|err| RepoError::CommonError(err.into()))?;
+        self.cipher()?
+            .seal(
+                &serialized,
+                &value_aad(&self.clients_tree, client_id.as_bytes()),
+            )
+            .map_err(RepoError::CommonError)
+    }
+
+    fn deserialize(&self, client_id: ClientId, value: &[u8]) -> RepoResult<models_sled::Client> {
+        let serialized = self
+            .cipher()?
+            .open(value, &value_aad(&self.clients_tree, client_id.as_bytes()))
+            .map_err(RepoError::CommonError)?;
+        bincode::deserialize(&serialized).map_err(|err| RepoError::CommonError(err.into()))
+    }
+
+    /// Clients are not bound to a room in the repo, so they share one data
+    /// key. It also tags keys which name devices, addresses and invites
+    fn cipher(&self) -> RepoResult<Cipher> {
+        self.key_store
+            .get_cipher(CIPHER_SCOPE)
+            .map_err(RepoError::CommonError)
+    }
+
+    /// Device id is tagged, room id is kept to delete devices of the room
+    fn known_device_key(&self, room_id: RoomId, device_id: DeviceId) -> RepoResult<Vec<u8>> {
+        let mut key = room_id.to_ne_bytes().to_vec();
+        key.extend_from_slice(&self.cipher()?.tag(device_id.as_bytes()));
+        Ok(key)
+    }
+
+    fn login_attempts_key(&self, key: LoginAttemptsKey) -> RepoResult<Vec<u8>> {
+        let mut buf = Vec::new();
+        match key {
+            LoginAttemptsKey::Room(room_id) => {
+                buf.extend_from_slice(b"room/");
+                buf.extend_from_slice(&room_id.to_be_bytes());
+            }
+            LoginAttemptsKey::Ip(ip) => {
+                // Dual-stack socket reports IPv4 clients as `::ffff:a.b.c.d`
+                let ip = match ip {
+                    IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+                    IpAddr::V4(_) => ip,
+                };
+
+                // Client addresses are not stored as is
+                buf.extend_from_slice(b"ip/");
+                let cipher = self.cipher()?;
+                match ip {
+                    IpAddr::V4(v4) => buf.extend_from_slice(&cipher.tag(&v4.octets())),
+                    // Single host usually owns whole /64 network
+                    IpAddr::V6(v6) => buf.extend_from_slice(&cipher.tag(&v6.octets()[..8])),
+                }
+            }
+        }
+        Ok(buf)
+    }
+
+    fn invite_uses_key(&self, invite_id: InviteId) -> RepoResult<Vec<u8>> {
+        Ok(self.cipher()?.tag(invite_id.as_bytes()))
+    }
 }
 
 #[async_trait::async_trait]
@@ -33,17 +124,31 @@ where
     async fn create_client(&self, req: CreateClientRequest) -> RepoResult<CreateClientResponse> {
         let client = models_sled::Client {
             id: req.client_id,
+            room_id: req.room_id,
+            owner: req.owner,
             refresh_token_salt: req.refresh_token_salt,
             refresh_token_exp: req.refresh_token_exp,
             fingerprint: req.fingerprint,
         };
 
-        let client_serialized =
-            bincode::serialize(&client).map_err(|err| RepoError::CommonError(err.into()))?;
+        let client_serialized = self.serialize(client.id, &client)?;
 
-        self.clients_tree
+        // Index goes first, so client is never left out of room revocation
+        self.room_clients_tree
+            .insert(room_client_key(client.room_id, client.id), &[])?;
+        let old = self
+            .clients_tree
             .insert(client.id.as_bytes(), client_serialized)?;
 
+        // Replaced client may be bound to another room
+        if let Some(old) = old {
+            let old: models_sled::Client = self.deserialize(client.id, old.as_ref())?;
+            if old.room_id != client.room_id {
+                self.room_clients_tree
+                    .remove(room_client_key(old.room_id, old.id))?;
+            }
+        }
+
         let res = CreateClientResponse {
             client: client.into(),
         };
@@ -59,11 +164,12 @@ where
                     req.client_id
                 )))
             }
-            Some(v) => bincode::deserialize(v.as_ref())
-                .map_err(|err| RepoError::CommonError(err.into()))?,
+            Some(v) => self.deserialize(req.client_id, v.as_ref())?,
         };
 
         self.clients_tree.remove(client.id.as_bytes())?;
+        self.room_clients_tree
+            .remove(room_client_key(client.room_id, client.id))?;
 
         let res = DeleteClientResponse {
             client: client.into(),
@@ -72,42 +178,89 @@ where
         Ok(res)
     }
 
+    async fn delete_room_clients(
+        &self,
+        req: DeleteRoomClientsRequest,
+    ) -> RepoResult<DeleteRoomClientsResponse> {
+        let mut client_ids = Vec::new();
+        for entry in self
+            .room_clients_tree
+            .scan_prefix(req.room_id.to_ne_bytes())
+        {
+            let (key, _) = entry?;
+            let client_id = ClientId::from_slice(&key[ROOM_ID_LEN..])
+                .map_err(|err| RepoError::CommonError(err.into()))?;
+
+            self.clients_tree.remove(client_id.as_bytes())?;
+            self.room_clients_tree.remove(key)?;
+            client_ids.push(client_id);
+        }
+
+        for key in self
+            .known_devices_tree
+            .scan_prefix(req.room_id.to_ne_bytes())
+            .keys()
+        {
+            self.known_devices_tree.remove(key?)?;
+        }
+
+        let res = DeleteRoomClientsResponse { client_ids };
+
+        Ok(res)
+    }
+
     async fn update_client(&self, req: UpdateClientRequest) -> RepoResult<UpdateClientResponse> {
-        let mut client: models_sled::Client =
-            match self.clients_tree.get(req.client_id.as_bytes())? {
+        // Compare and swap, so only one of concurrent rotations of the same
+        // refresh token wins
+        loop {
+            let old = match self.clients_tree.get(req.client_id.as_bytes())? {
                 None => {
                     return Err(RepoError::CommonError(anyhow::anyhow!(
                         "no client with id={}",
                         req.client_id
                     )))
                 }
-                Some(v) => bincode::deserialize(v.as_ref())
-                    .map_err(|err| RepoError::CommonError(err.into()))?,
+                Some(v) => v,
             };
 
-        if let Some(refresh_token_salt) = req.refresh_token_salt {
-            client.refresh_token_salt = refresh_token_salt;
-        }
+            let mut client: models_sled::Client = self.deserialize(req.client_id, old.as_ref())?;
 
-        if let Some(refresh_token_exp) = req.refresh_token_exp {
-            client.refresh_token_exp = refresh_token_exp;
-        }
+            if let Some(expected_salt) = req.expected_refresh_token_salt {
+                if client.refresh_token_salt != expected_salt {
+                    let res = UpdateClientResponse {
+                        client: client.into(),
+                        updated: false,
+                    };
+                    return Ok(res);
+                }
+            }
 
-        if let Some(fingerprint) = req.fingerprint {
-            client.fingerprint = fingerprint;
-        }
+            if let Some(refresh_token_salt) = req.refresh_token_salt {
+                client.refresh_token_salt = refresh_token_salt;
+            }
 
-        let client_serialized =
-            bincode::serialize(&client).map_err(|err| RepoError::CommonError(err.into()))?;
+            if let Some(refresh_token_exp) = req.refresh_token_exp {
+                client.refresh_token_exp = refresh_token_exp;
+            }
 
-        self.clients_tree
-            .insert(req.client_id.as_bytes(), client_serialized)?;
+            if let Some(fingerprint) = &req.fingerprint {
+                client.fingerprint = fingerprint.clone();
+            }
 
-        let res = UpdateClientResponse {
-            client: client.into(),
-        };
+            let client_serialized = self.serialize(client.id, &client)?;
 
-        Ok(res)
+            if self
+                .clients_tree
+                .compare_and_swap(req.client_id.as_bytes(), Some(old), Some(client_serialized))?
+                .is_ok()
+            {
+                let res = UpdateClientResponse {
+                    client: client.into(),
+                    updated: true,
+                };
+                return Ok(res);
+            }
+        }
     }
 
     async fn get_client(&self, req: GetClientRequest) -> RepoResult<GetClientResponse> {
@@ -117,13 +270,12 @@ where
             .map_err(RepoError::SledError)?
         {
             None => {
-                return Err(RepoError::CommonError(anyhow::anyhow!(
+                return Err(RepoError::NotFound(anyhow::anyhow!(
                     "no client with id={}",
                     req.client_id
                 )))
             }
-            Some(v) => bincode::deserialize(v.as_ref())
-                .map_err(|err| RepoError::CommonError(err.into()))?,
+            Some(v) => self.deserialize(req.client_id, v.as_ref())?,
         };
 
         let res = GetClientResponse {
@@ -141,6 +293,228 @@ where
         let res = get_room_cred_res.into();
         Ok(res)
     }
+
+    async fn delete_room_password(
+        &self,
+        req: DeleteRoomPasswordRequest,
+    ) -> RepoResult<DeleteRoomPasswordResponse> {
+        self.room_repo.delete_room_password(req.into()).await
+    }
+
+    async fn get_login_attempts(
+        &self,
+        req: GetLoginAttemptsRequest,
+    ) -> RepoResult<GetLoginAttemptsResponse> {
+        let attempts = match self
+            .login_attempts_tree
+            .get(self.login_attempts_key(req.key)?)?
+        {
+            None => None,
+            Some(v) => Some(deserialize_login_attempts(v.as_ref())?.into()),
+        };
+
+        let res = GetLoginAttemptsResponse { attempts };
+
+        Ok(res)
+    }
+
+    async fn add_login_failure(
+        &self,
+        req: AddLoginFailureRequest,
+    ) -> RepoResult<AddLoginFailureResponse> {
+        // Concurrent attempts must not lose increments, so update atomically
+        let updated = self.login_attempts_tree.update_and_fetch(
+            self.login_attempts_key(req.key)?,
+            |old| {
+                let failures = match old.map(deserialize_login_attempts) {
+                    Some(Ok(a)) if a.last_failure_at >= req.reset_before => {
+                        a.failures.saturating_add(1)
+                    }
+                    _ => 1,
+                };
+
+                let attempts = models_sled::LoginAttempts {
+                    failures,
+                    last_failure_at: req.failed_at,
+                };
+
+                bincode::serialize(&attempts).ok()
+            },
+        )?;
+
+        let attempts = match updated {
+            None => {
+                return Err(RepoError::CommonError(anyhow::anyhow!(
+                    "login attempts serialize error"
+                )))
+            }
+            Some(v) => deserialize_login_attempts(v.as_ref())?,
+        };
+
+        let res = AddLoginFailureResponse {
+            attempts: attempts.into(),
+        };
+
+        Ok(res)
+    }
+
+    async fn delete_login_attempts(
+        &self,
+        req: DeleteLoginAttemptsRequest,
+    ) -> RepoResult<DeleteLoginAttemptsResponse> {
+        self.login_attempts_tree
+            .remove(self.login_attempts_key(req.key)?)?;
+
+        Ok(())
+    }
+
+    async fn delete_expired_login_attempts(
+        &self,
+        req: DeleteExpiredLoginAttemptsRequest,
+    ) -> RepoResult<DeleteExpiredLoginAttemptsResponse> {
+        let mut deleted = 0;
+        for entry in self.login_attempts_tree.iter() {
+            let (key, value) = entry?;
+            let expired = deserialize_login_attempts(value.as_ref())
+                .map_or(true, |a| a.last_failure_at < req.last_failure_before);
+
+            // Counter which got a failure meanwhile is kept
+            if expired
+                && self
+                    .login_attempts_tree
+                    .compare_and_swap(key, Some(value), None as Option<&[u8]>)?
+                    .is_ok()
+            {
+                deleted += 1;
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    async fn use_invite(&self, req: UseInviteRequest) -> RepoResult<UseInviteResponse> {
+        // Concurrent logins must not exceed the limit, so count atomically.
+        // Closure may be retried, so flag is set on every call
+        let mut used = false;
+        self.invite_uses_tree
+            .update_and_fetch(self.invite_uses_key(req.invite_id)?, |old| {
+                let uses = old
+                    .and_then(deserialize_invite_uses)
+                    .map_or(0, |(uses, _)| uses);
+
+                used = uses < req.max_uses;
+                let uses = if used { uses + 1 } else { uses };
+
+                Some(serialize_invite_uses(uses, req.expires_at.timestamp()))
+            })?;
+
+        Ok(used)
+    }
+
+    async fn delete_expired_invite_uses(
+        &self,
+        req: DeleteExpiredInviteUsesRequest,
+    ) -> RepoResult<DeleteExpiredInviteUsesResponse> {
+        let mut deleted = 0;
+        for entry in self.invite_uses_tree.iter() {
+            let (key, value) = entry?;
+            let expired = deserialize_invite_uses(value.as_ref())
+                .is_none_or(|(_, exp)| exp < req.expired_before.timestamp());
+
+            if expired
+                && self
+                    .invite_uses_tree
+                    .compare_and_swap(key, Some(value), None as Option<&[u8]>)?
+                    .is_ok()
+            {
+                deleted += 1;
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    async fn add_known_device(
+        &self,
+        req: AddKnownDeviceRequest,
+    ) -> RepoResult<AddKnownDeviceResponse> {
+        self.known_devices_tree.insert(
+            self.known_device_key(req.room_id, req.device_id)?,
+            &req.seen_at.timestamp().to_be_bytes(),
+        )?;
+
+        Ok(())
+    }
+
+    async fn is_known_device(
+        &self,
+        req: IsKnownDeviceRequest,
+    ) -> RepoResult<IsKnownDeviceResponse> {
+        let known = self
+            .known_devices_tree
+            .get(self.known_device_key(req.room_id, req.device_id)?)?
+            .and_then(|v| deserialize_seen_at(v.as_ref()))
+            .is_some_and(|seen_at| seen_at > req.seen_after.timestamp());
+
+        Ok(known)
+    }
+
+    async fn delete_expired_known_devices(
+        &self,
+        req: DeleteExpiredKnownDevicesRequest,
+    ) -> RepoResult<DeleteExpiredKnownDevicesResponse> {
+        let mut deleted = 0;
+        for entry in self.known_devices_tree.iter() {
+            let (key, value) = entry?;
+            let expired = deserialize_seen_at(value.as_ref())
+                .is_none_or(|seen_at| seen_at < req.seen_before.timestamp());
+
+            // Device which logged in meanwhile is kept
+            if expired
+                && self
+                    .known_devices_tree
+                    .compare_and_swap(key, Some(value), None as Option<&[u8]>)?
+                    .is_ok()
+            {
+                deleted += 1;
+            }
+        }
+
+        Ok(deleted)
+    }
+}
+
+const ROOM_ID_LEN: usize = 8;
+const CIPHER_SCOPE: &[u8] = b"auth-clients";
+
+/// Key of client, or of device, in room indexes
+fn room_client_key(room_id: RoomId, client_id: ClientId) -> Vec<u8> {
+    let mut key = room_id.to_ne_bytes().to_vec();
+    key.extend_from_slice(client_id.as_bytes());
+    key
+}
+
+/// `uses | exp` of invite, both big-endian
+fn serialize_invite_uses(uses: u32, exp: i64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(12);
+    buf.extend_from_slice(&uses.to_be_bytes());
+    buf.extend_from_slice(&exp.to_be_bytes());
+    buf
+}
+
+fn deserialize_invite_uses(value: &[u8]) -> Option<(u32, i64)> {
+    let uses = <[u8; 4]>::try_from(value.get(..4)?).ok()?;
+    let exp = <[u8; 8]>::try_from(value.get(4..)?).ok()?;
+    Some((u32::from_be_bytes(uses), i64::from_be_bytes(exp)))
+}
+
+/// Unix timestamp of the last login of known device
+fn deserialize_seen_at(value: &[u8]) -> Option<i64> {
+    <[u8; 8]>::try_from(value).ok().map(i64::from_be_bytes)
+}
+
+fn deserialize_login_attempts(value: &[u8]) -> RepoResult<models_sled::LoginAttempts> {
+    bincode::deserialize(value).map_err(|err| RepoError::CommonError(err.into()))
 }
 
 impl From<GetRoomCredentialsRequest> for room_repo::GetRoomCredentialsRequest {
@@ -149,6 +523,15 @@ impl From<GetRoomCredentialsRequest> for room_repo::GetRoomCredentialsRequest {
     }
 }
 
+impl From<DeleteRoomPasswordRequest> for room_repo::DeleteRoomPasswordRequest {
+    fn from(f: DeleteRoomPasswordRequest) -> Self {
+        Self {
+            room_id: f.room_id,
+            hash: f.hash,
+        }
+    }
+}
+
 impl From<room_repo::GetRoomCredentialsResponse> for GetRoomCredentialsResponse {
     fn from(f: room_repo::GetRoomCredentialsResponse) -> Self {
         Self {
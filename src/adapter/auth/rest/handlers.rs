@@ -1,59 +1,212 @@
 use crate::adapter::auth::rest::models::*;
-use crate::adapter::auth::rest::REFRESH_TOKEN_COOKIE_NAME;
+use crate::adapter::auth::rest::{
+    ACCESS_TOKEN_COOKIE_NAME, AUTH_COOKIE_PATH, CSRF_TOKEN_COOKIE_NAME, DEVICE_ID_COOKIE_NAME,
+    REFRESH_TOKEN_COOKIE_NAME,
+};
 use crate::adapter::rest_prelude::*;
+use crate::adapter::room::rest::ws::{Kick, Relay, WsHub};
+use crate::adapter::room::rest::WsServerMessage;
+use crate::infra::cookie::CookiePolicy;
 use crate::port::auth::service as auth_service;
+use crate::port::ServiceError;
 
-use actix_web::web;
+use actix::SystemService;
+use actix_web::cookie::Cookie;
+use actix_web::{web, HttpMessage};
 use chrono::Utc;
+use uuid::Uuid;
+
+const DEVICE_ID_COOKIE_MAX_AGE_DAYS: i64 = 365;
 
 pub fn service_config(cfg: &mut web::ServiceConfig) {
-    cfg.service(login).service(logout).service(refresh_tokens);
+    cfg.service(login)
+        .service(login_with_invite)
+        .service(logout)
+        .service(refresh_tokens);
+}
+
+/// Routes outside of API scope
+pub fn well_known_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_jwks);
 }
 
 #[actix_web::post("/v1/auth/login")]
-async fn login(state: web::Data<State>, req: web::Json<LoginRequest>) -> ApiResult {
+async fn login(
+    _origin: TrustedOrigin,
+    state: web::Data<State>,
+    http_req: HttpRequest,
+    req: web::Json<LoginRequest>,
+) -> ApiResult {
+    let dpop_jkt = verify_dpop_proof(
+        &state,
+        http_req.head(),
+        &http_req.connection_info(),
+        None,
+        None,
+    )
+    .map_err(AnyhowErrorWrapper::from)
+    .map_err(|err| err_with_status(http::StatusCode::UNAUTHORIZED, err))?;
+
+    let room_id = req.0.room_id;
     let login_req = auth_service::LoginRequest {
         fingerprint: req.0.fingerprint,
-        room_id: req.0.room_id,
+        room_id,
         room_password: req.0.room_password,
+        client_ip: state.client_ip_resolver.client_ip(&http_req),
+        device_id: http_req
+            .cookie(DEVICE_ID_COOKIE_NAME)
+            .and_then(|c| c.value().parse::<Uuid>().ok()),
+        dpop_jkt,
+        anonymous_client_id: http_req.extensions().get::<AnonymousClient>().map(|c| c.0),
     };
 
+    let login_res = match state.auth_service.login(login_req).await {
+        Ok(res) => res,
+        Err(ServiceError::TooManyAttempts {
+            retry_after,
+            room_locked,
+        }) => {
+            // Warn room owners that somebody is guessing the password
+            if room_locked {
+                WsHub::from_registry().do_send(Relay {
+                    room_id,
+                    from: Uuid::nil(),
+                    to: None,
+                    owners_only: true,
+                    msg: WsServerMessage::LoginLocked { retry_after },
+                });
+            }
+
+            return Ok(too_many_attempts(retry_after));
+        }
+        Err(err) => return Err(err_from_login(err)),
+    };
+
+    let mut jwt: Jwt = login_res.jwt.into();
+
+    // Access token in cookie is usable only with CSRF token, see `JwtAuth`
+    let csrf_token = state
+        .auth_service
+        .access_token_cookie()
+        .then(|| Uuid::new_v4().to_string());
+    jwt.access_token.csrf = csrf_token.clone();
+
+    let access_token_encoded = jwt
+        .access_token
+        .encode(&state.jwt_keyring)
+        .map_err(AnyhowErrorWrapper::from)
+        .map_err(err_with_internal_error)?;
+
+    let refresh_token_encoded = jwt
+        .refresh_token
+        .encode(&state.jwt_keyring)
+        .map_err(AnyhowErrorWrapper::from)
+        .map_err(err_with_internal_error)?;
+
+    let login_res_json = LoginResponse {
+        access_token: access_token_encoded.clone(),
+    };
+
+    let res = {
+        let mut res = HttpResponse::Ok().json(login_res_json);
+        for cookie in auth_cookies(
+            &state.cookie_policy,
+            &jwt,
+            access_token_encoded,
+            refresh_token_encoded,
+            csrf_token,
+        ) {
+            res.add_cookie(&cookie).map_err(err_with_internal_error)?;
+        }
+        // Device outlives sessions, so it passes room lock after logout too
+        let device_cookie = state
+            .cookie_policy
+            .apply(Cookie::build(
+                DEVICE_ID_COOKIE_NAME,
+                login_res.device_id.to_string(),
+            ))
+            .path(AUTH_COOKIE_PATH)
+            .http_only(true)
+            .max_age(time::Duration::days(DEVICE_ID_COOKIE_MAX_AGE_DAYS))
+            .finish();
+        res.add_cookie(&device_cookie)
+            .map_err(err_with_internal_error)?;
+        res
+    };
+
+    Ok(res)
+}
+
+#[actix_web::post("/v1/auth/login-with-invite")]
+async fn login_with_invite(
+    _origin: TrustedOrigin,
+    state: web::Data<State>,
+    http_req: HttpRequest,
+    req: web::Json<LoginWithInviteRequest>,
+) -> ApiResult {
+    let dpop_jkt = verify_dpop_proof(
+        &state,
+        http_req.head(),
+        &http_req.connection_info(),
+        None,
+        None,
+    )
+    .map_err(AnyhowErrorWrapper::from)
+    .map_err(|err| err_with_status(http::StatusCode::UNAUTHORIZED, err))?;
+
+    // Signature and expiration are checked on decode
+    let invite = InviteTokenDecoded::decode(&state.jwt_keyring, &req.0.token)
+        .map_err(AnyhowErrorWrapper::from)
+        .map_err(|err| err_with_status(http::StatusCode::UNAUTHORIZED, err))?;
+
+    let login_req = auth_service::LoginWithInviteRequest {
+        fingerprint: req.0.fingerprint,
+        invite: invite.into(),
+        dpop_jkt,
+        anonymous_client_id: http_req.extensions().get::<AnonymousClient>().map(|c| c.0),
+    };
     let login_res = state
         .auth_service
-        .login(login_req)
+        .login_with_invite(login_req)
         .await
-        .map_err(|err| err_with_status(http::StatusCode::UNAUTHORIZED, err))?;
+        .map_err(err_from_login)?;
+
+    let mut jwt: Jwt = login_res.jwt.into();
 
-    let jwt: Jwt = login_res.jwt.into();
+    // Access token in cookie is usable only with CSRF token, see `JwtAuth`
+    let csrf_token = state
+        .auth_service
+        .access_token_cookie()
+        .then(|| Uuid::new_v4().to_string());
+    jwt.access_token.csrf = csrf_token.clone();
 
     let access_token_encoded = jwt
         .access_token
-        .encode(state.auth_service.secret())
+        .encode(&state.jwt_keyring)
         .map_err(AnyhowErrorWrapper::from)
         .map_err(err_with_internal_error)?;
 
     let refresh_token_encoded = jwt
         .refresh_token
-        .encode(state.auth_service.secret())
+        .encode(&state.jwt_keyring)
         .map_err(AnyhowErrorWrapper::from)
         .map_err(err_with_internal_error)?;
 
     let login_res_json = LoginResponse {
-        access_token: access_token_encoded,
+        access_token: access_token_encoded.clone(),
     };
 
     let res = {
         let mut res = HttpResponse::Ok().json(login_res_json);
-        res.add_cookie(
-            &actix_web::cookie::Cookie::build(REFRESH_TOKEN_COOKIE_NAME, refresh_token_encoded)
-                .path("/api/v1/auth")
-                .http_only(true)
-                .max_age(time::Duration::seconds(
-                    jwt.refresh_token.exp.timestamp() - Utc::now().timestamp(),
-                ))
-                .finish(),
-        )
-        .map_err(err_with_internal_error)?;
+        for cookie in auth_cookies(
+            &state.cookie_policy,
+            &jwt,
+            access_token_encoded,
+            refresh_token_encoded,
+            csrf_token,
+        ) {
+            res.add_cookie(&cookie).map_err(err_with_internal_error)?;
+        }
         res
     };
 
@@ -61,7 +214,12 @@ async fn login(state: web::Data<State>, req: web::Json<LoginRequest>) -> ApiResu
 }
 
 #[actix_web::post("/v1/auth/logout")]
-async fn logout(state: web::Data<State>, jwt: Jwt) -> ApiResult {
+async fn logout(_origin: TrustedOrigin, state: web::Data<State>, jwt: Jwt) -> ApiResult {
+    let kick = Kick {
+        room_id: jwt.access_token.room_id,
+        client_id: Some(jwt.access_token.client_id),
+    };
+
     let logout_req = auth_service::LogoutRequest { jwt: jwt.into() };
     state
         .auth_service
@@ -69,57 +227,160 @@ async fn logout(state: web::Data<State>, jwt: Jwt) -> ApiResult {
         .await
         .map_err(err_with_internal_error)?;
 
-    Ok(HttpResponse::Ok().finish())
+    // Websockets are authorized once, they do not see the logout otherwise
+    WsHub::from_registry().do_send(kick);
+
+    let mut res = HttpResponse::Ok();
+    if state.auth_service.access_token_cookie() {
+        // Removal must match path and domain of the set cookie
+        let policy = &state.cookie_policy;
+        res.del_cookie(
+            &policy
+                .apply_strict(Cookie::build(ACCESS_TOKEN_COOKIE_NAME, ""))
+                .path(AUTH_COOKIE_PATH)
+                .finish(),
+        )
+        .del_cookie(
+            &policy
+                .apply_strict(Cookie::build(CSRF_TOKEN_COOKIE_NAME, ""))
+                .path("/")
+                .finish(),
+        );
+    }
+
+    Ok(res.finish())
 }
 
 #[actix_web::post("/v1/auth/refresh-tokens")]
 async fn refresh_tokens(
+    _origin: TrustedOrigin,
     state: web::Data<State>,
-    jwt: Jwt,
+    jwt: UncheckedJwt,
     req: web::Json<RefreshTokensRequest>,
 ) -> ApiResult {
+    // Session is checked by refresh itself, outdated refresh token revokes it
     let refresh_tokens_req = auth_service::RefreshTokensRequest {
         fingerprint: req.0.fingerprint,
-        jwt: jwt.into(),
+        jwt: jwt.0.into(),
     };
     let refresh_tokens_res = state
         .auth_service
         .refresh_tokens(refresh_tokens_req)
         .await
-        .map_err(err_with_internal_error)?;
+        .map_err(|err| err_with_status(http::StatusCode::UNAUTHORIZED, err))?;
+
+    let mut jwt: Jwt = refresh_tokens_res.jwt.into();
 
-    let jwt: Jwt = refresh_tokens_res.jwt.into();
+    // Access token in cookie is usable only with CSRF token, see `JwtAuth`
+    let csrf_token = state
+        .auth_service
+        .access_token_cookie()
+        .then(|| Uuid::new_v4().to_string());
+    jwt.access_token.csrf = csrf_token.clone();
 
     let access_token_encoded = jwt
         .access_token
-        .encode(state.auth_service.secret())
+        .encode(&state.jwt_keyring)
         .map_err(AnyhowErrorWrapper::from)
         .map_err(err_with_internal_error)?;
 
     let refresh_token_encoded = jwt
         .refresh_token
-        .encode(state.auth_service.secret())
+        .encode(&state.jwt_keyring)
         .map_err(AnyhowErrorWrapper::from)
         .map_err(err_with_internal_error)?;
 
     let refresh_tokens_res_json = RefreshTokensResponse {
-        access_token: access_token_encoded,
+        access_token: access_token_encoded.clone(),
     };
 
     let res = {
         let mut res = HttpResponse::Ok().json(refresh_tokens_res_json);
-        res.add_cookie(
-            &actix_web::cookie::Cookie::build(REFRESH_TOKEN_COOKIE_NAME, refresh_token_encoded)
-                .path("/api/v1/auth")
-                .http_only(true)
-                .max_age(time::Duration::seconds(
-                    jwt.refresh_token.exp.timestamp() - Utc::now().timestamp(),
-                ))
-                .finish(),
-        )
-        .map_err(err_with_internal_error)?;
+        for cookie in auth_cookies(
+            &state.cookie_policy,
+            &jwt,
+            access_token_encoded,
+            refresh_token_encoded,
+            csrf_token,
+        ) {
+            res.add_cookie(&cookie).map_err(err_with_internal_error)?;
+        }
         res
     };
 
     Ok(res)
 }
+
+#[actix_web::get("/.well-known/jwks.json")]
+async fn get_jwks(state: web::Data<State>) -> ApiResult {
+    let res = JwksResponse {
+        keys: state.jwt_keyring.jwks(),
+    };
+
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Refresh token cookie, and access and CSRF token ones in cookie mode
+fn auth_cookies(
+    policy: &CookiePolicy,
+    jwt: &Jwt,
+    access_token: AccessTokenEncoded,
+    refresh_token: RefreshTokenEncoded,
+    csrf_token: Option<String>,
+) -> Vec<Cookie<'static>> {
+    let now = Utc::now().timestamp();
+
+    let mut cookies = vec![policy
+        .apply(Cookie::build(REFRESH_TOKEN_COOKIE_NAME, refresh_token))
+        .path(AUTH_COOKIE_PATH)
+        .http_only(true)
+        .max_age(time::Duration::seconds(
+            jwt.refresh_token.exp.timestamp() - now,
+        ))
+        .finish()];
+
+    if let Some(csrf_token) = csrf_token {
+        let max_age = time::Duration::seconds(jwt.access_token.exp.timestamp() - now);
+        cookies.push(
+            policy
+                .apply_strict(Cookie::build(ACCESS_TOKEN_COOKIE_NAME, access_token))
+                .path(AUTH_COOKIE_PATH)
+                .http_only(true)
+                .max_age(max_age)
+                .finish(),
+        );
+        // Read by client script, so it is visible on every page
+        cookies.push(
+            policy
+                .apply_strict(Cookie::build(CSRF_TOKEN_COOKIE_NAME, csrf_token))
+                .path("/")
+                .max_age(max_age)
+                .finish(),
+        );
+    }
+
+    cookies
+}
+
+fn too_many_attempts(retry_after: i64) -> HttpResponse {
+    let mut res = ApiError::builder(http::StatusCode::TOO_MANY_REQUESTS)
+        .message("too many login attempts")
+        .field("retry_after", retry_after)
+        .finish()
+        .into_actix_web_response();
+
+    res.headers_mut().insert(
+        actix_web::http::header::RETRY_AFTER,
+        actix_web::http::HeaderValue::from(retry_after),
+    );
+
+    res
+}
+
+/// Rejected credentials are client errors, everything else is internal one
+fn err_from_login(err: ServiceError) -> ApiError {
+    match err {
+        ServiceError::AuthError(_) => err_with_status(http::StatusCode::UNAUTHORIZED, err),
+        err => err_with_internal_error(err),
+    }
+}
@@ -1,19 +1,30 @@
 use crate::adapter::auth::rest::models::*;
+use crate::adapter::auth::rest::ACCESS_TOKEN_COOKIE_NAME;
 use crate::adapter::auth::rest::ACCESS_TOKEN_HEADER_NAME;
 use crate::adapter::auth::rest::ACCESS_TOKEN_PREFIX;
+use crate::adapter::auth::rest::ANONYMOUS_CLIENT_COOKIE_NAME;
+use crate::adapter::auth::rest::CSRF_TOKEN_HEADER_NAME;
+use crate::adapter::auth::rest::DPOP_ACCESS_TOKEN_PREFIX;
 use crate::adapter::auth::rest::REFRESH_TOKEN_COOKIE_NAME;
 use crate::adapter::rest_prelude::*;
+use crate::infra::cookie::CookiePolicy;
 use crate::port::auth::service as auth_service;
 
-use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::cookie::Cookie;
+use actix_web::dev::{
+    ConnectionInfo, RequestHead, Service, ServiceRequest, ServiceResponse, Transform,
+};
 use actix_web::{web, Error as ActixError, HttpMessage};
 use futures::future::LocalBoxFuture;
 use futures::{future, FutureExt};
 use regex::Regex;
-use std::cell::RefCell;
+use std::cell::{Ref, RefCell};
 use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use std::task;
+use subtle::ConstantTimeEq;
+
+const ANONYMOUS_CLIENT_COOKIE_MAX_AGE_DAYS: i64 = 365;
 
 struct Inner {
     exclude_fn: Option<Box<dyn Fn(&ServiceRequest) -> bool>>,
@@ -236,37 +247,173 @@ where
             exclude_fn_res || exclude_res || exclude_regexp_res
         };
 
-        if !exclude {
-            let service = Rc::clone(&self.service);
-            async move {
+        let service = Rc::clone(&self.service);
+        async move {
+            let state = req.app_data::<web::Data<State>>().expect("no state");
+
+            // Without auth clients are told apart by cookie only. Excluded
+            // routes get it too, rooms are created and joined there
+            if !state.auth_service.enabled() {
+                let (client_id, new_cookie) = anonymous_client(&req, &state.cookie_policy);
+                if !exclude {
+                    anonymous_auth(&req, client_id).await?;
+                }
+                let fut = service.borrow_mut().call(req);
+                let mut res = fut.await?;
+                if let Some(cookie) = new_cookie {
+                    res.response_mut().add_cookie(&cookie)?;
+                }
+                return Ok(res);
+            }
+
+            if !exclude {
                 auth(&req).await?;
-                service.borrow_mut().call(req).await
             }
-            .boxed_local()
-        } else {
-            self.service.call(req).boxed_local()
+            service.borrow_mut().call(req).await
         }
+        .boxed_local()
     }
 }
 
+/// Returns client id and cookie to set if client is not known yet. Cookie
+/// holds secret token of the client, never the id itself
+fn anonymous_client(
+    req: &ServiceRequest,
+    policy: &CookiePolicy,
+) -> (ClientId, Option<Cookie<'static>>) {
+    let token = req
+        .cookie(ANONYMOUS_CLIENT_COOKIE_NAME)
+        .and_then(|c| AnonymousClientToken::parse(c.value()));
+
+    let (client_id, cookie) = match token {
+        Some(token) => (token.client_id(), None),
+        None => {
+            let token = AnonymousClientToken::generate();
+            let cookie = policy
+                .apply(Cookie::build(
+                    ANONYMOUS_CLIENT_COOKIE_NAME,
+                    token.to_string(),
+                ))
+                .path("/")
+                .http_only(true)
+                .max_age(time::Duration::days(ANONYMOUS_CLIENT_COOKIE_MAX_AGE_DAYS))
+                .finish();
+            (token.client_id(), Some(cookie))
+        }
+    };
+
+    req.extensions_mut().insert(AnonymousClient(client_id));
+
+    (client_id, cookie)
+}
+
+/// Anonymous client acts in the room it created or joined
+async fn anonymous_auth(req: &ServiceRequest, client_id: ClientId) -> Result<(), ActixError> {
+    let ctx = req.app_data::<web::Data<State>>().expect("no state");
+
+    let auth_req = auth_service::AuthorizeAnonymousRequest { client_id };
+    let auth_res = ctx
+        .auth_service
+        .authorize_anonymous(auth_req)
+        .await
+        .map_err(actix_web::error::ErrorUnauthorized)?;
+
+    let jwt: Jwt = auth_res.jwt.into();
+
+    req.extensions_mut().insert(jwt);
+
+    Ok(())
+}
+
 async fn auth(req: &ServiceRequest) -> Result<(), ActixError> {
     let ctx = req.app_data::<web::Data<State>>().expect("no state");
 
+    let jwt = decode_jwt(req)?;
+
+    // Authorize
+    let auth_req = auth_service::AuthorizeRequest { jwt: jwt.into() };
+    let auth_res = ctx
+        .auth_service
+        .authorize(auth_req)
+        .await
+        .map_err(actix_web::error::ErrorUnauthorized)?;
+
+    let jwt: Jwt = auth_res.jwt.into();
+
+    req.extensions_mut().insert(jwt);
+
+    Ok(())
+}
+
+/// Request parts needed to decode tokens. `ServiceRequest` of actix-web 3
+/// has no `HttpRequest` view, so both of them are accepted through this
+pub trait TokenRequest: HttpMessage {
+    fn state(&self) -> &web::Data<State>;
+    fn head(&self) -> &RequestHead;
+    fn connection_info(&self) -> Ref<'_, ConnectionInfo>;
+}
+
+impl TokenRequest for HttpRequest {
+    fn state(&self) -> &web::Data<State> {
+        self.app_data::<web::Data<State>>().expect("no state")
+    }
+
+    fn head(&self) -> &RequestHead {
+        HttpRequest::head(self)
+    }
+
+    fn connection_info(&self) -> Ref<'_, ConnectionInfo> {
+        HttpRequest::connection_info(self)
+    }
+}
+
+impl TokenRequest for ServiceRequest {
+    fn state(&self) -> &web::Data<State> {
+        self.app_data::<web::Data<State>>().expect("no state")
+    }
+
+    fn head(&self) -> &RequestHead {
+        ServiceRequest::head(self)
+    }
+
+    fn connection_info(&self) -> Ref<'_, ConnectionInfo> {
+        ServiceRequest::connection_info(self)
+    }
+}
+
+/// Decodes tokens of the request and checks its CSRF token and DPoP proof,
+/// but not the session. Used as is by endpoints excluded from `JwtAuth`
+pub fn decode_jwt<R: TokenRequest>(req: &R) -> Result<Jwt, ActixError> {
+    let ctx = req.state();
+
     let auth_service = &ctx.auth_service;
 
-    // Get access token from header
-    let access_token_encoded = req
-        .headers()
-        .get(ACCESS_TOKEN_HEADER_NAME)
-        .ok_or_else(|| {
-            actix_web::error::ErrorUnauthorized(format!(
+    // Get access token from header, or from cookie if it is enabled
+    let (access_token_encoded, from_cookie) = match req.headers().get(ACCESS_TOKEN_HEADER_NAME) {
+        Some(header) => {
+            let access_token_encoded = header
+                .to_str()
+                .map_err(actix_web::error::ErrorBadRequest)?
+                .trim_start_matches(ACCESS_TOKEN_PREFIX)
+                .trim_start_matches(DPOP_ACCESS_TOKEN_PREFIX);
+            (access_token_encoded.to_owned(), false)
+        }
+        None if auth_service.access_token_cookie() => {
+            let cookie = req.cookie(ACCESS_TOKEN_COOKIE_NAME).ok_or_else(|| {
+                actix_web::error::ErrorUnauthorized(format!(
+                    r#""{}" header or "{}" cookie not found"#,
+                    ACCESS_TOKEN_HEADER_NAME, ACCESS_TOKEN_COOKIE_NAME
+                ))
+            })?;
+            (cookie.value().to_owned(), true)
+        }
+        None => {
+            return Err(actix_web::error::ErrorUnauthorized(format!(
                 r#""{}" header not found"#,
                 ACCESS_TOKEN_HEADER_NAME
-            ))
-        })?
-        .to_str()
-        .map_err(actix_web::error::ErrorBadRequest)?
-        .trim_start_matches(ACCESS_TOKEN_PREFIX);
+            )))
+        }
+    };
 
     // Get refresh token from cookie
     let refresh_token_encoded = req.cookie(REFRESH_TOKEN_COOKIE_NAME).ok_or_else(|| {
@@ -277,30 +424,49 @@ async fn auth(req: &ServiceRequest) -> Result<(), ActixError> {
     })?;
 
     // Decode access token
-    let access_token_decoded =
-        AccessTokenDecoded::decode(auth_service.secret(), access_token_encoded)
-            .map_err(actix_web::error::ErrorBadRequest)?;
+    let access_token_decoded = AccessTokenDecoded::decode(&ctx.jwt_keyring, &access_token_encoded)
+        .map_err(actix_web::error::ErrorBadRequest)?;
+
+    // Browser sends cookie with cross-site requests too, so state-changing
+    // ones must prove that they are made by client script
+    if from_cookie && !req.head().method.is_safe() {
+        let csrf_token = req
+            .headers()
+            .get(CSRF_TOKEN_HEADER_NAME)
+            .and_then(|h| h.to_str().ok());
+        let valid = match (csrf_token, &access_token_decoded.csrf) {
+            (Some(token), Some(expected)) => token.as_bytes().ct_eq(expected.as_bytes()).into(),
+            _ => false,
+        };
+        if !valid {
+            return Err(actix_web::error::ErrorForbidden("invalid csrf token"));
+        }
+    }
+
+    // Bound token is useless without the key
+    if let Some(cnf) = &access_token_decoded.cnf {
+        let jkt = verify_dpop_proof(
+            ctx,
+            req.head(),
+            &req.connection_info(),
+            Some(&access_token_encoded),
+            Some(&cnf.jkt),
+        )
+        .map_err(actix_web::error::ErrorUnauthorized)?;
+        if jkt.as_ref() != Some(&cnf.jkt) {
+            return Err(actix_web::error::ErrorUnauthorized(
+                "valid dpop proof is required",
+            ));
+        }
+    }
 
     // Decode refresh token
     let refresh_token_decoded =
-        RefreshTokenDecoded::decode(auth_service.secret(), refresh_token_encoded.value())
+        RefreshTokenDecoded::decode(&ctx.jwt_keyring, refresh_token_encoded.value())
             .map_err(actix_web::error::ErrorBadRequest)?;
 
-    // Authorize
-    let auth_req = auth_service::AuthorizeRequest {
-        jwt: auth_service::Jwt {
-            access_token: access_token_decoded.into(),
-            refresh_token: refresh_token_decoded.into(),
-        },
-    };
-    let auth_res = auth_service
-        .authorize(auth_req)
-        .await
-        .map_err(actix_web::error::ErrorUnauthorized)?;
-
-    let jwt: Jwt = auth_res.jwt.into();
-
-    req.extensions_mut().insert(jwt);
-
-    Ok(())
+    Ok(Jwt {
+        access_token: access_token_decoded,
+        refresh_token: refresh_token_decoded,
+    })
 }
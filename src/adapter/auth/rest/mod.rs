@@ -7,5 +7,14 @@ pub use middleware::*;
 pub use models::*;
 
 pub const REFRESH_TOKEN_COOKIE_NAME: &str = "refreshToken";
+pub const ACCESS_TOKEN_COOKIE_NAME: &str = "accessToken";
+pub const CSRF_TOKEN_COOKIE_NAME: &str = "csrfToken";
+pub const CSRF_TOKEN_HEADER_NAME: &str = "X-CSRF-Token";
+/// Middleware needs auth cookies on every API route
+pub const AUTH_COOKIE_PATH: &str = "/api";
+pub const ANONYMOUS_CLIENT_COOKIE_NAME: &str = "clientToken";
+pub const DEVICE_ID_COOKIE_NAME: &str = "deviceId";
 pub const ACCESS_TOKEN_HEADER_NAME: &str = "Authorization";
 pub const ACCESS_TOKEN_PREFIX: &str = "Bearer ";
+pub const DPOP_ACCESS_TOKEN_PREFIX: &str = "DPoP ";
+pub const DPOP_HEADER_NAME: &str = "DPoP";
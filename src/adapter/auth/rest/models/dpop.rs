@@ -0,0 +1,33 @@
+use crate::adapter::auth::rest::DPOP_HEADER_NAME;
+use crate::adapter::rest_prelude::*;
+use crate::infra::dpop::VerifyProofRequest;
+
+use actix_web::dev::{ConnectionInfo, RequestHead};
+
+/// Verifies DPoP proof of the request if there is one and returns
+/// thumbprint of its key. Proof of request with access token must be
+/// bound to that token and signed by the key `jkt` of the token
+pub fn verify_dpop_proof(
+    state: &State,
+    head: &RequestHead,
+    conn: &ConnectionInfo,
+    access_token: Option<&str>,
+    jkt: Option<&str>,
+) -> anyhow::Result<Option<String>> {
+    let proof = match head.headers().get(DPOP_HEADER_NAME) {
+        Some(proof) => proof.to_str()?,
+        None => return Ok(None),
+    };
+
+    let url = format!("{}://{}{}", conn.scheme(), conn.host(), head.uri.path());
+
+    let jkt = state.dpop_verifier.verify(VerifyProofRequest {
+        proof,
+        method: head.method.as_str(),
+        url: &url,
+        access_token,
+        jkt,
+    })?;
+
+    Ok(Some(jkt))
+}
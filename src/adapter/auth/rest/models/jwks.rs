@@ -0,0 +1,6 @@
+use crate::infra::jwt::Jwk;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct JwksResponse {
+    pub keys: Vec<Jwk>,
+}
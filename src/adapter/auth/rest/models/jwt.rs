@@ -1,5 +1,7 @@
+use crate::adapter::auth::rest::middleware::decode_jwt;
 use crate::adapter::rest_prelude::*;
 use crate::adapter::room::rest::RoomId;
+use crate::infra::jwt::{JwtKeyring, TokenType};
 use crate::port::auth::service::models as auth_models;
 
 use actix_web::dev::Payload;
@@ -7,22 +9,20 @@ use actix_web::Error as ActixError;
 use actix_web::FromRequest;
 use chrono::NaiveDateTime;
 use futures::future;
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
 use uuid::Uuid;
 
 pub trait Encode {
     type Output: AsRef<str>;
-    fn encode<S>(&self, secret: S) -> anyhow::Result<Self::Output>
-    where
-        S: AsRef<str>;
+    fn encode(&self, keyring: &JwtKeyring) -> anyhow::Result<Self::Output>;
 }
 
 pub trait Decode {
     type Output;
-    fn decode<S1, S2>(secret: S1, value: S2) -> anyhow::Result<Self::Output>
+    fn decode<S>(keyring: &JwtKeyring, value: S) -> anyhow::Result<Self::Output>
     where
-        S1: AsRef<str>,
-        S2: AsRef<str>;
+        S: AsRef<str>;
 }
 
 pub type ClientId = Uuid;
@@ -30,6 +30,7 @@ pub type RefreshTokenSalt = Uuid;
 
 pub type AccessTokenEncoded = String;
 pub type RefreshTokenEncoded = String;
+pub type InviteTokenEncoded = String;
 
 // Access token
 
@@ -39,16 +40,27 @@ pub struct AccessTokenDecoded {
     pub exp: NaiveDateTime,
     pub client_id: ClientId,
     pub room_id: RoomId,
+    #[serde(default)]
+    pub owner: bool,
+    /// Confirmation claim (RFC 7800) of DPoP bound token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cnf: Option<Confirmation>,
+    /// Expected CSRF token of requests with this token in cookie
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub csrf: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Confirmation {
+    /// JWK thumbprint of DPoP key
+    pub jkt: String,
 }
 
 impl Encode for AccessTokenDecoded {
     type Output = AccessTokenEncoded;
 
-    fn encode<S>(&self, secret: S) -> anyhow::Result<Self::Output>
-    where
-        S: AsRef<str>,
-    {
-        let token = encode_jwt(secret, self)?;
+    fn encode(&self, keyring: &JwtKeyring) -> anyhow::Result<Self::Output> {
+        let token = keyring.encode(TokenType::Access, self)?;
         log::debug!("access token encode: {:?}", token);
 
         Ok(token)
@@ -58,12 +70,12 @@ impl Encode for AccessTokenDecoded {
 impl Decode for AccessTokenDecoded {
     type Output = Self;
 
-    fn decode<S1, S2>(secret: S1, value: S2) -> anyhow::Result<Self::Output>
+    fn decode<S>(keyring: &JwtKeyring, value: S) -> anyhow::Result<Self::Output>
     where
-        S1: AsRef<str>,
-        S2: AsRef<str>,
+        S: AsRef<str>,
     {
-        let token_decoded: AccessTokenDecoded = decode_jwt(secret.as_ref(), value)?;
+        let token_decoded: AccessTokenDecoded =
+            keyring.decode(TokenType::Access, value.as_ref())?;
         log::debug!("access token decode: {:?}", token_decoded);
 
         Ok(token_decoded)
@@ -76,13 +88,22 @@ impl From<auth_models::AccessTokenDecoded> for AccessTokenDecoded {
             exp: f.exp,
             client_id: f.client_id,
             room_id: f.room_id,
+            owner: f.owner,
+            cnf: f.dpop_jkt.map(|jkt| Confirmation { jkt }),
+            csrf: None,
         }
     }
 }
 
 impl From<AccessTokenDecoded> for auth_models::AccessTokenDecoded {
     fn from(f: AccessTokenDecoded) -> Self {
-        Self::new(f.exp, f.client_id, f.room_id)
+        Self::new(
+            f.exp,
+            f.client_id,
+            f.room_id,
+            f.owner,
+            f.cnf.map(|cnf| cnf.jkt),
+        )
     }
 }
 
@@ -98,11 +119,8 @@ pub struct RefreshTokenDecoded {
 impl Encode for RefreshTokenDecoded {
     type Output = RefreshTokenEncoded;
 
-    fn encode<S>(&self, secret: S) -> anyhow::Result<Self::Output>
-    where
-        S: AsRef<str>,
-    {
-        let token = encode_jwt(secret, self)?;
+    fn encode(&self, keyring: &JwtKeyring) -> anyhow::Result<Self::Output> {
+        let token = keyring.encode(TokenType::Refresh, self)?;
         log::debug!("refresh token encode: {:?}", token);
 
         Ok(token)
@@ -112,12 +130,12 @@ impl Encode for RefreshTokenDecoded {
 impl Decode for RefreshTokenDecoded {
     type Output = Self;
 
-    fn decode<S1, S2>(secret: S1, value: S2) -> anyhow::Result<Self::Output>
+    fn decode<S>(keyring: &JwtKeyring, value: S) -> anyhow::Result<Self::Output>
     where
-        S1: AsRef<str>,
-        S2: AsRef<str>,
+        S: AsRef<str>,
     {
-        let token_decoded: RefreshTokenDecoded = decode_jwt(secret.as_ref(), value)?;
+        let token_decoded: RefreshTokenDecoded =
+            keyring.decode(TokenType::Refresh, value.as_ref())?;
         log::debug!("refresh token decode: {:?}", token_decoded);
 
         Ok(token_decoded)
@@ -139,36 +157,92 @@ impl From<RefreshTokenDecoded> for auth_models::RefreshTokenDecoded {
     }
 }
 
-// JWT utils
+// Invite token
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum InviteRole {
+    #[default]
+    Member,
+    Owner,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct InviteTokenDecoded {
+    #[serde(with = "naive_date_time_format")]
+    pub exp: NaiveDateTime,
+    pub invite_id: Uuid,
+    pub room_id: RoomId,
+    pub role: InviteRole,
+    pub max_uses: u32,
+}
+
+impl Encode for InviteTokenDecoded {
+    type Output = InviteTokenEncoded;
+
+    fn encode(&self, keyring: &JwtKeyring) -> anyhow::Result<Self::Output> {
+        let token = keyring.encode(TokenType::Invite, self)?;
+        log::debug!("invite token encode: {:?}", token);
+
+        Ok(token)
+    }
+}
+
+impl Decode for InviteTokenDecoded {
+    type Output = Self;
+
+    fn decode<S>(keyring: &JwtKeyring, value: S) -> anyhow::Result<Self::Output>
+    where
+        S: AsRef<str>,
+    {
+        let token_decoded: InviteTokenDecoded =
+            keyring.decode(TokenType::Invite, value.as_ref())?;
+        log::debug!("invite token decode: {:?}", token_decoded);
+
+        Ok(token_decoded)
+    }
+}
 
-fn encode_jwt<S, T>(secret: S, token_decoded: &T) -> Result<String, jsonwebtoken::errors::Error>
-where
-    S: AsRef<str>,
-    T: serde::Serialize,
-{
-    let token = jsonwebtoken::encode(
-        &Header::default(),
-        token_decoded,
-        &EncodingKey::from_secret(secret.as_ref().as_bytes()),
-    )?;
+impl From<auth_models::InviteRole> for InviteRole {
+    fn from(f: auth_models::InviteRole) -> Self {
+        match f {
+            auth_models::InviteRole::Member => Self::Member,
+            auth_models::InviteRole::Owner => Self::Owner,
+        }
+    }
+}
 
-    Ok(token)
+impl From<InviteRole> for auth_models::InviteRole {
+    fn from(f: InviteRole) -> Self {
+        match f {
+            InviteRole::Member => Self::Member,
+            InviteRole::Owner => Self::Owner,
+        }
+    }
 }
 
-fn decode_jwt<T, S1, S2>(secret: S1, token: S2) -> Result<T, jsonwebtoken::errors::Error>
-where
-    T: serde::de::DeserializeOwned,
-    S1: AsRef<str>,
-    S2: AsRef<str>,
-{
-    let access_token_decoded = jsonwebtoken::decode::<T>(
-        token.as_ref(),
-        &DecodingKey::from_secret(secret.as_ref().as_bytes()),
-        &Validation::default(),
-    )
-    .map(|token_data| token_data.claims)?;
+impl From<auth_models::InviteDecoded> for InviteTokenDecoded {
+    fn from(f: auth_models::InviteDecoded) -> Self {
+        Self {
+            exp: f.exp,
+            invite_id: f.id,
+            room_id: f.room_id,
+            role: f.role.into(),
+            max_uses: f.max_uses,
+        }
+    }
+}
 
-    Ok(access_token_decoded)
+impl From<InviteTokenDecoded> for auth_models::InviteDecoded {
+    fn from(f: InviteTokenDecoded) -> Self {
+        Self {
+            id: f.invite_id,
+            exp: f.exp,
+            room_id: f.room_id,
+            role: f.role.into(),
+            max_uses: f.max_uses,
+        }
+    }
 }
 
 mod naive_date_time_format {
@@ -244,3 +318,63 @@ impl FromRequest for Jwt {
         }
     }
 }
+
+/// Tokens of request which is excluded from `JwtAuth`, session is not
+/// checked. Refresh needs them to detect reuse of outdated refresh token
+#[derive(Debug, Clone)]
+pub struct UncheckedJwt(pub Jwt);
+
+impl FromRequest for UncheckedJwt {
+    type Error = ActixError;
+    type Future = future::Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        future::ready(decode_jwt(req).map(UncheckedJwt))
+    }
+}
+
+/// Client id from cookie, set by middleware if auth is disabled
+#[derive(Debug, Clone, Copy)]
+pub struct AnonymousClient(pub ClientId);
+
+const ANONYMOUS_CLIENT_TOKEN_LEN: usize = 32;
+
+/// Random secret of anonymous client kept in cookie. Client id is derived
+/// from it and may be seen by others, while the secret can not be
+/// recovered from the id, so a known id does not let anyone act as that
+/// client
+pub struct AnonymousClientToken([u8; ANONYMOUS_CLIENT_TOKEN_LEN]);
+
+impl AnonymousClientToken {
+    pub fn generate() -> Self {
+        Self(rand::random())
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        let bytes = base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok()?;
+        <[u8; ANONYMOUS_CLIENT_TOKEN_LEN]>::try_from(bytes.as_slice())
+            .ok()
+            .map(Self)
+    }
+
+    pub fn client_id(&self) -> ClientId {
+        let digest = Sha256::new()
+            .chain_update(b"ezspot anonymous client")
+            .chain_update(self.0)
+            .finalize();
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&digest[..16]);
+
+        uuid::Builder::from_bytes(bytes)
+            .set_variant(uuid::Variant::RFC4122)
+            .set_version(uuid::Version::Random)
+            .build()
+    }
+}
+
+impl std::fmt::Display for AnonymousClientToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&base64::encode_config(self.0, base64::URL_SAFE_NO_PAD))
+    }
+}
@@ -1,8 +1,10 @@
-use crate::adapter::auth::rest::AccessTokenEncoded;
+use crate::adapter::auth::rest::{AccessTokenEncoded, InviteTokenEncoded};
 use crate::adapter::room::rest::RoomId;
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct LoginRequest {
+    /// May be empty for clients which send DPoP proofs
+    #[serde(default)]
     pub fingerprint: String,
     pub room_id: RoomId,
     pub room_password: String,
@@ -12,3 +14,11 @@ pub struct LoginRequest {
 pub struct LoginResponse {
     pub access_token: AccessTokenEncoded,
 }
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct LoginWithInviteRequest {
+    /// May be empty for clients which send DPoP proofs
+    #[serde(default)]
+    pub fingerprint: String,
+    pub token: InviteTokenEncoded,
+}
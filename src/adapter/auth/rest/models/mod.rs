@@ -1,7 +1,13 @@
+pub mod dpop;
+pub mod jwks;
 pub mod jwt;
 pub mod login;
+pub mod origin;
 pub mod refresh_tokens;
 
+pub use dpop::*;
+pub use jwks::*;
 pub use jwt::*;
 pub use login::*;
+pub use origin::*;
 pub use refresh_tokens::*;
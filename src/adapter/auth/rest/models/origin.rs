@@ -0,0 +1,64 @@
+use crate::adapter::rest_prelude::*;
+
+use actix_web::dev::Payload;
+use actix_web::Error as ActixError;
+use actix_web::FromRequest;
+use futures::future;
+
+/// Guard of endpoints which set or use auth cookies. Browsers send
+/// `Origin` or at least `Referer`, that origin must be the API own one or
+/// one of `auth.allowed_origins`. Requests without both are not made by
+/// browser on behalf of another site, so they pass
+#[derive(Debug, Clone, Copy)]
+pub struct TrustedOrigin;
+
+impl FromRequest for TrustedOrigin {
+    type Error = ActixError;
+    type Future = future::Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        future::ready(check_origin(req).map(|_| TrustedOrigin))
+    }
+}
+
+fn check_origin(req: &HttpRequest) -> Result<(), ActixError> {
+    let state = req
+        .app_data::<actix_web::web::Data<State>>()
+        .expect("no state");
+
+    let header = |name| {
+        req.headers()
+            .get(name)
+            .map(|h| h.to_str().map_err(actix_web::error::ErrorForbidden))
+            .transpose()
+    };
+    let origin = match (
+        header(http::header::ORIGIN)?,
+        header(http::header::REFERER)?,
+    ) {
+        (Some(origin), _) => origin,
+        (None, Some(referer)) => referer_origin(referer),
+        (None, None) => return Ok(()),
+    };
+
+    let own_origin = {
+        let conn = req.connection_info();
+        format!("{}://{}", conn.scheme(), conn.host())
+    };
+
+    if !state.cookie_policy.is_origin_allowed(origin, &own_origin) {
+        return Err(actix_web::error::ErrorForbidden("origin is not allowed"));
+    }
+
+    Ok(())
+}
+
+/// `scheme://host[:port]` part of URL
+fn referer_origin(referer: &str) -> &str {
+    let host_start = referer.find("://").map_or(0, |i| i + 3);
+    match referer[host_start..].find(['/', '?', '#']) {
+        Some(host_len) => &referer[..host_start + host_len],
+        None => referer,
+    }
+}
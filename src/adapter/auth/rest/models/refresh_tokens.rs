@@ -2,6 +2,8 @@ use crate::adapter::auth::rest::AccessTokenEncoded;
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct RefreshTokensRequest {
+    /// May be empty for clients which send DPoP proofs
+    #[serde(default)]
     pub fingerprint: String,
 }
 
@@ -5,7 +5,6 @@ pub use actix_web::HttpResponse;
 
 pub use crate::infra::rest::err_with_internal_error;
 pub use crate::infra::rest::err_with_status;
-pub use crate::infra::rest::msg_with_internal_error;
 pub use crate::infra::rest::msg_with_status;
 pub use crate::infra::rest::AnyhowErrorWrapper;
 pub use crate::infra::rest::ApiError;
@@ -1,21 +1,48 @@
 use crate::port::room::repo as room_repo;
 
 use chrono::NaiveDateTime;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Hash, Eq, PartialEq, Clone)]
 pub enum RoomPasswordFeature {
     OneOff,
-    Expiring { expires_in: NaiveDateTime },
+    Expiring {
+        expires_in: NaiveDateTime,
+    },
+    Rolling {
+        secret: Vec<u8>,
+        step: i64,
+        grace: i64,
+        digits: u32,
+    },
+    Master,
+    Invite {
+        expires_in: NaiveDateTime,
+    },
 }
 
 impl From<RoomPasswordFeature> for room_repo::RoomPasswordFeature {
     fn from(f: RoomPasswordFeature) -> Self {
         match f {
+            RoomPasswordFeature::Master => room_repo::RoomPasswordFeature::Master,
             RoomPasswordFeature::OneOff => room_repo::RoomPasswordFeature::OneOff,
             RoomPasswordFeature::Expiring { expires_in } => {
                 room_repo::RoomPasswordFeature::Expiring { expires_in }
             }
+            RoomPasswordFeature::Invite { expires_in } => {
+                room_repo::RoomPasswordFeature::Invite { expires_in }
+            }
+            RoomPasswordFeature::Rolling {
+                secret,
+                step,
+                grace,
+                digits,
+            } => room_repo::RoomPasswordFeature::Rolling {
+                secret,
+                step,
+                grace,
+                digits,
+            },
         }
     }
 }
@@ -23,27 +50,70 @@ impl From<RoomPasswordFeature> for room_repo::RoomPasswordFeature {
 impl From<room_repo::RoomPasswordFeature> for RoomPasswordFeature {
     fn from(f: room_repo::RoomPasswordFeature) -> Self {
         match f {
+            room_repo::RoomPasswordFeature::Master => RoomPasswordFeature::Master,
             room_repo::RoomPasswordFeature::OneOff => RoomPasswordFeature::OneOff,
             room_repo::RoomPasswordFeature::Expiring { expires_in } => {
                 RoomPasswordFeature::Expiring { expires_in }
             }
+            room_repo::RoomPasswordFeature::Invite { expires_in } => {
+                RoomPasswordFeature::Invite { expires_in }
+            }
+            room_repo::RoomPasswordFeature::Rolling {
+                secret,
+                step,
+                grace,
+                digits,
+            } => RoomPasswordFeature::Rolling {
+                secret,
+                step,
+                grace,
+                digits,
+            },
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct RoomPassword {
+    pub hash: String,
+    pub lookup_tag: u8,
+    pub passphrase: bool,
+    pub feature: RoomPasswordFeature,
+}
+
+impl From<RoomPassword> for room_repo::RoomPassword {
+    fn from(f: RoomPassword) -> Self {
+        Self {
+            hash: f.hash,
+            lookup_tag: f.lookup_tag,
+            passphrase: f.passphrase,
+            feature: f.feature.into(),
+        }
+    }
+}
+
+impl From<room_repo::RoomPassword> for RoomPassword {
+    fn from(f: room_repo::RoomPassword) -> Self {
+        Self {
+            hash: f.hash,
+            lookup_tag: f.lookup_tag,
+            passphrase: f.passphrase,
+            feature: f.feature.into(),
         }
     }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct RoomCredentials {
-    pub passwords: HashMap<String, RoomPasswordFeature>,
+    pub lookup_key: Vec<u8>,
+    pub passwords: Vec<RoomPassword>,
 }
 
 impl From<RoomCredentials> for room_repo::RoomCredentials {
     fn from(f: RoomCredentials) -> Self {
         Self {
-            passwords: f
-                .passwords
-                .into_iter()
-                .map(|(k, v)| (k, v.into()))
-                .collect(),
+            lookup_key: f.lookup_key,
+            passwords: f.passwords.into_iter().map(|p| p.into()).collect(),
         }
     }
 }
@@ -51,32 +121,90 @@ impl From<RoomCredentials> for room_repo::RoomCredentials {
 impl From<room_repo::RoomCredentials> for RoomCredentials {
     fn from(f: room_repo::RoomCredentials) -> Self {
         Self {
-            passwords: f
-                .passwords
-                .into_iter()
-                .map(|(k, v)| (k, v.into()))
-                .collect(),
+            lookup_key: f.lookup_key,
+            passwords: f.passwords.into_iter().map(|p| p.into()).collect(),
         }
     }
 }
 
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct RoomSettings {
+    pub e2ee: bool,
+}
+
+impl From<RoomSettings> for room_repo::RoomSettings {
+    fn from(f: RoomSettings) -> Self {
+        Self { e2ee: f.e2ee }
+    }
+}
+
+impl From<room_repo::RoomSettings> for RoomSettings {
+    fn from(f: room_repo::RoomSettings) -> Self {
+        Self { e2ee: f.e2ee }
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct File {
     pub id: room_repo::FileId,
     pub name: String,
-    pub size: usize,
-    pub mime_type: String,
-    pub source_client_id: room_repo::ClientId,
+    pub expires_at: Option<NaiveDateTime>,
+    /// Ordered from oldest to newest, the last one is the current version
+    pub versions: Vec<FileVersion>,
+    pub downloads_left: Option<u32>,
+}
+
+impl File {
+    pub fn current_version(&self) -> &FileVersion {
+        self.versions.last().expect("file without versions")
+    }
+
+    /// Appends new version and drops the oldest ones above `max_versions`.
+    /// Number of `version` is ignored, it gets the next number of the file.
+    /// Returns blobs of dropped versions which no kept version shares
+    pub fn push_version(&mut self, mut version: FileVersion, max_versions: usize) -> Vec<Blob> {
+        version.number = self.current_version().number + 1;
+        self.versions.push(version);
+
+        let max_versions = max_versions.max(1);
+        if self.versions.len() <= max_versions {
+            return Vec::new();
+        }
+
+        let excess = self.versions.len() - max_versions;
+        let dropped: Vec<_> = self.versions.drain(..excess).collect();
+        let mut blobs: Vec<Blob> = Vec::new();
+        for blob in dropped.into_iter().filter_map(|v| v.blob) {
+            let shared =
+                self.blobs().any(|b| b.id == blob.id) || blobs.iter().any(|b| b.id == blob.id);
+            if !shared {
+                blobs.push(blob);
+            }
+        }
+        blobs
+    }
+
+    /// Blobs of all versions, shared ones are repeated
+    pub fn blobs(&self) -> impl Iterator<Item = &Blob> {
+        self.versions.iter().filter_map(|v| v.blob.as_ref())
+    }
 }
 
 impl From<File> for room_repo::File {
     fn from(f: File) -> Self {
+        let cur_version = f.current_version().clone();
         Self {
             id: f.id,
             name: f.name,
-            size: f.size,
-            mime_type: f.mime_type,
-            source_client_id: f.source_client_id,
+            size: cur_version.size,
+            mime_type: cur_version.mime_type,
+            source_client_id: cur_version.source_client_id,
+            expires_at: f.expires_at,
+            uploaded_at: cur_version.uploaded_at,
+            version: cur_version.number,
+            blob: cur_version.blob.map(|b| b.into()),
+            downloads_left: f.downloads_left,
+            versions: f.versions.into_iter().map(|v| v.into()).collect(),
         }
     }
 }
@@ -86,19 +214,107 @@ impl From<room_repo::File> for File {
         Self {
             id: f.id,
             name: f.name,
+            expires_at: f.expires_at,
+            versions: f.versions.into_iter().map(|v| v.into()).collect(),
+            downloads_left: f.downloads_left,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct FileVersion {
+    pub number: room_repo::FileVersionNumber,
+    pub size: usize,
+    pub mime_type: String,
+    pub source_client_id: room_repo::ClientId,
+    pub uploaded_at: NaiveDateTime,
+    pub blob: Option<Blob>,
+}
+
+impl From<FileVersion> for room_repo::FileVersion {
+    fn from(f: FileVersion) -> Self {
+        Self {
+            number: f.number,
             size: f.size,
             mime_type: f.mime_type,
             source_client_id: f.source_client_id,
+            uploaded_at: f.uploaded_at,
+            blob: f.blob.map(|b| b.into()),
+        }
+    }
+}
+
+impl From<room_repo::FileVersion> for FileVersion {
+    fn from(f: room_repo::FileVersion) -> Self {
+        Self {
+            number: f.number,
+            size: f.size,
+            mime_type: f.mime_type,
+            source_client_id: f.source_client_id,
+            uploaded_at: f.uploaded_at,
+            blob: f.blob.map(|b| b.into()),
         }
     }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
-pub struct Clients {
-    pub client_ids: HashSet<room_repo::ClientId>,
+pub struct Blob {
+    pub id: room_repo::BlobId,
+    pub size: usize,
+    pub sha256: Vec<u8>,
+    pub stored_size: usize,
+    pub compression: BlobCompression,
+}
+
+impl From<Blob> for room_repo::Blob {
+    fn from(f: Blob) -> Self {
+        Self {
+            id: f.id,
+            size: f.size,
+            stored_size: f.stored_size,
+            sha256: f.sha256,
+            compression: f.compression.into(),
+        }
+    }
+}
+
+impl From<room_repo::Blob> for Blob {
+    fn from(f: room_repo::Blob) -> Self {
+        Self {
+            id: f.id,
+            size: f.size,
+            sha256: f.sha256,
+            stored_size: f.stored_size,
+            compression: f.compression.into(),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+pub enum BlobCompression {
+    None,
+    Zstd,
+}
+
+impl From<BlobCompression> for room_repo::BlobCompression {
+    fn from(f: BlobCompression) -> Self {
+        match f {
+            BlobCompression::None => room_repo::BlobCompression::None,
+            BlobCompression::Zstd => room_repo::BlobCompression::Zstd,
+        }
+    }
+}
+
+impl From<room_repo::BlobCompression> for BlobCompression {
+    fn from(f: room_repo::BlobCompression) -> Self {
+        match f {
+            room_repo::BlobCompression::None => BlobCompression::None,
+            room_repo::BlobCompression::Zstd => BlobCompression::Zstd,
+        }
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
-pub struct Files {
-    pub files: HashMap<room_repo::FileId, File>,
+pub struct Clients {
+    pub client_ids: HashSet<room_repo::ClientId>,
 }
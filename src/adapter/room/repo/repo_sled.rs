@@ -1,31 +1,67 @@
 use crate::adapter::room::repo::models_sled;
+use crate::infra::crypto::{value_aad, Cipher, KeyStore};
 use crate::port::room::repo::*;
 use crate::port::{RepoError, RepoResult};
 
-use std::sync::atomic::{AtomicU64, Ordering};
+use sled::transaction::{
+    ConflictableTransactionError, ConflictableTransactionResult, TransactionError,
+    TransactionalTree,
+};
+use sled::Transactional;
+use std::convert::TryFrom;
+use std::ops::Bound;
+use std::sync::Arc;
 use uuid::Uuid;
 
-const START_ROOM_ID: RoomId = 100_000;
-
 pub struct RoomRepoSled {
     creds_tree: sled::Tree,
+    settings_tree: sled::Tree,
     files_tree: sled::Tree,
+    file_names_tree: sled::Tree,
+    files_by_name_tree: sled::Tree,
+    files_by_size_tree: sled::Tree,
+    files_by_uploaded_at_tree: sled::Tree,
+    files_by_source_client_id_tree: sled::Tree,
+    /// Chunks of file contents, `room_id | blob_id | index` to chunk
+    blobs_tree: sled::Tree,
     clients_tree: sled::Tree,
+    /// Ids of deleted rooms to deletion timestamp, they are not given out
+    /// again until tombstone expires
+    tombstones_tree: sled::Tree,
+    meta_tree: sled::Tree,
 
-    cur_id: AtomicU64,
+    key_store: Arc<KeyStore>,
 }
 
 impl RoomRepoSled {
-    pub fn new(sled_db: sled::Db) -> RepoResult<Self> {
+    pub fn new(sled_db: sled::Db, key_store: Arc<KeyStore>) -> RepoResult<Self> {
         let creds_tree = sled_db.open_tree("room-creds")?;
+        let settings_tree = sled_db.open_tree("room-settings")?;
         let files_tree = sled_db.open_tree("room-files")?;
+        let file_names_tree = sled_db.open_tree("room-file-names")?;
+        let files_by_name_tree = sled_db.open_tree("room-files-by-name")?;
+        let files_by_size_tree = sled_db.open_tree("room-files-by-size")?;
+        let files_by_uploaded_at_tree = sled_db.open_tree("room-files-by-uploaded-at")?;
+        let files_by_source_client_id_tree = sled_db.open_tree("room-files-by-source-client-id")?;
+        let blobs_tree = sled_db.open_tree("room-blobs")?;
         let clients_tree = sled_db.open_tree("room-clients")?;
+        let tombstones_tree = sled_db.open_tree("room-tombstones")?;
+        let meta_tree = sled_db.open_tree("room-meta")?;
 
         Ok(Self {
             creds_tree,
+            settings_tree,
             files_tree,
+            file_names_tree,
+            files_by_name_tree,
+            files_by_size_tree,
+            files_by_uploaded_at_tree,
+            files_by_source_client_id_tree,
+            blobs_tree,
             clients_tree,
-            cur_id: AtomicU64::new(START_ROOM_ID),
+            tombstones_tree,
+            meta_tree,
+            key_store,
         })
     }
 }
@@ -33,54 +69,165 @@ impl RoomRepoSled {
 #[async_trait::async_trait]
 impl RoomRepo for RoomRepoSled {
     async fn create_room(&self, req: CreateRoomRequest) -> RepoResult<CreateRoomResponse> {
-        // Next room id
-        let room_id = self.cur_id.fetch_add(1, Ordering::Relaxed);
+        let room_id = req.room_id;
+
+        // Room id is picked by caller, do not overwrite existing room. Id is
+        // reserved by empty credentials, which are replaced once the room has
+        // its data key
+        if self
+            .creds_tree
+            .compare_and_swap(
+                room_id.to_ne_bytes(),
+                None as Option<&[u8]>,
+                Some(&[] as &[u8]),
+            )?
+            .is_err()
+        {
+            return Err(RepoError::AlreadyExists(anyhow::anyhow!(
+                "room with id={} already exists",
+                room_id
+            )));
+        }
+
+        // Tombstone is checked after credentials are taken: deletion adds it
+        // before credentials are removed, so it is always seen here
+        if self.tombstones_tree.contains_key(room_id.to_ne_bytes())? {
+            self.creds_tree.remove(room_id.to_ne_bytes())?;
+            return Err(RepoError::AlreadyExists(anyhow::anyhow!(
+                "room with id={} was deleted",
+                room_id
+            )));
+        }
+
+        // Id is ours now, key of the previous room with it is replaced
+        self.key_store
+            .create_key(&cipher_scope(room_id))
+            .map_err(RepoError::CommonError)?;
 
         // Create room cred
-        let new_cred = models_sled::RoomCredentials {
-            passwords: req
-                .room_passwords
-                .into_iter()
-                .map(|(k, v)| (k, v.into()))
-                .collect(),
-        };
+        let new_cred: models_sled::RoomCredentials = req.room_cred.into();
 
         let new_cred_serialized =
-            bincode::serialize(&new_cred).map_err(|err| RepoError::CommonError(err.into()))?;
+            self.serialize(&self.creds_tree, room_id, &room_id.to_ne_bytes(), &new_cred)?;
 
         self.creds_tree
             .insert(room_id.to_ne_bytes(), new_cred_serialized)?;
 
+        // Create room settings
+        let new_settings: models_sled::RoomSettings = req.room_settings.into();
+
+        let new_settings_serialized = self.serialize(
+            &self.settings_tree,
+            room_id,
+            &room_id.to_ne_bytes(),
+            &new_settings,
+        )?;
+
+        self.settings_tree
+            .insert(room_id.to_ne_bytes(), new_settings_serialized)?;
+
         // Create room clients
         let new_clients = models_sled::Clients {
             client_ids: Default::default(),
         };
 
-        let new_clients_serialized =
-            bincode::serialize(&new_clients).map_err(|err| RepoError::CommonError(err.into()))?;
+        let new_clients_serialized = self.serialize(
+            &self.clients_tree,
+            room_id,
+            &room_id.to_ne_bytes(),
+            &new_clients,
+        )?;
 
         self.clients_tree
             .insert(room_id.to_ne_bytes(), new_clients_serialized)?;
 
-        // Create room files
-        let new_files = models_sled::Files {
-            files: Default::default(),
-        };
-
-        let new_files_serialized =
-            bincode::serialize(&new_files).map_err(|err| RepoError::CommonError(err.into()))?;
-
-        self.files_tree
-            .insert(room_id.to_ne_bytes(), new_files_serialized)?;
-
         let res = CreateRoomResponse {
             room_id,
             room_cred: new_cred.into(),
+            room_settings: new_settings.into(),
         };
 
         Ok(res)
     }
 
+    async fn delete_room(&self, req: DeleteRoomRequest) -> RepoResult<DeleteRoomResponse> {
+        self.check_room(req.room_id)?;
+
+        // Tokens of old members still name the room, so its id is not reused.
+        // Credentials go next, so nobody can log in while the rest is removed
+        let key = req.room_id.to_ne_bytes();
+        self.tombstones_tree
+            .insert(key, &req.deleted_at.timestamp().to_be_bytes())?;
+        self.creds_tree.remove(key)?;
+        self.settings_tree.remove(key)?;
+        self.clients_tree.remove(key)?;
+
+        let room_trees = [
+            &self.files_tree,
+            &self.file_names_tree,
+            &self.files_by_name_tree,
+            &self.files_by_size_tree,
+            &self.files_by_uploaded_at_tree,
+            &self.files_by_source_client_id_tree,
+            &self.blobs_tree,
+        ];
+        for tree in room_trees.iter() {
+            for entry in tree.scan_prefix(key) {
+                let (k, _) = entry?;
+                tree.remove(k)?;
+            }
+        }
+
+        // Anything left behind, e.g. in old snapshots, is unreadable without the key
+        self.key_store
+            .erase(&cipher_scope(req.room_id))
+            .map_err(RepoError::CommonError)?;
+
+        Ok(())
+    }
+
+    async fn delete_expired_tombstones(
+        &self,
+        req: DeleteExpiredTombstonesRequest,
+    ) -> RepoResult<DeleteExpiredTombstonesResponse> {
+        let mut deleted = 0;
+        for entry in self.tombstones_tree.iter() {
+            let (key, value) = entry?;
+            // Tombstones of old versions have no timestamp
+            let expired = <[u8; 8]>::try_from(value.as_ref())
+                .map(i64::from_be_bytes)
+                .map_or(true, |deleted_at| {
+                    deleted_at < req.deleted_before.timestamp()
+                });
+
+            if expired {
+                self.tombstones_tree.remove(key)?;
+                deleted += 1;
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    async fn next_room_number(&self) -> RepoResult<NextRoomNumberResponse> {
+        // Counter survives restarts, so ids of deleted rooms are not walked again
+        let updated = self
+            .meta_tree
+            .update_and_fetch(NEXT_ROOM_NUMBER_KEY, |old| {
+                let number = old
+                    .and_then(|v| <[u8; 8]>::try_from(v).ok())
+                    .map_or(0, u64::from_be_bytes);
+                Some(number.wrapping_add(1).to_be_bytes().to_vec())
+            })?;
+
+        let next = updated
+            .and_then(|v| <[u8; 8]>::try_from(v.as_ref()).ok())
+            .map(u64::from_be_bytes)
+            .ok_or_else(|| RepoError::CommonError(anyhow::anyhow!("invalid room number")))?;
+
+        Ok(next - 1)
+    }
+
     async fn add_client(&self, req: AddClientRequest) -> RepoResult<AddClientResponse> {
         let mut clients: models_sled::Clients =
             match self.clients_tree.get(req.room_id.to_ne_bytes())? {
@@ -90,8 +237,12 @@ impl RoomRepo for RoomRepoSled {
                         req.room_id
                     )))
                 }
-                Some(v) => bincode::deserialize(v.as_ref())
-                    .map_err(|err| RepoError::CommonError(err.into()))?,
+                Some(v) => self.deserialize(
+                    &self.clients_tree,
+                    req.room_id,
+                    &req.room_id.to_ne_bytes(),
+                    v.as_ref(),
+                )?,
             };
 
         if clients.client_ids.contains(&req.client_id) {
@@ -103,8 +254,12 @@ impl RoomRepo for RoomRepoSled {
 
         clients.client_ids.insert(req.client_id);
 
-        let clients_serialized =
-            bincode::serialize(&clients).map_err(|err| RepoError::CommonError(err.into()))?;
+        let clients_serialized = self.serialize(
+            &self.clients_tree,
+            req.room_id,
+            &req.room_id.to_ne_bytes(),
+            &clients,
+        )?;
 
         self.clients_tree
             .insert(req.room_id.to_ne_bytes(), clients_serialized)?;
@@ -121,8 +276,12 @@ impl RoomRepo for RoomRepoSled {
                         req.room_id
                     )))
                 }
-                Some(v) => bincode::deserialize(v.as_ref())
-                    .map_err(|err| RepoError::CommonError(err.into()))?,
+                Some(v) => self.deserialize(
+                    &self.clients_tree,
+                    req.room_id,
+                    &req.room_id.to_ne_bytes(),
+                    v.as_ref(),
+                )?,
             };
 
         let has = clients.client_ids.contains(&req.client_id);
@@ -139,8 +298,12 @@ impl RoomRepo for RoomRepoSled {
                         req.room_id
                     )))
                 }
-                Some(v) => bincode::deserialize(v.as_ref())
-                    .map_err(|err| RepoError::CommonError(err.into()))?,
+                Some(v) => self.deserialize(
+                    &self.clients_tree,
+                    req.room_id,
+                    &req.room_id.to_ne_bytes(),
+                    v.as_ref(),
+                )?,
             };
 
         // If no client
@@ -151,8 +314,12 @@ impl RoomRepo for RoomRepoSled {
             )));
         }
 
-        let clients_serialized =
-            bincode::serialize(&clients).map_err(|err| RepoError::CommonError(err.into()))?;
+        let clients_serialized = self.serialize(
+            &self.clients_tree,
+            req.room_id,
+            &req.room_id.to_ne_bytes(),
+            &clients,
+        )?;
 
         self.clients_tree
             .insert(req.room_id.to_ne_bytes(), clients_serialized)?;
@@ -161,61 +328,293 @@ impl RoomRepo for RoomRepoSled {
     }
 
     async fn add_file(&self, req: AddFileRequest) -> RepoResult<AddFileResponse> {
-        let mut files: models_sled::Files = match self.files_tree.get(req.room_id.to_ne_bytes())? {
-            None => {
-                return Err(RepoError::CommonError(anyhow::anyhow!(
-                    "no room with id={}",
-                    req.room_id
-                )))
-            }
-            Some(v) => bincode::deserialize(v.as_ref())
-                .map_err(|err| RepoError::CommonError(err.into()))?,
-        };
+        self.check_room(req.room_id)?;
 
-        let file = models_sled::File {
-            id: Uuid::new_v4(),
-            name: req.file_name,
+        let version = models_sled::FileVersion {
+            number: 1,
             size: req.file_size,
             mime_type: req.file_mime_type,
             source_client_id: req.file_source_client_id,
+            uploaded_at: req.file_uploaded_at,
+            blob: req.file_blob.map(|b| b.into()),
         };
 
-        files.files.insert(file.id, file.clone());
+        let (room_id, file_name, expires_at, max_versions, max_downloads, now) = (
+            req.room_id,
+            req.file_name,
+            req.file_expires_at,
+            req.file_max_versions,
+            req.file_max_downloads,
+            req.file_uploaded_at,
+        );
+
+        // If file with the same name exists then add new version to it. Lookup
+        // is in the transaction, so concurrent uploads do not make two files.
+        // Expired file is not deleted yet, it is replaced by a new one
+        let (file, dropped_blobs, expired_file_id) = self.files_transaction(|tx| {
+            let existing = self.find_file_by_name(tx, room_id, &file_name)?;
+            let (file, dropped_blobs, expired_file_id) = match existing {
+                Some(mut file) if !is_expired(&file, now) => {
+                    self.remove_file(tx, room_id, &file)?;
+                    file.expires_at = expires_at;
+                    file.downloads_left = max_downloads;
+                    let dropped_blobs = file.push_version(version.clone(), max_versions);
+                    (file, dropped_blobs, None)
+                }
+                existing => {
+                    let mut dropped_blobs = Vec::new();
+                    let mut expired_file_id = None;
+                    if let Some(expired) = existing {
+                        self.remove_file(tx, room_id, &expired)?;
+                        dropped_blobs = expired.blobs().cloned().collect();
+                        expired_file_id = Some(expired.id);
+                    }
+
+                    let file = models_sled::File {
+                        id: Uuid::new_v4(),
+                        name: file_name.clone(),
+                        expires_at,
+                        versions: vec![version.clone()],
+                        downloads_left: max_downloads,
+                    };
+                    (file, dropped_blobs, expired_file_id)
+                }
+            };
 
-        let files_serialized =
-            bincode::serialize(&files).map_err(|err| RepoError::CommonError(err.into()))?;
+            self.insert_file(tx, room_id, &file)?;
 
-        self.files_tree
-            .insert(req.room_id.to_ne_bytes(), files_serialized)?;
+            Ok((file, dropped_blobs, expired_file_id))
+        })?;
 
-        let res = AddFileResponse { file: file.into() };
+        // Contents go after the transaction, nothing refers to them anymore
+        for blob_id in distinct_blob_ids(dropped_blobs.iter()) {
+            self.remove_blob(room_id, blob_id)?;
+        }
+
+        let res = AddFileResponse {
+            file: file.into(),
+            expired_file_id,
+        };
+
+        Ok(res)
+    }
+
+    async fn get_file(&self, req: GetFileRequest) -> RepoResult<GetFileResponse> {
+        self.check_room(req.room_id)?;
+
+        let file = self.load_file(req.room_id, req.file_id)?;
+
+        let res = GetFileResponse { file: file.into() };
 
         Ok(res)
     }
 
     async fn get_files(&self, req: GetFilesRequest) -> RepoResult<GetFilesResponse> {
-        let files: models_sled::Files = match self.files_tree.get(req.room_id.to_ne_bytes())? {
-            None => {
-                return Err(RepoError::CommonError(anyhow::anyhow!(
-                    "no room with id={}",
-                    req.room_id
-                )))
-            }
-            Some(v) => bincode::deserialize(v.as_ref())
-                .map_err(|err| RepoError::CommonError(err.into()))?,
+        self.check_room(req.room_id)?;
+
+        let (files, next_cursor) = match self.cipher(req.room_id)?.is_plain() {
+            true => self.files_page_indexed(&req)?,
+            false => self.files_page_in_memory(&req)?,
         };
 
-        let files = files
-            .files
-            .into_iter()
-            .map(|(k, v)| (k, v.into()))
-            .collect();
+        let res = GetFilesResponse {
+            files: files.into_iter().map(|f| f.into()).collect(),
+            next_cursor,
+        };
 
-        let res = GetFilesResponse { files };
+        Ok(res)
+    }
+
+    async fn restore_file_version(
+        &self,
+        req: RestoreFileVersionRequest,
+    ) -> RepoResult<RestoreFileVersionResponse> {
+        self.check_room(req.room_id)?;
+
+        let (file, dropped_blobs) = self.files_transaction(|tx| {
+            let mut file = self.load_file_tx(tx, req.room_id, req.file_id)?;
+
+            let old_version = match file.versions.iter().find(|v| v.number == req.file_version) {
+                None => {
+                    return Err(ConflictableTransactionError::Abort(RepoError::NotFound(
+                        anyhow::anyhow!(
+                            "file with id={} has no version={}",
+                            req.file_id,
+                            req.file_version
+                        ),
+                    )))
+                }
+                Some(v) => v.clone(),
+            };
+
+            self.remove_file(tx, req.room_id, &file)?;
+
+            // Restored version becomes the newest one, history is kept. Number
+            // is assigned by `push_version`, content is shared
+            let version = models_sled::FileVersion {
+                number: 0,
+                size: old_version.size,
+                mime_type: old_version.mime_type,
+                source_client_id: req.file_source_client_id,
+                uploaded_at: req.file_uploaded_at,
+                blob: old_version.blob,
+            };
+            let dropped_blobs = file.push_version(version, req.file_max_versions);
+
+            self.insert_file(tx, req.room_id, &file)?;
+
+            Ok((file, dropped_blobs))
+        })?;
+
+        for blob in dropped_blobs {
+            self.remove_blob(req.room_id, blob.id)?;
+        }
+
+        let res = RestoreFileVersionResponse { file: file.into() };
 
         Ok(res)
     }
 
+    async fn delete_file(&self, req: DeleteFileRequest) -> RepoResult<DeleteFileResponse> {
+        self.check_room(req.room_id)?;
+
+        let file = self.files_transaction(|tx| {
+            let file = self.load_file_tx(tx, req.room_id, req.file_id)?;
+            self.remove_file(tx, req.room_id, &file)?;
+            Ok(file)
+        })?;
+
+        for blob_id in distinct_blob_ids(file.blobs()) {
+            self.remove_blob(req.room_id, blob_id)?;
+        }
+
+        let res = DeleteFileResponse { file: file.into() };
+
+        Ok(res)
+    }
+
+    async fn delete_expired_files(
+        &self,
+        req: DeleteExpiredFilesRequest,
+    ) -> RepoResult<DeleteExpiredFilesResponse> {
+        let mut deleted = Vec::new();
+        for entry in self.files_tree.iter() {
+            let (key, value) = entry?;
+            let room_id = <[u8; 8]>::try_from(&key[..8])
+                .map(RoomId::from_ne_bytes)
+                .map_err(|err| RepoError::CommonError(err.into()))?;
+            let file: models_sled::File =
+                self.deserialize(&self.files_tree, room_id, &key, value.as_ref())?;
+            if !is_expired(&file, req.expired_before) {
+                continue;
+            }
+
+            // File may be replaced since the scan, expiry is checked again
+            let removed = self.files_transaction(|tx| {
+                let file = match self.load_file_tx(tx, room_id, file.id) {
+                    Err(ConflictableTransactionError::Abort(RepoError::NotFound(_))) => {
+                        return Ok(None)
+                    }
+                    res => res?,
+                };
+                if !is_expired(&file, req.expired_before) {
+                    return Ok(None);
+                }
+                self.remove_file(tx, room_id, &file)?;
+                Ok(Some(file))
+            })?;
+
+            if let Some(file) = removed {
+                for blob_id in distinct_blob_ids(file.blobs()) {
+                    self.remove_blob(room_id, blob_id)?;
+                }
+                deleted.push((room_id, file.id));
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    async fn take_file_download(
+        &self,
+        req: TakeFileDownloadRequest,
+    ) -> RepoResult<TakeFileDownloadResponse> {
+        self.check_room(req.room_id)?;
+
+        let (file, removed) = self.files_transaction(|tx| {
+            let mut file = self.load_file_tx(tx, req.room_id, req.file_id)?;
+            match file.downloads_left {
+                None => Ok((file, false)),
+                Some(n) if n <= 1 => {
+                    file.downloads_left = Some(0);
+                    self.remove_file(tx, req.room_id, &file)?;
+                    Ok((file, true))
+                }
+                Some(n) => {
+                    // Indexes do not hold the counter, only the file is updated
+                    file.downloads_left = Some(n - 1);
+                    let key = file_key(req.room_id, file.id);
+                    let file_serialized = self
+                        .serialize(&self.files_tree, req.room_id, &key, &file)
+                        .map_err(ConflictableTransactionError::Abort)?;
+                    tx.files.insert(key, file_serialized)?;
+                    Ok((file, false))
+                }
+            }
+        })?;
+
+        let res = TakeFileDownloadResponse {
+            file: file.into(),
+            removed,
+        };
+
+        Ok(res)
+    }
+
+    async fn add_blob(&self, req: AddBlobRequest) -> RepoResult<AddBlobResponse> {
+        self.check_room(req.room_id)?;
+
+        // Chunks are sealed one by one, so content is read in constant memory
+        let blob_id = Uuid::new_v4();
+        let cipher = self.cipher(req.room_id)?;
+        for (index, chunk) in req.content.chunks(BLOB_CHUNK_SIZE).enumerate() {
+            let key = blob_chunk_key(req.room_id, blob_id, index as u32);
+            let sealed = cipher
+                .seal(chunk, &value_aad(&self.blobs_tree, &key))
+                .map_err(RepoError::CommonError)?;
+            self.blobs_tree.insert(key, sealed)?;
+        }
+
+        let blob = Blob {
+            id: blob_id,
+            size: req.size,
+            stored_size: req.content.len(),
+            sha256: req.sha256,
+            compression: req.compression,
+        };
+
+        let res = AddBlobResponse { blob };
+
+        Ok(res)
+    }
+
+    async fn get_blob_chunk(&self, req: GetBlobChunkRequest) -> RepoResult<GetBlobChunkResponse> {
+        let key = blob_chunk_key(req.room_id, req.blob_id, req.index);
+        let chunk = match self.blobs_tree.get(&key)? {
+            None => return Ok(None),
+            Some(v) => self
+                .cipher(req.room_id)?
+                .open(v.as_ref(), &value_aad(&self.blobs_tree, &key))
+                .map_err(RepoError::CommonError)?,
+        };
+
+        Ok(Some(chunk))
+    }
+
+    async fn delete_blob(&self, req: DeleteBlobRequest) -> RepoResult<DeleteBlobResponse> {
+        self.remove_blob(req.room_id, req.blob_id)
+    }
+
     async fn get_room_credentials(
         &self,
         req: GetRoomCredentialsRequest,
@@ -223,13 +622,17 @@ impl RoomRepo for RoomRepoSled {
         let room_cred: models_sled::RoomCredentials =
             match self.creds_tree.get(req.room_id.to_ne_bytes())? {
                 None => {
-                    return Err(RepoError::CommonError(anyhow::anyhow!(
+                    return Err(RepoError::NotFound(anyhow::anyhow!(
                         "no room with id={}",
                         req.room_id
                     )))
                 }
-                Some(v) => bincode::deserialize(v.as_ref())
-                    .map_err(|err| RepoError::CommonError(err.into()))?,
+                Some(v) => self.deserialize(
+                    &self.creds_tree,
+                    req.room_id,
+                    &req.room_id.to_ne_bytes(),
+                    v.as_ref(),
+                )?,
             };
 
         let res = GetRoomCredentialsResponse {
@@ -238,4 +641,549 @@ impl RoomRepo for RoomRepoSled {
 
         Ok(res)
     }
+
+    async fn get_room_settings(
+        &self,
+        req: GetRoomSettingsRequest,
+    ) -> RepoResult<GetRoomSettingsResponse> {
+        let room_settings: models_sled::RoomSettings =
+            match self.settings_tree.get(req.room_id.to_ne_bytes())? {
+                None => {
+                    return Err(RepoError::CommonError(anyhow::anyhow!(
+                        "no room with id={}",
+                        req.room_id
+                    )))
+                }
+                Some(v) => self.deserialize(
+                    &self.settings_tree,
+                    req.room_id,
+                    &req.room_id.to_ne_bytes(),
+                    v.as_ref(),
+                )?,
+            };
+
+        let res = GetRoomSettingsResponse {
+            room_settings: room_settings.into(),
+        };
+
+        Ok(res)
+    }
+
+    async fn add_room_password(
+        &self,
+        req: AddRoomPasswordRequest,
+    ) -> RepoResult<AddRoomPasswordResponse> {
+        let (room_id, expired_before, max_invites) =
+            (req.room_id, req.expired_before, req.max_invites);
+        let room_password: models_sled::RoomPassword = req.room_password.into();
+        let is_invite = matches!(
+            room_password.feature,
+            models_sled::RoomPasswordFeature::Invite { .. }
+        );
+        let mut added = false;
+        self.update_room_cred(room_id, |cred| {
+            let len = cred.passwords.len();
+            cred.passwords.retain(|p| match &p.feature {
+                models_sled::RoomPasswordFeature::Expiring { expires_in }
+                | models_sled::RoomPasswordFeature::Invite { expires_in } => {
+                    *expires_in > expired_before
+                }
+                _ => true,
+            });
+
+            let invites = cred
+                .passwords
+                .iter()
+                .filter(|p| matches!(p.feature, models_sled::RoomPasswordFeature::Invite { .. }))
+                .count();
+            added = !is_invite || invites < max_invites;
+            if added {
+                cred.passwords.push(room_password.clone());
+            }
+            // Expired passwords are still worth removing
+            added || cred.passwords.len() != len
+        })?;
+
+        Ok(added)
+    }
+
+    async fn delete_room_password(
+        &self,
+        req: DeleteRoomPasswordRequest,
+    ) -> RepoResult<DeleteRoomPasswordResponse> {
+        self.update_room_cred(req.room_id, |cred| {
+            let len = cred.passwords.len();
+            cred.passwords.retain(|p| p.hash != req.hash);
+            cred.passwords.len() != len
+        })
+    }
+}
+
+const FILE_ID_LEN: usize = 16;
+const BLOB_CHUNK_SIZE: usize = 64 * 1024;
+const NEXT_ROOM_NUMBER_KEY: &[u8] = b"next-room-number";
+
+impl RoomRepoSled {
+    /// Atomically updates room credentials, `f` returns whether it changed
+    /// them. Retries if credentials were changed concurrently
+    fn update_room_cred<F>(&self, room_id: RoomId, mut f: F) -> RepoResult<bool>
+    where
+        F: FnMut(&mut models_sled::RoomCredentials) -> bool,
+    {
+        let key = room_id.to_ne_bytes();
+        loop {
+            let old = match self.creds_tree.get(key)? {
+                None => {
+                    return Err(RepoError::CommonError(anyhow::anyhow!(
+                        "no room with id={}",
+                        room_id
+                    )))
+                }
+                Some(v) => v,
+            };
+
+            let mut room_cred: models_sled::RoomCredentials =
+                self.deserialize(&self.creds_tree, room_id, &key, old.as_ref())?;
+            if !f(&mut room_cred) {
+                return Ok(false);
+            }
+
+            let new = self.serialize(&self.creds_tree, room_id, &key, &room_cred)?;
+            if self
+                .creds_tree
+                .compare_and_swap(key, Some(old), Some(new))?
+                .is_ok()
+            {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Page of files in index order, index keys hold plain sort values
+    fn files_page_indexed(
+        &self,
+        req: &GetFilesRequest,
+    ) -> RepoResult<(Vec<models_sled::File>, Option<FilesCursor>)> {
+        let prefix = req.room_id.to_ne_bytes();
+        let index_tree = self.index_tree(req.sort_by);
+
+        // Start right after the cursor, in the requested direction
+        let range =
+            match (req.order, &req.cursor) {
+                (_, None) => index_tree.scan_prefix(prefix),
+                (SortOrder::Asc, Some(cursor)) => index_tree
+                    .range::<&[u8], _>((Bound::Excluded(cursor.as_slice()), Bound::Unbounded)),
+                (SortOrder::Desc, Some(cursor)) => index_tree
+                    .range::<&[u8], _>((Bound::Unbounded, Bound::Excluded(cursor.as_slice()))),
+            };
+        let entries: Box<dyn Iterator<Item = sled::Result<(sled::IVec, sled::IVec)>>> =
+            match req.order {
+                SortOrder::Asc => Box::new(range),
+                SortOrder::Desc => Box::new(range.rev()),
+            };
+
+        let mut files = Vec::with_capacity(req.limit);
+        let mut next_cursor = None;
+        for entry in entries {
+            let (index_key, file_id) = entry?;
+            if !index_key.starts_with(&prefix) {
+                break;
+            }
+
+            // Filter by name before loading the file, the name is a part of the key
+            if let (FileSortBy::Name, Some(name_contains)) =
+                (req.sort_by, &req.filter.name_contains)
+            {
+                let name = &index_key[prefix.len()..index_key.len() - FILE_ID_LEN - 1];
+                if !String::from_utf8_lossy(name).contains(name_contains.as_str()) {
+                    continue;
+                }
+            }
+
+            let file_id = Uuid::from_slice(file_id.as_ref())
+                .map_err(|err| RepoError::CommonError(err.into()))?;
+            let file = self.load_file(req.room_id, file_id)?;
+            if !matches_filter(&file, &req.filter) {
+                continue;
+            }
+
+            // One more file after the page means there is a next page
+            if files.len() == req.limit {
+                next_cursor = files
+                    .last()
+                    .map(|f| index_key_for(req.sort_by, req.room_id, f));
+                break;
+            }
+
+            files.push(file);
+        }
+
+        Ok((files, next_cursor))
+    }
+
+    /// Page of files sorted after decryption. There are no sort indexes with
+    /// encryption at rest, they would leak names, sizes and uploaders, so
+    /// every page reads all files of the room
+    fn files_page_in_memory(
+        &self,
+        req: &GetFilesRequest,
+    ) -> RepoResult<(Vec<models_sled::File>, Option<FilesCursor>)> {
+        let mut entries = Vec::new();
+        for entry in self.files_tree.scan_prefix(req.room_id.to_ne_bytes()) {
+            let (key, value) = entry?;
+            let file: models_sled::File =
+                self.deserialize(&self.files_tree, req.room_id, &key, value.as_ref())?;
+            if !matches_filter(&file, &req.filter) {
+                continue;
+            }
+
+            // Cursor is the same as with indexes, it never reaches the disk
+            let index_key = index_key_for(req.sort_by, req.room_id, &file);
+            let after_cursor = match (req.order, &req.cursor) {
+                (_, None) => true,
+                (SortOrder::Asc, Some(cursor)) => &index_key > cursor,
+                (SortOrder::Desc, Some(cursor)) => &index_key < cursor,
+            };
+            if after_cursor {
+                entries.push((index_key, file));
+            }
+        }
+
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        if let SortOrder::Desc = req.order {
+            entries.reverse();
+        }
+
+        let mut next_cursor = None;
+        if entries.len() > req.limit {
+            entries.truncate(req.limit);
+            next_cursor = entries.last().map(|(index_key, _)| index_key.clone());
+        }
+
+        Ok((
+            entries.into_iter().map(|(_, file)| file).collect(),
+            next_cursor,
+        ))
+    }
+
+    fn check_room(&self, room_id: RoomId) -> RepoResult<()> {
+        if !self.creds_tree.contains_key(room_id.to_ne_bytes())? {
+            return Err(RepoError::CommonError(anyhow::anyhow!(
+                "no room with id={}",
+                room_id
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Every room has its own data key, values of the room are sealed by it.
+    /// Key is created with the room, deleted room has none
+    fn cipher(&self, room_id: RoomId) -> RepoResult<Cipher> {
+        self.key_store
+            .get_cipher(&cipher_scope(room_id))
+            .map_err(RepoError::CommonError)
+    }
+
+    fn serialize<T>(
+        &self,
+        tree: &sled::Tree,
+        room_id: RoomId,
+        key: &[u8],
+        value: &T,
+    ) -> RepoResult<Vec<u8>>
+    where
+        T: serde::Serialize,
+    {
+        let serialized =
+            bincode::serialize(value).map_err(|err| RepoError::CommonError(err.into()))?;
+        self.cipher(room_id)?
+            .seal(&serialized, &value_aad(tree, key))
+            .map_err(RepoError::CommonError)
+    }
+
+    fn deserialize<T>(
+        &self,
+        tree: &sled::Tree,
+        room_id: RoomId,
+        key: &[u8],
+        value: &[u8],
+    ) -> RepoResult<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let serialized = self
+            .cipher(room_id)?
+            .open(value, &value_aad(tree, key))
+            .map_err(RepoError::CommonError)?;
+        bincode::deserialize(&serialized).map_err(|err| RepoError::CommonError(err.into()))
+    }
+
+    fn index_tree(&self, sort_by: FileSortBy) -> &sled::Tree {
+        match sort_by {
+            FileSortBy::Name => &self.files_by_name_tree,
+            FileSortBy::Size => &self.files_by_size_tree,
+            FileSortBy::UploadedAt => &self.files_by_uploaded_at_tree,
+            FileSortBy::SourceClientId => &self.files_by_source_client_id_tree,
+        }
+    }
+
+    fn load_file(&self, room_id: RoomId, file_id: FileId) -> RepoResult<models_sled::File> {
+        let key = file_key(room_id, file_id);
+        match self.files_tree.get(&key)? {
+            None => Err(RepoError::NotFound(anyhow::anyhow!(
+                "file with id={} not exists",
+                file_id
+            ))),
+            Some(v) => self.deserialize(&self.files_tree, room_id, &key, v.as_ref()),
+        }
+    }
+
+    /// Runs `f` in one transaction over the file and its indexes. `f` may be
+    /// retried on conflict
+    fn files_transaction<T, F>(&self, f: F) -> RepoResult<T>
+    where
+        F: Fn(&FilesTx) -> TxResult<T>,
+    {
+        (
+            &self.files_tree,
+            &self.file_names_tree,
+            &self.files_by_name_tree,
+            &self.files_by_size_tree,
+            &self.files_by_uploaded_at_tree,
+            &self.files_by_source_client_id_tree,
+        )
+            .transaction(
+                |(files, file_names, by_name, by_size, by_uploaded_at, by_source)| {
+                    f(&FilesTx {
+                        files,
+                        file_names,
+                        indexes: [by_name, by_size, by_uploaded_at, by_source],
+                    })
+                },
+            )
+            .map_err(|err| match err {
+                TransactionError::Abort(err) => err,
+                TransactionError::Storage(err) => RepoError::SledError(err),
+            })
+    }
+
+    fn load_file_tx(
+        &self,
+        tx: &FilesTx,
+        room_id: RoomId,
+        file_id: FileId,
+    ) -> TxResult<models_sled::File> {
+        let key = file_key(room_id, file_id);
+        match tx.files.get(&key)? {
+            None => Err(ConflictableTransactionError::Abort(RepoError::NotFound(
+                anyhow::anyhow!("file with id={} not exists", file_id),
+            ))),
+            Some(v) => self
+                .deserialize(&self.files_tree, room_id, &key, v.as_ref())
+                .map_err(ConflictableTransactionError::Abort),
+        }
+    }
+
+    fn find_file_by_name(
+        &self,
+        tx: &FilesTx,
+        room_id: RoomId,
+        name: &str,
+    ) -> TxResult<Option<models_sled::File>> {
+        let name_key = self
+            .file_name_key(room_id, name)
+            .map_err(ConflictableTransactionError::Abort)?;
+
+        match tx.file_names.get(name_key)? {
+            None => Ok(None),
+            Some(file_id) => {
+                let file_id = Uuid::from_slice(file_id.as_ref()).map_err(|err| {
+                    ConflictableTransactionError::Abort(RepoError::CommonError(err.into()))
+                })?;
+                self.load_file_tx(tx, room_id, file_id).map(Some)
+            }
+        }
+    }
+
+    /// `room_id | name`, name is tagged with encryption at rest
+    fn file_name_key(&self, room_id: RoomId, name: &str) -> RepoResult<Vec<u8>> {
+        let mut key = room_id.to_ne_bytes().to_vec();
+        key.extend_from_slice(&self.cipher(room_id)?.tag(name.as_bytes()));
+        Ok(key)
+    }
+
+    fn insert_file(&self, tx: &FilesTx, room_id: RoomId, file: &models_sled::File) -> TxResult<()> {
+        if file.name.contains('\0') {
+            return Err(ConflictableTransactionError::Abort(RepoError::CommonError(
+                anyhow::anyhow!("file name must not contain NUL character"),
+            )));
+        }
+
+        let key = file_key(room_id, file.id);
+        let file_serialized = self
+            .serialize(&self.files_tree, room_id, &key, file)
+            .map_err(ConflictableTransactionError::Abort)?;
+        let name_key = self
+            .file_name_key(room_id, &file.name)
+            .map_err(ConflictableTransactionError::Abort)?;
+
+        tx.files.insert(key, file_serialized)?;
+        tx.file_names.insert(name_key, file.id.as_bytes())?;
+
+        if self.is_plain(room_id)? {
+            for (sort_by, index) in ALL_FILE_SORT_BY.iter().zip(tx.indexes.iter()) {
+                index.insert(index_key_for(*sort_by, room_id, file), file.id.as_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_file(&self, tx: &FilesTx, room_id: RoomId, file: &models_sled::File) -> TxResult<()> {
+        let name_key = self
+            .file_name_key(room_id, &file.name)
+            .map_err(ConflictableTransactionError::Abort)?;
+
+        tx.files.remove(file_key(room_id, file.id))?;
+        tx.file_names.remove(name_key)?;
+
+        if self.is_plain(room_id)? {
+            for (sort_by, index) in ALL_FILE_SORT_BY.iter().zip(tx.indexes.iter()) {
+                index.remove(index_key_for(*sort_by, room_id, file))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_blob(&self, room_id: RoomId, blob_id: BlobId) -> RepoResult<()> {
+        for entry in self.blobs_tree.scan_prefix(blob_key(room_id, blob_id)) {
+            let (key, _) = entry?;
+            self.blobs_tree.remove(key)?;
+        }
+
+        Ok(())
+    }
+
+    fn is_plain(&self, room_id: RoomId) -> TxResult<bool> {
+        self.cipher(room_id)
+            .map(|cipher| cipher.is_plain())
+            .map_err(ConflictableTransactionError::Abort)
+    }
+}
+
+type TxResult<T> = ConflictableTransactionResult<T, RepoError>;
+
+/// File trees in a transaction, indexes are in `ALL_FILE_SORT_BY` order
+struct FilesTx<'a> {
+    files: &'a TransactionalTree,
+    file_names: &'a TransactionalTree,
+    indexes: [&'a TransactionalTree; 4],
+}
+
+const ALL_FILE_SORT_BY: [FileSortBy; 4] = [
+    FileSortBy::Name,
+    FileSortBy::Size,
+    FileSortBy::UploadedAt,
+    FileSortBy::SourceClientId,
+];
+
+fn cipher_scope(room_id: RoomId) -> Vec<u8> {
+    let mut scope = b"room/".to_vec();
+    scope.extend_from_slice(&room_id.to_ne_bytes());
+    scope
+}
+
+fn file_key(room_id: RoomId, file_id: FileId) -> Vec<u8> {
+    let mut key = room_id.to_ne_bytes().to_vec();
+    key.extend_from_slice(file_id.as_bytes());
+    key
+}
+
+fn blob_key(room_id: RoomId, blob_id: BlobId) -> Vec<u8> {
+    let mut key = room_id.to_ne_bytes().to_vec();
+    key.extend_from_slice(blob_id.as_bytes());
+    key
+}
+
+/// Index is big endian, so chunks of a blob are in order
+fn blob_chunk_key(room_id: RoomId, blob_id: BlobId, index: u32) -> Vec<u8> {
+    let mut key = blob_key(room_id, blob_id);
+    key.extend_from_slice(&index.to_be_bytes());
+    key
+}
+
+/// Index keys are `room_id | sort value | file_id`, sort values are encoded
+/// so that byte order is equal to value order. They hold plain values, so
+/// they are stored only without encryption at rest
+fn index_key_for(sort_by: FileSortBy, room_id: RoomId, file: &models_sled::File) -> Vec<u8> {
+    let cur_version = file.current_version();
+
+    let mut key = room_id.to_ne_bytes().to_vec();
+    match sort_by {
+        FileSortBy::Name => {
+            key.extend_from_slice(file.name.as_bytes());
+            key.push(0);
+        }
+        FileSortBy::Size => key.extend_from_slice(&(cur_version.size as u64).to_be_bytes()),
+        FileSortBy::UploadedAt => {
+            // Flip sign bit to keep negative timestamps before positive ones
+            let ts = cur_version.uploaded_at.timestamp_nanos() as u64 ^ (1 << 63);
+            key.extend_from_slice(&ts.to_be_bytes())
+        }
+        FileSortBy::SourceClientId => {
+            key.extend_from_slice(cur_version.source_client_id.as_bytes())
+        }
+    }
+    key.extend_from_slice(file.id.as_bytes());
+    key
+}
+
+fn is_expired(file: &models_sled::File, now: chrono::NaiveDateTime) -> bool {
+    matches!(file.expires_at, Some(expires_at) if expires_at <= now)
+}
+
+/// Versions may share a blob, each one is returned once
+fn distinct_blob_ids<'a>(blobs: impl Iterator<Item = &'a models_sled::Blob>) -> Vec<BlobId> {
+    let mut blob_ids: Vec<_> = blobs.map(|b| b.id).collect();
+    blob_ids.sort();
+    blob_ids.dedup();
+    blob_ids
+}
+
+fn matches_filter(file: &models_sled::File, filter: &FileFilter) -> bool {
+    let cur_version = file.current_version();
+
+    if let Some(unexpired_at) = filter.unexpired_at {
+        if is_expired(file, unexpired_at) {
+            return false;
+        }
+    }
+
+    if let Some(mime_type_prefix) = &filter.mime_type_prefix {
+        if !cur_version.mime_type.starts_with(mime_type_prefix.as_str()) {
+            return false;
+        }
+    }
+
+    if let Some(source_client_id) = &filter.source_client_id {
+        if &cur_version.source_client_id != source_client_id {
+            return false;
+        }
+    }
+
+    if let Some(name_contains) = &filter.name_contains {
+        if !file.name.contains(name_contains.as_str()) {
+            return false;
+        }
+    }
+
+    if let Some(name_prefix) = &filter.name_prefix {
+        if !file.name.starts_with(name_prefix.as_str()) {
+            return false;
+        }
+    }
+
+    true
 }
@@ -1,49 +1,140 @@
-use crate::adapter::auth::rest::Jwt;
+use crate::adapter::auth::rest::{AnonymousClient, Encode, InviteTokenDecoded, Jwt};
 use crate::adapter::rest_prelude::*;
 use crate::adapter::room::rest::models::*;
-use crate::adapter::room::rest::ws::WsConn;
+use crate::adapter::room::rest::ws::{Broadcast, Kick, Relay, WsConn, WsHub};
+use crate::domain::room::archive::{
+    safe_entry_name, unpack_archive, UnpackedEntry, ZipStreamWriter,
+};
+use crate::port::auth::service as auth_service;
 use crate::port::room::service as room_service;
+use crate::port::{RepoError, ServiceError};
 
+use actix::SystemService;
+use actix_web::error::BlockingError;
+use actix_web::http::header::{
+    Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue,
+};
 use actix_web::web;
 use actix_web_actors::ws;
+use futures::{SinkExt, StreamExt};
+use std::sync::Arc;
+
+const DEFAULT_QR_SIZE: u32 = 256;
+const MIN_QR_SIZE: u32 = 64;
+const MAX_QR_SIZE: u32 = 2048;
+const DEFAULT_FILES_LIMIT: usize = 100;
 
 pub fn service_config(cfg: &mut web::ServiceConfig) {
     cfg.service(create_room)
+        .service(delete_room)
         .service(connect_room)
         .service(disconnect_room)
         .service(add_file)
+        .service(upload_file)
+        .service(upload_archive)
         .service(get_files)
+        .service(get_file_content)
+        .service(get_files_zip)
+        .service(get_file_versions)
+        .service(restore_file_version)
+        .service(create_invite)
+        .service(create_room_qr)
+        .service(create_ws_ticket)
         .service(ws_conn);
 }
 
 #[actix_web::post("/v1/rooms")]
-async fn create_room(state: web::Data<State>) -> ApiResult {
-    let svc_req = room_service::CreateRoomRequest {};
+async fn create_room(
+    state: web::Data<State>,
+    http_req: HttpRequest,
+    req_body: web::Bytes,
+) -> ApiResult {
+    let req_body: CreateRoomBodyRequest = optional_json_body(&req_body)
+        .map_err(|err| err_with_status(http::StatusCode::BAD_REQUEST, err))?;
+
+    let svc_req = room_service::CreateRoomRequest {
+        room_settings: room_service::RoomSettings {
+            e2ee: req_body.e2ee,
+        },
+        join_code: req_body.join_code,
+    };
     let svc_res = state
         .room_service
         .create_room(svc_req)
         .await
-        .map_err(err_with_internal_error)?;
+        .map_err(|err| match err {
+            ServiceError::Exhausted(_) => {
+                err_with_status(http::StatusCode::SERVICE_UNAVAILABLE, err)
+            }
+            err => err_with_internal_error(err),
+        })?;
+
+    // Without auth the creator is the only owner of the room
+    let anonymous_client = http_req.extensions().get::<AnonymousClient>().copied();
+    if let Some(AnonymousClient(client_id)) = anonymous_client {
+        let bind_req = auth_service::BindAnonymousRequest {
+            client_id,
+            room_id: svc_res.room_id,
+        };
+        state
+            .auth_service
+            .bind_anonymous(bind_req)
+            .await
+            .map_err(err_with_internal_error)?;
+    }
 
     let res = CreateRoomResponse {
         room_id: svc_res.room_id,
-        master_password: svc_res
-            .room_cred
-            .passwords
-            .iter()
-            .next()
-            .map(|(p, _)| p.clone())
-            .ok_or_else(|| {
-                msg_with_internal_error(format!(
-                    "no master password in room id={}",
-                    svc_res.room_id
-                ))
-            })?,
+        master_password: svc_res.master_password,
+        e2ee: svc_res.room_settings.e2ee,
     };
 
     Ok(HttpResponse::Ok().json(res))
 }
 
+#[actix_web::delete("/v1/rooms/{room_id}")]
+async fn delete_room(
+    state: web::Data<State>,
+    req_path: web::Path<DeleteRoomPathRequest>,
+    jwt: Jwt,
+) -> ApiResult {
+    // Check auth
+    check_room_access(req_path.room_id, &jwt)?;
+
+    if !jwt.access_token.owner {
+        return Err(msg_with_status(
+            http::StatusCode::FORBIDDEN,
+            "room owner only",
+        ));
+    }
+
+    let svc_req = room_service::DeleteRoomRequest {
+        room_id: jwt.access_token.room_id,
+    };
+    state
+        .room_service
+        .delete_room(svc_req)
+        .await
+        .map_err(err_with_internal_error)?;
+
+    // Room is gone, so nobody can log in again, and its id is never reused
+    let svc_req = auth_service::LogoutRoomRequest {
+        room_id: jwt.access_token.room_id,
+    };
+    state
+        .auth_service
+        .logout_room(svc_req)
+        .await
+        .map_err(err_with_internal_error)?;
+
+    WsHub::from_registry().do_send(Kick {
+        room_id: jwt.access_token.room_id,
+        client_id: None,
+    });
+
+    Ok(HttpResponse::Ok().finish())
+}
+
 #[actix_web::post("/v1/rooms/{room_id}/connect")]
 async fn connect_room(
     state: web::Data<State>,
@@ -101,15 +192,23 @@ async fn add_file(
     let svc_req = room_service::AddFileRequest {
         room_id: req_path.room_id,
         file_name: req_body.0.name,
-        file_size: req_body.0.size,
+        file_content: room_service::FileContent::Absent {
+            size: req_body.0.size,
+        },
         file_mime_type: req_body.0.mime_type,
         file_source_client_id: jwt.access_token.client_id,
+        file_expires_at: req_body.0.expires_at,
+        file_max_downloads: req_body.0.max_downloads,
     };
     let svc_res = state
         .room_service
         .add_file(svc_req)
         .await
-        .map_err(err_with_internal_error)?;
+        .map_err(err_from_service)?;
+
+    if let Some(file_id) = svc_res.expired_file_id {
+        notify_file_removed(req_path.room_id, file_id);
+    }
 
     let res = AddFileResponse {
         file: svc_res.file.into(),
@@ -118,35 +217,690 @@ async fn add_file(
     Ok(HttpResponse::Ok().json(res))
 }
 
+/// Adds file with its content, new version if the name is taken
+#[actix_web::post("/v1/rooms/{room_id}/files/upload")]
+async fn upload_file(
+    state: web::Data<State>,
+    req_path: web::Path<UploadFilePathRequest>,
+    req_query: web::Query<UploadFileQueryRequest>,
+    http_req: HttpRequest,
+    mut payload: web::Payload,
+    jwt: Jwt,
+) -> ApiResult {
+    // Check auth
+    check_room_access(req_path.room_id, &jwt)?;
+
+    // Body is read as it comes, so oversized one is cut off early
+    let max_file_size = state.room_service.max_file_size();
+    let mut content = Vec::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|err| err_with_status(http::StatusCode::BAD_REQUEST, err))?;
+        if content.len() + chunk.len() > max_file_size {
+            return Err(msg_with_status(
+                http::StatusCode::PAYLOAD_TOO_LARGE,
+                format!("file is larger than {} bytes", max_file_size),
+            ));
+        }
+        content.extend_from_slice(&chunk);
+    }
+
+    let mime_type = http_req
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream");
+
+    let svc_req = room_service::AddFileRequest {
+        room_id: req_path.room_id,
+        file_name: req_query.0.name,
+        file_content: room_service::FileContent::Bytes(content),
+        file_mime_type: mime_type.to_owned(),
+        file_source_client_id: jwt.access_token.client_id,
+        file_expires_at: req_query.0.expires_at,
+        file_max_downloads: req_query.0.max_downloads,
+    };
+    let svc_res = state
+        .room_service
+        .add_file(svc_req)
+        .await
+        .map_err(err_from_service)?;
+
+    if let Some(file_id) = svc_res.expired_file_id {
+        notify_file_removed(req_path.room_id, file_id);
+    }
+
+    let res = UploadFileResponse {
+        file: svc_res.file.into(),
+        removed_metadata: svc_res
+            .removed_metadata
+            .into_iter()
+            .map(|m| m.into())
+            .collect(),
+    };
+
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Unpacks .zip or .tar.gz archive into the room, every regular file is
+/// added as by `upload_file`. Progress is sent to the uploader over ws
+#[actix_web::post("/v1/rooms/{room_id}/files/archive")]
+async fn upload_archive(
+    state: web::Data<State>,
+    req_path: web::Path<UploadArchivePathRequest>,
+    req_query: web::Query<UploadArchiveQueryRequest>,
+    mut payload: web::Payload,
+    jwt: Jwt,
+) -> ApiResult {
+    // Check auth
+    check_room_access(req_path.room_id, &jwt)?;
+
+    let directory = match &req_query.directory {
+        None => String::new(),
+        Some(directory) => match safe_entry_name(directory) {
+            Some(directory) => format!("{}/", directory),
+            None => {
+                return Err(msg_with_status(
+                    http::StatusCode::BAD_REQUEST,
+                    "invalid directory",
+                ))
+            }
+        },
+    };
+
+    let svc_req = room_service::GetArchiveLimitsRequest {
+        room_id: req_path.room_id,
+    };
+    let limits = state
+        .room_service
+        .get_archive_limits(svc_req)
+        .await
+        .map_err(err_from_service)?
+        .limits;
+
+    let mut content = Vec::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|err| err_with_status(http::StatusCode::BAD_REQUEST, err))?;
+        if content.len() + chunk.len() > limits.max_size {
+            return Err(msg_with_status(
+                http::StatusCode::PAYLOAD_TOO_LARGE,
+                format!("archive is larger than {} bytes", limits.max_size),
+            ));
+        }
+        content.extend_from_slice(&chunk);
+    }
+
+    // Inflating is blocking, entries come one by one and wait to be added
+    let (mut tx, mut entries) = futures::channel::mpsc::channel(1);
+    let unpacking = web::block(move || {
+        // Nothing is added unless the whole archive is valid
+        unpack_archive(&content, &limits, |_, _| true)?;
+        unpack_archive(&content, &limits, |entry, progress| {
+            futures::executor::block_on(tx.send((entry, progress))).is_ok()
+        })
+    });
+
+    let room_id = req_path.room_id;
+    let client_id = jwt.access_token.client_id;
+    let adding = async move {
+        let mut files = Vec::new();
+        let mut skipped = Vec::new();
+        while let Some((entry, progress)) = entries.next().await {
+            match entry {
+                UnpackedEntry::Skipped { name } => {
+                    skipped.push(format!("{}{}", directory, name));
+                }
+                UnpackedEntry::File { name, content } => {
+                    let mime_type = mime_guess::from_path(&name).first_or_octet_stream();
+                    let svc_req = room_service::AddFileRequest {
+                        room_id,
+                        file_name: format!("{}{}", directory, name),
+                        file_content: room_service::FileContent::Bytes(content),
+                        file_mime_type: mime_type.to_string(),
+                        file_source_client_id: client_id,
+                        file_expires_at: req_query.expires_at,
+                        file_max_downloads: req_query.max_downloads,
+                    };
+                    let svc_res = state
+                        .room_service
+                        .add_file(svc_req)
+                        .await
+                        .map_err(err_from_service)?;
+
+                    if let Some(file_id) = svc_res.expired_file_id {
+                        notify_file_removed(room_id, file_id);
+                    }
+                    files.push(File::from(svc_res.file));
+                }
+            }
+
+            WsHub::from_registry().do_send(Relay {
+                room_id,
+                from: client_id,
+                to: Some(client_id),
+                owners_only: false,
+                msg: WsServerMessage::ArchiveProgress {
+                    read: progress.read,
+                    total: progress.total,
+                    added: files.len(),
+                },
+            });
+        }
+
+        Ok::<_, ApiError>((files, skipped))
+    };
+
+    // Blocking task starts only when polled, so both go at once
+    let (unpacked, added) = futures::future::join(unpacking, adding).await;
+    unpacked.map_err(|err| match err {
+        BlockingError::Error(err) => err_from_service(err),
+        BlockingError::Canceled => msg_with_status(
+            http::StatusCode::INTERNAL_SERVER_ERROR,
+            "unpacking is canceled",
+        ),
+    })?;
+    let (files, skipped) = added?;
+
+    let res = UploadArchiveResponse { files, skipped };
+
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[actix_web::get("/v1/rooms/{room_id}/files/{file_id}/content")]
+async fn get_file_content(
+    state: web::Data<State>,
+    req_path: web::Path<GetFileContentPathRequest>,
+    req_query: web::Query<GetFileContentQueryRequest>,
+    http_req: HttpRequest,
+    jwt: Jwt,
+) -> ApiResult {
+    // Check auth
+    check_room_access(req_path.room_id, &jwt)?;
+
+    let svc_req = room_service::GetFileContentRequest {
+        room_id: req_path.room_id,
+        file_id: req_path.file_id,
+        file_version: req_query.version,
+        accept_encodings: accept_encodings(&http_req),
+    };
+    let svc_res = state
+        .room_service
+        .get_file_content(svc_req)
+        .await
+        .map_err(err_from_service)?;
+
+    if svc_res.file_removed {
+        notify_file_removed(req_path.room_id, req_path.file_id);
+    }
+
+    // Name and mime type of e2ee room are ciphertexts, clients decrypt them
+    // from the listing
+    let (file_name, mime_type) = match svc_res.e2ee {
+        true => (svc_res.file.id.to_string(), None),
+        false => (svc_res.file.name, Some(svc_res.version.mime_type)),
+    };
+    let content_type = mime_type
+        .and_then(|m| http::HeaderValue::from_str(&m).ok())
+        .unwrap_or_else(|| http::HeaderValue::from_static("application/octet-stream"));
+
+    let content = futures::stream::try_unfold(svc_res.content, |mut content| async move {
+        match content.next_chunk().await {
+            Ok(Some(chunk)) => Ok(Some((web::Bytes::from(chunk), content))),
+            Ok(None) => Ok(None),
+            Err(err) => Err(err_with_internal_error(err)),
+        }
+    });
+
+    // Content is never rendered in place, it comes from room members
+    let mut res = HttpResponse::Ok();
+    if let Some(content_size) = svc_res.content_size {
+        res.no_chunking(content_size as u64);
+    }
+    if let Some(content_encoding) = svc_res.content_encoding {
+        let content_encoding = match content_encoding {
+            room_service::ContentEncoding::Zstd => "zstd",
+            room_service::ContentEncoding::Gzip => "gzip",
+        };
+        res.header(http::header::CONTENT_ENCODING, content_encoding);
+    }
+    let res = res
+        .header(http::header::VARY, "Accept-Encoding")
+        .header(http::header::CONTENT_TYPE, content_type)
+        .header(http::header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .set(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::FilenameExt(ExtendedValue {
+                charset: Charset::Ext("UTF-8".to_owned()),
+                language_tag: None,
+                value: file_name.into_bytes(),
+            })],
+        })
+        .streaming(Box::pin(content));
+
+    Ok(res)
+}
+
+/// Streams ZIP archive of the files, their content is read as the archive
+/// is sent. Files removed in the meantime are left out
+#[actix_web::post("/v1/rooms/{room_id}/files/zip")]
+async fn get_files_zip(
+    state: web::Data<State>,
+    req_path: web::Path<GetFilesZipPathRequest>,
+    req_body: web::Json<GetFilesZipBodyRequest>,
+    jwt: Jwt,
+) -> ApiResult {
+    // Check auth
+    check_room_access(req_path.room_id, &jwt)?;
+
+    let (selection, archive_name) = match (req_body.0.file_ids, req_body.0.directory) {
+        (Some(file_ids), None) => (
+            room_service::ArchiveSelection::Files(file_ids),
+            "files".to_owned(),
+        ),
+        (None, Some(directory)) => {
+            let archive_name = safe_entry_name(&directory)
+                .and_then(|d| d.rsplit('/').next().map(|n| n.to_owned()))
+                .unwrap_or_else(|| "files".to_owned());
+            (
+                room_service::ArchiveSelection::Directory(directory),
+                archive_name,
+            )
+        }
+        _ => {
+            return Err(msg_with_status(
+                http::StatusCode::BAD_REQUEST,
+                "either file_ids or directory is required",
+            ))
+        }
+    };
+
+    let svc_req = room_service::GetArchiveFilesRequest {
+        room_id: req_path.room_id,
+        selection,
+    };
+    let svc_res = state
+        .room_service
+        .get_archive_files(svc_req)
+        .await
+        .map_err(err_from_service)?;
+
+    let zip_state = ZipState {
+        room_service: Arc::clone(&state.room_service),
+        room_id: req_path.room_id,
+        e2ee: svc_res.e2ee,
+        files: svc_res.files.into_iter(),
+        writer: Some(ZipStreamWriter::new()),
+        content: None,
+    };
+    let content = futures::stream::try_unfold(zip_state, |mut zip_state| async move {
+        match zip_state.next_part().await {
+            Ok(Some(part)) => Ok(Some((web::Bytes::from(part), zip_state))),
+            Ok(None) => Ok(None),
+            Err(err) => Err(err_with_internal_error(err)),
+        }
+    });
+
+    let res = HttpResponse::Ok()
+        .content_type("application/zip")
+        .header(http::header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .set(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::FilenameExt(ExtendedValue {
+                charset: Charset::Ext("UTF-8".to_owned()),
+                language_tag: None,
+                value: format!("{}.zip", archive_name).into_bytes(),
+            })],
+        })
+        .streaming(Box::pin(content));
+
+    Ok(res)
+}
+
+/// Archive being streamed, one file is read at a time
+struct ZipState {
+    room_service: Arc<dyn room_service::RoomService>,
+    room_id: RoomId,
+    e2ee: bool,
+    files: std::vec::IntoIter<room_service::File>,
+    /// Taken when the archive is finished
+    writer: Option<ZipStreamWriter>,
+    content: Option<Box<dyn room_service::FileContentReader>>,
+}
+
+impl ZipState {
+    async fn next_part(&mut self) -> Result<Option<Vec<u8>>, ServiceError> {
+        let writer = match self.writer.as_mut() {
+            None => return Ok(None),
+            Some(w) => w,
+        };
+
+        if let Some(content) = self.content.as_mut() {
+            return match content.next_chunk().await? {
+                Some(chunk) => {
+                    writer.write(&chunk)?;
+                    Ok(Some(chunk))
+                }
+                None => {
+                    self.content = None;
+                    writer.finish_entry().map(Some)
+                }
+            };
+        }
+
+        for file in self.files.by_ref() {
+            let svc_req = room_service::GetFileContentRequest {
+                room_id: self.room_id,
+                file_id: file.id,
+                file_version: None,
+                // Entries are stored as is, so content is decoded
+                accept_encodings: Vec::new(),
+            };
+            let svc_res = match self.room_service.get_file_content(svc_req).await {
+                Ok(res) => res,
+                Err(ServiceError::RepoError(RepoError::NotFound(_))) => continue,
+                Err(err) => return Err(err),
+            };
+            if svc_res.file_removed {
+                notify_file_removed(self.room_id, file.id);
+            }
+
+            let name = match self.e2ee {
+                true => None,
+                false => safe_entry_name(&svc_res.file.name),
+            }
+            .unwrap_or_else(|| file.id.to_string());
+            let header = writer.start_entry(
+                &name,
+                svc_res.version.uploaded_at,
+                svc_res.version.size as u64,
+            )?;
+            self.content = Some(svc_res.content);
+
+            return Ok(Some(header));
+        }
+
+        match self.writer.take() {
+            Some(writer) => writer.finish().map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
 #[actix_web::get("/v1/rooms/{room_id}/files")]
 async fn get_files(
     state: web::Data<State>,
     req_path: web::Path<GetFilesPathRequest>,
+    req_query: web::Query<GetFilesQueryRequest>,
     jwt: Jwt,
 ) -> ApiResult {
     // Check auth
     check_room_access(req_path.room_id, &jwt)?;
 
+    let cursor = match req_query.0.cursor {
+        None => None,
+        Some(cursor) => Some(
+            base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+                .map_err(|err| err_with_status(http::StatusCode::BAD_REQUEST, err))?,
+        ),
+    };
+
     let svc_req = room_service::GetFilesRequest {
         room_id: req_path.room_id,
+        sort_by: req_query.0.sort_by.unwrap_or(FileSortBy::Name).into(),
+        order: req_query.0.order.unwrap_or(SortOrder::Asc).into(),
+        filter: room_service::FileFilter {
+            mime_type_prefix: req_query.0.mime_type,
+            source_client_id: req_query.0.source_client_id,
+            name_contains: req_query.0.name,
+        },
+        cursor,
+        limit: req_query.0.limit.unwrap_or(DEFAULT_FILES_LIMIT),
     };
     let svc_res = state
         .room_service
         .get_files(svc_req)
         .await
-        .map_err(err_with_internal_error)?;
+        .map_err(err_from_service)?;
 
     let res = GetFilesResponse {
-        files: svc_res
-            .files
-            .into_iter()
-            .map(|(k, v)| (k, v.into()))
-            .collect(),
+        files: svc_res.files.into_iter().map(|f| f.into()).collect(),
+        next_cursor: svc_res
+            .next_cursor
+            .map(|c| base64::encode_config(c, base64::URL_SAFE_NO_PAD)),
+    };
+
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[actix_web::get("/v1/rooms/{room_id}/files/{file_id}/versions")]
+async fn get_file_versions(
+    state: web::Data<State>,
+    req_path: web::Path<GetFileVersionsPathRequest>,
+    jwt: Jwt,
+) -> ApiResult {
+    // Check auth
+    check_room_access(req_path.room_id, &jwt)?;
+
+    let svc_req = room_service::GetFileVersionsRequest {
+        room_id: req_path.room_id,
+        file_id: req_path.file_id,
+    };
+    let svc_res = state
+        .room_service
+        .get_file_versions(svc_req)
+        .await
+        .map_err(err_from_service)?;
+
+    let res = GetFileVersionsResponse {
+        versions: svc_res.versions.into_iter().map(|v| v.into()).collect(),
+    };
+
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[actix_web::post("/v1/rooms/{room_id}/files/{file_id}/versions/{version}/restore")]
+async fn restore_file_version(
+    state: web::Data<State>,
+    req_path: web::Path<RestoreFileVersionPathRequest>,
+    jwt: Jwt,
+) -> ApiResult {
+    // Check auth
+    check_room_access(req_path.room_id, &jwt)?;
+
+    let svc_req = room_service::RestoreFileVersionRequest {
+        room_id: req_path.room_id,
+        file_id: req_path.file_id,
+        file_version: req_path.version,
+        client_id: jwt.access_token.client_id,
+    };
+    let svc_res = state
+        .room_service
+        .restore_file_version(svc_req)
+        .await
+        .map_err(err_from_service)?;
+
+    let res = RestoreFileVersionResponse {
+        file: svc_res.file.into(),
+    };
+
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[actix_web::post("/v1/rooms/{room_id}/invites")]
+async fn create_invite(
+    state: web::Data<State>,
+    req_path: web::Path<CreateInvitePathRequest>,
+    req_body: web::Bytes,
+    jwt: Jwt,
+) -> ApiResult {
+    // Check auth
+    check_room_access(req_path.room_id, &jwt)?;
+
+    if !jwt.access_token.owner {
+        return Err(msg_with_status(
+            http::StatusCode::FORBIDDEN,
+            "room owner only",
+        ));
+    }
+
+    let req_body: CreateInviteBodyRequest = optional_json_body(&req_body)
+        .map_err(|err| err_with_status(http::StatusCode::BAD_REQUEST, err))?;
+
+    let svc_req = auth_service::CreateInviteRequest {
+        room_id: jwt.access_token.room_id,
+        role: req_body.role.into(),
+        expires_in: req_body.expires_in,
+        max_uses: req_body.max_uses,
+    };
+    let svc_res = state
+        .auth_service
+        .create_invite(svc_req)
+        .await
+        .map_err(err_from_service)?;
+
+    let invite: InviteTokenDecoded = svc_res.invite.into();
+    let token = invite
+        .encode(&state.jwt_keyring)
+        .map_err(AnyhowErrorWrapper::from)
+        .map_err(err_with_internal_error)?;
+
+    let res = CreateInviteResponse {
+        join_url: format!("{}#token={}", state.room_service.join_url(), token),
+        token,
+        expires_at: invite.exp,
+        max_uses: invite.max_uses,
     };
 
     Ok(HttpResponse::Ok().json(res))
 }
 
+#[actix_web::post("/v1/rooms/{room_id}/qr")]
+async fn create_room_qr(
+    state: web::Data<State>,
+    req_path: web::Path<CreateRoomQrPathRequest>,
+    req_query: web::Query<CreateRoomQrQueryRequest>,
+    jwt: Jwt,
+) -> ApiResult {
+    // Check auth
+    check_room_access(req_path.room_id, &jwt)?;
+
+    if !jwt.access_token.owner {
+        return Err(msg_with_status(
+            http::StatusCode::FORBIDDEN,
+            "room owner only",
+        ));
+    }
+
+    // Every QR code carries its own one-off password, so it is created by POST
+    let svc_req = room_service::CreateInviteRequest {
+        room_id: jwt.access_token.room_id,
+    };
+    let svc_res = state
+        .room_service
+        .create_invite(svc_req)
+        .await
+        .map_err(err_from_service)?;
+
+    let ec = req_query.ec.unwrap_or(QrEcLevel::M);
+    let size = req_query
+        .size
+        .unwrap_or(DEFAULT_QR_SIZE)
+        .clamp(MIN_QR_SIZE, MAX_QR_SIZE);
+
+    let code = qrcode::QrCode::with_error_correction_level(svc_res.join_url, ec.into())
+        .map_err(err_with_internal_error)?;
+
+    let res = match req_query.format.unwrap_or(QrFormat::Svg) {
+        QrFormat::Svg => {
+            let image = code
+                .render::<qrcode::render::svg::Color>()
+                .min_dimensions(size, size)
+                .build();
+
+            HttpResponse::Ok()
+                .content_type("image/svg+xml")
+                .header(http::header::CACHE_CONTROL, "no-store")
+                .body(image)
+        }
+        QrFormat::Png => {
+            let image = code
+                .render::<image::Luma<u8>>()
+                .min_dimensions(size, size)
+                .build();
+
+            let mut png = Vec::new();
+            image::DynamicImage::ImageLuma8(image)
+                .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+                .map_err(err_with_internal_error)?;
+
+            HttpResponse::Ok()
+                .content_type("image/png")
+                .header(http::header::CACHE_CONTROL, "no-store")
+                .body(png)
+        }
+    };
+
+    Ok(res)
+}
+
+/// Empty body is the default request, but malformed one is rejected
+fn optional_json_body<T>(body: &web::Bytes) -> serde_json::Result<T>
+where
+    T: serde::de::DeserializeOwned + Default,
+{
+    if body.is_empty() {
+        return Ok(T::default());
+    }
+
+    serde_json::from_slice(body)
+}
+
+/// Invalid requests are client errors, everything else is internal one
+fn err_from_service(err: ServiceError) -> ApiError {
+    match err {
+        ServiceError::ValidationError(_) => err_with_status(http::StatusCode::BAD_REQUEST, err),
+        ServiceError::RepoError(RepoError::NotFound(_)) => {
+            err_with_status(http::StatusCode::NOT_FOUND, err)
+        }
+        err => err_with_internal_error(err),
+    }
+}
+
+fn notify_file_removed(room_id: RoomId, file_id: FileId) {
+    WsHub::from_registry().do_send(Broadcast {
+        room_id,
+        msg: WsServerMessage::FileRemoved { file_id },
+    });
+}
+
+/// Encodings of `Accept-Encoding` header, ones with zero weight are not
+/// acceptable
+fn accept_encodings(http_req: &HttpRequest) -> Vec<room_service::ContentEncoding> {
+    let header = http_req
+        .headers()
+        .get_all(http::header::ACCEPT_ENCODING)
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let mut encodings = Vec::new();
+    for item in header.split(',') {
+        let mut params = item.split(';').map(|p| p.trim());
+        let encoding = match params.next().map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("zstd") => room_service::ContentEncoding::Zstd,
+            Some("gzip") | Some("x-gzip") => room_service::ContentEncoding::Gzip,
+            _ => continue,
+        };
+        let rejected = params
+            .filter_map(|p| p.strip_prefix("q="))
+            .any(|q| matches!(q.parse::<f32>(), Ok(q) if q <= 0.0));
+        if !rejected {
+            encodings.push(encoding);
+        }
+    }
+
+    encodings
+}
+
 fn check_room_access(room_id: RoomId, jwt: &Jwt) -> Result<(), ApiError> {
     if jwt.access_token.room_id != room_id {
         return Err(msg_with_status(
@@ -158,17 +912,60 @@ fn check_room_access(room_id: RoomId, jwt: &Jwt) -> Result<(), ApiError> {
     Ok(())
 }
 
+#[actix_web::post("/v1/rooms/{room_id}/ws-ticket")]
+async fn create_ws_ticket(
+    state: web::Data<State>,
+    req_path: web::Path<CreateWsTicketPathRequest>,
+    jwt: Jwt,
+) -> ApiResult {
+    // Check auth
+    check_room_access(req_path.room_id, &jwt)?;
+
+    let svc_req = auth_service::CreateWsTicketRequest { jwt: jwt.into() };
+    let svc_res = state
+        .auth_service
+        .create_ws_ticket(svc_req)
+        .await
+        .map_err(err_with_internal_error)?;
+
+    let res = CreateWsTicketResponse {
+        ticket: svc_res.ticket,
+        expires_at: svc_res.exp,
+    };
+
+    Ok(HttpResponse::Ok().json(res))
+}
+
+/// Authorized by ticket instead of access token, see `create_ws_ticket`
 #[actix_web::get("/v1/rooms/{room_id}/ws")]
 async fn ws_conn(
+    state: web::Data<State>,
     req_path: web::Path<WsConnPathRequest>,
+    req_query: web::Query<WsConnQueryRequest>,
     http_req: HttpRequest,
     stream: web::Payload,
-    jwt: Jwt,
 ) -> ApiResult {
+    let svc_req = auth_service::RedeemWsTicketRequest {
+        ticket: req_query.ticket,
+    };
+    let svc_res = state
+        .auth_service
+        .redeem_ws_ticket(svc_req)
+        .await
+        .map_err(|err| err_with_status(http::StatusCode::UNAUTHORIZED, err))?;
+    let jwt: Jwt = svc_res.jwt.into();
+
     // Check auth
     check_room_access(req_path.room_id, &jwt)?;
 
-    let resp = ws::start(WsConn::default(), &http_req, stream)
+    let conn = WsConn::new(
+        req_path.room_id,
+        jwt.access_token.client_id,
+        jwt.access_token.owner,
+        Arc::clone(&state.room_service),
+        Arc::clone(&state.auth_service),
+    );
+    let resp = ws::start(conn, &http_req, stream)
         .map_err(|err| anyhow::anyhow!("{:?}", err))
         .map_err(AnyhowErrorWrapper::from)
         .map_err(err_with_internal_error)?;
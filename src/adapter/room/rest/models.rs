@@ -1,31 +1,57 @@
+use crate::adapter::auth::rest::{InviteRole, InviteTokenEncoded};
 use crate::adapter::rest_prelude::*;
+use crate::port::auth::service as auth_service;
 use crate::port::room::service as room_service;
 
 use actix::prelude::*;
-use std::collections::HashMap;
+use chrono::NaiveDateTime;
 
 pub type RoomId = room_service::RoomId;
 pub type FileId = room_service::FileId;
+pub type FileVersionNumber = room_service::FileVersionNumber;
 pub type ClientId = room_service::ClientId;
+pub type WsTicket = auth_service::WsTicket;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+pub struct CreateRoomBodyRequest {
+    /// End-to-end encrypted room, file names and mime types must be
+    /// encrypted by clients
+    #[serde(default)]
+    pub e2ee: bool,
+    /// Rolling join code pushed to owners over websocket, see
+    /// `WsServerMessage::JoinCode`
+    #[serde(default)]
+    pub join_code: bool,
+}
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct CreateRoomResponse {
     pub room_id: RoomId,
     pub master_password: String,
+    pub e2ee: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct DeleteRoomPathRequest {
+    #[serde(deserialize_with = "RoomId::deserialize_str")]
+    pub room_id: RoomId,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct ConnectRoomPathRequest {
+    #[serde(deserialize_with = "RoomId::deserialize_str")]
     pub room_id: RoomId,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct DisconnectRoomPathRequest {
+    #[serde(deserialize_with = "RoomId::deserialize_str")]
     pub room_id: RoomId,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct AddFilePathRequest {
+    #[serde(deserialize_with = "RoomId::deserialize_str")]
     pub room_id: RoomId,
 }
 
@@ -34,6 +60,10 @@ pub struct AddFileBodyRequest {
     pub name: String,
     pub size: usize,
     pub mime_type: String,
+    #[serde(default)]
+    pub expires_at: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub max_downloads: Option<u32>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
@@ -41,21 +71,279 @@ pub struct AddFileResponse {
     pub file: File,
 }
 
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct UploadFilePathRequest {
+    #[serde(deserialize_with = "RoomId::deserialize_str")]
+    pub room_id: RoomId,
+}
+
+/// Content is the request body, mime type is its `Content-Type`
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct UploadFileQueryRequest {
+    pub name: String,
+    #[serde(default)]
+    pub expires_at: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub max_downloads: Option<u32>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct UploadFileResponse {
+    pub file: File,
+    /// Metadata stripped from uploaded image
+    pub removed_metadata: Vec<ImageMetadata>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct UploadArchivePathRequest {
+    #[serde(deserialize_with = "RoomId::deserialize_str")]
+    pub room_id: RoomId,
+}
+
+/// Archive is the request body, .zip or .tar.gz
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct UploadArchiveQueryRequest {
+    /// Entries are added under this directory, to the root if absent
+    #[serde(default)]
+    pub directory: Option<String>,
+    #[serde(default)]
+    pub expires_at: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub max_downloads: Option<u32>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct UploadArchiveResponse {
+    pub files: Vec<File>,
+    /// Names of symlinks and other special entries which are not added
+    pub skipped: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageMetadata {
+    Exif,
+    Gps,
+    Xmp,
+    Iptc,
+    Comment,
+}
+
+impl From<room_service::ImageMetadata> for ImageMetadata {
+    fn from(f: room_service::ImageMetadata) -> Self {
+        match f {
+            room_service::ImageMetadata::Exif => ImageMetadata::Exif,
+            room_service::ImageMetadata::Gps => ImageMetadata::Gps,
+            room_service::ImageMetadata::Xmp => ImageMetadata::Xmp,
+            room_service::ImageMetadata::Iptc => ImageMetadata::Iptc,
+            room_service::ImageMetadata::Comment => ImageMetadata::Comment,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct GetFileContentPathRequest {
+    #[serde(deserialize_with = "RoomId::deserialize_str")]
+    pub room_id: RoomId,
+    pub file_id: FileId,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+pub struct GetFileContentQueryRequest {
+    /// Current version if absent
+    pub version: Option<FileVersionNumber>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct GetFilesZipPathRequest {
+    #[serde(deserialize_with = "RoomId::deserialize_str")]
+    pub room_id: RoomId,
+}
+
+/// Either files or a directory, e.g. `photos` for files named `photos/...`
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct GetFilesZipBodyRequest {
+    #[serde(default)]
+    pub file_ids: Option<Vec<FileId>>,
+    #[serde(default)]
+    pub directory: Option<String>,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct GetFilesPathRequest {
+    #[serde(deserialize_with = "RoomId::deserialize_str")]
     pub room_id: RoomId,
 }
 
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+pub struct GetFilesQueryRequest {
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+    pub sort_by: Option<FileSortBy>,
+    pub order: Option<SortOrder>,
+    /// Filter by mime type prefix, e.g. `image/`
+    pub mime_type: Option<String>,
+    pub source_client_id: Option<ClientId>,
+    /// Filter by name substring
+    pub name: Option<String>,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct GetFilesResponse {
-    pub files: HashMap<FileId, File>,
+    pub files: Vec<File>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum FileSortBy {
+    Name,
+    Size,
+    UploadedAt,
+    SourceClientId,
+}
+
+impl From<FileSortBy> for room_service::FileSortBy {
+    fn from(f: FileSortBy) -> Self {
+        match f {
+            FileSortBy::Name => room_service::FileSortBy::Name,
+            FileSortBy::Size => room_service::FileSortBy::Size,
+            FileSortBy::UploadedAt => room_service::FileSortBy::UploadedAt,
+            FileSortBy::SourceClientId => room_service::FileSortBy::SourceClientId,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl From<SortOrder> for room_service::SortOrder {
+    fn from(f: SortOrder) -> Self {
+        match f {
+            SortOrder::Asc => room_service::SortOrder::Asc,
+            SortOrder::Desc => room_service::SortOrder::Desc,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct GetFileVersionsPathRequest {
+    #[serde(deserialize_with = "RoomId::deserialize_str")]
+    pub room_id: RoomId,
+    pub file_id: FileId,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct GetFileVersionsResponse {
+    pub versions: Vec<FileVersion>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct RestoreFileVersionPathRequest {
+    #[serde(deserialize_with = "RoomId::deserialize_str")]
+    pub room_id: RoomId,
+    pub file_id: FileId,
+    pub version: FileVersionNumber,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct RestoreFileVersionResponse {
+    pub file: File,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct CreateInvitePathRequest {
+    #[serde(deserialize_with = "RoomId::deserialize_str")]
+    pub room_id: RoomId,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct CreateInviteBodyRequest {
+    pub role: InviteRole,
+    /// Seconds
+    pub expires_in: Option<i64>,
+    pub max_uses: Option<u32>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct CreateInviteResponse {
+    pub token: InviteTokenEncoded,
+    /// `room.join_url` with the token in fragment
+    pub join_url: String,
+    pub expires_at: NaiveDateTime,
+    pub max_uses: u32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct CreateRoomQrPathRequest {
+    #[serde(deserialize_with = "RoomId::deserialize_str")]
+    pub room_id: RoomId,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+pub struct CreateRoomQrQueryRequest {
+    pub format: Option<QrFormat>,
+    /// Minimal width and height in pixels
+    pub size: Option<u32>,
+    pub ec: Option<QrEcLevel>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum QrFormat {
+    Svg,
+    Png,
+}
+
+/// Error correction level, from ~7% (`l`) to ~30% (`h`) of restorable data
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum QrEcLevel {
+    L,
+    M,
+    Q,
+    H,
+}
+
+impl From<QrEcLevel> for qrcode::EcLevel {
+    fn from(f: QrEcLevel) -> Self {
+        match f {
+            QrEcLevel::L => qrcode::EcLevel::L,
+            QrEcLevel::M => qrcode::EcLevel::M,
+            QrEcLevel::Q => qrcode::EcLevel::Q,
+            QrEcLevel::H => qrcode::EcLevel::H,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct CreateWsTicketPathRequest {
+    #[serde(deserialize_with = "RoomId::deserialize_str")]
+    pub room_id: RoomId,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct CreateWsTicketResponse {
+    pub ticket: WsTicket,
+    pub expires_at: NaiveDateTime,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct WsConnPathRequest {
+    #[serde(deserialize_with = "RoomId::deserialize_str")]
     pub room_id: RoomId,
 }
 
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct WsConnQueryRequest {
+    pub ticket: WsTicket,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct File {
     pub id: FileId,
@@ -63,6 +351,13 @@ pub struct File {
     pub size: usize,
     pub mime_type: String,
     pub source_client_id: ClientId,
+    pub expires_at: Option<NaiveDateTime>,
+    pub uploaded_at: NaiveDateTime,
+    pub version: FileVersionNumber,
+    /// Hex, absent if the file has no stored content
+    pub sha256: Option<String>,
+    /// Unlimited if absent
+    pub downloads_left: Option<u32>,
 }
 
 impl From<room_service::File> for File {
@@ -73,11 +368,92 @@ impl From<room_service::File> for File {
             size: f.size,
             mime_type: f.mime_type,
             source_client_id: f.source_client_id,
+            expires_at: f.expires_at,
+            uploaded_at: f.uploaded_at,
+            version: f.version,
+            sha256: f.sha256.map(|h| to_hex(&h)),
+            downloads_left: f.downloads_left,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct FileVersion {
+    pub number: FileVersionNumber,
+    pub size: usize,
+    pub mime_type: String,
+    pub source_client_id: ClientId,
+    pub uploaded_at: NaiveDateTime,
+    pub sha256: Option<String>,
+}
+
+impl From<room_service::FileVersion> for FileVersion {
+    fn from(f: room_service::FileVersion) -> Self {
+        Self {
+            number: f.number,
+            size: f.size,
+            mime_type: f.mime_type,
+            source_client_id: f.source_client_id,
+            uploaded_at: f.uploaded_at,
+            sha256: f.sha256.map(|h| to_hex(&h)),
         }
     }
 }
 
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
 #[derive(Debug, Message)]
 #[rtype(result = "Result<(), ApiError>")]
 #[repr(transparent)]
 pub struct FilePart(pub actix_web::web::Bytes);
+
+/// Message sent by client over room websocket
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsClientMessage {
+    /// Opaque key exchange payload for e2ee rooms, relayed to `to` client
+    /// or to all other room members if `to` is absent
+    KeyExchange {
+        to: Option<ClientId>,
+        payload: String,
+    },
+}
+
+/// Message sent by server over room websocket
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Message)]
+#[rtype(result = "()")]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WsServerMessage {
+    KeyExchange {
+        from: ClientId,
+        payload: String,
+    },
+    /// Room login is locked for `retry_after` seconds after too many
+    /// failed attempts, somebody is probably guessing the password. Sent
+    /// to room owners only
+    LoginLocked {
+        retry_after: i64,
+    },
+    /// Current rolling join code, sent to room owners on connect and on
+    /// every rotation
+    JoinCode {
+        code: String,
+        expires_at: NaiveDateTime,
+    },
+    /// Session of the connection is logged out or revoked, or the room is
+    /// deleted. Connection is closed right after it
+    SessionClosed,
+    /// File is deleted because it expired or its downloads ran out
+    FileRemoved {
+        file_id: FileId,
+    },
+    /// Archive upload of the client is unpacked up to `read` bytes of
+    /// `total`, `added` files are added so far
+    ArchiveProgress {
+        read: u64,
+        total: u64,
+        added: usize,
+    },
+}
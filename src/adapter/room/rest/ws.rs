@@ -1,14 +1,131 @@
 use crate::adapter::rest_prelude::*;
 use crate::adapter::room::rest::models::*;
+use crate::port::auth::service as auth_service;
+use crate::port::auth::service::AuthService;
+use crate::port::room::service as room_service;
+use crate::port::room::service::RoomService;
 
 use actix::prelude::*;
 use actix_web_actors::ws;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
 
-#[derive(Default)]
-pub struct WsConn;
+/// Connection outlives access token, so its session is rechecked, e.g. it
+/// may be revoked on refresh token reuse
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Client may have several connections, e.g. in different tabs
+pub type ConnId = Uuid;
+
+pub struct WsConn {
+    id: ConnId,
+    room_id: RoomId,
+    client_id: ClientId,
+    /// Join code and login lock are pushed only to room owners
+    owner: bool,
+    room_service: Arc<dyn RoomService>,
+    auth_service: Arc<dyn AuthService>,
+}
+
+impl WsConn {
+    pub fn new(
+        room_id: RoomId,
+        client_id: ClientId,
+        owner: bool,
+        room_service: Arc<dyn RoomService>,
+        auth_service: Arc<dyn AuthService>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            room_id,
+            client_id,
+            owner,
+            room_service,
+            auth_service,
+        }
+    }
+
+    /// Closes connection if its session is gone
+    fn check_session(&self, ctx: &mut <Self as Actor>::Context) {
+        let auth_service = Arc::clone(&self.auth_service);
+        let svc_req = auth_service::HasSessionRequest {
+            client_id: self.client_id,
+            room_id: self.room_id,
+        };
+
+        let fut = async move { auth_service.has_session(svc_req).await };
+        ctx.spawn(
+            fut.into_actor(self)
+                .map(|svc_res, _act, ctx| match svc_res {
+                    Ok(true) => {}
+                    Ok(false) => ctx.notify(WsServerMessage::SessionClosed),
+                    // Connection is not dropped because of transient failure
+                    Err(err) => log::error!("check ws session error: {:?}", err),
+                }),
+        );
+    }
+
+    /// Sends current join code of the room, if it has one, and schedules
+    /// sending of the next one at rotation
+    fn push_join_code(&self, ctx: &mut <Self as Actor>::Context) {
+        let room_service = Arc::clone(&self.room_service);
+        let svc_req = room_service::GetJoinCodeRequest {
+            room_id: self.room_id,
+        };
+
+        let fut = async move { room_service.get_join_code(svc_req).await };
+        ctx.spawn(fut.into_actor(self).map(|svc_res, _act, ctx| {
+            let join_code = match svc_res {
+                Ok(res) => match res.join_code {
+                    Some(c) => c,
+                    None => return,
+                },
+                Err(err) => {
+                    log::error!("get join code error: {:?}", err);
+                    return;
+                }
+            };
+
+            let until_rotation = (join_code.expires_at - Utc::now().naive_utc())
+                .to_std()
+                .unwrap_or_default();
+
+            ctx.notify(WsServerMessage::JoinCode {
+                code: join_code.code,
+                expires_at: join_code.expires_at,
+            });
+            ctx.run_later(until_rotation, |act, ctx| act.push_join_code(ctx));
+        }));
+    }
+}
 
 impl Actor for WsConn {
     type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        WsHub::from_registry().do_send(Join {
+            conn_id: self.id,
+            room_id: self.room_id,
+            client_id: self.client_id,
+            owner: self.owner,
+            addr: ctx.address().recipient(),
+        });
+
+        if self.owner {
+            self.push_join_code(ctx);
+        }
+        ctx.run_interval(SESSION_CHECK_INTERVAL, |act, ctx| act.check_session(ctx));
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        WsHub::from_registry().do_send(Leave {
+            conn_id: self.id,
+            room_id: self.room_id,
+        });
+    }
 }
 
 /// Handler for ws::Message message
@@ -20,6 +137,22 @@ impl Handler<FilePart> for WsConn {
     }
 }
 
+impl Handler<WsServerMessage> for WsConn {
+    type Result = ();
+
+    fn handle(&mut self, msg: WsServerMessage, ctx: &mut <Self as Actor>::Context) {
+        match serde_json::to_string(&msg) {
+            Ok(text) => ctx.text(text),
+            Err(err) => log::error!("ws message serialize error: {:?}", err),
+        }
+
+        if let WsServerMessage::SessionClosed = msg {
+            ctx.close(Some(ws::CloseCode::Policy.into()));
+            ctx.stop();
+        }
+    }
+}
+
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsConn {
     fn handle(
         &mut self,
@@ -28,7 +161,21 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsConn {
     ) {
         match msg {
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
-            Ok(ws::Message::Text(text)) => ctx.text(text),
+            Ok(ws::Message::Text(text)) => match serde_json::from_str::<WsClientMessage>(&text) {
+                Ok(WsClientMessage::KeyExchange { to, payload }) => {
+                    WsHub::from_registry().do_send(Relay {
+                        room_id: self.room_id,
+                        from: self.client_id,
+                        to,
+                        owners_only: false,
+                        msg: WsServerMessage::KeyExchange {
+                            from: self.client_id,
+                            payload,
+                        },
+                    })
+                }
+                Err(_) => ctx.text(text),
+            },
             Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
             Ok(ws::Message::Close(reason)) => {
                 ctx.close(reason);
@@ -38,3 +185,162 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsConn {
         }
     }
 }
+
+/// Keeps websocket connections of every room and delivers messages
+/// between room members
+#[derive(Default)]
+pub struct WsHub {
+    rooms: HashMap<RoomId, HashMap<ConnId, WsMember>>,
+}
+
+struct WsMember {
+    client_id: ClientId,
+    owner: bool,
+    addr: Recipient<WsServerMessage>,
+}
+
+impl Actor for WsHub {
+    type Context = Context<Self>;
+}
+
+impl Supervised for WsHub {}
+
+impl SystemService for WsHub {}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Join {
+    pub conn_id: ConnId,
+    pub room_id: RoomId,
+    pub client_id: ClientId,
+    pub owner: bool,
+    pub addr: Recipient<WsServerMessage>,
+}
+
+impl Handler<Join> for WsHub {
+    type Result = ();
+
+    fn handle(&mut self, msg: Join, _ctx: &mut Self::Context) {
+        let member = WsMember {
+            client_id: msg.client_id,
+            owner: msg.owner,
+            addr: msg.addr,
+        };
+        self.rooms
+            .entry(msg.room_id)
+            .or_default()
+            .insert(msg.conn_id, member);
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+/// Removes only the connection which leaves, other connections of the
+/// same client stay
+pub struct Leave {
+    pub conn_id: ConnId,
+    pub room_id: RoomId,
+}
+
+impl Handler<Leave> for WsHub {
+    type Result = ();
+
+    fn handle(&mut self, msg: Leave, _ctx: &mut Self::Context) {
+        if let Some(members) = self.rooms.get_mut(&msg.room_id) {
+            members.remove(&msg.conn_id);
+            if members.is_empty() {
+                self.rooms.remove(&msg.room_id);
+            }
+        }
+    }
+}
+
+/// Sends message to all connections of `to` client of the room, or to all
+/// room members except `from` if `to` is absent
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Relay {
+    pub room_id: RoomId,
+    pub from: ClientId,
+    pub to: Option<ClientId>,
+    /// Connections of other members are skipped
+    pub owners_only: bool,
+    pub msg: WsServerMessage,
+}
+
+impl Handler<Relay> for WsHub {
+    type Result = ();
+
+    fn handle(&mut self, msg: Relay, _ctx: &mut Self::Context) {
+        let members = match self.rooms.get(&msg.room_id) {
+            None => return,
+            Some(m) => m,
+        };
+
+        for member in members.values() {
+            if msg.owners_only && !member.owner {
+                continue;
+            }
+
+            let receive = match msg.to {
+                Some(to) => member.client_id == to,
+                None => member.client_id != msg.from,
+            };
+
+            if receive {
+                let _ = member.addr.do_send(msg.msg.clone());
+            }
+        }
+    }
+}
+
+/// Sends message to all connections of the room, e.g. about its files
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Broadcast {
+    pub room_id: RoomId,
+    pub msg: WsServerMessage,
+}
+
+impl Handler<Broadcast> for WsHub {
+    type Result = ();
+
+    fn handle(&mut self, msg: Broadcast, _ctx: &mut Self::Context) {
+        if let Some(members) = self.rooms.get(&msg.room_id) {
+            for member in members.values() {
+                let _ = member.addr.do_send(msg.msg.clone());
+            }
+        }
+    }
+}
+
+/// Closes connections of `client_id`, or of all room members if it is
+/// absent, e.g. on logout or room deletion
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Kick {
+    pub room_id: RoomId,
+    pub client_id: Option<ClientId>,
+}
+
+impl Handler<Kick> for WsHub {
+    type Result = ();
+
+    fn handle(&mut self, msg: Kick, _ctx: &mut Self::Context) {
+        let members = match self.rooms.get_mut(&msg.room_id) {
+            None => return,
+            Some(m) => m,
+        };
+
+        members.retain(|_, member| {
+            let kick = msg.client_id.is_none_or(|c| c == member.client_id);
+            if kick {
+                let _ = member.addr.do_send(WsServerMessage::SessionClosed);
+            }
+            !kick
+        });
+        if members.is_empty() {
+            self.rooms.remove(&msg.room_id);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix::prelude::*;
+use chrono::Utc;
+
+use crate::adapter::room::rest::ws::{Broadcast, WsHub};
+use crate::adapter::room::rest::WsServerMessage;
+use crate::config;
+use crate::port::auth::service as auth_service;
+use crate::port::auth::service::AuthService;
+use crate::port::room::service as room_service;
+use crate::port::room::service::RoomService;
+
+/// Periodically removes data which outlived its purpose
+pub struct Janitor {
+    cfg: config::Server,
+    auth_service: Arc<dyn AuthService>,
+    room_service: Arc<dyn RoomService>,
+}
+
+impl Janitor {
+    pub fn new(
+        cfg: config::Server,
+        auth_service: Arc<dyn AuthService>,
+        room_service: Arc<dyn RoomService>,
+    ) -> Self {
+        Self {
+            cfg,
+            auth_service,
+            room_service,
+        }
+    }
+
+    fn cleanup(&mut self, ctx: &mut Context<Self>) {
+        let auth_service = Arc::clone(&self.auth_service);
+        let room_service = Arc::clone(&self.room_service);
+        let fut = async move {
+            let now = Utc::now().naive_utc();
+
+            let req = auth_service::DeleteExpiredRequest { now };
+            if let Err(err) = auth_service.delete_expired(req).await {
+                log::error!("failed to delete expired auth data: {}", err);
+            }
+
+            let req = room_service::DeleteExpiredRequest { now };
+            match room_service.delete_expired(req).await {
+                Ok(res) => {
+                    for (room_id, file_id) in res.removed_files {
+                        WsHub::from_registry().do_send(Broadcast {
+                            room_id,
+                            msg: WsServerMessage::FileRemoved { file_id },
+                        });
+                    }
+                }
+                Err(err) => log::error!("failed to delete expired room data: {}", err),
+            }
+        };
+        ctx.spawn(fut.into_actor(self));
+    }
+}
+
+impl Actor for Janitor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let interval = Duration::from_secs(self.cfg.cleanup_interval);
+        ctx.run_interval(interval, Self::cleanup);
+    }
+}
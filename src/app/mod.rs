@@ -1 +1,2 @@
+pub mod janitor;
 pub mod rest;
@@ -8,6 +8,10 @@ use crate::adapter::health_check::rest as health_check_rest;
 use crate::adapter::room::rest as room_rest;
 
 use crate::config;
+use crate::infra::client_ip::ClientIpResolver;
+use crate::infra::cookie::CookiePolicy;
+use crate::infra::dpop::DpopVerifier;
+use crate::infra::jwt::JwtKeyring;
 use crate::infra::state::State;
 
 use crate::port::auth::service::AuthService;
@@ -19,6 +23,10 @@ pub struct Options {
     pub example_service: Arc<dyn ExampleService>,
     pub auth_service: Arc<dyn AuthService>,
     pub room_service: Arc<dyn RoomService>,
+    pub jwt_keyring: Arc<JwtKeyring>,
+    pub dpop_verifier: Arc<DpopVerifier>,
+    pub cookie_policy: Arc<CookiePolicy>,
+    pub client_ip_resolver: Arc<ClientIpResolver>,
 }
 
 pub async fn run(opts: Options) -> std::io::Result<()> {
@@ -28,6 +36,10 @@ pub async fn run(opts: Options) -> std::io::Result<()> {
         example_service: opts.example_service,
         auth_service: opts.auth_service,
         room_service: opts.room_service,
+        jwt_keyring: opts.jwt_keyring,
+        dpop_verifier: opts.dpop_verifier,
+        cookie_policy: opts.cookie_policy,
+        client_ip_resolver: opts.client_ip_resolver,
     };
 
     HttpServer::new(move || {
@@ -38,9 +50,13 @@ pub async fn run(opts: Options) -> std::io::Result<()> {
             .wrap(
                 auth_rest::middleware::JwtAuth::default()
                     .exclude_regex("v[0-9]+/auth/login")
+                    // Refresh checks the session itself, see `UncheckedJwt`
+                    .exclude_regex("v[0-9]+/auth/refresh-tokens")
                     .exclude_regex("v[0-9]+/example")
                     .exclude_regex("v[0-9]+/health-check")
-                    .exclude_regex(("v[0-9]+/rooms", http::Method::POST)),
+                    .exclude_regex(("v[0-9]+/rooms$", http::Method::POST))
+                    .exclude_regex(("v[0-9]+/rooms/[^/]+/ws$", http::Method::GET))
+                    .exclude_regex("^/.well-known/"),
             )
             .data(state.clone())
             // limit size of the payload (global configuration)
@@ -52,6 +68,7 @@ pub async fn run(opts: Options) -> std::io::Result<()> {
                     .configure(example_rest::service_config)
                     .configure(room_rest::service_config),
             )
+            .configure(auth_rest::well_known_config)
     })
     .bind(opts.cfg.addr)?
     .run()
@@ -1,5 +1,5 @@
 use config as config_lib;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct Config {
@@ -7,6 +7,10 @@ pub struct Config {
     pub auth: Auth,
     pub room: Room,
     pub ws: Ws,
+    #[serde(default)]
+    pub encryption: Encryption,
+    #[serde(default)]
+    pub privacy: Privacy,
     #[serde(default = "default_logger")]
     pub logger: serde_yaml::Value,
 }
@@ -15,6 +19,13 @@ pub struct Config {
 pub struct Server {
     pub addr: SocketAddr,
     pub env: Environment,
+    /// Reverse proxies in front of the server. Client address is taken from
+    /// `Forwarded` or `X-Forwarded-For` only of requests which come from them
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Seconds between removals of expired data, like login attempts
+    #[serde(default = "default_cleanup_interval")]
+    pub cleanup_interval: u64,
 }
 
 #[derive(Debug, Clone, serde::Deserialize)]
@@ -26,10 +37,179 @@ pub enum Environment {
 
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct Auth {
+    /// If disabled, clients are anonymous and identified by cookie only.
+    /// Client owns the room it created and joins others with their
+    /// passwords. For trusted networks
     pub enabled: bool,
+    /// Access token is also set as http only cookie, so requests which
+    /// cannot set headers, like downloads, are authorized. State-changing
+    /// requests with that cookie must carry CSRF token header
+    #[serde(default)]
+    pub access_token_cookie: bool,
+    /// HS256 key of tokens if `keys` are empty
     pub secret: String,
+    /// Signing keys of tokens, exactly one of them is active
+    #[serde(default)]
+    pub keys: Vec<JwtKey>,
+    /// `iss` claim of tokens
+    #[serde(default = "default_jwt_issuer")]
+    pub issuer: String,
+    /// `aud` claim of tokens
+    #[serde(default = "default_jwt_audience")]
+    pub audience: String,
+    /// Seconds, allowed clock skew of time claims
+    #[serde(default = "default_jwt_leeway")]
+    pub leeway: u64,
     pub access_expires: i64,
     pub refresh_expires: i64,
+    /// Seconds, lifetime of single-use WebSocket tickets
+    #[serde(default = "default_ws_ticket_expires")]
+    pub ws_ticket_expires: i64,
+    /// Seconds, for how long checked session is trusted without repo lookup
+    #[serde(default = "default_session_cache_ttl")]
+    pub session_cache_ttl: i64,
+    #[serde(default)]
+    pub login_throttle: LoginThrottle,
+    #[serde(default)]
+    pub invite: Invite,
+    #[serde(default)]
+    pub dpop: Dpop,
+    #[serde(default)]
+    pub cookie: AuthCookie,
+    /// Origins of client app served from another host. Browser requests to
+    /// auth endpoints from other origins than these and the API own are
+    /// rejected
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct JwtKey {
+    pub kid: String,
+    pub alg: JwtAlgorithm,
+    /// PEM private key (PKCS#8, or PKCS#1 for RS256), secret for HS256
+    pub key: String,
+    /// Signs new tokens, inactive keys only verify
+    #[serde(default)]
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum JwtAlgorithm {
+    HS256,
+    RS256,
+    ES256,
+    EdDSA,
+}
+
+/// Brute-force protection of room login. Failed attempts are counted per room
+/// and per client IP, after free attempts every next one is delayed twice
+/// as long as the previous
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct LoginThrottle {
+    pub ip_free_attempts: u32,
+    pub room_free_attempts: u32,
+    /// Seconds
+    pub backoff_base: i64,
+    /// Seconds
+    pub backoff_max: i64,
+    /// Seconds without failures after which counter is reset
+    pub reset_after: i64,
+    /// Seconds, device which has not logged in to the room for that long
+    /// is subject to room lock again
+    #[serde(default = "default_known_device_expires")]
+    pub known_device_expires: i64,
+}
+
+impl Default for LoginThrottle {
+    fn default() -> Self {
+        Self {
+            ip_free_attempts: 5,
+            room_free_attempts: 20,
+            backoff_base: 1,
+            backoff_max: 3600,
+            reset_after: 3600,
+            known_device_expires: default_known_device_expires(),
+        }
+    }
+}
+
+/// Signed invite tokens created by room owner
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Invite {
+    /// Seconds, used if request does not set it
+    pub expires: i64,
+    /// Seconds
+    pub max_expires: i64,
+    /// Used if request does not set it
+    pub max_uses: u32,
+}
+
+impl Default for Invite {
+    fn default() -> Self {
+        Self {
+            expires: 86400,
+            max_expires: 604800,
+            max_uses: 1,
+        }
+    }
+}
+
+/// Proof-of-possession of client key (RFC 9449). Tokens of clients which
+/// logged in with a proof are bound to their key
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Dpop {
+    /// Seconds, older proofs are rejected
+    pub max_age: i64,
+    /// Proofs of one key accepted within `max_age`
+    #[serde(default = "default_dpop_max_proofs_per_key")]
+    pub max_proofs_per_key: usize,
+    /// Proofs without access token, i.e. of logins, accepted within
+    /// `max_age` from all keys. Proofs bound to tokens have separate room
+    #[serde(default = "default_dpop_max_login_proofs")]
+    pub max_login_proofs: usize,
+}
+
+impl Default for Dpop {
+    fn default() -> Self {
+        Self {
+            max_age: 60,
+            max_proofs_per_key: default_dpop_max_proofs_per_key(),
+            max_login_proofs: default_dpop_max_login_proofs(),
+        }
+    }
+}
+
+/// Attributes of auth cookies. Unset ones are strict in prod: `Secure` and
+/// `SameSite=Strict`, in dev cookies are not secure and `SameSite=Lax`
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct AuthCookie {
+    pub secure: Option<bool>,
+    /// `none` requires `secure`
+    pub same_site: Option<CookieSameSite>,
+    pub domain: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CookieSameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// How new room ids are picked, numeric ids are within
+/// `start_id..start_id + max_rooms`. Ids of deleted rooms are never
+/// picked again
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoomIdStrategy {
+    #[default]
+    Sequential,
+    Random,
+    /// Random word codes, e.g. `brave-otter-apple-42`
+    Words,
 }
 
 #[derive(Debug, Clone, serde::Deserialize)]
@@ -37,9 +217,77 @@ pub struct Room {
     pub idle_time: i64,
     pub start_id: u64,
     pub max_rooms: usize,
+    #[serde(default)]
+    pub id_strategy: RoomIdStrategy,
+    /// Seconds, id of deleted room is not given out again meanwhile. Must
+    /// be longer than `auth.refresh_expires`, tokens of old members name it
+    #[serde(default = "default_tombstone_expires")]
+    pub tombstone_expires: i64,
+    /// Page of the client app which joins a room by invite link
+    #[serde(default = "default_join_url")]
+    pub join_url: String,
+    /// Seconds, lifetime of one-off password of a QR code
+    #[serde(default = "default_qr_password_expires")]
+    pub qr_password_expires: i64,
+    /// Unexpired one-off passwords of QR codes a room may have at once
+    #[serde(default = "default_max_active_invites")]
+    pub max_active_invites: usize,
+    #[serde(default = "default_max_file_versions")]
+    pub max_file_versions: usize,
+    /// Bytes, larger uploaded content is rejected
+    #[serde(default = "default_max_file_size")]
+    pub max_file_size: usize,
+    /// Limits of archives unpacked into the room
+    #[serde(default)]
+    pub archive: Archive,
+    /// Compression of stored file content
+    #[serde(default)]
+    pub compression: Compression,
     pub password: Password,
 }
 
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct Archive {
+    /// Bytes, larger uploaded archive is rejected
+    pub max_size: usize,
+    /// Bytes, sum of unpacked files. Every file is also limited by
+    /// `room.max_file_size`
+    pub max_total_size: u64,
+    /// Unpacked bytes per byte of the archive, more is a zip bomb
+    pub max_ratio: u64,
+    pub max_entries: usize,
+}
+
+impl Default for Archive {
+    fn default() -> Self {
+        Self {
+            max_size: 268_435_456,
+            max_total_size: 1_073_741_824,
+            max_ratio: 100,
+            max_entries: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct Compression {
+    /// Content is stored compressed with zstd if that saves space
+    pub enabled: bool,
+    /// Level of zstd, from 1 to 22
+    pub level: i32,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            level: 3,
+        }
+    }
+}
+
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct Password {
     pub expires: i64,
@@ -51,6 +299,61 @@ pub struct Password {
     pub use_spaces: bool,
     pub use_exclude_similar_characters: bool,
     pub strict: bool,
+    #[serde(default)]
+    pub mode: PasswordMode,
+    #[serde(default)]
+    pub passphrase: Passphrase,
+    #[serde(default)]
+    pub join_code: JoinCode,
+}
+
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PasswordMode {
+    /// Random characters, see `length` and `use_*` options
+    #[default]
+    Chars,
+    /// Random words of embedded BIP-39 English list, 11 bits each
+    Passphrase,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Passphrase {
+    pub words: usize,
+    /// Must be non-empty and have no letters or digits, logins split
+    /// passphrases into words by them
+    #[serde(deserialize_with = "deserialize_passphrase_separator")]
+    pub separator: String,
+    /// Capitalize first letter of every word
+    pub capitalize: bool,
+}
+
+impl Default for Passphrase {
+    fn default() -> Self {
+        // 3 words is 33 bits, a bit more than 6 lowercase alphanumeric chars
+        Self {
+            words: 3,
+            separator: "-".to_owned(),
+            capitalize: false,
+        }
+    }
+}
+
+/// Rolling join code of a room, rotates every `expires` seconds
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct JoinCode {
+    pub digits: u32,
+    /// Seconds after rotation while previous code is still accepted
+    pub grace: i64,
+}
+
+impl Default for JoinCode {
+    fn default() -> Self {
+        Self {
+            digits: 6,
+            grace: 15,
+        }
+    }
 }
 
 #[derive(Debug, Clone, serde::Deserialize)]
@@ -58,6 +361,99 @@ pub struct Ws {
     pub max_connections: usize,
 }
 
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct Encryption {
+    pub enabled: bool,
+    /// Base64 encoded 32 bytes key
+    pub master_key: Option<String>,
+    /// Path to file with base64 encoded 32 bytes key
+    pub master_key_file: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct Privacy {
+    /// Remove EXIF (GPS included), XMP, IPTC and comments from uploaded
+    /// JPEG, PNG, WebP and HEIC images. Content of e2ee rooms is opaque and
+    /// never stripped
+    #[serde(default)]
+    pub strip_image_metadata: bool,
+}
+
+fn default_cleanup_interval() -> u64 {
+    300
+}
+
+fn default_known_device_expires() -> i64 {
+    2592000
+}
+
+fn default_dpop_max_proofs_per_key() -> usize {
+    600
+}
+
+fn default_dpop_max_login_proofs() -> usize {
+    10_000
+}
+
+fn default_tombstone_expires() -> i64 {
+    604800
+}
+
+fn default_jwt_issuer() -> String {
+    "ezspot".to_owned()
+}
+
+fn default_jwt_audience() -> String {
+    "ezspot".to_owned()
+}
+
+fn default_jwt_leeway() -> u64 {
+    30
+}
+
+fn default_session_cache_ttl() -> i64 {
+    5
+}
+
+fn default_ws_ticket_expires() -> i64 {
+    30
+}
+
+fn default_join_url() -> String {
+    "http://127.0.0.1:8001/join".to_owned()
+}
+
+fn default_qr_password_expires() -> i64 {
+    600
+}
+
+fn default_max_active_invites() -> usize {
+    20
+}
+
+fn default_max_file_versions() -> usize {
+    10
+}
+
+fn default_max_file_size() -> usize {
+    67_108_864
+}
+
+fn deserialize_passphrase_separator<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let separator = <String as serde::Deserialize>::deserialize(deserializer)?;
+    if separator.is_empty() || separator.chars().any(char::is_alphanumeric) {
+        return Err(serde::de::Error::custom(format!(
+            "passphrase separator '{}' must be non-empty and have no letters or digits",
+            separator
+        )));
+    }
+
+    Ok(separator)
+}
+
 fn default_logger() -> serde_yaml::Value {
     const DEFAULT_LOG4RS_SETTINGS: &str = r##"
     appenders:
@@ -113,16 +509,58 @@ impl Default for Config {
         server:
           addr: "127.0.0.1:8001"
           env: "dev" # "dev" | "prod"
+          trusted_proxies: []
+          cleanup_interval: 300 # 5 min
         auth:
           enabled: true
+          access_token_cookie: false
           secret: "secret"
-          ws_ticket_expires: 300 # 5 min
+          issuer: "ezspot"
+          audience: "ezspot"
+          leeway: 30
+          ws_ticket_expires: 30
           access_expires: 900 # 15 min
           refresh_expires: 86400 # 1 day
+          session_cache_ttl: 5
+          login_throttle:
+            ip_free_attempts: 5
+            room_free_attempts: 20
+            backoff_base: 1 # doubled on every next failure
+            backoff_max: 3600 # 1 hour
+            reset_after: 3600 # 1 hour
+            known_device_expires: 2592000 # 30 days
+          invite:
+            expires: 86400 # 1 day
+            max_expires: 604800 # 1 week
+            max_uses: 1
+          dpop:
+            max_age: 60 # 1 min
+            max_proofs_per_key: 600
+            max_login_proofs: 10000
+          cookie:
+            secure: ~ # true in prod
+            same_site: ~ # "strict" | "lax" | "none", strict in prod
+            domain: ~
+          allowed_origins: []
         room:
           idle_time: 1800 # 30 min
           start_id: 100000
           max_rooms: 1000000 # 100'000 - 1'100'000
+          id_strategy: "sequential" # "sequential" | "random" | "words"
+          tombstone_expires: 604800 # 1 week, ids of deleted rooms are not reused meanwhile
+          join_url: "http://127.0.0.1:8001/join"
+          qr_password_expires: 600 # 10 min
+          max_active_invites: 20
+          max_file_versions: 10
+          max_file_size: 67108864 # 64 MiB
+          archive:
+            max_size: 268435456 # 256 MiB
+            max_total_size: 1073741824 # 1 GiB
+            max_ratio: 100
+            max_entries: 10000
+          compression:
+            enabled: true
+            level: 3
           password:
             expires: 60 # 1 min
             length: 6 # example: 0xy12z
@@ -133,8 +571,22 @@ impl Default for Config {
             use_spaces: false
             use_exclude_similar_characters: false
             strict: true
+            mode: "chars" # "chars" | "passphrase"
+            passphrase:
+              words: 3 # example: brave-otter-apple
+              separator: "-"
+              capitalize: false
+            join_code:
+              digits: 6
+              grace: 15 # previous code is accepted 15 sec after rotation
         ws:
           max_connections: 65000
+        encryption:
+          enabled: false
+          master_key: ~ # base64, 32 bytes
+          master_key_file: ~
+        privacy:
+          strip_image_metadata: false
         logger:
           appenders:
             stdout:
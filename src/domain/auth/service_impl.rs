@@ -1,21 +1,193 @@
 use crate::config;
 use crate::domain::local_prelude::*;
+use crate::domain::room::password;
 use crate::port::auth::repo as auth_repo;
 use crate::port::auth::repo::AuthRepo;
 use crate::port::auth::service::*;
-use crate::port::{ServiceError, ServiceResult};
+use crate::port::room::service::RoomId;
+use crate::port::{RepoError, ServiceError, ServiceResult};
 
 use chrono::{Duration, NaiveDateTime, Utc};
+use std::collections::HashMap;
 use uuid::Uuid;
 
+/// Sessions cache is cleaned up once it grows over that
+const SESSION_CACHE_CAPACITY: usize = 10_000;
+/// Expired tickets are cleaned up once there are more of them
+const WS_TICKETS_CAPACITY: usize = 10_000;
+
 pub struct AuthServiceImpl<R: AuthRepo> {
     cfg: config::Auth,
     repo: Arc<R>,
+    sessions: Mutex<HashMap<ClientId, CachedSession>>,
+    ws_tickets: Mutex<HashMap<WsTicket, IssuedWsTicket>>,
+}
+
+struct CachedSession {
+    refresh_token_salt: RefreshTokenSalt,
+    checked_at: NaiveDateTime,
+}
+
+struct IssuedWsTicket {
+    jwt: Jwt,
+    exp: NaiveDateTime,
 }
 
 impl<R: AuthRepo> AuthServiceImpl<R> {
     pub fn new(cfg: config::Auth, repo: Arc<R>) -> Self {
-        Self { cfg, repo }
+        Self {
+            cfg,
+            repo,
+            sessions: Default::default(),
+            ws_tickets: Default::default(),
+        }
+    }
+
+    /// Checks that client still exists and token belongs to its current
+    /// session. Result is cached for `session_cache_ttl`, so revoked
+    /// session may still work for that long on other instances only.
+    ///
+    /// Outdated refresh token only fails the request: concurrent requests
+    /// of the same client may still carry it while tokens are refreshed.
+    /// Reuse is detected by `refresh_tokens`, which is not authorized here
+    async fn check_session(&self, jwt: &Jwt) -> ServiceResult<()> {
+        let client_id = jwt.access_token.client_id;
+        let now = Utc::now().naive_utc();
+        let ttl = Duration::seconds(self.cfg.session_cache_ttl);
+
+        // Only match is trusted, mismatch is confirmed by repo
+        let cached_salt = self
+            .sessions
+            .lock()
+            .await
+            .get(&client_id)
+            .filter(|s| now - s.checked_at < ttl)
+            .map(|s| s.refresh_token_salt);
+        if cached_salt == Some(jwt.refresh_token.salt) {
+            return Ok(());
+        }
+
+        let get_client_req = auth_repo::GetClientRequest { client_id };
+        let get_client_res = self
+            .repo
+            .get_client(get_client_req)
+            .await
+            .map_err(|_| ServiceError::AuthError(anyhow::anyhow!("session not found")))?;
+        let refresh_token_salt = get_client_res.client.refresh_token_salt;
+
+        if refresh_token_salt != jwt.refresh_token.salt {
+            return Err(ServiceError::AuthError(anyhow::anyhow!(
+                "refresh token is outdated"
+            )));
+        }
+
+        let mut sessions = self.sessions.lock().await;
+        if sessions.len() >= SESSION_CACHE_CAPACITY {
+            sessions.retain(|_, s| now - s.checked_at < ttl);
+        }
+        sessions.insert(
+            client_id,
+            CachedSession {
+                refresh_token_salt,
+                checked_at: now,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Deletes client, so all its access and refresh tokens stop working
+    async fn revoke_session(&self, client_id: ClientId) {
+        let delete_client_req = auth_repo::DeleteClientRequest { client_id };
+        // Client may be already deleted by concurrent request
+        if let Err(err) = self.repo.delete_client(delete_client_req).await {
+            log::debug!("revoke session error: {:?}", err);
+        }
+        self.forget_session(client_id).await;
+    }
+
+    async fn forget_session(&self, client_id: ClientId) {
+        self.sessions.lock().await.remove(&client_id);
+    }
+
+    /// Returns seconds left until next attempt is allowed, 0 if not locked
+    fn lockout_remaining(
+        &self,
+        key: auth_repo::LoginAttemptsKey,
+        attempts: &auth_repo::LoginAttempts,
+    ) -> i64 {
+        let throttle = &self.cfg.login_throttle;
+        let free_attempts = match key {
+            auth_repo::LoginAttemptsKey::Room(_) => throttle.room_free_attempts,
+            auth_repo::LoginAttemptsKey::Ip(_) => throttle.ip_free_attempts,
+        };
+
+        if attempts.failures < free_attempts {
+            return 0;
+        }
+
+        // Counter is already reset
+        let now = Utc::now().naive_utc();
+        if now - attempts.last_failure_at >= Duration::seconds(throttle.reset_after) {
+            return 0;
+        }
+
+        let backoff = 2i64
+            .checked_pow(attempts.failures - free_attempts)
+            .and_then(|m| m.checked_mul(throttle.backoff_base))
+            .map_or(throttle.backoff_max, |b| b.min(throttle.backoff_max));
+        let locked_until = attempts.last_failure_at + Duration::seconds(backoff);
+
+        // Round up, client retrying exactly after `Retry-After` must not be rejected
+        let remaining_ms = (locked_until - now).num_milliseconds();
+        (remaining_ms + 999).div_euclid(1000).max(0)
+    }
+
+    /// Creates new client and its access and refresh tokens. Anonymous
+    /// client keeps its id, its previous session is replaced
+    async fn create_session(
+        &self,
+        anonymous_client_id: Option<ClientId>,
+        room_id: RoomId,
+        owner: bool,
+        fingerprint: String,
+        dpop_jkt: Option<String>,
+    ) -> ServiceResult<Jwt> {
+        // Anonymous clients exist only if auth is disabled
+        let client_id = anonymous_client_id
+            .filter(|_| !self.cfg.enabled)
+            .unwrap_or_else(Uuid::new_v4);
+
+        // Create new client
+        let create_client_req = auth_repo::CreateClientRequest {
+            client_id,
+            room_id,
+            owner,
+            refresh_token_salt: Uuid::new_v4(),
+            refresh_token_exp: expires_timestamp(self.cfg.refresh_expires),
+            fingerprint,
+        };
+        let create_client_res = self.repo.create_client(create_client_req).await?;
+
+        // Create access token
+        let access_token = AccessTokenDecoded::new(
+            expires_timestamp(self.cfg.access_expires),
+            create_client_res.client.id,
+            room_id,
+            owner,
+            dpop_jkt,
+        );
+
+        // Create refresh token
+        let refresh_token = RefreshTokenDecoded::new(
+            create_client_res.client.refresh_token_exp,
+            create_client_res.client.refresh_token_salt,
+        );
+
+        Ok(Jwt {
+            access_token,
+            refresh_token,
+        })
     }
 }
 
@@ -25,8 +197,68 @@ impl<R: AuthRepo> AuthService for AuthServiceImpl<R> {
         self.cfg.enabled
     }
 
-    fn secret(&self) -> &str {
-        &self.cfg.secret
+    fn access_token_cookie(&self) -> bool {
+        self.cfg.access_token_cookie
+    }
+
+    async fn authorize_anonymous(
+        &self,
+        req: AuthorizeAnonymousRequest,
+    ) -> ServiceResult<AuthorizeAnonymousResponse> {
+        if self.cfg.enabled {
+            return Err(ServiceError::AuthError(anyhow::anyhow!(
+                "anonymous access is disabled"
+            )));
+        }
+
+        // Client gets its room by creating or joining it
+        let get_client_req = auth_repo::GetClientRequest {
+            client_id: req.client_id,
+        };
+        let client = self
+            .repo
+            .get_client(get_client_req)
+            .await
+            .map_err(|_| ServiceError::AuthError(anyhow::anyhow!("client has no room")))?
+            .client;
+
+        // Tokens are never encoded and exist only during one request
+        let access_token = AccessTokenDecoded::new(
+            expires_timestamp(self.cfg.access_expires),
+            client.id,
+            client.room_id,
+            client.owner,
+            None,
+        );
+        let refresh_token = RefreshTokenDecoded::new(
+            expires_timestamp(self.cfg.refresh_expires),
+            client.refresh_token_salt,
+        );
+
+        let res = AuthorizeAnonymousResponse {
+            jwt: Jwt {
+                access_token,
+                refresh_token,
+            },
+        };
+
+        Ok(res)
+    }
+
+    async fn bind_anonymous(
+        &self,
+        req: BindAnonymousRequest,
+    ) -> ServiceResult<BindAnonymousResponse> {
+        if self.cfg.enabled {
+            return Err(ServiceError::AuthError(anyhow::anyhow!(
+                "anonymous access is disabled"
+            )));
+        }
+
+        self.create_session(Some(req.client_id), req.room_id, true, String::new(), None)
+            .await?;
+
+        Ok(())
     }
 
     async fn authorize(&self, req: AuthorizeRequest) -> ServiceResult<AuthorizeResponse> {
@@ -44,60 +276,323 @@ impl<R: AuthRepo> AuthService for AuthServiceImpl<R> {
             )));
         }
 
+        // If session is logged out or replaced
+        self.check_session(&req.jwt).await?;
+
         let res = AuthorizeResponse { jwt: req.jwt };
 
         Ok(res)
     }
 
     async fn login(&self, req: LoginRequest) -> ServiceResult<LoginResponse> {
+        let room_key = auth_repo::LoginAttemptsKey::Room(req.room_id);
+        let ip_key = req.client_ip.map(auth_repo::LoginAttemptsKey::Ip);
+
+        // Reject attempts while room or client is locked. Devices which
+        // logged in to the room before pass its lock, so members still get
+        // in while somebody guesses the password
+        let mut retry_after = 0;
+        if let Some(key) = ip_key {
+            let get_attempts_req = auth_repo::GetLoginAttemptsRequest { key };
+            let get_attempts_res = self.repo.get_login_attempts(get_attempts_req).await?;
+
+            if let Some(attempts) = get_attempts_res.attempts {
+                retry_after = self.lockout_remaining(key, &attempts);
+            }
+        }
+
+        let known_device = match req.device_id {
+            Some(device_id) => {
+                let is_known_req = auth_repo::IsKnownDeviceRequest {
+                    room_id: req.room_id,
+                    device_id,
+                    seen_after: Utc::now().naive_utc()
+                        - Duration::seconds(self.cfg.login_throttle.known_device_expires),
+                };
+                self.repo.is_known_device(is_known_req).await?
+            }
+            None => false,
+        };
+
+        if !known_device {
+            let get_attempts_req = auth_repo::GetLoginAttemptsRequest { key: room_key };
+            let get_attempts_res = self.repo.get_login_attempts(get_attempts_req).await?;
+
+            if let Some(attempts) = get_attempts_res.attempts {
+                retry_after = retry_after.max(self.lockout_remaining(room_key, &attempts));
+            }
+        }
+
+        if retry_after > 0 {
+            return Err(ServiceError::TooManyAttempts {
+                retry_after,
+                room_locked: false,
+            });
+        }
+
         // Check room password
         let get_room_cred_req = auth_repo::GetRoomCredentialsRequest {
             room_id: req.room_id,
         };
-        let get_room_cred_res = self.repo.get_room_credentials(get_room_cred_req).await?;
-
-        match get_room_cred_res
-            .room_cred
-            .passwords
-            .contains_key(&req.room_password)
-        {
-            true => { /* do nothing */ }
-            false => {
-                return Err(ServiceError::CommonError(anyhow::anyhow!(
-                    "invalid credentials"
+        let room_cred = match self.repo.get_room_credentials(get_room_cred_req).await {
+            Ok(res) => Some(res.room_cred),
+            // Unknown room fails like wrong password, so room ids can not be probed
+            Err(RepoError::NotFound(_)) => None,
+            Err(err) => return Err(err.into()),
+        };
+        let passwords = room_cred.as_ref().map_or(&[][..], |c| &c.passwords[..]);
+
+        // Rolling join codes are derived from their secrets, so check them apart
+        let now = Utc::now().timestamp();
+        let mut join_code_matched = false;
+        for p in passwords.iter() {
+            if let auth_repo::RoomPasswordFeature::Rolling {
+                secret,
+                step,
+                grace,
+                digits,
+            } = &p.feature
+            {
+                join_code_matched |= password::verify_join_code(
+                    secret,
+                    *step,
+                    *grace,
+                    *digits,
+                    now,
+                    &req.room_password,
+                );
+            }
+        }
+
+        let lookup_key = room_cred.as_ref().map_or(&[][..], |c| &c.lookup_key[..]);
+        let lookup_tags = [
+            password::lookup_tag(lookup_key, &req.room_password, false),
+            password::lookup_tag(lookup_key, &req.room_password, true),
+        ];
+        // Expired passwords stay in the room until the next one is added
+        let expired_before = Utc::now().naive_utc();
+        let candidates = passwords
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| {
+                !matches!(p.feature, auth_repo::RoomPasswordFeature::Rolling { .. })
+                    && p.feature.expires_in().is_none_or(|e| e > expired_before)
+                    && p.lookup_tag == lookup_tags[p.passphrase as usize]
+            })
+            .map(|(idx, p)| (idx, p.hash.as_str(), p.passphrase));
+
+        let matched = password::verify(candidates, &req.room_password).map(|idx| &passwords[idx]);
+
+        if matched.is_none() && !join_code_matched {
+            let now = Utc::now().naive_utc();
+            let mut retry_after = 0;
+            let mut room_locked = false;
+
+            // Unknown rooms are not counted, there is nothing to lock
+            let room_key = room_cred.as_ref().map(|_| room_key);
+            for key in room_key.into_iter().chain(ip_key) {
+                let add_failure_req = auth_repo::AddLoginFailureRequest {
+                    key,
+                    failed_at: now,
+                    reset_before: now - Duration::seconds(self.cfg.login_throttle.reset_after),
+                };
+                let add_failure_res = self.repo.add_login_failure(add_failure_req).await?;
+
+                let remaining = self.lockout_remaining(key, &add_failure_res.attempts);
+                if remaining > 0 {
+                    retry_after = retry_after.max(remaining);
+
+                    // Notify room only once, when its lockout starts
+                    if let auth_repo::LoginAttemptsKey::Room(_) = key {
+                        room_locked = add_failure_res.attempts.failures
+                            == self.cfg.login_throttle.room_free_attempts;
+                    }
+                }
+            }
+
+            if retry_after > 0 {
+                return Err(ServiceError::TooManyAttempts {
+                    retry_after,
+                    room_locked,
+                });
+            }
+
+            return Err(ServiceError::AuthError(anyhow::anyhow!(
+                "invalid credentials"
+            )));
+        }
+
+        // One-off password is removed atomically, only one of concurrent logins wins
+        let mut owner = false;
+        if let Some(matched) = matched {
+            match matched.feature {
+                auth_repo::RoomPasswordFeature::Master => owner = true,
+                auth_repo::RoomPasswordFeature::OneOff
+                | auth_repo::RoomPasswordFeature::Invite { .. } => {
+                    let delete_password_req = auth_repo::DeleteRoomPasswordRequest {
+                        room_id: req.room_id,
+                        hash: matched.hash.clone(),
+                    };
+                    if !self.repo.delete_room_password(delete_password_req).await? {
+                        return Err(ServiceError::AuthError(anyhow::anyhow!(
+                            "invalid credentials"
+                        )));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Successful login resets backoff of the client, but not of the room
+        if let Some(key) = ip_key {
+            let delete_attempts_req = auth_repo::DeleteLoginAttemptsRequest { key };
+            self.repo.delete_login_attempts(delete_attempts_req).await?;
+        }
+
+        let device_id = req.device_id.unwrap_or_else(Uuid::new_v4);
+        let add_known_req = auth_repo::AddKnownDeviceRequest {
+            room_id: req.room_id,
+            device_id,
+            seen_at: Utc::now().naive_utc(),
+        };
+        self.repo.add_known_device(add_known_req).await?;
+
+        let jwt = self
+            .create_session(
+                req.anonymous_client_id,
+                req.room_id,
+                owner,
+                req.fingerprint,
+                req.dpop_jkt,
+            )
+            .await?;
+
+        let res = LoginResponse { jwt, device_id };
+
+        Ok(res)
+    }
+
+    async fn login_with_invite(
+        &self,
+        req: LoginWithInviteRequest,
+    ) -> ServiceResult<LoginWithInviteResponse> {
+        let invite = req.invite;
+
+        // If invite expires
+        if Utc::now().naive_utc() >= invite.exp {
+            return Err(ServiceError::AuthError(anyhow::anyhow!("invite expires")));
+        }
+
+        // Room may be already deleted
+        let get_room_cred_req = auth_repo::GetRoomCredentialsRequest {
+            room_id: invite.room_id,
+        };
+        match self.repo.get_room_credentials(get_room_cred_req).await {
+            Ok(_) => {}
+            Err(RepoError::NotFound(_)) => {
+                return Err(ServiceError::AuthError(anyhow::anyhow!(
+                    "room of invite is deleted"
                 )))
             }
+            Err(err) => return Err(err.into()),
         }
 
-        // Create new client
-        let create_client_req = auth_repo::CreateClientRequest {
-            client_id: Uuid::new_v4(),
-            refresh_token_salt: Uuid::new_v4(),
-            refresh_token_exp: expires_timestamp(self.cfg.refresh_expires),
-            fingerprint: req.fingerprint,
+        let use_invite_req = auth_repo::UseInviteRequest {
+            invite_id: invite.id,
+            max_uses: invite.max_uses,
+            expires_at: invite.exp,
         };
-        let create_client_res = self.repo.create_client(create_client_req).await?;
+        if !self.repo.use_invite(use_invite_req).await? {
+            return Err(ServiceError::AuthError(anyhow::anyhow!(
+                "invite is used up"
+            )));
+        }
 
-        // Create access token
-        let access_token = AccessTokenDecoded::new(
-            expires_timestamp(self.cfg.access_expires),
-            create_client_res.client.id,
-            req.room_id,
-        );
+        let jwt = self
+            .create_session(
+                req.anonymous_client_id,
+                invite.room_id,
+                invite.role == InviteRole::Owner,
+                req.fingerprint,
+                req.dpop_jkt,
+            )
+            .await?;
 
-        // Create refresh token
-        let refresh_token = RefreshTokenDecoded::new(
-            create_client_res.client.refresh_token_exp,
-            create_client_res.client.refresh_token_salt,
-        );
+        let res = LoginWithInviteResponse { jwt };
 
-        let res = LoginResponse {
-            jwt: Jwt {
-                access_token,
-                refresh_token,
-            },
+        Ok(res)
+    }
+
+    async fn create_invite(&self, req: CreateInviteRequest) -> ServiceResult<CreateInviteResponse> {
+        let invite_cfg = &self.cfg.invite;
+
+        let expires_in = req.expires_in.unwrap_or(invite_cfg.expires);
+        if expires_in <= 0 || expires_in > invite_cfg.max_expires {
+            return Err(ServiceError::ValidationError(anyhow::anyhow!(
+                "invite expiration must be within 1..={} seconds",
+                invite_cfg.max_expires
+            )));
+        }
+
+        let max_uses = req.max_uses.unwrap_or(invite_cfg.max_uses);
+        if max_uses == 0 {
+            return Err(ServiceError::ValidationError(anyhow::anyhow!(
+                "invite must allow at least one use"
+            )));
+        }
+
+        let invite = InviteDecoded {
+            id: Uuid::new_v4(),
+            exp: expires_timestamp(expires_in),
+            room_id: req.room_id,
+            role: req.role,
+            max_uses,
         };
 
+        let res = CreateInviteResponse { invite };
+
+        Ok(res)
+    }
+
+    async fn create_ws_ticket(
+        &self,
+        req: CreateWsTicketRequest,
+    ) -> ServiceResult<CreateWsTicketResponse> {
+        let ticket = Uuid::new_v4();
+        let exp = expires_timestamp(self.cfg.ws_ticket_expires);
+
+        let mut ws_tickets = self.ws_tickets.lock().await;
+        if ws_tickets.len() >= WS_TICKETS_CAPACITY {
+            let now = Utc::now().naive_utc();
+            ws_tickets.retain(|_, t| now < t.exp);
+        }
+        ws_tickets.insert(ticket, IssuedWsTicket { jwt: req.jwt, exp });
+
+        let res = CreateWsTicketResponse { ticket, exp };
+
+        Ok(res)
+    }
+
+    async fn redeem_ws_ticket(
+        &self,
+        req: RedeemWsTicketRequest,
+    ) -> ServiceResult<RedeemWsTicketResponse> {
+        let issued = self
+            .ws_tickets
+            .lock()
+            .await
+            .remove(&req.ticket)
+            .filter(|t| Utc::now().naive_utc() < t.exp)
+            .ok_or_else(|| ServiceError::AuthError(anyhow::anyhow!("invalid ws ticket")))?;
+
+        // Session may be logged out since the ticket is issued. Anonymous
+        // clients have no sessions
+        if self.cfg.enabled {
+            self.check_session(&issued.jwt).await?;
+        }
+
+        let res = RedeemWsTicketResponse { jwt: issued.jwt };
+
         Ok(res)
     }
 
@@ -107,56 +602,116 @@ impl<R: AuthRepo> AuthService for AuthServiceImpl<R> {
             client_id: req.jwt.access_token.client_id,
         };
         self.repo.delete_client(delete_client_req).await?;
+        self.forget_session(req.jwt.access_token.client_id).await;
 
         Ok(())
     }
 
+    async fn logout_room(&self, req: LogoutRoomRequest) -> ServiceResult<LogoutRoomResponse> {
+        let delete_clients_req = auth_repo::DeleteRoomClientsRequest {
+            room_id: req.room_id,
+        };
+        let delete_clients_res = self.repo.delete_room_clients(delete_clients_req).await?;
+
+        for client_id in delete_clients_res.client_ids {
+            self.forget_session(client_id).await;
+        }
+
+        Ok(())
+    }
+
+    async fn has_session(&self, req: HasSessionRequest) -> ServiceResult<HasSessionResponse> {
+        let get_client_req = auth_repo::GetClientRequest {
+            client_id: req.client_id,
+        };
+        match self.repo.get_client(get_client_req).await {
+            Ok(res) => Ok(res.client.room_id == req.room_id),
+            Err(RepoError::NotFound(_)) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
     async fn refresh_tokens(
         &self,
         req: RefreshTokensRequest,
     ) -> ServiceResult<RefreshTokensResponse> {
-        // Remove client
-        let delete_client_req = auth_repo::DeleteClientRequest {
-            client_id: req.jwt.access_token.client_id,
-        };
-        let delete_client_res = self.repo.delete_client(delete_client_req).await?;
+        let client_id = req.jwt.access_token.client_id;
 
         // If refresh token expires
         if Utc::now().naive_utc() >= req.jwt.refresh_token.exp {
+            self.revoke_session(client_id).await;
             return Err(ServiceError::AuthError(anyhow::anyhow!(
                 "refresh token expires"
             )));
         }
 
+        // Revoked session fails like an outdated one
+        let get_client_req = auth_repo::GetClientRequest { client_id };
+        let get_client_res = self
+            .repo
+            .get_client(get_client_req)
+            .await
+            .map_err(|_| ServiceError::AuthError(anyhow::anyhow!("session not found")))?;
+
+        // Every refresh token is used only once, so outdated one is stolen
+        // or replayed. Legit client can not tell it from the attacker, so
+        // the whole session is revoked
+        if get_client_res.client.refresh_token_salt != req.jwt.refresh_token.salt {
+            log::warn!(
+                "refresh token reuse detected, session is revoked, client_id={}",
+                client_id
+            );
+            self.revoke_session(client_id).await;
+            return Err(ServiceError::AuthError(anyhow::anyhow!(
+                "refresh token is already used"
+            )));
+        }
+
         // If old fingerprint and new are not equal
-        if delete_client_res.client.fingerprint != req.fingerprint {
+        if get_client_res.client.fingerprint != req.fingerprint {
+            self.revoke_session(client_id).await;
             return Err(ServiceError::AuthError(anyhow::anyhow!(
                 "fingerprints not equal"
             )));
         }
 
-        // Create new client
-        let create_client_req = auth_repo::CreateClientRequest {
-            client_id: delete_client_res.client.id,
-            refresh_token_salt: delete_client_res.client.refresh_token_salt,
-            refresh_token_exp: expires_timestamp(self.cfg.refresh_expires),
-            fingerprint: delete_client_res.client.fingerprint,
+        // Rotate refresh token, every one is used only once
+        let update_client_req = auth_repo::UpdateClientRequest {
+            client_id,
+            expected_refresh_token_salt: Some(req.jwt.refresh_token.salt),
+            refresh_token_salt: Some(Uuid::new_v4()),
+            refresh_token_exp: Some(expires_timestamp(self.cfg.refresh_expires)),
+            fingerprint: None,
         };
+        let update_client_res = self.repo.update_client(update_client_req).await?;
+        self.forget_session(client_id).await;
 
-        // Save new auth session
-        let create_client_res = self.repo.create_client(create_client_req).await?;
+        // Concurrent refresh with the same token has won
+        if !update_client_res.updated {
+            log::warn!(
+                "refresh token reuse detected, session is revoked, client_id={}",
+                client_id
+            );
+            self.revoke_session(client_id).await;
+            return Err(ServiceError::AuthError(anyhow::anyhow!(
+                "refresh token is already used"
+            )));
+        }
 
         // Create access token
         let access_token = AccessTokenDecoded::new(
             expires_timestamp(self.cfg.access_expires),
-            create_client_res.client.id,
+            update_client_res.client.id,
             req.jwt.access_token.room_id,
+            req.jwt.access_token.owner,
+            // Key stays the same for the whole session
+            req.jwt.access_token.dpop_jkt,
         );
 
         // Create refresh token
         let refresh_token = RefreshTokenDecoded::new(
-            create_client_res.client.refresh_token_exp,
-            create_client_res.client.refresh_token_salt,
+            update_client_res.client.refresh_token_exp,
+            update_client_res.client.refresh_token_salt,
         );
 
         let res = RefreshTokensResponse {
@@ -168,6 +723,40 @@ impl<R: AuthRepo> AuthService for AuthServiceImpl<R> {
 
         Ok(res)
     }
+
+    async fn delete_expired(
+        &self,
+        req: DeleteExpiredRequest,
+    ) -> ServiceResult<DeleteExpiredResponse> {
+        let throttle = &self.cfg.login_throttle;
+
+        // Counter is reset after `reset_after`, but lockout may last longer
+        let attempts_ttl = throttle.reset_after.max(throttle.backoff_max);
+        let repo_req = auth_repo::DeleteExpiredLoginAttemptsRequest {
+            last_failure_before: req.now - Duration::seconds(attempts_ttl),
+        };
+        let deleted_attempts = self.repo.delete_expired_login_attempts(repo_req).await?;
+
+        let repo_req = auth_repo::DeleteExpiredKnownDevicesRequest {
+            seen_before: req.now - Duration::seconds(throttle.known_device_expires),
+        };
+        let deleted_devices = self.repo.delete_expired_known_devices(repo_req).await?;
+
+        // Expired invite is rejected before its uses are counted
+        let repo_req = auth_repo::DeleteExpiredInviteUsesRequest {
+            expired_before: req.now,
+        };
+        let deleted_invites = self.repo.delete_expired_invite_uses(repo_req).await?;
+
+        log::debug!(
+            "expired auth data deleted, login_attempts={}, known_devices={}, invite_uses={}",
+            deleted_attempts,
+            deleted_devices,
+            deleted_invites
+        );
+
+        Ok(())
+    }
 }
 
 fn expires_timestamp(sec_duration: i64) -> NaiveDateTime {
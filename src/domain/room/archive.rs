@@ -0,0 +1,963 @@
+use crate::port::room::service::ArchiveLimits;
+use crate::port::{ServiceError, ServiceResult};
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::io::{self, Cursor, Read};
+use std::rc::Rc;
+
+const LOCAL_HEADER_SIG: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR_SIG: u32 = 0x0807_4b50;
+const CENTRAL_HEADER_SIG: u32 = 0x0201_4b50;
+const ZIP64_END_SIG: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIG: u32 = 0x0706_4b50;
+const END_SIG: u32 = 0x0605_4b50;
+/// Sizes are in data descriptor, names are UTF-8
+const FLAGS: u16 = 1 << 3 | 1 << 11;
+const METHOD_STORED: u16 = 0;
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+/// Unix host, so permissions below are used on extraction
+const VERSION_MADE_BY: u16 = 3 << 8 | VERSION_ZIP64;
+/// Regular file, rw-r--r--
+const EXTERNAL_ATTRS: u32 = 0o100_644 << 16;
+const ZIP64_EXTRA_TAG: u16 = 0x0001;
+const TIMESTAMP_EXTRA_TAG: u16 = 0x5455;
+/// Fields of this size and above go to ZIP64 extra field
+const ZIP64_LIMIT: u64 = 0xffff_ffff;
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+/// Archive without entries has only end of central directory
+const ZIP_EMPTY_MAGIC: &[u8] = b"PK\x05\x06";
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+/// File type bits of unix mode
+const S_IFMT: u32 = 0o170_000;
+const S_IFREG: u32 = 0o100_000;
+/// Bytes of tar stream per entry besides content: header, padding and
+/// extension headers with long names
+const TAR_ENTRY_OVERHEAD: u64 = 8192;
+
+/// Writes ZIP archive as it is streamed. Content is stored as is, its CRC
+/// is known only after the content, so it goes to data descriptor. Only
+/// central directory is kept, memory does not depend on content size
+pub struct ZipStreamWriter {
+    offset: u64,
+    entries: Vec<ZipEntry>,
+    names: HashSet<String>,
+    current: Option<CurrentEntry>,
+    zip64_limit: u64,
+}
+
+struct ZipEntry {
+    name: String,
+    modified: NaiveDateTime,
+    /// Sizes of the entry are 8 bytes in data descriptor
+    zip64: bool,
+    crc32: u32,
+    size: u64,
+    offset: u64,
+}
+
+struct CurrentEntry {
+    entry: ZipEntry,
+    hasher: crc32fast::Hasher,
+}
+
+impl Default for ZipStreamWriter {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            entries: Vec::new(),
+            names: HashSet::new(),
+            current: None,
+            zip64_limit: ZIP64_LIMIT,
+        }
+    }
+}
+
+impl ZipStreamWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns local header of the entry. Name is made unique within the
+    /// archive, `size` is checked when the entry is finished
+    pub fn start_entry(
+        &mut self,
+        name: &str,
+        modified: NaiveDateTime,
+        size: u64,
+    ) -> ServiceResult<Vec<u8>> {
+        if self.current.is_some() {
+            return Err(zip_error("previous entry is not finished"));
+        }
+
+        let entry = ZipEntry {
+            name: self.unique_name(name),
+            modified,
+            zip64: size >= self.zip64_limit,
+            crc32: 0,
+            size,
+            offset: self.offset,
+        };
+
+        let (time, date) = dos_date_time(entry.modified);
+        let mut header = Vec::with_capacity(30 + entry.name.len() + 29);
+        put_u32(&mut header, LOCAL_HEADER_SIG);
+        put_u16(&mut header, version_needed(entry.zip64));
+        put_u16(&mut header, FLAGS);
+        put_u16(&mut header, METHOD_STORED);
+        put_u16(&mut header, time);
+        put_u16(&mut header, date);
+        // CRC and sizes are in data descriptor
+        put_u32(&mut header, 0);
+        put_u32(&mut header, if entry.zip64 { u32::MAX } else { 0 });
+        put_u32(&mut header, if entry.zip64 { u32::MAX } else { 0 });
+        put_u16(&mut header, entry.name.len() as u16);
+        put_u16(&mut header, if entry.zip64 { 20 + 9 } else { 9 });
+        header.extend_from_slice(entry.name.as_bytes());
+        if entry.zip64 {
+            put_u16(&mut header, ZIP64_EXTRA_TAG);
+            put_u16(&mut header, 16);
+            put_u64(&mut header, 0);
+            put_u64(&mut header, 0);
+        }
+        put_timestamp_extra(&mut header, entry.modified);
+
+        self.offset += header.len() as u64;
+        self.names.insert(entry.name.clone());
+        self.current = Some(CurrentEntry {
+            entry,
+            hasher: crc32fast::Hasher::new(),
+        });
+
+        Ok(header)
+    }
+
+    /// Counts content of the current entry, the content itself is sent as is
+    pub fn write(&mut self, chunk: &[u8]) -> ServiceResult<()> {
+        let current = self
+            .current
+            .as_mut()
+            .ok_or_else(|| zip_error("no entry is started"))?;
+        current.hasher.update(chunk);
+        self.offset += chunk.len() as u64;
+
+        Ok(())
+    }
+
+    /// Returns data descriptor of the current entry
+    pub fn finish_entry(&mut self) -> ServiceResult<Vec<u8>> {
+        let current = self
+            .current
+            .take()
+            .ok_or_else(|| zip_error("no entry is started"))?;
+        let mut entry = current.entry;
+
+        let written = self.offset - entry.offset - local_header_len(&entry);
+        if written != entry.size {
+            return Err(zip_error(format!(
+                "entry {} has {} bytes instead of {}",
+                entry.name, written, entry.size
+            )));
+        }
+        entry.crc32 = current.hasher.finalize();
+
+        let mut descriptor = Vec::with_capacity(24);
+        put_u32(&mut descriptor, DATA_DESCRIPTOR_SIG);
+        put_u32(&mut descriptor, entry.crc32);
+        if entry.zip64 {
+            put_u64(&mut descriptor, entry.size);
+            put_u64(&mut descriptor, entry.size);
+        } else {
+            put_u32(&mut descriptor, entry.size as u32);
+            put_u32(&mut descriptor, entry.size as u32);
+        }
+
+        self.offset += descriptor.len() as u64;
+        self.entries.push(entry);
+
+        Ok(descriptor)
+    }
+
+    /// Returns central directory and end of the archive
+    pub fn finish(self) -> ServiceResult<Vec<u8>> {
+        if self.current.is_some() {
+            return Err(zip_error("last entry is not finished"));
+        }
+
+        let limit = self.zip64_limit;
+        let mut out = Vec::new();
+        let central_offset = self.offset;
+        for entry in &self.entries {
+            let big_size = entry.size >= limit;
+            let big_offset = entry.offset >= limit;
+
+            let mut zip64_extra = Vec::new();
+            if big_size {
+                put_u64(&mut zip64_extra, entry.size);
+                put_u64(&mut zip64_extra, entry.size);
+            }
+            if big_offset {
+                put_u64(&mut zip64_extra, entry.offset);
+            }
+            let extra_len = match zip64_extra.is_empty() {
+                true => 9,
+                false => 4 + zip64_extra.len() + 9,
+            };
+
+            let (time, date) = dos_date_time(entry.modified);
+            put_u32(&mut out, CENTRAL_HEADER_SIG);
+            put_u16(&mut out, VERSION_MADE_BY);
+            put_u16(&mut out, version_needed(entry.zip64 || big_offset));
+            put_u16(&mut out, FLAGS);
+            put_u16(&mut out, METHOD_STORED);
+            put_u16(&mut out, time);
+            put_u16(&mut out, date);
+            put_u32(&mut out, entry.crc32);
+            put_u32(&mut out, clamp_u32(entry.size, big_size));
+            put_u32(&mut out, clamp_u32(entry.size, big_size));
+            put_u16(&mut out, entry.name.len() as u16);
+            put_u16(&mut out, extra_len as u16);
+            // Comment, disk, internal attributes
+            put_u16(&mut out, 0);
+            put_u16(&mut out, 0);
+            put_u16(&mut out, 0);
+            put_u32(&mut out, EXTERNAL_ATTRS);
+            put_u32(&mut out, clamp_u32(entry.offset, big_offset));
+            out.extend_from_slice(entry.name.as_bytes());
+            if !zip64_extra.is_empty() {
+                put_u16(&mut out, ZIP64_EXTRA_TAG);
+                put_u16(&mut out, zip64_extra.len() as u16);
+                out.extend_from_slice(&zip64_extra);
+            }
+            put_timestamp_extra(&mut out, entry.modified);
+        }
+        let central_size = out.len() as u64;
+        let count = self.entries.len() as u64;
+
+        let zip64 =
+            count >= u64::from(u16::MAX) || central_offset >= limit || central_size >= limit;
+        if zip64 {
+            let zip64_end_offset = central_offset + central_size;
+            put_u32(&mut out, ZIP64_END_SIG);
+            put_u64(&mut out, 44);
+            put_u16(&mut out, VERSION_MADE_BY);
+            put_u16(&mut out, VERSION_ZIP64);
+            put_u32(&mut out, 0);
+            put_u32(&mut out, 0);
+            put_u64(&mut out, count);
+            put_u64(&mut out, count);
+            put_u64(&mut out, central_size);
+            put_u64(&mut out, central_offset);
+
+            put_u32(&mut out, ZIP64_LOCATOR_SIG);
+            put_u32(&mut out, 0);
+            put_u64(&mut out, zip64_end_offset);
+            put_u32(&mut out, 1);
+        }
+
+        let count = match zip64 {
+            true => u16::MAX,
+            false => count as u16,
+        };
+        put_u32(&mut out, END_SIG);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, 0);
+        put_u16(&mut out, count);
+        put_u16(&mut out, count);
+        put_u32(&mut out, clamp_u32(central_size, zip64));
+        put_u32(&mut out, clamp_u32(central_offset, zip64));
+        put_u16(&mut out, 0);
+
+        Ok(out)
+    }
+
+    /// Repeated name gets a number before its extension, `a (1).txt`
+    fn unique_name(&self, name: &str) -> String {
+        if !self.names.contains(name) {
+            return name.to_owned();
+        }
+
+        let (dir, file) = match name.rfind('/') {
+            Some(i) => name.split_at(i + 1),
+            None => ("", name),
+        };
+        let (stem, ext) = match file.rfind('.') {
+            Some(i) if i > 0 => file.split_at(i),
+            _ => (file, ""),
+        };
+        (1..)
+            .map(|n| format!("{}{} ({}){}", dir, stem, n, ext))
+            .find(|candidate| !self.names.contains(candidate))
+            .expect("unbounded range has a free name")
+    }
+}
+
+/// Entry of unpacked archive
+#[derive(Debug)]
+pub enum UnpackedEntry {
+    File {
+        name: String,
+        content: Vec<u8>,
+    },
+    /// Links and special files, they are never created
+    Skipped {
+        name: String,
+    },
+}
+
+/// Bytes of the archive read so far
+#[derive(Debug, Clone, Copy)]
+pub struct UnpackProgress {
+    pub read: u64,
+    pub total: u64,
+}
+
+/// Unpacks .zip or .tar.gz entry by entry, `on_entry` returns false to stop.
+/// Sizes are counted as content is inflated, sizes in headers are not
+/// trusted. Entry which escapes the archive rejects the whole archive
+pub fn unpack_archive<F>(content: &[u8], limits: &ArchiveLimits, on_entry: F) -> ServiceResult<()>
+where
+    F: FnMut(UnpackedEntry, UnpackProgress) -> bool,
+{
+    if content.len() > limits.max_size {
+        return Err(invalid(format!(
+            "archive is larger than {} bytes",
+            limits.max_size
+        )));
+    }
+
+    let mut counter = UnpackCounter {
+        limits,
+        packed: content.len() as u64,
+        unpacked: 0,
+        entries: 0,
+    };
+    if content.starts_with(ZIP_MAGIC) || content.starts_with(ZIP_EMPTY_MAGIC) {
+        unpack_zip(content, &mut counter, on_entry)
+    } else if content.starts_with(GZIP_MAGIC) {
+        unpack_tar_gz(content, &mut counter, on_entry)
+    } else {
+        Err(invalid("archive must be .zip or .tar.gz"))
+    }
+}
+
+fn unpack_zip<F>(content: &[u8], counter: &mut UnpackCounter, mut on_entry: F) -> ServiceResult<()>
+where
+    F: FnMut(UnpackedEntry, UnpackProgress) -> bool,
+{
+    let mut archive = zip::ZipArchive::new(Cursor::new(content)).map_err(malformed_zip)?;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(malformed_zip)?;
+        if file.is_dir() {
+            continue;
+        }
+        counter.count_entry()?;
+
+        let progress = UnpackProgress {
+            read: file.data_start() + file.compressed_size(),
+            total: counter.packed,
+        };
+        let name = entry_name(file.name())?;
+        // Mode is absent in archives made on Windows, they are regular files
+        let special = matches!(
+            file.unix_mode(),
+            Some(mode) if mode & S_IFMT != 0 && mode & S_IFMT != S_IFREG
+        );
+        let entry = match special {
+            true => UnpackedEntry::Skipped { name },
+            false => UnpackedEntry::File {
+                content: counter.read_entry(&name, &mut file)?,
+                name,
+            },
+        };
+
+        if !on_entry(entry, progress) {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn unpack_tar_gz<F>(
+    content: &[u8],
+    counter: &mut UnpackCounter,
+    mut on_entry: F,
+) -> ServiceResult<()>
+where
+    F: FnMut(UnpackedEntry, UnpackProgress) -> bool,
+{
+    // Skipped entries are inflated too, so the whole stream is limited, with
+    // room for headers, padding and long names
+    let limits = counter.limits;
+    let overhead = TAR_ENTRY_OVERHEAD * (limits.max_entries as u64 + 1);
+    let stream_limit = limits
+        .max_total_size
+        .min(limits.max_ratio.saturating_mul(counter.packed))
+        .saturating_add(overhead);
+
+    let read = Rc::new(Cell::new(0));
+    let reader = CountingReader {
+        inner: content,
+        read: Rc::clone(&read),
+    };
+    let mut archive = tar::Archive::new(LimitedReader {
+        inner: flate2::read::MultiGzDecoder::new(reader),
+        left: stream_limit,
+    });
+
+    for entry in archive.entries().map_err(malformed_tar)? {
+        let mut entry = entry.map_err(malformed_tar)?;
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_dir() {
+            continue;
+        }
+        counter.count_entry()?;
+
+        let name = entry_name(&String::from_utf8_lossy(&entry.path_bytes()))?;
+        let unpacked = match entry_type.is_file() || entry_type.is_contiguous() {
+            true => UnpackedEntry::File {
+                content: counter.read_entry(&name, &mut entry)?,
+                name,
+            },
+            false => UnpackedEntry::Skipped { name },
+        };
+        let progress = UnpackProgress {
+            read: read.get(),
+            total: counter.packed,
+        };
+
+        if !on_entry(unpacked, progress) {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+struct UnpackCounter<'a> {
+    limits: &'a ArchiveLimits,
+    packed: u64,
+    unpacked: u64,
+    entries: usize,
+}
+
+impl UnpackCounter<'_> {
+    fn count_entry(&mut self) -> ServiceResult<()> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(invalid(format!(
+                "archive has more than {} entries",
+                self.limits.max_entries
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Reads at most what is left of every limit, so a bomb is cut off
+    /// right after the limit
+    fn read_entry<R: Read>(&mut self, name: &str, reader: R) -> ServiceResult<Vec<u8>> {
+        let max_entry_size = self.limits.max_entry_size as u64;
+        let max_by_ratio = self.limits.max_ratio.saturating_mul(self.packed);
+        let allowed = max_entry_size
+            .min(self.limits.max_total_size.saturating_sub(self.unpacked))
+            .min(max_by_ratio.saturating_sub(self.unpacked));
+
+        let mut content = Vec::new();
+        reader
+            .take(allowed + 1)
+            .read_to_end(&mut content)
+            .map_err(|err| invalid(format!("archive entry {:?}: {}", name, err)))?;
+
+        let size = content.len() as u64;
+        if size > allowed {
+            let msg = if size > max_entry_size {
+                format!(
+                    "archive entry {:?} is larger than {} bytes",
+                    name, max_entry_size
+                )
+            } else if self.unpacked + size > self.limits.max_total_size {
+                format!(
+                    "archive unpacks to more than {} bytes",
+                    self.limits.max_total_size
+                )
+            } else {
+                format!(
+                    "archive unpacks to more than {} times its size",
+                    self.limits.max_ratio
+                )
+            };
+            return Err(invalid(msg));
+        }
+        self.unpacked += size;
+
+        Ok(content)
+    }
+}
+
+/// Counts bytes read from the archive, for progress
+struct CountingReader<'a> {
+    inner: &'a [u8],
+    read: Rc<Cell<u64>>,
+}
+
+impl Read for CountingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read.set(self.read.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// Fails once more than `left` bytes are read
+struct LimitedReader<R> {
+    inner: R,
+    left: u64,
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.left = self
+            .left
+            .checked_sub(n as u64)
+            .ok_or_else(|| io::Error::other("archive unpacks to more than allowed"))?;
+        Ok(n)
+    }
+}
+
+/// Room file name of archive entry. Absolute and `..` paths are rejected,
+/// they are never meant well
+fn entry_name(raw: &str) -> ServiceResult<String> {
+    let escapes = raw.starts_with(['/', '\\'])
+        || raw.split(['/', '\\']).any(|c| c == "..")
+        || raw.chars().nth(1) == Some(':');
+    if escapes {
+        return Err(invalid(format!(
+            "archive entry {:?} escapes the archive",
+            raw
+        )));
+    }
+
+    safe_entry_name(raw).ok_or_else(|| invalid(format!("archive entry {:?} has no name", raw)))
+}
+
+/// Relative path which cannot escape the extraction directory: no drive,
+/// no root, no `.` and `..` components, no control characters. Absent if
+/// nothing is left of the name
+pub fn safe_entry_name(name: &str) -> Option<String> {
+    let components: Vec<String> = name
+        .split(['/', '\\'])
+        .map(|c| c.chars().filter(|ch| !ch.is_control()).collect::<String>())
+        .map(|c| c.trim().to_owned())
+        .filter(|c| !c.is_empty() && c != "." && c != "..")
+        // Drive letters, e.g. `C:`
+        .filter(|c| !(c.len() == 2 && c.ends_with(':')))
+        .collect();
+
+    match components.is_empty() {
+        true => None,
+        false => Some(components.join("/")),
+    }
+}
+
+fn zip_error<E: std::fmt::Display>(msg: E) -> ServiceError {
+    ServiceError::CommonError(anyhow::anyhow!("zip archive: {}", msg))
+}
+
+fn invalid<E: std::fmt::Display>(msg: E) -> ServiceError {
+    ServiceError::ValidationError(anyhow::anyhow!("{}", msg))
+}
+
+fn malformed_zip(err: zip::result::ZipError) -> ServiceError {
+    invalid(format!("malformed .zip archive: {}", err))
+}
+
+fn malformed_tar(err: io::Error) -> ServiceError {
+    invalid(format!("malformed .tar.gz archive: {}", err))
+}
+
+fn version_needed(zip64: bool) -> u16 {
+    match zip64 {
+        true => VERSION_ZIP64,
+        false => VERSION_DEFAULT,
+    }
+}
+
+fn local_header_len(entry: &ZipEntry) -> u64 {
+    let extra = if entry.zip64 { 20 + 9 } else { 9 };
+    30 + entry.name.len() as u64 + extra
+}
+
+/// Value which does not fit is replaced by the ZIP64 marker
+fn clamp_u32(value: u64, zip64: bool) -> u32 {
+    match zip64 {
+        true => u32::MAX,
+        false => value as u32,
+    }
+}
+
+/// MS-DOS time has 2 seconds precision and covers years 1980..=2107
+fn dos_date_time(dt: NaiveDateTime) -> (u16, u16) {
+    let min = NaiveDate::from_ymd(1980, 1, 1).and_hms(0, 0, 0);
+    let max = NaiveDate::from_ymd(2107, 12, 31).and_hms(23, 59, 58);
+    let dt = dt.max(min).min(max);
+
+    let time = (dt.hour() << 11 | dt.minute() << 5 | (dt.second() / 2)) as u16;
+    let date = ((dt.year() as u32 - 1980) << 9 | dt.month() << 5 | dt.day()) as u16;
+    (time, date)
+}
+
+/// Extended timestamp with modification time, seconds precision
+fn put_timestamp_extra(out: &mut Vec<u8>, modified: NaiveDateTime) {
+    let mtime = i32::try_from(modified.timestamp()).unwrap_or_else(|_| {
+        if modified.timestamp() < 0 {
+            i32::MIN
+        } else {
+            i32::MAX
+        }
+    });
+    put_u16(out, TIMESTAMP_EXTRA_TAG);
+    put_u16(out, 5);
+    out.push(1);
+    out.extend_from_slice(&mtime.to_le_bytes());
+}
+
+fn put_u16(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, v: u64) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    fn write_zip(mut writer: ZipStreamWriter, entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let modified = NaiveDate::from_ymd(2021, 3, 4).and_hms(5, 6, 7);
+        let mut out = Vec::new();
+        for (name, content) in entries {
+            out.extend(
+                writer
+                    .start_entry(name, modified, content.len() as u64)
+                    .unwrap(),
+            );
+            for chunk in content.chunks(3) {
+                writer.write(chunk).unwrap();
+                out.extend_from_slice(chunk);
+            }
+            out.extend(writer.finish_entry().unwrap());
+        }
+        out.extend(writer.finish().unwrap());
+        out
+    }
+
+    fn read_zip(archive: Vec<u8>) -> Vec<(String, Vec<u8>, zip::DateTime)> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+        (0..archive.len())
+            .map(|i| {
+                let mut file = archive.by_index(i).unwrap();
+                let mut content = Vec::new();
+                file.read_to_end(&mut content).unwrap();
+                (file.name().to_owned(), content, file.last_modified())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_zip_entries() {
+        let archive = write_zip(
+            ZipStreamWriter::new(),
+            &[("a.txt", b"hello"), ("dir/b.bin", b""), ("a.txt", b"again")],
+        );
+
+        let entries = read_zip(archive);
+        let names: Vec<_> = entries.iter().map(|(n, _, _)| n.as_str()).collect();
+        assert_eq!(names, ["a.txt", "dir/b.bin", "a (1).txt"], "entry names");
+        assert_eq!(entries[0].1, b"hello", "entry content");
+        assert_eq!(entries[2].1, b"again", "repeated entry content");
+
+        let modified = entries[0].2;
+        assert_eq!(
+            (modified.year(), modified.month(), modified.day()),
+            (2021, 3, 4),
+            "entry date"
+        );
+        assert_eq!(
+            (modified.hour(), modified.minute(), modified.second()),
+            (5, 6, 6),
+            "entry time"
+        );
+    }
+
+    #[test]
+    fn test_zip64_entries() {
+        // Every size and offset goes to ZIP64 fields
+        let writer = ZipStreamWriter {
+            zip64_limit: 0,
+            ..ZipStreamWriter::new()
+        };
+        let archive = write_zip(writer, &[("a.txt", b"hello"), ("b.txt", b"world")]);
+
+        assert!(
+            archive.windows(4).any(|w| w == ZIP64_END_SIG.to_le_bytes()),
+            "zip64 end of central directory"
+        );
+
+        let entries = read_zip(archive);
+        assert_eq!(entries.len(), 2, "entries amount");
+        assert_eq!(entries[1].0, "b.txt", "entry name");
+        assert_eq!(entries[1].1, b"world", "entry content");
+    }
+
+    #[test]
+    fn test_zip_entry_size_mismatch() {
+        let mut writer = ZipStreamWriter::new();
+        let modified = NaiveDate::from_ymd(2021, 3, 4).and_hms(5, 6, 7);
+        writer.start_entry("a.txt", modified, 5).unwrap();
+        writer.write(b"hell").unwrap();
+
+        assert!(writer.finish_entry().is_err(), "short entry");
+    }
+
+    #[test]
+    fn test_safe_entry_name() {
+        let cases = [
+            ("report.pdf", Some("report.pdf")),
+            ("../../etc/passwd", Some("etc/passwd")),
+            ("/abs/./path", Some("abs/path")),
+            ("C:\\Users\\me\\a.txt", Some("Users/me/a.txt")),
+            ("bad\u{0}\nname", Some("badname")),
+            ("../..", None),
+        ];
+        for (name, expected) in cases.iter() {
+            assert_eq!(
+                safe_entry_name(name).as_deref(),
+                *expected,
+                "safe name of {:?}",
+                name
+            );
+        }
+    }
+
+    fn limits() -> ArchiveLimits {
+        ArchiveLimits {
+            max_size: 1 << 20,
+            max_total_size: 1 << 20,
+            max_ratio: 100,
+            max_entries: 10,
+            max_entry_size: 1 << 20,
+        }
+    }
+
+    fn build_zip(build: impl FnOnce(&mut zip::ZipWriter<Cursor<Vec<u8>>>)) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        build(&mut writer);
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn build_tar_gz(entries: &[(&str, tar::EntryType, &[u8])]) -> Vec<u8> {
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for (name, entry_type, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(*entry_type);
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            if *entry_type == tar::EntryType::Symlink {
+                header.set_link_name("/etc/passwd").unwrap();
+            }
+            builder.append_data(&mut header, name, *content).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn unpack(archive: &[u8], limits: &ArchiveLimits) -> ServiceResult<Vec<UnpackedEntry>> {
+        let mut entries = Vec::new();
+        unpack_archive(archive, limits, |entry, progress| {
+            assert!(progress.read <= progress.total, "progress within archive");
+            entries.push(entry);
+            true
+        })?;
+        Ok(entries)
+    }
+
+    fn names(entries: &[UnpackedEntry]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|entry| match entry {
+                UnpackedEntry::File { name, .. } => name.clone(),
+                UnpackedEntry::Skipped { name } => format!("skipped {}", name),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_unpack_zip() {
+        let options = zip::write::FileOptions::default();
+        let archive = build_zip(|w| {
+            w.add_directory("docs/", options).unwrap();
+            w.start_file("docs/a.txt", options).unwrap();
+            w.write_all(b"hello").unwrap();
+            w.add_symlink("docs/link", "/etc/passwd", options).unwrap();
+            w.start_file("b.bin", options).unwrap();
+        });
+
+        let entries = unpack(&archive, &limits()).unwrap();
+        assert_eq!(
+            names(&entries),
+            ["docs/a.txt", "skipped docs/link", "b.bin"],
+            "entry names"
+        );
+        assert!(
+            matches!(&entries[0], UnpackedEntry::File { content, .. } if content == b"hello"),
+            "entry content"
+        );
+    }
+
+    #[test]
+    fn test_unpack_tar_gz() {
+        let archive = build_tar_gz(&[
+            ("docs/a.txt", tar::EntryType::Regular, b"hello"),
+            ("docs/link", tar::EntryType::Symlink, b""),
+            ("b.bin", tar::EntryType::Regular, b""),
+        ]);
+
+        let entries = unpack(&archive, &limits()).unwrap();
+        assert_eq!(
+            names(&entries),
+            ["docs/a.txt", "skipped docs/link", "b.bin"],
+            "entry names"
+        );
+        assert!(
+            matches!(&entries[0], UnpackedEntry::File { content, .. } if content == b"hello"),
+            "entry content"
+        );
+    }
+
+    #[test]
+    fn test_unpack_traversal() {
+        let options = zip::write::FileOptions::default();
+        for name in [
+            "../evil.sh",
+            "/etc/cron.d/evil",
+            "C:\\evil.bat",
+            "a/../../evil",
+        ]
+        .iter()
+        {
+            let archive = build_zip(|w| {
+                w.start_file(*name, options).unwrap();
+                w.write_all(b"evil").unwrap();
+            });
+            assert!(
+                matches!(
+                    unpack(&archive, &limits()),
+                    Err(ServiceError::ValidationError(_))
+                ),
+                "zip entry {:?}",
+                name
+            );
+        }
+
+        // Builder itself refuses `..`, so the name is written into the header
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.as_old_mut().name[..13].copy_from_slice(b"../../evil.sh");
+        header.set_cksum();
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::default(),
+        ));
+        builder.append(&header, &b"evil"[..]).unwrap();
+        let archive = builder.into_inner().unwrap().finish().unwrap();
+        assert!(
+            matches!(
+                unpack(&archive, &limits()),
+                Err(ServiceError::ValidationError(_))
+            ),
+            "tar entry"
+        );
+    }
+
+    #[test]
+    fn test_unpack_limits() {
+        let zeros = vec![0; 64 * 1024];
+        let options = zip::write::FileOptions::default();
+        let zip_bomb = build_zip(|w| {
+            w.start_file("zeros", options).unwrap();
+            w.write_all(&zeros).unwrap();
+        });
+        let tar_bomb = build_tar_gz(&[("zeros", tar::EntryType::Regular, &zeros)]);
+        // Skipped entry is inflated as well, past the allowance for headers
+        let tar_skipped_bomb = build_tar_gz(&[("zeros", tar::EntryType::Fifo, &vec![0; 1 << 20])]);
+
+        for archive in [&zip_bomb, &tar_bomb, &tar_skipped_bomb].iter() {
+            let ratio = ArchiveLimits {
+                max_ratio: 2,
+                ..limits()
+            };
+            assert!(
+                matches!(
+                    unpack(archive, &ratio),
+                    Err(ServiceError::ValidationError(_))
+                ),
+                "ratio limit"
+            );
+        }
+        for archive in [&zip_bomb, &tar_bomb].iter() {
+            let total = ArchiveLimits {
+                max_total_size: 1024,
+                ..limits()
+            };
+            assert!(
+                matches!(
+                    unpack(archive, &total),
+                    Err(ServiceError::ValidationError(_))
+                ),
+                "total size limit"
+            );
+            let loose = ArchiveLimits {
+                max_ratio: 10_000,
+                ..limits()
+            };
+            assert!(unpack(archive, &loose).is_ok(), "within limits");
+        }
+
+        let many = build_zip(|w| {
+            for i in 0..3 {
+                w.start_file(format!("{}.txt", i), options).unwrap();
+            }
+        });
+        let entries = ArchiveLimits {
+            max_entries: 2,
+            ..limits()
+        };
+        assert!(
+            matches!(
+                unpack(&many, &entries),
+                Err(ServiceError::ValidationError(_))
+            ),
+            "entries limit"
+        );
+        assert!(
+            matches!(
+                unpack(b"plain text", &limits()),
+                Err(ServiceError::ValidationError(_))
+            ),
+            "not an archive"
+        );
+    }
+}
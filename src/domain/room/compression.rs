@@ -0,0 +1,125 @@
+use crate::port::{ServiceError, ServiceResult};
+
+use std::io::Write;
+
+/// Compressed content is kept if it saves at least this part of the size,
+/// otherwise decompression is not worth it
+const MIN_SAVING_DIVISOR: usize = 10;
+
+/// Content compressed with zstd, absent if compression does not help, e.g.
+/// for images and archives
+pub fn compress(content: &[u8], level: i32) -> ServiceResult<Option<Vec<u8>>> {
+    let compressed = zstd::bulk::compress(content, level).map_err(compression_error)?;
+    if compressed.len() > content.len() - content.len() / MIN_SAVING_DIVISOR {
+        return Ok(None);
+    }
+
+    Ok(Some(compressed))
+}
+
+/// Decompresses zstd content chunk by chunk
+pub struct ZstdDecoder {
+    decoder: zstd::stream::write::Decoder<'static, Vec<u8>>,
+}
+
+impl ZstdDecoder {
+    pub fn new() -> ServiceResult<Self> {
+        let decoder = zstd::stream::write::Decoder::new(Vec::new()).map_err(compression_error)?;
+        Ok(Self { decoder })
+    }
+
+    /// Content decompressed so far, may be empty
+    pub fn decode(&mut self, chunk: &[u8]) -> ServiceResult<Vec<u8>> {
+        self.decoder.write_all(chunk).map_err(compression_error)?;
+        self.decoder.flush().map_err(compression_error)?;
+        Ok(std::mem::take(self.decoder.get_mut()))
+    }
+}
+
+/// Compresses content with gzip chunk by chunk
+pub struct GzipEncoder {
+    encoder: flate2::write::GzEncoder<Vec<u8>>,
+}
+
+impl Default for GzipEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GzipEncoder {
+    pub fn new() -> Self {
+        let encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        Self { encoder }
+    }
+
+    /// Content compressed so far, may be empty
+    pub fn encode(&mut self, chunk: &[u8]) -> ServiceResult<Vec<u8>> {
+        self.encoder.write_all(chunk).map_err(compression_error)?;
+        Ok(std::mem::take(self.encoder.get_mut()))
+    }
+
+    /// Rest of the content with gzip trailer
+    pub fn finish(self) -> ServiceResult<Vec<u8>> {
+        self.encoder.finish().map_err(compression_error)
+    }
+}
+
+fn compression_error(err: std::io::Error) -> ServiceError {
+    ServiceError::CommonError(anyhow::anyhow!("compression: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    #[test]
+    fn test_compress() {
+        let text = "timestamp,level,message\n".repeat(1000);
+        let compressed = compress(text.as_bytes(), 3).unwrap();
+        assert!(
+            matches!(&compressed, Some(c) if c.len() < text.len()),
+            "text is compressed"
+        );
+
+        // Chunks of the stored content are decoded one by one
+        let mut decoder = ZstdDecoder::new().unwrap();
+        let mut decoded = Vec::new();
+        for chunk in compressed.unwrap().chunks(7) {
+            decoded.extend(decoder.decode(chunk).unwrap());
+        }
+        assert_eq!(decoded, text.as_bytes(), "decoded content");
+
+        let random: Vec<u8> = (0..4096).map(|_| rand::random()).collect();
+        assert!(
+            compress(&random, 3).unwrap().is_none(),
+            "random content is not compressed"
+        );
+        assert!(compress(b"", 3).unwrap().is_none(), "empty content");
+    }
+
+    #[test]
+    fn test_zstd_decoder_corrupted() {
+        let mut decoder = ZstdDecoder::new().unwrap();
+        assert!(decoder.decode(b"not zstd at all").is_err(), "corrupted");
+    }
+
+    #[test]
+    fn test_gzip_encoder() {
+        let text = "hello gzip\n".repeat(100);
+        let mut encoder = GzipEncoder::new();
+        let mut encoded = Vec::new();
+        for chunk in text.as_bytes().chunks(30) {
+            encoded.extend(encoder.encode(chunk).unwrap());
+        }
+        encoded.extend(encoder.finish().unwrap());
+
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&encoded[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text, "decoded content");
+    }
+}
@@ -0,0 +1,748 @@
+use crate::port::room::service::ImageMetadata;
+use crate::port::{ServiceError, ServiceResult};
+
+use std::convert::{TryFrom, TryInto};
+
+const JPEG_SOI: &[u8] = &[0xff, 0xd8];
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const EXIF_PREFIX: &[u8] = b"Exif\0\0";
+const XMP_PREFIX: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const XMP_EXTENSION_PREFIX: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+const PHOTOSHOP_PREFIX: &[u8] = b"Photoshop 3.0\0";
+const MPF_PREFIX: &[u8] = b"MPF\0";
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp\0";
+/// Brands of HEIF based images, AVIF included
+const HEIF_BRANDS: [&[u8]; 9] = [
+    b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1", b"avif",
+];
+/// Tag of GPS IFD pointer in TIFF IFD0
+const TIFF_GPS_TAG: u16 = 0x8825;
+
+/// Removes EXIF, XMP, IPTC and comments from JPEG, PNG, WebP and HEIF
+/// images, content of other types is returned as is. Orientation goes with
+/// EXIF. Image which cannot be parsed is rejected, it may hide metadata
+pub fn strip_image_metadata(content: Vec<u8>) -> ServiceResult<(Vec<u8>, Vec<ImageMetadata>)> {
+    let mut removed = Vec::new();
+
+    let content = if content.starts_with(JPEG_SOI) {
+        strip_jpeg(&content, &mut removed)?
+    } else if content.starts_with(PNG_SIGNATURE) {
+        strip_png(&content, &mut removed)?
+    } else if content.len() >= 12 && &content[..4] == b"RIFF" && &content[8..12] == b"WEBP" {
+        strip_webp(&content, &mut removed)?
+    } else if is_heif(&content) {
+        strip_heif(content, &mut removed)?
+    } else {
+        content
+    };
+
+    removed.sort();
+    removed.dedup();
+
+    Ok((content, removed))
+}
+
+/// Segments are copied up to the end of image, trailing data is dropped:
+/// it may be anything, e.g. secondary images of MPF with their own EXIF
+fn strip_jpeg(data: &[u8], removed: &mut Vec<ImageMetadata>) -> ServiceResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(JPEG_SOI);
+
+    let mut pos = JPEG_SOI.len();
+    loop {
+        // Markers may be padded by any number of 0xff
+        if data.get(pos) != Some(&0xff) {
+            return Err(malformed("jpeg"));
+        }
+        while data.get(pos + 1) == Some(&0xff) {
+            pos += 1;
+        }
+        let marker = *data.get(pos + 1).ok_or_else(|| malformed("jpeg"))?;
+
+        match marker {
+            // EOI
+            0xd9 => {
+                out.extend_from_slice(&[0xff, 0xd9]);
+                return Ok(out);
+            }
+            // TEM and RSTn have no length
+            0x01 | 0xd0..=0xd7 => {
+                out.extend_from_slice(&data[pos..pos + 2]);
+                pos += 2;
+                continue;
+            }
+            _ => {}
+        }
+
+        let len = read_u16_be(data, pos + 2).ok_or_else(|| malformed("jpeg"))? as usize;
+        let end = pos + 2 + len;
+        if len < 2 || end > data.len() {
+            return Err(malformed("jpeg"));
+        }
+        let contents = &data[pos + 4..end];
+
+        let kind = match marker {
+            0xe1 if contents.starts_with(EXIF_PREFIX) => {
+                if tiff_has_gps(&contents[EXIF_PREFIX.len()..]) {
+                    removed.push(ImageMetadata::Gps);
+                }
+                Some(ImageMetadata::Exif)
+            }
+            0xe1 if contents.starts_with(XMP_PREFIX)
+                || contents.starts_with(XMP_EXTENSION_PREFIX) =>
+            {
+                Some(ImageMetadata::Xmp)
+            }
+            0xed if contents.starts_with(PHOTOSHOP_PREFIX) => Some(ImageMetadata::Iptc),
+            0xfe => Some(ImageMetadata::Comment),
+            _ => None,
+        };
+        match kind {
+            Some(kind) => removed.push(kind),
+            // Offsets of MPF point into the dropped trailing data
+            None if marker == 0xe2 && contents.starts_with(MPF_PREFIX) => {}
+            None => out.extend_from_slice(&data[pos..end]),
+        }
+        pos = end;
+
+        // SOS is followed by entropy-coded data up to the next marker, 0xff
+        // in it is stuffed with 0x00 or is a restart marker
+        if marker == 0xda {
+            let start = pos;
+            loop {
+                match (data.get(pos), data.get(pos + 1)) {
+                    (Some(0xff), Some(0x00)) | (Some(0xff), Some(0xd0..=0xd7)) => pos += 2,
+                    (Some(0xff), Some(_)) => break,
+                    (Some(_), _) => pos += 1,
+                    (None, _) => return Err(malformed("jpeg")),
+                }
+            }
+            out.extend_from_slice(&data[start..pos]);
+        }
+    }
+}
+
+/// Chunks are copied up to IEND, CRCs stay valid as chunks are not changed
+fn strip_png(data: &[u8], removed: &mut Vec<ImageMetadata>) -> ServiceResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(PNG_SIGNATURE);
+
+    let mut pos = PNG_SIGNATURE.len();
+    loop {
+        let len = read_u32_be(data, pos).ok_or_else(|| malformed("png"))? as usize;
+        let end = pos
+            .checked_add(12)
+            .and_then(|p| p.checked_add(len))
+            .filter(|end| *end <= data.len())
+            .ok_or_else(|| malformed("png"))?;
+        let kind = &data[pos + 4..pos + 8];
+        let contents = &data[pos + 8..end - 4];
+
+        let metadata = match kind {
+            b"eXIf" => {
+                if tiff_has_gps(contents) {
+                    removed.push(ImageMetadata::Gps);
+                }
+                Some(ImageMetadata::Exif)
+            }
+            b"iTXt" if contents.starts_with(PNG_XMP_KEYWORD) => Some(ImageMetadata::Xmp),
+            b"tEXt" | b"zTXt" | b"iTXt" => Some(ImageMetadata::Comment),
+            _ => None,
+        };
+        match metadata {
+            Some(metadata) => removed.push(metadata),
+            None => out.extend_from_slice(&data[pos..end]),
+        }
+        pos = end;
+
+        if kind == b"IEND" {
+            return Ok(out);
+        }
+    }
+}
+
+/// Flags of removed chunks are cleared in VP8X, RIFF size is recomputed
+fn strip_webp(data: &[u8], removed: &mut Vec<ImageMetadata>) -> ServiceResult<Vec<u8>> {
+    let riff_end = read_u32_le(data, 4)
+        .and_then(|size| (size as usize).checked_add(8))
+        .filter(|end| *end <= data.len())
+        .ok_or_else(|| malformed("webp"))?;
+
+    let mut out = Vec::with_capacity(riff_end);
+    out.extend_from_slice(&data[..12]);
+
+    let mut pos = 12;
+    while pos < riff_end {
+        let len = read_u32_le(data, pos + 4).ok_or_else(|| malformed("webp"))? as usize;
+        // Chunks are padded to even size
+        let end = (pos + 8)
+            .checked_add(len + len % 2)
+            .filter(|end| *end <= riff_end)
+            .ok_or_else(|| malformed("webp"))?;
+        let contents = &data[pos + 8..pos + 8 + len];
+
+        match &data[pos..pos + 4] {
+            b"EXIF" => {
+                // Some writers keep JPEG prefix of EXIF
+                let tiff = contents.strip_prefix(EXIF_PREFIX).unwrap_or(contents);
+                if tiff_has_gps(tiff) {
+                    removed.push(ImageMetadata::Gps);
+                }
+                removed.push(ImageMetadata::Exif);
+            }
+            b"XMP " => removed.push(ImageMetadata::Xmp),
+            b"VP8X" if len >= 1 => {
+                let flags_pos = out.len() + 8;
+                out.extend_from_slice(&data[pos..end]);
+                // EXIF and XMP flags
+                out[flags_pos] &= !0b0000_1100;
+            }
+            _ => out.extend_from_slice(&data[pos..end]),
+        }
+        pos = end;
+    }
+
+    let riff_size = u32::try_from(out.len() - 8).map_err(|_| malformed("webp"))?;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+
+    Ok(out)
+}
+
+fn is_heif(data: &[u8]) -> bool {
+    let ftyp = match (read_u32_be(data, 0), data.get(4..8)) {
+        (Some(size), Some(b"ftyp")) => match data.get(8..(size as usize).min(data.len())) {
+            Some(ftyp) => ftyp,
+            None => return false,
+        },
+        _ => return false,
+    };
+
+    // Major brand, minor version, compatible brands
+    std::iter::once(&ftyp[..ftyp.len().min(4)])
+        .chain(ftyp.get(8..).unwrap_or_default().chunks(4))
+        .any(|brand| HEIF_BRANDS.contains(&brand))
+}
+
+/// EXIF and XMP are items of HEIF. Their data is zeroed in place, so
+/// offsets in the file stay valid and its size does not change
+fn strip_heif(mut data: Vec<u8>, removed: &mut Vec<ImageMetadata>) -> ServiceResult<Vec<u8>> {
+    let meta = find_box(&data, 0..data.len(), b"meta").ok_or_else(|| malformed("heif"))?;
+    // Full box: version and flags
+    let children = meta.start + 4..meta.end;
+
+    let iinf = find_box(&data, children.clone(), b"iinf").ok_or_else(|| malformed("heif"))?;
+    let iloc = find_box(&data, children.clone(), b"iloc").ok_or_else(|| malformed("heif"))?;
+    let idat = find_box(&data, children, b"idat");
+
+    let items = heif_metadata_items(&data, iinf)?;
+    if items.is_empty() {
+        return Ok(data);
+    }
+
+    for (item_id, extents) in heif_item_extents(&data, iloc)? {
+        let kind = match items.iter().find(|(id, _)| *id == item_id) {
+            Some((_, kind)) => *kind,
+            None => continue,
+        };
+
+        for (construction_method, offset, len) in extents {
+            let base = match (construction_method, &idat) {
+                (0, _) => 0,
+                (1, Some(idat)) => idat.start as u64,
+                _ => return Err(malformed("heif")),
+            };
+            let start = base
+                .checked_add(offset)
+                .and_then(|s| usize::try_from(s).ok())
+                .ok_or_else(|| malformed("heif"))?;
+            let end = start
+                .checked_add(usize::try_from(len).map_err(|_| malformed("heif"))?)
+                .filter(|end| *end <= data.len())
+                .ok_or_else(|| malformed("heif"))?;
+
+            // Exif item starts with offset of TIFF header
+            if kind == ImageMetadata::Exif {
+                let tiff_offset = read_u32_be(&data, start).unwrap_or_default() as usize;
+                let tiff = data.get(start + 4 + tiff_offset..end).unwrap_or_default();
+                if tiff_has_gps(tiff) {
+                    removed.push(ImageMetadata::Gps);
+                }
+            }
+            data[start..end].iter_mut().for_each(|b| *b = 0);
+        }
+        removed.push(kind);
+    }
+
+    Ok(data)
+}
+
+/// Ids of EXIF and XMP items from `infe` entries of `iinf` box
+fn heif_metadata_items(
+    data: &[u8],
+    iinf: std::ops::Range<usize>,
+) -> ServiceResult<Vec<(u32, ImageMetadata)>> {
+    let version = *data.get(iinf.start).ok_or_else(|| malformed("heif"))?;
+    let entries_start = iinf.start + 4 + if version == 0 { 2 } else { 4 };
+
+    let mut items = Vec::new();
+    for infe in boxes(data, entries_start..iinf.end) {
+        let (kind, infe) = infe?;
+        if kind != *b"infe" {
+            continue;
+        }
+
+        // Versions 0 and 1 have no item type
+        let version = data[infe.start];
+        let (item_id, rest) = match version {
+            2 => (
+                read_u16_be(data, infe.start + 4).map(u32::from),
+                infe.start + 8,
+            ),
+            3 => (read_u32_be(data, infe.start + 4), infe.start + 10),
+            _ => continue,
+        };
+        let item_id = item_id.ok_or_else(|| malformed("heif"))?;
+        let item_type = data.get(rest..rest + 4).ok_or_else(|| malformed("heif"))?;
+
+        if item_type == b"Exif" {
+            items.push((item_id, ImageMetadata::Exif));
+        } else if item_type == b"mime" {
+            // Name and content type are NUL terminated strings
+            let mut strings = data[rest + 4..infe.end].split(|b| *b == 0);
+            let content_type = strings.nth(1).unwrap_or_default();
+            if content_type == b"application/rdf+xml" {
+                items.push((item_id, ImageMetadata::Xmp));
+            }
+        }
+    }
+
+    Ok(items)
+}
+
+type HeifExtent = (u8, u64, u64);
+
+/// Extents of every item from `iloc` box as construction method, offset
+/// and length
+fn heif_item_extents(
+    data: &[u8],
+    iloc: std::ops::Range<usize>,
+) -> ServiceResult<Vec<(u32, Vec<HeifExtent>)>> {
+    let mut reader = BoxReader {
+        data: &data[..iloc.end],
+        pos: iloc.start,
+    };
+
+    let version = reader.read(1)? as u8;
+    reader.read(3)?;
+    let sizes = reader.read(2)?;
+    let offset_size = (sizes >> 12) as usize;
+    let length_size = ((sizes >> 8) & 0xf) as usize;
+    let base_offset_size = ((sizes >> 4) & 0xf) as usize;
+    let index_size = match version {
+        1 | 2 => (sizes & 0xf) as usize,
+        _ => 0,
+    };
+    let item_count = reader.read(if version < 2 { 2 } else { 4 })?;
+
+    let mut items = Vec::new();
+    for _ in 0..item_count {
+        let item_id = reader.read(if version < 2 { 2 } else { 4 })? as u32;
+        let construction_method = match version {
+            1 | 2 => (reader.read(2)? & 0xf) as u8,
+            _ => 0,
+        };
+        // Data reference index
+        reader.read(2)?;
+        let base_offset = reader.read(base_offset_size)?;
+        let extent_count = reader.read(2)?;
+
+        let mut extents = Vec::new();
+        for _ in 0..extent_count {
+            reader.read(index_size)?;
+            let offset = reader.read(offset_size)?;
+            let len = reader.read(length_size)?;
+            let offset = base_offset
+                .checked_add(offset)
+                .ok_or_else(|| malformed("heif"))?;
+            extents.push((construction_method, offset, len));
+        }
+        items.push((item_id, extents));
+    }
+
+    Ok(items)
+}
+
+/// Reads big endian integers of iloc variable sizes
+struct BoxReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BoxReader<'_> {
+    fn read(&mut self, size: usize) -> ServiceResult<u64> {
+        if size > 8 {
+            return Err(malformed("heif"));
+        }
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + size)
+            .ok_or_else(|| malformed("heif"))?;
+        self.pos += size;
+        Ok(bytes.iter().fold(0, |n, b| (n << 8) | *b as u64))
+    }
+}
+
+/// First box of `kind` within `range`, returns range of its contents
+fn find_box(
+    data: &[u8],
+    range: std::ops::Range<usize>,
+    kind: &[u8; 4],
+) -> Option<std::ops::Range<usize>> {
+    boxes(data, range)
+        .map_while(Result::ok)
+        .find(|(k, _)| k == kind)
+        .map(|(_, contents)| contents)
+}
+
+/// ISO BMFF boxes within `range` as type and range of contents
+fn boxes(
+    data: &[u8],
+    range: std::ops::Range<usize>,
+) -> impl Iterator<Item = ServiceResult<([u8; 4], std::ops::Range<usize>)>> + '_ {
+    let mut pos = range.start;
+    let end = range.end.min(data.len());
+    std::iter::from_fn(move || {
+        if pos + 8 > end {
+            return None;
+        }
+
+        let kind: [u8; 4] = data[pos + 4..pos + 8].try_into().ok()?;
+        let (header_len, size) = match read_u32_be(data, pos)? {
+            0 => (8, (end - pos) as u64),
+            1 => (16, read_u64_be(data, pos + 8).unwrap_or_default()),
+            size => (8, size as u64),
+        };
+        let box_end = match usize::try_from(size)
+            .ok()
+            .and_then(|size| pos.checked_add(size))
+            .filter(|box_end| size >= header_len && *box_end <= end)
+        {
+            Some(box_end) => box_end,
+            None => {
+                pos = end;
+                return Some(Err(malformed("heif")));
+            }
+        };
+
+        let contents = pos + header_len as usize..box_end;
+        pos = box_end;
+        Some(Ok((kind, contents)))
+    })
+}
+
+/// Whether IFD0 of TIFF structure of EXIF points to GPS IFD
+fn tiff_has_gps(tiff: &[u8]) -> bool {
+    let big_endian = match tiff.get(..4) {
+        Some(b"MM\0*") => true,
+        Some(b"II*\0") => false,
+        _ => return false,
+    };
+    let read_u16 = |pos| match big_endian {
+        true => read_u16_be(tiff, pos),
+        false => tiff
+            .get(pos..pos + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]])),
+    };
+    let read_u32 = |pos| match big_endian {
+        true => read_u32_be(tiff, pos),
+        false => read_u32_le(tiff, pos),
+    };
+
+    let ifd0 = match read_u32(4) {
+        Some(offset) => offset as usize,
+        None => return false,
+    };
+    let count = read_u16(ifd0).unwrap_or_default() as usize;
+
+    (0..count).any(|i| read_u16(ifd0 + 2 + i * 12) == Some(TIFF_GPS_TAG))
+}
+
+fn read_u16_be(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn read_u32_be(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn read_u32_le(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn read_u64_be(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+fn malformed(format: &str) -> ServiceError {
+    ServiceError::ValidationError(anyhow::anyhow!("malformed {} image", format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Little endian TIFF with only GPS IFD pointer in IFD0
+    fn tiff_with_gps() -> Vec<u8> {
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        tiff.extend_from_slice(&TIFF_GPS_TAG.to_le_bytes());
+        tiff.extend_from_slice(&4u16.to_le_bytes());
+        tiff.extend_from_slice(&1u32.to_le_bytes());
+        tiff.extend_from_slice(&26u32.to_le_bytes());
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff
+    }
+
+    fn jpeg_segment(marker: u8, contents: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xff, marker];
+        segment.extend_from_slice(&(contents.len() as u16 + 2).to_be_bytes());
+        segment.extend_from_slice(contents);
+        segment
+    }
+
+    fn png_chunk(kind: &[u8], contents: &[u8]) -> Vec<u8> {
+        let mut chunk = (contents.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(contents);
+        // CRC is not checked
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    }
+
+    fn iso_box(kind: &[u8], contents: &[u8]) -> Vec<u8> {
+        let mut b = (contents.len() as u32 + 8).to_be_bytes().to_vec();
+        b.extend_from_slice(kind);
+        b.extend_from_slice(contents);
+        b
+    }
+
+    #[test]
+    fn test_strip_jpeg() -> ServiceResult<()> {
+        let exif = [EXIF_PREFIX, &tiff_with_gps()].concat();
+        let sos = [
+            jpeg_segment(0xda, &[1, 1, 0, 0, 0x3f, 0]),
+            vec![1, 0xff, 0, 2, 0xff, 0xd0, 3],
+        ]
+        .concat();
+        let image = [
+            JPEG_SOI.to_vec(),
+            jpeg_segment(0xe0, b"JFIF\0\x01\x01"),
+            jpeg_segment(0xe1, &exif),
+            jpeg_segment(0xe1, &[XMP_PREFIX, b"<x:xmpmeta/>"].concat()),
+            jpeg_segment(0xfe, b"comment"),
+            jpeg_segment(0xdb, &[0; 65]),
+            sos.clone(),
+            vec![0xff, 0xd9],
+            b"trailer".to_vec(),
+        ]
+        .concat();
+
+        let (stripped, removed) = strip_image_metadata(image)?;
+
+        let expected = [
+            JPEG_SOI.to_vec(),
+            jpeg_segment(0xe0, b"JFIF\0\x01\x01"),
+            jpeg_segment(0xdb, &[0; 65]),
+            sos,
+            vec![0xff, 0xd9],
+        ]
+        .concat();
+        assert_eq!(stripped, expected, "stripped jpeg");
+        assert_eq!(
+            removed,
+            vec![
+                ImageMetadata::Exif,
+                ImageMetadata::Gps,
+                ImageMetadata::Xmp,
+                ImageMetadata::Comment
+            ],
+            "removed metadata"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_strip_png() -> ServiceResult<()> {
+        let ihdr = png_chunk(b"IHDR", &[0; 13]);
+        let idat = png_chunk(b"IDAT", &[1, 2, 3]);
+        let iend = png_chunk(b"IEND", &[]);
+        let image = [
+            PNG_SIGNATURE.to_vec(),
+            ihdr.clone(),
+            png_chunk(b"eXIf", b"MM\0*\0\0\0\x08\0\0"),
+            png_chunk(
+                b"iTXt",
+                &[PNG_XMP_KEYWORD, b"\0\0\0\0<x:xmpmeta/>"].concat(),
+            ),
+            png_chunk(b"tEXt", b"Author\0me"),
+            idat.clone(),
+            iend.clone(),
+        ]
+        .concat();
+
+        let (stripped, removed) = strip_image_metadata(image)?;
+
+        assert_eq!(
+            stripped,
+            [PNG_SIGNATURE.to_vec(), ihdr, idat, iend].concat(),
+            "stripped png"
+        );
+        assert_eq!(
+            removed,
+            vec![
+                ImageMetadata::Exif,
+                ImageMetadata::Xmp,
+                ImageMetadata::Comment
+            ],
+            "removed metadata"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_strip_webp() -> ServiceResult<()> {
+        let riff = |chunks: &[u8]| {
+            let mut image = b"RIFF".to_vec();
+            image.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+            image.extend_from_slice(b"WEBP");
+            image.extend_from_slice(chunks);
+            image
+        };
+        let chunk = |kind: &[u8], contents: &[u8]| {
+            let mut chunk = kind.to_vec();
+            chunk.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            chunk.extend_from_slice(contents);
+            if contents.len() % 2 == 1 {
+                chunk.push(0);
+            }
+            chunk
+        };
+
+        let image = riff(
+            &[
+                chunk(b"VP8X", &[0b0011_1100, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+                chunk(b"VP8L", &[1, 2, 3]),
+                chunk(b"EXIF", &tiff_with_gps()),
+                chunk(b"XMP ", b"<x:xmpmeta/>"),
+            ]
+            .concat(),
+        );
+
+        let (stripped, removed) = strip_image_metadata(image)?;
+
+        let expected = riff(
+            &[
+                chunk(b"VP8X", &[0b0011_0000, 0, 0, 0, 0, 0, 0, 0, 0, 0]),
+                chunk(b"VP8L", &[1, 2, 3]),
+            ]
+            .concat(),
+        );
+        assert_eq!(stripped, expected, "stripped webp");
+        assert_eq!(
+            removed,
+            vec![ImageMetadata::Exif, ImageMetadata::Gps, ImageMetadata::Xmp],
+            "removed metadata"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_strip_heif() -> ServiceResult<()> {
+        let infe = |item_id: u16, item_type: &[u8], extra: &[u8]| {
+            let mut contents = vec![2, 0, 0, 0];
+            contents.extend_from_slice(&item_id.to_be_bytes());
+            contents.extend_from_slice(&[0, 0]);
+            contents.extend_from_slice(item_type);
+            contents.extend_from_slice(extra);
+            iso_box(b"infe", &contents)
+        };
+        let iinf = iso_box(
+            b"iinf",
+            &[
+                vec![0, 0, 0, 0, 0, 3],
+                infe(1, b"hvc1", b"\0"),
+                infe(2, b"Exif", b"\0"),
+                infe(3, b"mime", b"\0application/rdf+xml\0"),
+            ]
+            .concat(),
+        );
+
+        let exif = [&[0, 0, 0, 0][..], &tiff_with_gps()].concat();
+        let xmp = b"<x:xmpmeta/>".to_vec();
+        let pixels = vec![7; 5];
+        let ftyp = iso_box(b"ftyp", b"heic\0\0\0\0mif1heic");
+
+        // iloc v0 with 4 byte offsets and lengths, without base offset
+        let iloc_len = 8 + 4 + 2 + 2 + 3 * 14;
+        let meta_len = 8 + 4 + iinf.len() + iloc_len;
+        let mdat_data = ftyp.len() + meta_len + 8;
+        let mut iloc = vec![0, 0, 0, 0, 0x44, 0x00, 0, 3];
+        for (item_id, offset, len) in [
+            (1u16, mdat_data, pixels.len()),
+            (2, mdat_data + pixels.len(), exif.len()),
+            (3, mdat_data + pixels.len() + exif.len(), xmp.len()),
+        ]
+        .iter()
+        {
+            iloc.extend_from_slice(&item_id.to_be_bytes());
+            iloc.extend_from_slice(&[0, 0, 0, 1]);
+            iloc.extend_from_slice(&(*offset as u32).to_be_bytes());
+            iloc.extend_from_slice(&(*len as u32).to_be_bytes());
+        }
+        let iloc = iso_box(b"iloc", &iloc);
+        let meta = iso_box(b"meta", &[vec![0; 4], iinf, iloc].concat());
+        let mdat = iso_box(
+            b"mdat",
+            &[pixels.clone(), exif.clone(), xmp.clone()].concat(),
+        );
+        let image = [ftyp.clone(), meta.clone(), mdat].concat();
+        assert_eq!(
+            image.len(),
+            mdat_data + pixels.len() + exif.len() + xmp.len(),
+            "test image layout"
+        );
+
+        let (stripped, removed) = strip_image_metadata(image)?;
+
+        let mdat = iso_box(
+            b"mdat",
+            &[pixels, vec![0; exif.len()], vec![0; xmp.len()]].concat(),
+        );
+        assert_eq!(stripped, [ftyp, meta, mdat].concat(), "stripped heif");
+        assert_eq!(
+            removed,
+            vec![ImageMetadata::Exif, ImageMetadata::Gps, ImageMetadata::Xmp],
+            "removed metadata"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_strip_other() -> ServiceResult<()> {
+        let (stripped, removed) = strip_image_metadata(b"Exif\0\0 plain text".to_vec())?;
+        assert_eq!(stripped, b"Exif\0\0 plain text", "not an image");
+        assert!(removed.is_empty(), "removed metadata");
+
+        assert!(
+            strip_image_metadata([JPEG_SOI, &[0xff, 0xe1, 0xff]].concat()).is_err(),
+            "truncated jpeg"
+        );
+
+        Ok(())
+    }
+}
@@ -1,3 +1,7 @@
+pub mod archive;
+pub mod compression;
+pub mod metadata;
+pub mod password;
 pub mod service_impl;
 
 pub use service_impl::*;
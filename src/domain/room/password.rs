@@ -0,0 +1,281 @@
+use crate::port::{ServiceError, ServiceResult};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+use subtle::ConstantTimeEq;
+
+/// Length of random per room key of lookup tags
+pub const LOOKUP_KEY_LEN: usize = 16;
+
+/// Length of random per room secret of rolling join codes
+pub const JOIN_CODE_SECRET_LEN: usize = 20;
+
+/// Lookup tag keeps only that many low bits of the digest. The tag is stored
+/// next to the hash, so it must be short to not help offline guessing, but
+/// still splits room passwords into buckets so login verifies ~1 hash
+const LOOKUP_TAG_MASK: u8 = 0x0f;
+
+pub struct HashedPassword {
+    /// Argon2id hash in PHC string format
+    pub hash: String,
+    pub lookup_tag: u8,
+}
+
+pub fn new_lookup_key() -> Vec<u8> {
+    let mut key = vec![0; LOOKUP_KEY_LEN];
+    OsRng.fill_bytes(&mut key);
+    key
+}
+
+pub fn new_join_code_secret() -> Vec<u8> {
+    let mut secret = vec![0; JOIN_CODE_SECRET_LEN];
+    OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+/// Random one-off invite password, 128 bits in URL safe base64
+pub fn new_invite_password() -> String {
+    let mut password = [0; 16];
+    OsRng.fill_bytes(&mut password);
+    base64::encode_config(password, base64::URL_SAFE_NO_PAD)
+}
+
+/// Join code of step `counter`, HOTP (RFC 4226) truncation over HMAC-SHA256
+pub fn join_code(secret: &[u8], counter: i64, digits: u32) -> String {
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(secret).expect("HMAC accepts key of any size");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    let digits = digits.clamp(1, 9);
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(digits),
+        width = digits as usize
+    )
+}
+
+/// Checks code of the current step, or of the previous one during the first
+/// `grace` seconds of the current step. `now` is unix timestamp
+pub fn verify_join_code(
+    secret: &[u8],
+    step: i64,
+    grace: i64,
+    digits: u32,
+    now: i64,
+    code: &str,
+) -> bool {
+    let step = step.max(1);
+    let counter = now.div_euclid(step);
+    let code = code.trim().as_bytes();
+
+    // Both codes are always computed and compared in constant time
+    let current: bool = join_code(secret, counter, digits)
+        .as_bytes()
+        .ct_eq(code)
+        .into();
+    let previous: bool = join_code(secret, counter - 1, digits)
+        .as_bytes()
+        .ct_eq(code)
+        .into();
+
+    current | (previous & (now.rem_euclid(step) < grace))
+}
+
+/// Hashes password with random salt. Passphrases are normalized first
+pub fn hash(lookup_key: &[u8], password: &str, passphrase: bool) -> ServiceResult<HashedPassword> {
+    let password = normalize(password, passphrase);
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|err| ServiceError::CommonError(anyhow::anyhow!("{}", err)))?
+        .to_string();
+
+    Ok(HashedPassword {
+        hash,
+        lookup_tag: lookup_tag(lookup_key, &password, false),
+    })
+}
+
+/// Selects bucket of candidate hashes for the password
+pub fn lookup_tag(lookup_key: &[u8], password: &str, passphrase: bool) -> u8 {
+    let password = normalize(password, passphrase);
+    let digest = Sha256::new()
+        .chain_update(lookup_key)
+        .chain_update(password.as_bytes())
+        .finalize();
+
+    digest[0] & LOOKUP_TAG_MASK
+}
+
+/// Verifies password against every candidate `(index, hash, passphrase)`,
+/// returns index of the matched one. All candidates are checked even after
+/// a match and at least one hash is always computed, so response time
+/// depends neither on position of the password nor on whether its bucket
+/// is empty.
+///
+/// It is not constant time: one hash is computed per candidate, so time
+/// grows with the bucket and tells how many room passwords share the tag
+/// of the guess. This is accepted, the tag already splits passwords into
+/// only 16 buckets and the count reveals nothing about the passwords
+pub fn verify<'a, I>(candidates: I, password: &str) -> Option<usize>
+where
+    I: IntoIterator<Item = (usize, &'a str, bool)>,
+{
+    let mut matched = None;
+    let mut checked = false;
+
+    for (idx, hash, passphrase) in candidates {
+        checked = true;
+        if verify_one(hash, &normalize(password, passphrase)) && matched.is_none() {
+            matched = Some(idx);
+        }
+    }
+
+    if !checked {
+        verify_one(dummy_hash(), password);
+    }
+
+    matched
+}
+
+/// Passphrase words are matched case-insensitively with any separator,
+/// e.g. `Brave Otter-apple` equals `brave-otter-apple`. Word list is all
+/// lowercase and separators are fixed per room, so neither of them adds
+/// entropy and nothing is lost by normalizing them away. Configured
+/// separator is checked on load to have no letters or digits, so it always
+/// splits words here
+fn normalize(password: &str, passphrase: bool) -> String {
+    if !passphrase {
+        return password.to_owned();
+    }
+
+    password
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn verify_one(hash: &str, password: &str) -> bool {
+    // Argon2 compares digests in constant time
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(err) => {
+            log::error!("invalid room password hash: {}", err);
+            false
+        }
+    }
+}
+
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    DUMMY_HASH.get_or_init(|| {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(b"", &salt)
+            .map(|h| h.to_string())
+            .unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_verify() -> ServiceResult<()> {
+        let lookup_key = new_lookup_key();
+        let hashed = hash(&lookup_key, "secret", false)?;
+
+        assert!(hashed.hash.starts_with("$argon2id$"), "argon2id hash");
+        assert_eq!(
+            hashed.lookup_tag,
+            lookup_tag(&lookup_key, "secret", false),
+            "lookup tag"
+        );
+        assert_eq!(
+            verify(vec![(0, hashed.hash.as_str(), false)], "secret"),
+            Some(0)
+        );
+        assert_eq!(
+            verify(vec![(0, hashed.hash.as_str(), false)], "Secret"),
+            None
+        );
+        assert_eq!(verify(Vec::<(usize, &str, bool)>::new(), "secret"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lookup_tag_collision() -> ServiceResult<()> {
+        let lookup_key = new_lookup_key();
+
+        // Tags are only 4 bits, so the bucket of the first password is
+        // shared by some of the next ones
+        let tag = lookup_tag(&lookup_key, "password-0", false);
+        let passwords: Vec<String> = (0..)
+            .map(|i| format!("password-{}", i))
+            .filter(|p| lookup_tag(&lookup_key, p, false) == tag)
+            .take(3)
+            .collect();
+
+        let hashes = passwords
+            .iter()
+            .map(|p| hash(&lookup_key, p, false).map(|h| h.hash))
+            .collect::<ServiceResult<Vec<_>>>()?;
+        let candidates = || {
+            hashes
+                .iter()
+                .enumerate()
+                .map(|(i, h)| (i, h.as_str(), false))
+        };
+
+        for (i, password) in passwords.iter().enumerate() {
+            assert_eq!(verify(candidates(), password), Some(i), "password {}", i);
+        }
+
+        // Wrong password is rejected even if its bucket is populated
+        let wrong = (0..)
+            .map(|i| format!("wrong-{}", i))
+            .find(|p| lookup_tag(&lookup_key, p, false) == tag)
+            .expect("wrong password in the same bucket");
+        assert_eq!(verify(candidates(), &wrong), None, "wrong password");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_passphrase_normalized() -> ServiceResult<()> {
+        let lookup_key = new_lookup_key();
+        let hashed = hash(&lookup_key, "brave-otter-apple", true)?;
+
+        assert_eq!(
+            hashed.lookup_tag,
+            lookup_tag(&lookup_key, "Brave Otter-apple", true),
+            "lookup tag"
+        );
+        assert_eq!(
+            verify(vec![(0, hashed.hash.as_str(), true)], "Brave Otter-apple"),
+            Some(0)
+        );
+
+        Ok(())
+    }
+}
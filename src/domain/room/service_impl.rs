@@ -1,43 +1,190 @@
 use crate::config;
 use crate::domain::local_prelude::*;
+use crate::domain::room::compression::{self, GzipEncoder, ZstdDecoder};
+use crate::domain::room::{metadata, password};
 use crate::port::room::repo as room_repo;
 use crate::port::room::repo::RoomRepo;
 use crate::port::room::service::*;
-use crate::port::{ServiceError, ServiceResult};
+use crate::port::{RepoError, ServiceError, ServiceResult};
+
+use chrono::{Duration, NaiveDateTime, Utc};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+
+const MAX_FILES_LIMIT: usize = 1000;
+/// Attempts to pick free room id before giving up
+const MAX_ROOM_ID_ATTEMPTS: usize = 16;
 
 pub struct RoomServiceImpl<R: RoomRepo> {
     cfg: config::Room,
+    privacy: config::Privacy,
     repo: Arc<R>,
 }
 
 impl<R: RoomRepo> RoomServiceImpl<R> {
-    pub fn new(cfg: config::Room, repo: Arc<R>) -> Self {
-        Self { cfg, repo }
+    pub fn new(cfg: config::Room, privacy: config::Privacy, repo: Arc<R>) -> Self {
+        Self { cfg, privacy, repo }
+    }
+
+    async fn new_room_id(&self) -> ServiceResult<RoomId> {
+        let start_id = self.cfg.start_id;
+        let max_rooms = (self.cfg.max_rooms as u64).max(1);
+
+        let room_id = match self.cfg.id_strategy {
+            config::RoomIdStrategy::Sequential => {
+                let number = self.repo.next_room_number().await?;
+                RoomId::new(start_id + number % max_rooms)
+            }
+            config::RoomIdStrategy::Random => {
+                RoomId::new(rand::thread_rng().gen_range(start_id..start_id + max_rooms))
+            }
+            config::RoomIdStrategy::Words => {
+                let mut rng = rand::thread_rng();
+                let words = [
+                    rng.gen_range(0..2048),
+                    rng.gen_range(0..2048),
+                    rng.gen_range(0..2048),
+                ];
+                RoomId::from_code(words, rng.gen_range(0..100))
+            }
+        };
+
+        Ok(room_id)
+    }
+
+    /// Expired file is reported as not found, janitor deletes it
+    async fn get_unexpired_file(
+        &self,
+        room_id: RoomId,
+        file_id: FileId,
+    ) -> ServiceResult<room_repo::File> {
+        let repo_req = room_repo::GetFileRequest { room_id, file_id };
+        let repo_res = self.repo.get_file(repo_req).await?;
+
+        match repo_res.file.expires_at {
+            Some(expires_at) if Utc::now().naive_utc() >= expires_at => {
+                Err(ServiceError::RepoError(RepoError::NotFound(
+                    anyhow::anyhow!("file with id={} is expired", file_id),
+                )))
+            }
+            _ => Ok(repo_res.file),
+        }
+    }
+
+    async fn delete_blobs(&self, room_id: RoomId, file: &room_repo::File) -> ServiceResult<()> {
+        let mut blob_ids: Vec<_> = file
+            .versions
+            .iter()
+            .filter_map(|v| v.blob.as_ref().map(|b| b.id))
+            .collect();
+        blob_ids.sort();
+        blob_ids.dedup();
+        for blob_id in blob_ids {
+            let repo_req = room_repo::DeleteBlobRequest { room_id, blob_id };
+            self.repo.delete_blob(repo_req).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn is_e2ee(&self, room_id: RoomId) -> ServiceResult<bool> {
+        let repo_req = room_repo::GetRoomSettingsRequest { room_id };
+        let repo_res = self.repo.get_room_settings(repo_req).await?;
+
+        Ok(repo_res.room_settings.e2ee)
     }
 }
 
 #[async_trait::async_trait]
-impl<R: RoomRepo> RoomService for RoomServiceImpl<R> {
-    async fn create_room(&self, _: CreateRoomRequest) -> ServiceResult<CreateRoomResponse> {
+impl<R: RoomRepo + 'static> RoomService for RoomServiceImpl<R> {
+    fn join_url(&self) -> &str {
+        &self.cfg.join_url
+    }
+
+    fn max_file_size(&self) -> usize {
+        self.cfg.max_file_size
+    }
+
+    async fn create_room(&self, req: CreateRoomRequest) -> ServiceResult<CreateRoomResponse> {
         // Generate master password
         let master_password = generate_password(&self.cfg.password)?;
 
-        let repo_req = room_repo::CreateRoomRequest {
-            client_ids: Default::default(),
-            room_passwords: vec![(master_password, room_repo::RoomPasswordFeature::OneOff)]
-                .into_iter()
-                .collect(),
+        // Store only hash of it
+        let lookup_key = password::new_lookup_key();
+        let passphrase = matches!(self.cfg.password.mode, config::PasswordMode::Passphrase);
+        let hashed = password::hash(&lookup_key, &master_password, passphrase)?;
+
+        let mut room_cred = room_repo::RoomCredentials {
+            lookup_key,
+            passwords: vec![room_repo::RoomPassword {
+                hash: hashed.hash,
+                lookup_tag: hashed.lookup_tag,
+                passphrase,
+                feature: room_repo::RoomPasswordFeature::Master,
+            }],
         };
-        let repo_res = self.repo.create_room(repo_req).await?;
+
+        // Rolling join code is checked against its secret, there is nothing to hash
+        if req.join_code {
+            room_cred.passwords.push(room_repo::RoomPassword {
+                hash: String::new(),
+                lookup_tag: 0,
+                passphrase: false,
+                feature: room_repo::RoomPasswordFeature::Rolling {
+                    secret: password::new_join_code_secret(),
+                    step: self.cfg.password.expires,
+                    grace: self.cfg.password.join_code.grace,
+                    digits: self.cfg.password.join_code.digits,
+                },
+            });
+        }
+        let room_settings: room_repo::RoomSettings = req.room_settings.into();
+
+        // Ids collide with existing and recently deleted rooms. Sequential
+        // ones collide only after the range wraps around, nearly full range
+        // is not walked through
+        let mut created = None;
+        for _ in 0..MAX_ROOM_ID_ATTEMPTS {
+            let repo_req = room_repo::CreateRoomRequest {
+                room_id: self.new_room_id().await?,
+                client_ids: Default::default(),
+                room_cred: room_cred.clone(),
+                room_settings: room_settings.clone(),
+            };
+
+            match self.repo.create_room(repo_req).await {
+                Ok(repo_res) => {
+                    created = Some(repo_res);
+                    break;
+                }
+                Err(RepoError::AlreadyExists(_)) => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let repo_res = created
+            .ok_or_else(|| ServiceError::Exhausted(anyhow::anyhow!("no free room id left")))?;
 
         let res = CreateRoomResponse {
             room_id: repo_res.room_id,
-            room_cred: repo_res.room_cred.into(),
+            master_password,
+            room_settings: repo_res.room_settings.into(),
         };
 
         Ok(res)
     }
 
+    async fn delete_room(&self, req: DeleteRoomRequest) -> ServiceResult<()> {
+        let repo_req = room_repo::DeleteRoomRequest {
+            room_id: req.room_id,
+            deleted_at: Utc::now().naive_utc(),
+        };
+        self.repo.delete_room(repo_req).await?;
+
+        Ok(())
+    }
+
     async fn connect_room(&self, req: ConnectRoomRequest) -> ServiceResult<()> {
         let repo_req = room_repo::AddClientRequest {
             room_id: req.room_id,
@@ -59,41 +206,588 @@ impl<R: RoomRepo> RoomService for RoomServiceImpl<R> {
     }
 
     async fn add_file(&self, req: AddFileRequest) -> ServiceResult<AddFileResponse> {
+        let now = Utc::now().naive_utc();
+        if matches!(req.file_expires_at, Some(expires_at) if expires_at <= now) {
+            return Err(ServiceError::ValidationError(anyhow::anyhow!(
+                "file expiration time is in the past"
+            )));
+        }
+        if req.file_max_downloads == Some(0) {
+            return Err(ServiceError::ValidationError(anyhow::anyhow!(
+                "file max downloads must be positive"
+            )));
+        }
+
+        let mut removed_metadata = Vec::new();
+        let (file_size, file_blob) = match req.file_content {
+            FileContent::Absent { size } => (size, None),
+            FileContent::Bytes(mut content) => {
+                if content.len() > self.cfg.max_file_size {
+                    return Err(ServiceError::ValidationError(anyhow::anyhow!(
+                        "file is larger than {} bytes",
+                        self.cfg.max_file_size
+                    )));
+                }
+
+                // Content of e2ee room is ciphertext, there is nothing to strip
+                // and it does not compress
+                let e2ee = self.is_e2ee(req.room_id).await?;
+                if self.privacy.strip_image_metadata && !e2ee {
+                    let (stripped, removed) = metadata::strip_image_metadata(content)?;
+                    content = stripped;
+                    removed_metadata = removed;
+                }
+
+                let size = content.len();
+                let sha256 = Sha256::digest(&content).to_vec();
+                let compressed = match self.cfg.compression.enabled && !e2ee {
+                    true => compression::compress(&content, self.cfg.compression.level)?,
+                    false => None,
+                };
+                let (content, compression) = match compressed {
+                    Some(compressed) => (compressed, room_repo::BlobCompression::Zstd),
+                    None => (content, room_repo::BlobCompression::None),
+                };
+
+                let repo_req = room_repo::AddBlobRequest {
+                    room_id: req.room_id,
+                    content,
+                    compression,
+                    size,
+                    sha256,
+                };
+                let repo_res = self.repo.add_blob(repo_req).await?;
+
+                (repo_res.blob.size, Some(repo_res.blob))
+            }
+        };
+
         let repo_req = room_repo::AddFileRequest {
             room_id: req.room_id,
             file_name: req.file_name,
-            file_size: req.file_size,
+            file_size,
             file_mime_type: req.file_mime_type,
             file_source_client_id: req.file_source_client_id,
+            file_expires_at: req.file_expires_at,
+            file_uploaded_at: now,
+            file_max_versions: self.cfg.max_file_versions,
+            file_blob: file_blob.clone(),
+            file_max_downloads: req.file_max_downloads,
+        };
+        let repo_res = match self.repo.add_file(repo_req).await {
+            Ok(res) => res,
+            Err(err) => {
+                // Nothing refers to the content
+                if let Some(blob) = file_blob {
+                    let repo_req = room_repo::DeleteBlobRequest {
+                        room_id: req.room_id,
+                        blob_id: blob.id,
+                    };
+                    if let Err(err) = self.repo.delete_blob(repo_req).await {
+                        log::error!("delete blob error: {:?}", err);
+                    }
+                }
+                return Err(err.into());
+            }
         };
-        let repo_res = self.repo.add_file(repo_req).await?;
 
         let file = repo_res.file.into();
 
-        let res = AddFileResponse { file };
+        let res = AddFileResponse {
+            file,
+            removed_metadata,
+            expired_file_id: repo_res.expired_file_id,
+        };
 
         Ok(res)
     }
 
     async fn get_files(&self, req: GetFilesRequest) -> ServiceResult<GetFilesResponse> {
+        // Names and mime types of e2ee room are ciphertexts, server must not look into them
+        if (req.filter.mime_type_prefix.is_some() || req.filter.name_contains.is_some())
+            && self.is_e2ee(req.room_id).await?
+        {
+            return Err(ServiceError::ValidationError(anyhow::anyhow!(
+                "filtering by name or mime type is not supported in e2ee room"
+            )));
+        }
+
+        // Expired files are skipped by the page query, so pages stay full
+        let mut filter: room_repo::FileFilter = req.filter.into();
+        filter.unexpired_at = Some(Utc::now().naive_utc());
+
         let repo_req = room_repo::GetFilesRequest {
             room_id: req.room_id,
+            sort_by: req.sort_by.into(),
+            order: req.order.into(),
+            filter,
+            cursor: req.cursor,
+            limit: req.limit.clamp(1, MAX_FILES_LIMIT),
         };
         let repo_res = self.repo.get_files(repo_req).await?;
 
-        let files = repo_res
-            .files
+        let res = GetFilesResponse {
+            files: repo_res.files.into_iter().map(|f| f.into()).collect(),
+            next_cursor: repo_res.next_cursor,
+        };
+
+        Ok(res)
+    }
+
+    async fn get_file_content(
+        &self,
+        req: GetFileContentRequest,
+    ) -> ServiceResult<GetFileContentResponse> {
+        let mut file = self.get_unexpired_file(req.room_id, req.file_id).await?;
+
+        let version = match req.file_version {
+            None => file.versions.last(),
+            Some(number) => file.versions.iter().find(|v| v.number == number),
+        }
+        .cloned()
+        .ok_or_else(|| {
+            ServiceError::RepoError(RepoError::NotFound(anyhow::anyhow!(
+                "file with id={} has no such version",
+                req.file_id
+            )))
+        })?;
+
+        // Only metadata of the file was added
+        let blob = version.blob.clone().ok_or_else(|| {
+            ServiceError::RepoError(RepoError::NotFound(anyhow::anyhow!(
+                "file with id={} version={} has no content",
+                req.file_id,
+                version.number
+            )))
+        })?;
+
+        let reader = Box::new(BlobReader {
+            repo: Arc::clone(&self.repo),
+            room_id: req.room_id,
+            blob: blob.clone(),
+            index: 0,
+            read: 0,
+        });
+        let accepts = |encoding| req.accept_encodings.contains(&encoding);
+        let (mut content, content_encoding, content_size): (Box<dyn FileContentReader>, _, _) =
+            match blob.compression {
+                room_repo::BlobCompression::None => (reader, None, Some(blob.size)),
+                room_repo::BlobCompression::Zstd if accepts(ContentEncoding::Zstd) => {
+                    (reader, Some(ContentEncoding::Zstd), Some(blob.stored_size))
+                }
+                room_repo::BlobCompression::Zstd => {
+                    let decoded = Box::new(ZstdReader {
+                        inner: reader,
+                        decoder: ZstdDecoder::new()?,
+                        blob_id: blob.id,
+                        size: blob.size,
+                        read: 0,
+                    });
+                    // Content compresses well, or it would not be stored compressed
+                    match accepts(ContentEncoding::Gzip) {
+                        true => {
+                            let encoded = Box::new(GzipReader {
+                                inner: decoded,
+                                encoder: Some(GzipEncoder::new()),
+                            });
+                            (encoded, Some(ContentEncoding::Gzip), None)
+                        }
+                        false => (decoded, None, Some(blob.size)),
+                    }
+                }
+            };
+
+        // Download is counted before the content is sent
+        let mut file_removed = false;
+        if file.downloads_left.is_some() {
+            let repo_req = room_repo::TakeFileDownloadRequest {
+                room_id: req.room_id,
+                file_id: req.file_id,
+            };
+            let repo_res = self.repo.take_file_download(repo_req).await?;
+            file = repo_res.file;
+            file_removed = repo_res.removed;
+        }
+
+        // Content of the last download is read before its blobs go
+        if file_removed {
+            let mut chunks = VecDeque::new();
+            while let Some(chunk) = content.next_chunk().await? {
+                chunks.push_back(chunk);
+            }
+            self.delete_blobs(req.room_id, &file).await?;
+            content = Box::new(BufferedReader { chunks });
+        }
+
+        let res = GetFileContentResponse {
+            file: file.into(),
+            version: version.into(),
+            e2ee: self.is_e2ee(req.room_id).await?,
+            content,
+            content_encoding,
+            content_size,
+            file_removed,
+        };
+
+        Ok(res)
+    }
+
+    async fn get_archive_files(
+        &self,
+        req: GetArchiveFilesRequest,
+    ) -> ServiceResult<GetArchiveFilesResponse> {
+        let e2ee = self.is_e2ee(req.room_id).await?;
+
+        let files = match req.selection {
+            ArchiveSelection::Files(file_ids) => {
+                if file_ids.is_empty() || file_ids.len() > MAX_FILES_LIMIT {
+                    return Err(ServiceError::ValidationError(anyhow::anyhow!(
+                        "archive must have from 1 to {} files",
+                        MAX_FILES_LIMIT
+                    )));
+                }
+
+                let mut seen = HashSet::new();
+                let mut files = Vec::with_capacity(file_ids.len());
+                for file_id in file_ids {
+                    if seen.insert(file_id) {
+                        files.push(self.get_unexpired_file(req.room_id, file_id).await?);
+                    }
+                }
+                files
+            }
+            ArchiveSelection::Directory(directory) => {
+                // Names of e2ee room are ciphertexts, they have no directories
+                if e2ee {
+                    return Err(ServiceError::ValidationError(anyhow::anyhow!(
+                        "selecting directory is not supported in e2ee room"
+                    )));
+                }
+
+                let directory = directory.trim_matches('/');
+                let filter = room_repo::FileFilter {
+                    name_prefix: match directory.is_empty() {
+                        true => None,
+                        false => Some(format!("{}/", directory)),
+                    },
+                    unexpired_at: Some(Utc::now().naive_utc()),
+                    ..Default::default()
+                };
+
+                let mut files = Vec::new();
+                let mut cursor = None;
+                loop {
+                    let repo_req = room_repo::GetFilesRequest {
+                        room_id: req.room_id,
+                        sort_by: room_repo::FileSortBy::Name,
+                        order: room_repo::SortOrder::Asc,
+                        filter: filter.clone(),
+                        cursor,
+                        limit: MAX_FILES_LIMIT,
+                    };
+                    let repo_res = self.repo.get_files(repo_req).await?;
+                    files.extend(repo_res.files);
+
+                    cursor = match repo_res.next_cursor {
+                        None => break,
+                        c => c,
+                    };
+                }
+                files
+            }
+        };
+
+        let files = files
             .into_iter()
-            .map(|(k, v)| (k, v.into()))
+            .filter(|f| f.blob.is_some())
+            .map(|f| f.into())
             .collect();
 
-        let res = GetFilesResponse { files };
+        let res = GetArchiveFilesResponse { files, e2ee };
+
+        Ok(res)
+    }
+
+    async fn get_archive_limits(
+        &self,
+        req: GetArchiveLimitsRequest,
+    ) -> ServiceResult<GetArchiveLimitsResponse> {
+        if self.is_e2ee(req.room_id).await? {
+            return Err(ServiceError::ValidationError(anyhow::anyhow!(
+                "unpacking archive is not supported in e2ee room"
+            )));
+        }
+
+        let limits = ArchiveLimits {
+            max_size: self.cfg.archive.max_size,
+            max_total_size: self.cfg.archive.max_total_size,
+            max_ratio: self.cfg.archive.max_ratio,
+            max_entries: self.cfg.archive.max_entries,
+            max_entry_size: self.cfg.max_file_size,
+        };
+
+        Ok(GetArchiveLimitsResponse { limits })
+    }
+
+    async fn get_file_versions(
+        &self,
+        req: GetFileVersionsRequest,
+    ) -> ServiceResult<GetFileVersionsResponse> {
+        let file = self.get_unexpired_file(req.room_id, req.file_id).await?;
+
+        let versions = file.versions.into_iter().map(|v| v.into()).collect();
+
+        let res = GetFileVersionsResponse { versions };
+
+        Ok(res)
+    }
+
+    async fn restore_file_version(
+        &self,
+        req: RestoreFileVersionRequest,
+    ) -> ServiceResult<RestoreFileVersionResponse> {
+        self.get_unexpired_file(req.room_id, req.file_id).await?;
+
+        let repo_req = room_repo::RestoreFileVersionRequest {
+            room_id: req.room_id,
+            file_id: req.file_id,
+            file_version: req.file_version,
+            file_source_client_id: req.client_id,
+            file_uploaded_at: Utc::now().naive_utc(),
+            file_max_versions: self.cfg.max_file_versions,
+        };
+        let repo_res = self.repo.restore_file_version(repo_req).await?;
+
+        let file = repo_res.file.into();
+
+        let res = RestoreFileVersionResponse { file };
 
         Ok(res)
     }
+
+    async fn get_join_code(&self, req: GetJoinCodeRequest) -> ServiceResult<GetJoinCodeResponse> {
+        let repo_req = room_repo::GetRoomCredentialsRequest {
+            room_id: req.room_id,
+        };
+        let repo_res = self.repo.get_room_credentials(repo_req).await?;
+
+        let now = Utc::now().timestamp();
+        let join_code = repo_res
+            .room_cred
+            .passwords
+            .into_iter()
+            .find_map(|p| match p.feature {
+                room_repo::RoomPasswordFeature::Rolling {
+                    secret,
+                    step,
+                    digits,
+                    ..
+                } => {
+                    let step = step.max(1);
+                    let counter = now.div_euclid(step);
+                    Some(JoinCode {
+                        code: password::join_code(&secret, counter, digits),
+                        expires_at: NaiveDateTime::from_timestamp((counter + 1) * step, 0),
+                    })
+                }
+                _ => None,
+            });
+
+        let res = GetJoinCodeResponse { join_code };
+
+        Ok(res)
+    }
+
+    async fn create_invite(&self, req: CreateInviteRequest) -> ServiceResult<CreateInviteResponse> {
+        // Invite password is not typed by humans, so make it long
+        let invite_password = password::new_invite_password();
+
+        let repo_req = room_repo::GetRoomCredentialsRequest {
+            room_id: req.room_id,
+        };
+        let repo_res = self.repo.get_room_credentials(repo_req).await?;
+        let hashed = password::hash(&repo_res.room_cred.lookup_key, &invite_password, false)?;
+
+        // Unused invites expire, so shown QR codes do not pile up in the room
+        let now = Utc::now().naive_utc();
+        let expires_at = now + Duration::seconds(self.cfg.qr_password_expires);
+        let repo_req = room_repo::AddRoomPasswordRequest {
+            room_id: req.room_id,
+            room_password: room_repo::RoomPassword {
+                hash: hashed.hash,
+                lookup_tag: hashed.lookup_tag,
+                passphrase: false,
+                feature: room_repo::RoomPasswordFeature::Invite {
+                    expires_in: expires_at,
+                },
+            },
+            expired_before: now,
+            max_invites: self.cfg.max_active_invites,
+        };
+        if !self.repo.add_room_password(repo_req).await? {
+            return Err(ServiceError::ValidationError(anyhow::anyhow!(
+                "room already has {} active invites",
+                self.cfg.max_active_invites
+            )));
+        }
+
+        // Fragment is not sent to servers, so password does not get into logs
+        let join_url = format!(
+            "{}#room_id={}&password={}",
+            self.cfg.join_url, req.room_id, invite_password
+        );
+
+        let res = CreateInviteResponse {
+            room_password: invite_password,
+            join_url,
+            expires_at,
+        };
+
+        Ok(res)
+    }
+
+    async fn delete_expired(
+        &self,
+        req: DeleteExpiredRequest,
+    ) -> ServiceResult<DeleteExpiredResponse> {
+        let repo_req = room_repo::DeleteExpiredTombstonesRequest {
+            deleted_before: req.now - Duration::seconds(self.cfg.tombstone_expires),
+        };
+        let deleted_tombstones = self.repo.delete_expired_tombstones(repo_req).await?;
+
+        let repo_req = room_repo::DeleteExpiredFilesRequest {
+            expired_before: req.now,
+        };
+        let removed_files = self.repo.delete_expired_files(repo_req).await?;
+
+        log::debug!(
+            "expired room data deleted, tombstones={}, files={}",
+            deleted_tombstones,
+            removed_files.len()
+        );
+
+        let res = DeleteExpiredResponse { removed_files };
+
+        Ok(res)
+    }
+}
+
+/// Reads stored content of file version chunk by chunk, content cut short,
+/// e.g. by concurrent deletion, is an error
+struct BlobReader<R: RoomRepo> {
+    repo: Arc<R>,
+    room_id: RoomId,
+    blob: room_repo::Blob,
+    index: u32,
+    read: usize,
+}
+
+#[async_trait::async_trait]
+impl<R: RoomRepo + 'static> FileContentReader for BlobReader<R> {
+    async fn next_chunk(&mut self) -> ServiceResult<Option<Vec<u8>>> {
+        let repo_req = room_repo::GetBlobChunkRequest {
+            room_id: self.room_id,
+            blob_id: self.blob.id,
+            index: self.index,
+        };
+        let chunk = self.repo.get_blob_chunk(repo_req).await?;
+
+        match chunk {
+            Some(chunk) if self.read + chunk.len() <= self.blob.stored_size => {
+                self.index += 1;
+                self.read += chunk.len();
+                Ok(Some(chunk))
+            }
+            None if self.read == self.blob.stored_size => Ok(None),
+            _ => Err(ServiceError::CommonError(anyhow::anyhow!(
+                "content of blob with id={} does not match its size",
+                self.blob.id
+            ))),
+        }
+    }
+}
+
+/// Decompresses content read from blob stored with zstd
+struct ZstdReader {
+    inner: Box<dyn FileContentReader>,
+    decoder: ZstdDecoder,
+    blob_id: room_repo::BlobId,
+    size: usize,
+    read: usize,
+}
+
+#[async_trait::async_trait]
+impl FileContentReader for ZstdReader {
+    async fn next_chunk(&mut self) -> ServiceResult<Option<Vec<u8>>> {
+        while let Some(chunk) = self.inner.next_chunk().await? {
+            let chunk = self.decoder.decode(&chunk)?;
+            if chunk.is_empty() {
+                continue;
+            }
+
+            self.read += chunk.len();
+            if self.read > self.size {
+                break;
+            }
+            return Ok(Some(chunk));
+        }
+
+        match self.read == self.size {
+            true => Ok(None),
+            false => Err(ServiceError::CommonError(anyhow::anyhow!(
+                "decompressed content of blob with id={} does not match its size",
+                self.blob_id
+            ))),
+        }
+    }
+}
+
+/// Compresses content with gzip as it is read
+struct GzipReader {
+    inner: Box<dyn FileContentReader>,
+    /// Absent after the last chunk
+    encoder: Option<GzipEncoder>,
+}
+
+#[async_trait::async_trait]
+impl FileContentReader for GzipReader {
+    async fn next_chunk(&mut self) -> ServiceResult<Option<Vec<u8>>> {
+        let encoder = match self.encoder.as_mut() {
+            None => return Ok(None),
+            Some(encoder) => encoder,
+        };
+
+        while let Some(chunk) = self.inner.next_chunk().await? {
+            let chunk = encoder.encode(&chunk)?;
+            if !chunk.is_empty() {
+                return Ok(Some(chunk));
+            }
+        }
+
+        match self.encoder.take() {
+            None => Ok(None),
+            Some(encoder) => Ok(Some(encoder.finish()?)),
+        }
+    }
+}
+
+/// Content which is already read, e.g. of deleted file
+struct BufferedReader {
+    chunks: VecDeque<Vec<u8>>,
+}
+
+#[async_trait::async_trait]
+impl FileContentReader for BufferedReader {
+    async fn next_chunk(&mut self) -> ServiceResult<Option<Vec<u8>>> {
+        Ok(self.chunks.pop_front())
+    }
 }
 
 fn generate_password(password_settings: &config::Password) -> ServiceResult<String> {
+    if let config::PasswordMode::Passphrase = password_settings.mode {
+        return Ok(generate_passphrase(&password_settings.passphrase));
+    }
+
     let generator = passwords::PasswordGenerator {
         length: password_settings.length,
         numbers: password_settings.use_numbers,
@@ -110,26 +804,49 @@ fn generate_password(password_settings: &config::Password) -> ServiceResult<Stri
         .map_err(|err| ServiceError::CommonError(anyhow::anyhow!(err)))
 }
 
+fn generate_passphrase(passphrase_settings: &config::Passphrase) -> String {
+    let word_list = bip39::Language::English.word_list();
+    let mut rng = rand::thread_rng();
+
+    (0..passphrase_settings.words.max(1))
+        .map(|_| {
+            let word = word_list[rng.gen_range(0..word_list.len())];
+            match passphrase_settings.capitalize {
+                true => word[..1].to_uppercase() + &word[1..],
+                false => word.to_owned(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(&passphrase_settings.separator)
+}
+
 impl From<room_repo::RoomPasswordFeature> for RoomPasswordFeature {
     fn from(f: room_repo::RoomPasswordFeature) -> Self {
         match f {
+            room_repo::RoomPasswordFeature::Master => RoomPasswordFeature::Master,
             room_repo::RoomPasswordFeature::OneOff => RoomPasswordFeature::OneOff,
             room_repo::RoomPasswordFeature::Expiring { expires_in } => {
                 RoomPasswordFeature::Expiring { expires_in }
             }
+            room_repo::RoomPasswordFeature::Invite { expires_in } => {
+                RoomPasswordFeature::Invite { expires_in }
+            }
+            room_repo::RoomPasswordFeature::Rolling { step, .. } => {
+                RoomPasswordFeature::Rolling { step }
+            }
         }
     }
 }
 
-impl From<room_repo::RoomCredentials> for RoomCredentials {
-    fn from(f: room_repo::RoomCredentials) -> Self {
-        Self {
-            passwords: f
-                .passwords
-                .into_iter()
-                .map(|(p, f)| (p, f.into()))
-                .collect(),
-        }
+impl From<room_repo::RoomSettings> for RoomSettings {
+    fn from(f: room_repo::RoomSettings) -> Self {
+        Self { e2ee: f.e2ee }
+    }
+}
+
+impl From<RoomSettings> for room_repo::RoomSettings {
+    fn from(f: RoomSettings) -> Self {
+        Self { e2ee: f.e2ee }
     }
 }
 
@@ -141,6 +858,57 @@ impl From<room_repo::File> for File {
             size: f.size,
             mime_type: f.mime_type,
             source_client_id: f.source_client_id,
+            expires_at: f.expires_at,
+            uploaded_at: f.uploaded_at,
+            version: f.version,
+            sha256: f.blob.map(|b| b.sha256),
+            downloads_left: f.downloads_left,
+            versions: f.versions.into_iter().map(|v| v.into()).collect(),
+        }
+    }
+}
+
+impl From<room_repo::FileVersion> for FileVersion {
+    fn from(f: room_repo::FileVersion) -> Self {
+        Self {
+            number: f.number,
+            size: f.size,
+            mime_type: f.mime_type,
+            source_client_id: f.source_client_id,
+            uploaded_at: f.uploaded_at,
+            sha256: f.blob.map(|b| b.sha256),
+        }
+    }
+}
+
+impl From<FileSortBy> for room_repo::FileSortBy {
+    fn from(f: FileSortBy) -> Self {
+        match f {
+            FileSortBy::Name => room_repo::FileSortBy::Name,
+            FileSortBy::Size => room_repo::FileSortBy::Size,
+            FileSortBy::UploadedAt => room_repo::FileSortBy::UploadedAt,
+            FileSortBy::SourceClientId => room_repo::FileSortBy::SourceClientId,
+        }
+    }
+}
+
+impl From<SortOrder> for room_repo::SortOrder {
+    fn from(f: SortOrder) -> Self {
+        match f {
+            SortOrder::Asc => room_repo::SortOrder::Asc,
+            SortOrder::Desc => room_repo::SortOrder::Desc,
+        }
+    }
+}
+
+impl From<FileFilter> for room_repo::FileFilter {
+    fn from(f: FileFilter) -> Self {
+        Self {
+            mime_type_prefix: f.mime_type_prefix,
+            source_client_id: f.source_client_id,
+            name_contains: f.name_contains,
+            name_prefix: None,
+            unexpired_at: None,
         }
     }
 }
@@ -0,0 +1,93 @@
+use crate::config;
+
+use actix_web::HttpRequest;
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+
+/// Resolves address of the client behind trusted reverse proxies.
+///
+/// Forwarding headers are set by clients as they like, so they are read only
+/// if the peer is a trusted proxy, and only up to the first hop which is not
+/// one: every proxy appends the address it got the request from
+pub struct ClientIpResolver {
+    trusted_proxies: HashSet<IpAddr>,
+}
+
+impl ClientIpResolver {
+    pub fn new(cfg: &config::Server) -> Self {
+        Self {
+            trusted_proxies: cfg
+                .trusted_proxies
+                .iter()
+                .map(|ip| canonical(*ip))
+                .collect(),
+        }
+    }
+
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<IpAddr> {
+        let mut client_ip = canonical(req.peer_addr()?.ip());
+
+        for hop in forwarded_for(req).into_iter().rev() {
+            if !self.trusted_proxies.contains(&client_ip) {
+                break;
+            }
+            match hop {
+                Some(ip) => client_ip = canonical(ip),
+                // Obfuscated or unknown hop, the proxy is the last known one
+                None => break,
+            }
+        }
+
+        Some(client_ip)
+    }
+}
+
+/// Hops from the client to the last proxy, `Forwarded` (RFC 7239) is
+/// preferred over `X-Forwarded-For`
+fn forwarded_for(req: &HttpRequest) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<&str> = req
+        .headers()
+        .get_all(http::header::FORWARDED)
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+                name.eq_ignore_ascii_case("for").then_some(value)
+            })
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded.into_iter().map(parse_node).collect();
+    }
+
+    req.headers()
+        .get_all("x-forwarded-for")
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(parse_node)
+        .collect()
+}
+
+/// Node is an address, optionally quoted, bracketed and with port, e.g.
+/// `"[2001:db8::1]:4711"`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')
+        .and_then(|n| n.strip_suffix(']'))
+        .and_then(|n| n.parse().ok())
+}
+
+/// Dual-stack socket reports IPv4 clients as `::ffff:a.b.c.d`
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
@@ -0,0 +1,63 @@
+use crate::config;
+
+use actix_web::cookie::{CookieBuilder, SameSite};
+
+/// Attributes of auth cookies and origins allowed to get them, resolved
+/// for the environment
+pub struct CookiePolicy {
+    secure: bool,
+    same_site: SameSite,
+    domain: Option<String>,
+    allowed_origins: Vec<String>,
+}
+
+impl CookiePolicy {
+    pub fn new(cfg: &config::Auth, env: &config::Environment) -> anyhow::Result<Self> {
+        let prod = matches!(env, config::Environment::Prod);
+
+        let secure = cfg.cookie.secure.unwrap_or(prod);
+        let same_site = match cfg.cookie.same_site {
+            Some(config::CookieSameSite::Strict) => SameSite::Strict,
+            Some(config::CookieSameSite::Lax) => SameSite::Lax,
+            Some(config::CookieSameSite::None) => SameSite::None,
+            None if prod => SameSite::Strict,
+            None => SameSite::Lax,
+        };
+
+        // Browsers drop such cookies
+        if same_site == SameSite::None && !secure {
+            return Err(anyhow::anyhow!("same_site none cookie must be secure"));
+        }
+
+        Ok(Self {
+            secure,
+            same_site,
+            domain: cfg.cookie.domain.clone(),
+            allowed_origins: cfg
+                .allowed_origins
+                .iter()
+                .map(|o| o.trim_end_matches('/').to_owned())
+                .collect(),
+        })
+    }
+
+    /// Sets `Secure`, `SameSite` and `Domain` of the cookie
+    pub fn apply<'c>(&self, cookie: CookieBuilder<'c>) -> CookieBuilder<'c> {
+        let cookie = cookie.secure(self.secure).same_site(self.same_site);
+        match &self.domain {
+            Some(domain) => cookie.domain(domain.clone()),
+            None => cookie,
+        }
+    }
+
+    /// Like `apply`, but `SameSite` is always `Strict`
+    pub fn apply_strict<'c>(&self, cookie: CookieBuilder<'c>) -> CookieBuilder<'c> {
+        self.apply(cookie).same_site(SameSite::Strict)
+    }
+
+    /// `own_origin` is the one the API is served from
+    pub fn is_origin_allowed(&self, origin: &str, own_origin: &str) -> bool {
+        let origin = origin.trim_end_matches('/');
+        origin == own_origin || self.allowed_origins.iter().any(|o| o == origin)
+    }
+}
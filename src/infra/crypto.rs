@@ -0,0 +1,231 @@
+use crate::config;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 24;
+
+/// Seals and opens values stored in sled.
+///
+/// `Plain` is used when encryption at rest is disabled, then values are
+/// stored as is.
+#[derive(Clone)]
+pub enum Cipher {
+    Plain,
+    XChaCha20Poly1305 {
+        aead: Arc<XChaCha20Poly1305>,
+        /// Key of `tag`, derived from the data key
+        tag_key: Arc<[u8]>,
+    },
+}
+
+impl Cipher {
+    pub fn is_plain(&self) -> bool {
+        matches!(self, Cipher::Plain)
+    }
+
+    /// Keyed deterministic tag of `value`, usable in keys for equality
+    /// lookups. Order of values is lost, `Plain` returns the value as is
+    pub fn tag(&self, value: &[u8]) -> Vec<u8> {
+        match self {
+            Cipher::Plain => value.to_vec(),
+            Cipher::XChaCha20Poly1305 { tag_key, .. } => hmac_sha256(tag_key, value),
+        }
+    }
+
+    /// Encrypts `plaintext`, output is `nonce | ciphertext`.
+    /// `aad` binds the value to its location, e.g. tree name and key
+    pub fn seal(&self, plaintext: &[u8], aad: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Cipher::Plain => Ok(plaintext.to_vec()),
+            Cipher::XChaCha20Poly1305 { aead, .. } => {
+                let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+                let ciphertext = aead
+                    .encrypt(
+                        &nonce,
+                        Payload {
+                            msg: plaintext,
+                            aad,
+                        },
+                    )
+                    .map_err(|_| anyhow::anyhow!("encryption failed"))?;
+
+                let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+                sealed.extend_from_slice(&nonce);
+                sealed.extend_from_slice(&ciphertext);
+                Ok(sealed)
+            }
+        }
+    }
+
+    pub fn open(&self, sealed: &[u8], aad: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Cipher::Plain => Ok(sealed.to_vec()),
+            Cipher::XChaCha20Poly1305 { aead, .. } => {
+                if sealed.len() < NONCE_LEN {
+                    return Err(anyhow::anyhow!("sealed value is too short"));
+                }
+
+                let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+                aead.decrypt(
+                    XNonce::from_slice(nonce),
+                    Payload {
+                        msg: ciphertext,
+                        aad,
+                    },
+                )
+                .map_err(|_| anyhow::anyhow!("decryption failed"))
+            }
+        }
+    }
+}
+
+/// Envelope encryption key store.
+///
+/// Every scope (room, auth clients, ...) has its own random data key,
+/// stored in the `crypto-keys` tree wrapped by the server master key.
+/// Removing a data key makes all values sealed by it unreadable.
+pub struct KeyStore {
+    master: Option<Cipher>,
+    keys_tree: sled::Tree,
+    cache: RwLock<HashMap<Vec<u8>, Cipher>>,
+}
+
+impl KeyStore {
+    pub fn new(sled_db: sled::Db, cfg: &config::Encryption) -> anyhow::Result<Self> {
+        let master = match cfg.enabled {
+            true => Some(new_cipher(&load_master_key(cfg)?)),
+            false => None,
+        };
+
+        let keys_tree = sled_db.open_tree("crypto-keys")?;
+
+        Ok(Self {
+            master,
+            keys_tree,
+            cache: Default::default(),
+        })
+    }
+
+    /// Returns cipher of the scope, fails if it has no data key, e.g. the
+    /// key is erased
+    pub fn get_cipher(&self, scope: &[u8]) -> anyhow::Result<Cipher> {
+        let master = match &self.master {
+            None => return Ok(Cipher::Plain),
+            Some(m) => m,
+        };
+
+        if let Some(cipher) = self.cache.read().expect("poisoned lock").get(scope) {
+            return Ok(cipher.clone());
+        }
+
+        let wrapped = self
+            .keys_tree
+            .get(scope)?
+            .ok_or_else(|| anyhow::anyhow!("no data key of the scope"))?;
+        let data_key = master.open(wrapped.as_ref(), scope)?;
+
+        Ok(self.cache_cipher(scope, &data_key))
+    }
+
+    /// Creates new data key of the scope. Key left from the previous owner
+    /// of the scope, e.g. room with the same id, is replaced
+    pub fn create_key(&self, scope: &[u8]) -> anyhow::Result<Cipher> {
+        let master = match &self.master {
+            None => return Ok(Cipher::Plain),
+            Some(m) => m,
+        };
+
+        let data_key = XChaCha20Poly1305::generate_key(&mut OsRng).to_vec();
+        self.keys_tree
+            .insert(scope, master.seal(&data_key, scope)?)?;
+
+        Ok(self.cache_cipher(scope, &data_key))
+    }
+
+    /// Returns cipher of the scope which lives as long as the store, creates
+    /// its data key on first use
+    pub fn get_or_create_key(&self, scope: &[u8]) -> anyhow::Result<Cipher> {
+        let master = match &self.master {
+            None => return Ok(Cipher::Plain),
+            Some(m) => m,
+        };
+
+        let data_key = XChaCha20Poly1305::generate_key(&mut OsRng).to_vec();
+        let wrapped = master.seal(&data_key, scope)?;
+
+        // Somebody else could create the key concurrently, use the stored one then
+        match self
+            .keys_tree
+            .compare_and_swap(scope, None as Option<&[u8]>, Some(wrapped))?
+        {
+            Ok(()) => Ok(self.cache_cipher(scope, &data_key)),
+            Err(_) => self.get_cipher(scope),
+        }
+    }
+
+    fn cache_cipher(&self, scope: &[u8], data_key: &[u8]) -> Cipher {
+        let cipher = new_cipher(data_key);
+        self.cache
+            .write()
+            .expect("poisoned lock")
+            .insert(scope.to_vec(), cipher.clone());
+        cipher
+    }
+
+    /// Removes data key of the scope, values sealed by it become unreadable
+    pub fn erase(&self, scope: &[u8]) -> anyhow::Result<()> {
+        self.keys_tree.remove(scope)?;
+        self.cache.write().expect("poisoned lock").remove(scope);
+
+        Ok(())
+    }
+}
+
+/// Binds sealed value to its tree and key, so it can not be moved to other place
+pub fn value_aad(tree: &sled::Tree, key: &[u8]) -> Vec<u8> {
+    let mut aad = tree.name().to_vec();
+    aad.extend_from_slice(key);
+    aad
+}
+
+fn new_cipher(key: &[u8]) -> Cipher {
+    Cipher::XChaCha20Poly1305 {
+        aead: Arc::new(XChaCha20Poly1305::new(Key::from_slice(key))),
+        tag_key: hmac_sha256(key, b"ezspot/tag").into(),
+    }
+}
+
+fn hmac_sha256(key: &[u8], value: &[u8]) -> Vec<u8> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts key of any size");
+    mac.update(value);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn load_master_key(cfg: &config::Encryption) -> anyhow::Result<Vec<u8>> {
+    let encoded = match (&cfg.master_key, &cfg.master_key_file) {
+        (Some(key), _) => key.clone(),
+        (None, Some(path)) => std::fs::read_to_string(path)?,
+        (None, None) => {
+            return Err(anyhow::anyhow!(
+                "encryption enabled but neither master_key nor master_key_file is set"
+            ))
+        }
+    };
+
+    let key = base64::decode(encoded.trim())?;
+    if key.len() != KEY_LEN {
+        return Err(anyhow::anyhow!(
+            "master key must be {} bytes, got {}",
+            KEY_LEN,
+            key.len()
+        ));
+    }
+
+    Ok(key)
+}
@@ -0,0 +1,244 @@
+use crate::config;
+
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Expired proofs are cleaned up once there are more seen ones
+const SEEN_PROOFS_CLEANUP: usize = 10_000;
+const MAX_JTI_LEN: usize = 128;
+
+/// Verifies DPoP proofs (RFC 9449).
+///
+/// Proof is a JWT signed by client key, public part of which is in the
+/// header. It is bound to method and URL of the request, and to access
+/// token if there is one. Every proof is accepted only once.
+///
+/// Accepted proofs are remembered per key, and proofs without token are
+/// kept apart from bound ones: anyone can sign logins with fresh keys, and
+/// that must not lock out clients which are already logged in
+pub struct DpopVerifier {
+    max_age: i64,
+    leeway: i64,
+    max_proofs_per_key: usize,
+    max_login_proofs: usize,
+    seen_login: Mutex<SeenProofs>,
+    seen_bound: Mutex<SeenProofs>,
+}
+
+#[derive(Default)]
+struct SeenProofs {
+    /// Key thumbprint to `jti` to `iat` of accepted proofs
+    by_key: HashMap<String, HashMap<String, i64>>,
+    len: usize,
+    cleanup_at: usize,
+}
+
+pub struct VerifyProofRequest<'a> {
+    pub proof: &'a str,
+    pub method: &'a str,
+    /// Without query and fragment
+    pub url: &'a str,
+    pub access_token: Option<&'a str>,
+    /// Thumbprint of the key access token is bound to
+    pub jkt: Option<&'a str>,
+}
+
+#[derive(serde::Deserialize)]
+struct ProofHeader {
+    typ: String,
+    alg: String,
+    jwk: ProofJwk,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(tag = "kty")]
+enum ProofJwk {
+    #[serde(rename = "EC")]
+    Ec { crv: String, x: String, y: String },
+    #[serde(rename = "RSA")]
+    Rsa { n: String, e: String },
+}
+
+#[derive(serde::Deserialize)]
+struct ProofClaims {
+    jti: String,
+    htm: String,
+    htu: String,
+    iat: i64,
+    ath: Option<String>,
+}
+
+impl DpopVerifier {
+    pub fn new(cfg: &config::Auth) -> Self {
+        Self {
+            max_age: cfg.dpop.max_age,
+            leeway: cfg.leeway as i64,
+            max_proofs_per_key: cfg.dpop.max_proofs_per_key,
+            max_login_proofs: cfg.dpop.max_login_proofs,
+            seen_login: Default::default(),
+            seen_bound: Default::default(),
+        }
+    }
+
+    /// Returns JWK thumbprint (RFC 7638) of the proof key
+    pub fn verify(&self, req: VerifyProofRequest) -> anyhow::Result<String> {
+        let header = req
+            .proof
+            .split('.')
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("invalid dpop proof"))?;
+        let header: ProofHeader = serde_json::from_slice(&b64_decode(header)?)?;
+
+        if header.typ != "dpop+jwt" {
+            return Err(anyhow::anyhow!("invalid dpop proof type"));
+        }
+
+        let (alg, key) = match (header.alg.as_str(), &header.jwk) {
+            ("ES256", ProofJwk::Ec { crv, x, y }) if crv == "P-256" => {
+                // Uncompressed point: 0x04 | x | y
+                let mut point = vec![0x04];
+                point.extend(b64_decode(x)?);
+                point.extend(b64_decode(y)?);
+                if point.len() != 65 {
+                    return Err(anyhow::anyhow!("invalid dpop proof key"));
+                }
+                (Algorithm::ES256, DecodingKey::from_ec_der(&point))
+            }
+            ("RS256", ProofJwk::Rsa { n, e }) => {
+                (Algorithm::RS256, DecodingKey::from_rsa_components(n, e)?)
+            }
+            _ => return Err(anyhow::anyhow!("unsupported dpop proof algorithm")),
+        };
+
+        let mut validation = Validation::new(alg);
+        validation.validate_exp = false;
+        // Proof is bounded by `iat` below, it has no `exp`
+        validation.required_spec_claims.clear();
+        let claims = jsonwebtoken::decode::<ProofClaims>(req.proof, &key, &validation)?.claims;
+
+        let htu = claims.htu.split(['?', '#']).next().unwrap_or_default();
+        if claims.htm != req.method || htu != req.url {
+            return Err(anyhow::anyhow!("dpop proof is for another request"));
+        }
+
+        let now = Utc::now().timestamp();
+        if claims.iat > now + self.leeway || claims.iat < now - self.max_age - self.leeway {
+            return Err(anyhow::anyhow!("dpop proof is expired"));
+        }
+
+        if let Some(access_token) = req.access_token {
+            let ath = b64_encode(&Sha256::digest(access_token.as_bytes()));
+            if claims.ath.as_deref() != Some(ath.as_str()) {
+                return Err(anyhow::anyhow!("dpop proof is for another access token"));
+            }
+        }
+
+        if claims.jti.is_empty() || claims.jti.len() > MAX_JTI_LEN {
+            return Err(anyhow::anyhow!("invalid dpop proof id"));
+        }
+
+        let jkt = thumbprint(&header.jwk);
+        if req.jkt.is_some_and(|expected| expected != jkt) {
+            return Err(anyhow::anyhow!("dpop proof is signed by another key"));
+        }
+
+        // Replay check is the last one, so rejected proofs are not remembered
+        let (seen, max_proofs) = match req.access_token {
+            Some(_) => (&self.seen_bound, usize::MAX),
+            None => (&self.seen_login, self.max_login_proofs),
+        };
+        let oldest = now - self.max_age - self.leeway;
+        seen.lock().expect("dpop lock poisoned").insert(
+            &jkt,
+            claims.jti,
+            claims.iat,
+            oldest,
+            SeenProofsLimits {
+                per_key: self.max_proofs_per_key,
+                total: max_proofs,
+            },
+        )?;
+
+        Ok(jkt)
+    }
+}
+
+struct SeenProofsLimits {
+    per_key: usize,
+    total: usize,
+}
+
+impl SeenProofs {
+    /// Proofs still in their window can not be forgotten, or they could be
+    /// replayed, so new ones are refused until old ones expire
+    fn insert(
+        &mut self,
+        jkt: &str,
+        jti: String,
+        iat: i64,
+        oldest: i64,
+        limits: SeenProofsLimits,
+    ) -> anyhow::Result<()> {
+        if self.by_key.get(jkt).is_some_and(|s| s.contains_key(&jti)) {
+            return Err(anyhow::anyhow!("dpop proof is replayed"));
+        }
+
+        if self.len >= limits.total || self.len >= self.cleanup_at.max(SEEN_PROOFS_CLEANUP) {
+            self.delete_expired(oldest);
+            // Proofs of many active keys are all fresh, cleanup is amortized
+            self.cleanup_at = self.len * 2;
+        }
+        if self.len >= limits.total {
+            return Err(anyhow::anyhow!("too many dpop proofs, retry later"));
+        }
+
+        let seen = self.by_key.entry(jkt.to_owned()).or_default();
+        if seen.len() >= limits.per_key {
+            let len = seen.len();
+            seen.retain(|_, iat| *iat >= oldest);
+            self.len -= len - seen.len();
+        }
+        if seen.len() >= limits.per_key {
+            return Err(anyhow::anyhow!(
+                "too many dpop proofs of the key, retry later"
+            ));
+        }
+
+        seen.insert(jti, iat);
+        self.len += 1;
+
+        Ok(())
+    }
+
+    fn delete_expired(&mut self, oldest: i64) {
+        for seen in self.by_key.values_mut() {
+            seen.retain(|_, iat| *iat >= oldest);
+        }
+        self.by_key.retain(|_, seen| !seen.is_empty());
+        self.len = self.by_key.values().map(HashMap::len).sum();
+    }
+}
+
+/// Members are required ones only, in lexicographic order. Values are
+/// already checked to be valid base64, so they need no escaping
+fn thumbprint(jwk: &ProofJwk) -> String {
+    let canonical = match jwk {
+        ProofJwk::Ec { crv, x, y } => {
+            format!(r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#, crv, x, y)
+        }
+        ProofJwk::Rsa { n, e } => format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, e, n),
+    };
+
+    b64_encode(&Sha256::digest(canonical.as_bytes()))
+}
+
+fn b64_encode(input: &[u8]) -> String {
+    base64::encode_config(input, base64::URL_SAFE_NO_PAD)
+}
+
+fn b64_decode(input: &str) -> anyhow::Result<Vec<u8>> {
+    Ok(base64::decode_config(input, base64::URL_SAFE_NO_PAD)?)
+}
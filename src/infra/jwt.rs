@@ -0,0 +1,305 @@
+use crate::config;
+
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use ring::signature::{self, KeyPair};
+use uuid::Uuid;
+
+/// Keys of signed tokens.
+///
+/// Tokens are signed by the active key and carry its `kid`, decoding picks
+/// the key by `kid` and accepts only algorithm of that key. Inactive keys
+/// only verify tokens issued before rotation. Without configured keys
+/// `auth.secret` is used with HS256 and no `kid`
+pub struct JwtKeyring {
+    keys: Vec<JwtKey>,
+    active: usize,
+    issuer: String,
+    audience: String,
+    leeway: u64,
+}
+
+/// Kinds of tokens are told apart by `typ` claim, so one of them is never
+/// accepted in place of another
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenType {
+    Access,
+    Refresh,
+    Invite,
+}
+
+/// Registered claims (RFC 7519) common to all tokens, `exp` is part of
+/// token's own claims
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Claims<T> {
+    iss: String,
+    aud: String,
+    iat: i64,
+    nbf: i64,
+    jti: Uuid,
+    typ: TokenType,
+    #[serde(flatten)]
+    inner: T,
+}
+
+struct JwtKey {
+    kid: Option<String>,
+    alg: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    /// Public part, none for symmetric keys
+    jwk: Option<Jwk>,
+}
+
+/// Public key in JWK format (RFC 7517)
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(tag = "kty")]
+pub enum Jwk {
+    #[serde(rename = "RSA")]
+    Rsa {
+        kid: String,
+        alg: String,
+        #[serde(rename = "use")]
+        use_: String,
+        n: String,
+        e: String,
+    },
+    #[serde(rename = "EC")]
+    Ec {
+        kid: String,
+        alg: String,
+        #[serde(rename = "use")]
+        use_: String,
+        crv: String,
+        x: String,
+        y: String,
+    },
+    /// Octet key pair (RFC 8037)
+    #[serde(rename = "OKP")]
+    Okp {
+        kid: String,
+        alg: String,
+        #[serde(rename = "use")]
+        use_: String,
+        crv: String,
+        x: String,
+    },
+}
+
+impl JwtKeyring {
+    pub fn new(cfg: &config::Auth) -> anyhow::Result<Self> {
+        if cfg.keys.is_empty() {
+            let key = JwtKey {
+                kid: None,
+                alg: Algorithm::HS256,
+                encoding: EncodingKey::from_secret(cfg.secret.as_bytes()),
+                decoding: DecodingKey::from_secret(cfg.secret.as_bytes()),
+                jwk: None,
+            };
+
+            return Ok(Self {
+                keys: vec![key],
+                active: 0,
+                issuer: cfg.issuer.clone(),
+                audience: cfg.audience.clone(),
+                leeway: cfg.leeway,
+            });
+        }
+
+        let keys = cfg
+            .keys
+            .iter()
+            .map(|k| {
+                JwtKey::new(k).map_err(|err| anyhow::anyhow!("jwt key kid={}: {}", k.kid, err))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        for (i, key) in keys.iter().enumerate() {
+            if keys[..i].iter().any(|k| k.kid == key.kid) {
+                return Err(anyhow::anyhow!(
+                    "jwt key kid={} is duplicated",
+                    key.kid.as_deref().unwrap_or_default()
+                ));
+            }
+        }
+
+        let mut active = cfg.keys.iter().enumerate().filter(|(_, k)| k.active);
+        let active = match (active.next(), active.next()) {
+            (Some((idx, _)), None) => idx,
+            _ => return Err(anyhow::anyhow!("exactly one jwt key must be active")),
+        };
+
+        Ok(Self {
+            keys,
+            active,
+            issuer: cfg.issuer.clone(),
+            audience: cfg.audience.clone(),
+            leeway: cfg.leeway,
+        })
+    }
+
+    pub fn encode<T>(&self, typ: TokenType, claims: &T) -> anyhow::Result<String>
+    where
+        T: serde::Serialize,
+    {
+        let key = &self.keys[self.active];
+
+        let mut header = Header::new(key.alg);
+        header.kid = key.kid.clone();
+
+        let now = Utc::now().timestamp();
+        let claims = Claims {
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            iat: now,
+            nbf: now,
+            jti: Uuid::new_v4(),
+            typ,
+            inner: claims,
+        };
+
+        let token = jsonwebtoken::encode(&header, &claims, &key.encoding)?;
+
+        Ok(token)
+    }
+
+    pub fn decode<T>(&self, typ: TokenType, token: &str) -> anyhow::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let header = jsonwebtoken::decode_header(token)?;
+        let key = self
+            .keys
+            .iter()
+            .find(|k| k.kid == header.kid)
+            .ok_or_else(|| anyhow::anyhow!("unknown jwt key"))?;
+
+        // Algorithm comes from the key, never from the token
+        let mut validation = Validation::new(key.alg);
+        validation.leeway = self.leeway;
+        validation.validate_nbf = true;
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+
+        let claims = jsonwebtoken::decode::<Claims<T>>(token, &key.decoding, &validation)
+            .map(|token_data| token_data.claims)?;
+
+        if claims.typ != typ {
+            return Err(anyhow::anyhow!(
+                "expected {:?} token, got {:?}",
+                typ,
+                claims.typ
+            ));
+        }
+
+        if claims.iat > Utc::now().timestamp() + self.leeway as i64 {
+            return Err(anyhow::anyhow!("token is issued in the future"));
+        }
+
+        Ok(claims.inner)
+    }
+
+    /// Public keys of all asymmetric keys, including inactive ones
+    pub fn jwks(&self) -> Vec<Jwk> {
+        self.keys.iter().filter_map(|k| k.jwk.clone()).collect()
+    }
+}
+
+impl JwtKey {
+    fn new(cfg: &config::JwtKey) -> anyhow::Result<Self> {
+        let kid = cfg.kid.clone();
+
+        let key = match cfg.alg {
+            config::JwtAlgorithm::HS256 => Self {
+                kid: Some(kid),
+                alg: Algorithm::HS256,
+                encoding: EncodingKey::from_secret(cfg.key.as_bytes()),
+                decoding: DecodingKey::from_secret(cfg.key.as_bytes()),
+                jwk: None,
+            },
+            config::JwtAlgorithm::RS256 => {
+                let pem = pem::parse(&cfg.key)?;
+                let key_pair = match pem.tag.as_str() {
+                    "PRIVATE KEY" => signature::RsaKeyPair::from_pkcs8(&pem.contents),
+                    "RSA PRIVATE KEY" => signature::RsaKeyPair::from_der(&pem.contents),
+                    tag => return Err(anyhow::anyhow!("unexpected pem tag {}", tag)),
+                }
+                .map_err(|err| anyhow::anyhow!("invalid rsa key: {}", err))?;
+
+                let public_key = key_pair.public_key();
+                let n = b64_encode(public_key.modulus().big_endian_without_leading_zero());
+                let e = b64_encode(public_key.exponent().big_endian_without_leading_zero());
+
+                Self {
+                    kid: Some(kid.clone()),
+                    alg: Algorithm::RS256,
+                    encoding: EncodingKey::from_rsa_pem(cfg.key.as_bytes())?,
+                    decoding: DecodingKey::from_rsa_components(&n, &e)?,
+                    jwk: Some(Jwk::Rsa {
+                        kid,
+                        alg: "RS256".to_owned(),
+                        use_: "sig".to_owned(),
+                        n,
+                        e,
+                    }),
+                }
+            }
+            config::JwtAlgorithm::ES256 => {
+                let pem = pem::parse(&cfg.key)?;
+                let key_pair = signature::EcdsaKeyPair::from_pkcs8(
+                    &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+                    &pem.contents,
+                )
+                .map_err(|err| anyhow::anyhow!("invalid ec key: {}", err))?;
+
+                // Uncompressed point: 0x04 | x | y
+                let point = key_pair.public_key().as_ref();
+                let (x, y) = point[1..].split_at((point.len() - 1) / 2);
+
+                Self {
+                    kid: Some(kid.clone()),
+                    alg: Algorithm::ES256,
+                    encoding: EncodingKey::from_ec_pem(cfg.key.as_bytes())?,
+                    decoding: DecodingKey::from_ec_der(point),
+                    jwk: Some(Jwk::Ec {
+                        kid,
+                        alg: "ES256".to_owned(),
+                        use_: "sig".to_owned(),
+                        crv: "P-256".to_owned(),
+                        x: b64_encode(x),
+                        y: b64_encode(y),
+                    }),
+                }
+            }
+            config::JwtAlgorithm::EdDSA => {
+                let pem = pem::parse(&cfg.key)?;
+                // OpenSSL writes PKCS#8 v1, without the public key
+                let key_pair = signature::Ed25519KeyPair::from_pkcs8_maybe_unchecked(&pem.contents)
+                    .map_err(|err| anyhow::anyhow!("invalid ed25519 key: {}", err))?;
+
+                let public_key = key_pair.public_key().as_ref();
+
+                Self {
+                    kid: Some(kid.clone()),
+                    alg: Algorithm::EdDSA,
+                    encoding: EncodingKey::from_ed_der(&pem.contents),
+                    decoding: DecodingKey::from_ed_der(public_key),
+                    jwk: Some(Jwk::Okp {
+                        kid,
+                        alg: "EdDSA".to_owned(),
+                        use_: "sig".to_owned(),
+                        crv: "Ed25519".to_owned(),
+                        x: b64_encode(public_key),
+                    }),
+                }
+            }
+        };
+
+        Ok(key)
+    }
+}
+
+fn b64_encode(input: &[u8]) -> String {
+    base64::encode_config(input, base64::URL_SAFE_NO_PAD)
+}
@@ -1,3 +1,8 @@
+pub mod client_ip;
+pub mod cookie;
+pub mod crypto;
+pub mod dpop;
+pub mod jwt;
 pub mod rest;
 pub mod sled;
 pub mod state;
@@ -1,5 +1,9 @@
 use std::sync::Arc;
 
+use crate::infra::client_ip::ClientIpResolver;
+use crate::infra::cookie::CookiePolicy;
+use crate::infra::dpop::DpopVerifier;
+use crate::infra::jwt::JwtKeyring;
 use crate::port::auth::service::AuthService;
 use crate::port::example::service::ExampleService;
 use crate::port::room::service::RoomService;
@@ -9,4 +13,8 @@ pub struct State {
     pub example_service: Arc<dyn ExampleService>,
     pub auth_service: Arc<dyn AuthService>,
     pub room_service: Arc<dyn RoomService>,
+    pub jwt_keyring: Arc<JwtKeyring>,
+    pub dpop_verifier: Arc<DpopVerifier>,
+    pub cookie_policy: Arc<CookiePolicy>,
+    pub client_ip_resolver: Arc<ClientIpResolver>,
 }
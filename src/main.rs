@@ -18,7 +18,13 @@ use crate::adapter::room::repo::RoomRepoSled;
 use crate::domain::auth::AuthServiceImpl;
 use crate::domain::example::ExampleServiceImpl;
 use crate::domain::room::RoomServiceImpl;
+use crate::infra::client_ip::ClientIpResolver;
+use crate::infra::cookie::CookiePolicy;
+use crate::infra::crypto::KeyStore;
+use crate::infra::dpop::DpopVerifier;
+use crate::infra::jwt::JwtKeyring;
 
+use actix::Actor;
 use std::sync::Arc;
 
 #[actix_web::main]
@@ -35,24 +41,40 @@ async fn main() -> anyhow::Result<()> {
 
 pub async fn run(cfg: Config) -> anyhow::Result<()> {
     let sled_db = infra::sled::new_sled_db()?;
+    let key_store = Arc::new(KeyStore::new(sled_db.clone(), &cfg.encryption)?);
 
     let example_repo = Arc::new(ExampleRepoSled::new(sled_db.clone())?);
     let example_svc = Arc::new(ExampleServiceImpl::new(example_repo));
 
-    let room_repo = Arc::new(RoomRepoSled::new(sled_db.clone())?);
+    let room_repo = Arc::new(RoomRepoSled::new(sled_db.clone(), Arc::clone(&key_store))?);
     let room_svc = Arc::new(RoomServiceImpl::new(
         cfg.room.clone(),
+        cfg.privacy.clone(),
         Arc::clone(&room_repo),
     ));
 
-    let auth_repo = Arc::new(AuthRepoSled::new(sled_db, Arc::clone(&room_repo))?);
+    let auth_repo = Arc::new(AuthRepoSled::new(
+        sled_db,
+        key_store,
+        Arc::clone(&room_repo),
+    )?);
     let auth_svc = Arc::new(AuthServiceImpl::new(cfg.auth.clone(), auth_repo));
+    let jwt_keyring = Arc::new(JwtKeyring::new(&cfg.auth)?);
+    let dpop_verifier = Arc::new(DpopVerifier::new(&cfg.auth));
+    let cookie_policy = Arc::new(CookiePolicy::new(&cfg.auth, &cfg.server.env)?);
+    let client_ip_resolver = Arc::new(ClientIpResolver::new(&cfg.server));
+
+    app::janitor::Janitor::new(cfg.server.clone(), auth_svc.clone(), room_svc.clone()).start();
 
     let opts = app::rest::Options {
         cfg: cfg.server.clone(),
         example_service: example_svc,
         auth_service: auth_svc,
         room_service: room_svc,
+        jwt_keyring,
+        dpop_verifier,
+        cookie_policy,
+        client_ip_resolver,
     };
 
     app::rest::run(opts).await?;
@@ -2,6 +2,7 @@ pub mod models;
 
 pub use models::*;
 
+pub use crate::port::room::repo::RoomPasswordFeature;
 use crate::port::room::repo::{RoomCredentials, RoomId};
 use crate::port::RepoResult;
 
@@ -11,16 +12,58 @@ use chrono::NaiveDateTime;
 pub trait AuthRepo: Send + Sync {
     async fn create_client(&self, req: CreateClientRequest) -> RepoResult<CreateClientResponse>;
     async fn delete_client(&self, req: DeleteClientRequest) -> RepoResult<DeleteClientResponse>;
+    async fn delete_room_clients(
+        &self,
+        req: DeleteRoomClientsRequest,
+    ) -> RepoResult<DeleteRoomClientsResponse>;
     async fn update_client(&self, req: UpdateClientRequest) -> RepoResult<UpdateClientResponse>;
     async fn get_client(&self, req: GetClientRequest) -> RepoResult<GetClientResponse>;
     async fn get_room_credentials(
         &self,
         req: GetRoomCredentialsRequest,
     ) -> RepoResult<GetRoomCredentialsResponse>;
+    async fn delete_room_password(
+        &self,
+        req: DeleteRoomPasswordRequest,
+    ) -> RepoResult<DeleteRoomPasswordResponse>;
+    async fn get_login_attempts(
+        &self,
+        req: GetLoginAttemptsRequest,
+    ) -> RepoResult<GetLoginAttemptsResponse>;
+    async fn add_login_failure(
+        &self,
+        req: AddLoginFailureRequest,
+    ) -> RepoResult<AddLoginFailureResponse>;
+    async fn delete_login_attempts(
+        &self,
+        req: DeleteLoginAttemptsRequest,
+    ) -> RepoResult<DeleteLoginAttemptsResponse>;
+    async fn delete_expired_login_attempts(
+        &self,
+        req: DeleteExpiredLoginAttemptsRequest,
+    ) -> RepoResult<DeleteExpiredLoginAttemptsResponse>;
+    async fn use_invite(&self, req: UseInviteRequest) -> RepoResult<UseInviteResponse>;
+    async fn delete_expired_invite_uses(
+        &self,
+        req: DeleteExpiredInviteUsesRequest,
+    ) -> RepoResult<DeleteExpiredInviteUsesResponse>;
+    async fn add_known_device(
+        &self,
+        req: AddKnownDeviceRequest,
+    ) -> RepoResult<AddKnownDeviceResponse>;
+    async fn is_known_device(&self, req: IsKnownDeviceRequest)
+        -> RepoResult<IsKnownDeviceResponse>;
+    async fn delete_expired_known_devices(
+        &self,
+        req: DeleteExpiredKnownDevicesRequest,
+    ) -> RepoResult<DeleteExpiredKnownDevicesResponse>;
 }
 
 pub struct CreateClientRequest {
+    /// Existing client with that id is replaced
     pub client_id: ClientId,
+    pub room_id: RoomId,
+    pub owner: bool,
     pub refresh_token_salt: RefreshTokenSalt,
     pub refresh_token_exp: NaiveDateTime,
     pub fingerprint: String,
@@ -38,15 +81,28 @@ pub struct DeleteClientResponse {
     pub client: Client,
 }
 
+/// Known devices of the room are forgotten too
+pub struct DeleteRoomClientsRequest {
+    pub room_id: RoomId,
+}
+
+pub struct DeleteRoomClientsResponse {
+    pub client_ids: Vec<ClientId>,
+}
+
 pub struct UpdateClientRequest {
     pub client_id: ClientId,
+    /// Client is updated only if its current salt equals that
+    pub expected_refresh_token_salt: Option<RefreshTokenSalt>,
     pub refresh_token_salt: Option<RefreshTokenSalt>,
     pub refresh_token_exp: Option<NaiveDateTime>,
     pub fingerprint: Option<String>,
 }
 
 pub struct UpdateClientResponse {
+    /// Current client, not changed if `updated` is false
     pub client: Client,
+    pub updated: bool,
 }
 
 pub struct GetClientRequest {
@@ -64,3 +120,84 @@ pub struct GetRoomCredentialsRequest {
 pub struct GetRoomCredentialsResponse {
     pub room_cred: RoomCredentials,
 }
+
+pub struct DeleteRoomPasswordRequest {
+    pub room_id: RoomId,
+    pub hash: String,
+}
+
+pub type DeleteRoomPasswordResponse = bool;
+
+pub struct GetLoginAttemptsRequest {
+    pub key: LoginAttemptsKey,
+}
+
+pub struct GetLoginAttemptsResponse {
+    pub attempts: Option<LoginAttempts>,
+}
+
+pub struct AddLoginFailureRequest {
+    pub key: LoginAttemptsKey,
+    pub failed_at: NaiveDateTime,
+    /// Counter starts over if last failure is older than that
+    pub reset_before: NaiveDateTime,
+}
+
+pub struct AddLoginFailureResponse {
+    pub attempts: LoginAttempts,
+}
+
+pub struct DeleteLoginAttemptsRequest {
+    pub key: LoginAttemptsKey,
+}
+
+pub type DeleteLoginAttemptsResponse = ();
+
+pub struct DeleteExpiredLoginAttemptsRequest {
+    pub last_failure_before: NaiveDateTime,
+}
+
+/// Amount of deleted counters
+pub type DeleteExpiredLoginAttemptsResponse = usize;
+
+pub struct UseInviteRequest {
+    pub invite_id: InviteId,
+    pub max_uses: u32,
+    /// Counter is kept until the invite expires
+    pub expires_at: NaiveDateTime,
+}
+
+/// False if all uses are already spent
+pub type UseInviteResponse = bool;
+
+pub struct DeleteExpiredInviteUsesRequest {
+    pub expired_before: NaiveDateTime,
+}
+
+/// Amount of deleted counters
+pub type DeleteExpiredInviteUsesResponse = usize;
+
+/// Existing device is marked as seen again
+pub struct AddKnownDeviceRequest {
+    pub room_id: RoomId,
+    pub device_id: DeviceId,
+    pub seen_at: NaiveDateTime,
+}
+
+pub type AddKnownDeviceResponse = ();
+
+pub struct IsKnownDeviceRequest {
+    pub room_id: RoomId,
+    pub device_id: DeviceId,
+    pub seen_after: NaiveDateTime,
+}
+
+/// True if the device logged in to the room after `seen_after`
+pub type IsKnownDeviceResponse = bool;
+
+pub struct DeleteExpiredKnownDevicesRequest {
+    pub seen_before: NaiveDateTime,
+}
+
+/// Amount of deleted devices
+pub type DeleteExpiredKnownDevicesResponse = usize;
@@ -1,13 +1,34 @@
+use crate::port::room::repo::RoomId;
+
 use chrono::NaiveDateTime;
+use std::net::IpAddr;
 use uuid::Uuid;
 
 pub type ClientId = Uuid;
 pub type RefreshTokenSalt = Uuid;
+pub type InviteId = Uuid;
+pub type DeviceId = Uuid;
 
 #[derive(Debug)]
 pub struct Client {
     pub id: ClientId,
+    /// Sessions are revoked together with their room
+    pub room_id: RoomId,
+    /// Room creator or holder of its master password
+    pub owner: bool,
     pub refresh_token_salt: RefreshTokenSalt,
     pub refresh_token_exp: NaiveDateTime,
     pub fingerprint: String,
 }
+
+#[derive(Debug, Clone, Copy)]
+pub enum LoginAttemptsKey {
+    Room(RoomId),
+    Ip(IpAddr),
+}
+
+#[derive(Debug, Clone)]
+pub struct LoginAttempts {
+    pub failures: u32,
+    pub last_failure_at: NaiveDateTime,
+}
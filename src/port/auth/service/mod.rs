@@ -5,24 +5,75 @@ pub use models::*;
 use crate::port::room::service::RoomId;
 use crate::port::ServiceResult;
 
+use chrono::NaiveDateTime;
+use std::net::IpAddr;
+
 #[async_trait::async_trait]
 pub trait AuthService: Send + Sync {
     fn enabled(&self) -> bool {
         true
     }
 
-    fn secret(&self) -> &str;
+    /// Access token is also set as cookie, see `config::Auth`
+    fn access_token_cookie(&self) -> bool {
+        false
+    }
+
+    /// Issues identity of anonymous client in the room it is bound to,
+    /// allowed only if auth is disabled
+    async fn authorize_anonymous(
+        &self,
+        req: AuthorizeAnonymousRequest,
+    ) -> ServiceResult<AuthorizeAnonymousResponse>;
+
+    /// Binds anonymous client to the room it created, as its owner
+    async fn bind_anonymous(
+        &self,
+        req: BindAnonymousRequest,
+    ) -> ServiceResult<BindAnonymousResponse>;
 
     async fn authorize(&self, req: AuthorizeRequest) -> ServiceResult<AuthorizeResponse>;
 
     async fn login(&self, req: LoginRequest) -> ServiceResult<LoginResponse>;
 
+    async fn login_with_invite(
+        &self,
+        req: LoginWithInviteRequest,
+    ) -> ServiceResult<LoginWithInviteResponse>;
+
+    async fn create_invite(&self, req: CreateInviteRequest) -> ServiceResult<CreateInviteResponse>;
+
+    async fn create_ws_ticket(
+        &self,
+        req: CreateWsTicketRequest,
+    ) -> ServiceResult<CreateWsTicketResponse>;
+
+    /// Ticket is removed on first use, even if session check fails
+    async fn redeem_ws_ticket(
+        &self,
+        req: RedeemWsTicketRequest,
+    ) -> ServiceResult<RedeemWsTicketResponse>;
+
     async fn logout(&self, req: LogoutRequest) -> ServiceResult<LogoutResponse>;
 
+    /// Revokes sessions of all clients of the room, e.g. when it is deleted
+    async fn logout_room(&self, req: LogoutRoomRequest) -> ServiceResult<LogoutRoomResponse>;
+
+    /// False if session of the client is logged out or revoked. Refreshed
+    /// session is the same one, so long-lived connections can recheck it
+    async fn has_session(&self, req: HasSessionRequest) -> ServiceResult<HasSessionResponse>;
+
     async fn refresh_tokens(
         &self,
         req: RefreshTokensRequest,
     ) -> ServiceResult<RefreshTokensResponse>;
+
+    /// Removes login attempts and known devices which are outdated at `now`,
+    /// called periodically
+    async fn delete_expired(
+        &self,
+        req: DeleteExpiredRequest,
+    ) -> ServiceResult<DeleteExpiredResponse>;
 }
 
 pub struct AuthorizeRequest {
@@ -33,14 +84,85 @@ pub struct AuthorizeResponse {
     pub jwt: Jwt,
 }
 
+pub struct AuthorizeAnonymousRequest {
+    pub client_id: ClientId,
+}
+
+pub struct AuthorizeAnonymousResponse {
+    pub jwt: Jwt,
+}
+
+pub struct BindAnonymousRequest {
+    pub client_id: ClientId,
+    pub room_id: RoomId,
+}
+
+pub type BindAnonymousResponse = ();
+
 pub struct LoginRequest {
     pub fingerprint: String,
     pub room_id: RoomId,
     pub room_password: String,
+    /// Address of the client, attempts without it are throttled per room only
+    pub client_ip: Option<IpAddr>,
+    /// Device which logged in to the room before passes its lock
+    pub device_id: Option<DeviceId>,
+    /// Thumbprint of verified DPoP key, tokens are bound to it
+    pub dpop_jkt: Option<String>,
+    /// If auth is disabled, client joins the room under its cookie id
+    pub anonymous_client_id: Option<ClientId>,
 }
 
 pub struct LoginResponse {
     pub jwt: Jwt,
+    /// Device id of the request or a new one, now known to the room
+    pub device_id: DeviceId,
+}
+
+pub struct LoginWithInviteRequest {
+    pub fingerprint: String,
+    /// Already verified by signature
+    pub invite: InviteDecoded,
+    /// Thumbprint of verified DPoP key, tokens are bound to it
+    pub dpop_jkt: Option<String>,
+    /// If auth is disabled, client joins the room under its cookie id
+    pub anonymous_client_id: Option<ClientId>,
+}
+
+pub struct LoginWithInviteResponse {
+    pub jwt: Jwt,
+}
+
+pub struct CreateInviteRequest {
+    pub room_id: RoomId,
+    pub role: InviteRole,
+    /// Seconds, `auth.invite.expires` if not set
+    pub expires_in: Option<i64>,
+    /// `auth.invite.max_uses` if not set
+    pub max_uses: Option<u32>,
+}
+
+pub struct CreateInviteResponse {
+    pub invite: InviteDecoded,
+}
+
+pub struct CreateWsTicketRequest {
+    /// Already authorized
+    pub jwt: Jwt,
+}
+
+pub struct CreateWsTicketResponse {
+    pub ticket: WsTicket,
+    pub exp: NaiveDateTime,
+}
+
+pub struct RedeemWsTicketRequest {
+    pub ticket: WsTicket,
+}
+
+pub struct RedeemWsTicketResponse {
+    /// Tokens of the session which created the ticket
+    pub jwt: Jwt,
 }
 
 pub struct LogoutRequest {
@@ -49,6 +171,19 @@ pub struct LogoutRequest {
 
 pub type LogoutResponse = ();
 
+pub struct LogoutRoomRequest {
+    pub room_id: RoomId,
+}
+
+pub type LogoutRoomResponse = ();
+
+pub struct HasSessionRequest {
+    pub client_id: ClientId,
+    pub room_id: RoomId,
+}
+
+pub type HasSessionResponse = bool;
+
 pub struct RefreshTokensRequest {
     pub fingerprint: String,
     pub jwt: Jwt,
@@ -57,3 +192,9 @@ pub struct RefreshTokensRequest {
 pub struct RefreshTokensResponse {
     pub jwt: Jwt,
 }
+
+pub struct DeleteExpiredRequest {
+    pub now: NaiveDateTime,
+}
+
+pub type DeleteExpiredResponse = ();
@@ -1,6 +1,9 @@
 use chrono::NaiveDateTime;
 use uuid::Uuid;
 
+/// Long-lived id of the browser, kept in cookie across sessions
+pub type DeviceId = Uuid;
+
 #[derive(Debug)]
 pub struct Client {
     pub id: Uuid,
@@ -0,0 +1,24 @@
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+
+use crate::port::room::service::RoomId;
+
+pub type InviteId = Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InviteRole {
+    Member,
+    /// Gets the same rights as login with master password
+    Owner,
+}
+
+/// Claims of signed invite token. Token is not stored anywhere, only count
+/// of its uses is
+#[derive(Debug, Clone)]
+pub struct InviteDecoded {
+    pub id: InviteId,
+    pub exp: NaiveDateTime,
+    pub room_id: RoomId,
+    pub role: InviteRole,
+    pub max_uses: u32,
+}
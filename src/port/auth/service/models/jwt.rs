@@ -14,14 +14,26 @@ pub struct AccessTokenDecoded {
     pub exp: NaiveDateTime,
     pub client_id: ClientId,
     pub room_id: RoomId,
+    /// Logged in with master password of the room
+    pub owner: bool,
+    /// Thumbprint of DPoP key, requests must carry proof signed by it
+    pub dpop_jkt: Option<String>,
 }
 
 impl AccessTokenDecoded {
-    pub fn new(exp: NaiveDateTime, client_id: ClientId, room_id: RoomId) -> AccessTokenDecoded {
+    pub fn new(
+        exp: NaiveDateTime,
+        client_id: ClientId,
+        room_id: RoomId,
+        owner: bool,
+        dpop_jkt: Option<String>,
+    ) -> AccessTokenDecoded {
         AccessTokenDecoded {
             exp,
             client_id,
             room_id,
+            owner,
+            dpop_jkt,
         }
     }
 }
@@ -31,7 +43,9 @@ impl Default for AccessTokenDecoded {
         Self {
             exp: Utc::now().naive_utc(),
             client_id: Default::default(),
-            room_id: 0,
+            room_id: RoomId::new(0),
+            owner: false,
+            dpop_jkt: None,
         }
     }
 }
@@ -1,5 +1,9 @@
 pub mod client;
+pub mod invite;
 pub mod jwt;
+pub mod ws_ticket;
 
 pub use client::*;
+pub use invite::*;
 pub use jwt::*;
+pub use ws_ticket::*;
@@ -0,0 +1,4 @@
+use uuid::Uuid;
+
+/// One-off credential of WebSocket upgrade, browsers cannot set headers there
+pub type WsTicket = Uuid;
@@ -11,6 +11,10 @@ pub enum RepoError {
     CommonError(anyhow::Error),
     #[error(transparent)]
     SledError(#[from] sled::Error),
+    #[error("already exists: {0}")]
+    AlreadyExists(anyhow::Error),
+    #[error("not found: {0}")]
+    NotFound(anyhow::Error),
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -21,4 +25,14 @@ pub enum ServiceError {
     RepoError(#[from] RepoError),
     #[error("auth error: {0}")]
     AuthError(anyhow::Error),
+    /// Request is well-formed, but not acceptable
+    #[error("validation error: {0}")]
+    ValidationError(anyhow::Error),
+    /// Login is locked for `retry_after` seconds, `room_locked` is set when
+    /// this attempt started lockout of the whole room
+    #[error("too many login attempts, retry after {retry_after} seconds")]
+    TooManyAttempts { retry_after: i64, room_locked: bool },
+    /// Limited resource is used up, e.g. there is no free room id
+    #[error("exhausted: {0}")]
+    Exhausted(anyhow::Error),
 }
@@ -4,32 +4,95 @@ pub use models::*;
 
 use crate::port::RepoResult;
 
-use std::collections::{HashMap, HashSet};
+use chrono::NaiveDateTime;
+use std::collections::HashSet;
 
 #[async_trait::async_trait]
 pub trait RoomRepo: Send + Sync {
     async fn create_room(&self, req: CreateRoomRequest) -> RepoResult<CreateRoomResponse>;
+    /// Deleted room id is not accepted by `create_room` until its tombstone
+    /// is deleted
+    async fn delete_room(&self, req: DeleteRoomRequest) -> RepoResult<DeleteRoomResponse>;
+    async fn delete_expired_tombstones(
+        &self,
+        req: DeleteExpiredTombstonesRequest,
+    ) -> RepoResult<DeleteExpiredTombstonesResponse>;
+    /// Persistent counter of sequential room ids, starts from 0
+    async fn next_room_number(&self) -> RepoResult<NextRoomNumberResponse>;
     async fn add_client(&self, req: AddClientRequest) -> RepoResult<AddClientResponse>;
     async fn has_client(&self, req: HasClientRequest) -> RepoResult<HasClientResponse>;
     async fn delete_client(&self, req: DeleteClientRequest) -> RepoResult<DeleteClientResponse>;
     async fn add_file(&self, req: AddFileRequest) -> RepoResult<AddFileResponse>;
+    async fn get_file(&self, req: GetFileRequest) -> RepoResult<GetFileResponse>;
     async fn get_files(&self, req: GetFilesRequest) -> RepoResult<GetFilesResponse>;
+    async fn restore_file_version(
+        &self,
+        req: RestoreFileVersionRequest,
+    ) -> RepoResult<RestoreFileVersionResponse>;
+    /// Blobs of the file are deleted too
+    async fn delete_file(&self, req: DeleteFileRequest) -> RepoResult<DeleteFileResponse>;
+    /// Files are deleted with their blobs
+    async fn delete_expired_files(
+        &self,
+        req: DeleteExpiredFilesRequest,
+    ) -> RepoResult<DeleteExpiredFilesResponse>;
+    /// Counts download of file with limited downloads. File is removed
+    /// with the last one, but its blobs are kept, the content is still read
+    async fn take_file_download(
+        &self,
+        req: TakeFileDownloadRequest,
+    ) -> RepoResult<TakeFileDownloadResponse>;
+    /// Blob is not referenced by any file until it is passed to `add_file`
+    async fn add_blob(&self, req: AddBlobRequest) -> RepoResult<AddBlobResponse>;
+    async fn get_blob_chunk(&self, req: GetBlobChunkRequest) -> RepoResult<GetBlobChunkResponse>;
+    async fn delete_blob(&self, req: DeleteBlobRequest) -> RepoResult<DeleteBlobResponse>;
     async fn get_room_credentials(
         &self,
         req: GetRoomCredentialsRequest,
     ) -> RepoResult<GetRoomCredentialsResponse>;
+    async fn get_room_settings(
+        &self,
+        req: GetRoomSettingsRequest,
+    ) -> RepoResult<GetRoomSettingsResponse>;
+    async fn add_room_password(
+        &self,
+        req: AddRoomPasswordRequest,
+    ) -> RepoResult<AddRoomPasswordResponse>;
+    async fn delete_room_password(
+        &self,
+        req: DeleteRoomPasswordRequest,
+    ) -> RepoResult<DeleteRoomPasswordResponse>;
 }
 
 pub struct CreateRoomRequest {
+    pub room_id: RoomId,
     pub client_ids: HashSet<ClientId>,
-    pub room_passwords: HashMap<String, RoomPasswordFeature>,
+    pub room_cred: RoomCredentials,
+    pub room_settings: RoomSettings,
 }
 
 pub struct CreateRoomResponse {
     pub room_id: RoomId,
     pub room_cred: RoomCredentials,
+    pub room_settings: RoomSettings,
 }
 
+pub struct DeleteRoomRequest {
+    pub room_id: RoomId,
+    pub deleted_at: NaiveDateTime,
+}
+
+pub type DeleteRoomResponse = ();
+
+pub struct DeleteExpiredTombstonesRequest {
+    pub deleted_before: NaiveDateTime,
+}
+
+/// Amount of room ids which can be given out again
+pub type DeleteExpiredTombstonesResponse = usize;
+
+pub type NextRoomNumberResponse = u64;
+
 pub struct AddClientRequest {
     pub room_id: RoomId,
     pub client_id: ClientId,
@@ -57,20 +120,116 @@ pub struct AddFileRequest {
     pub file_size: usize,
     pub file_mime_type: String,
     pub file_source_client_id: ClientId,
+    pub file_expires_at: Option<NaiveDateTime>,
+    pub file_uploaded_at: NaiveDateTime,
+    pub file_max_versions: usize,
+    /// Blobs of versions dropped above `file_max_versions` are deleted
+    pub file_blob: Option<Blob>,
+    pub file_max_downloads: Option<u32>,
 }
 
 pub struct AddFileResponse {
     pub file: File,
+    /// Expired file of the same name, it is deleted instead of getting
+    /// the new version
+    pub expired_file_id: Option<FileId>,
+}
+
+pub struct GetFileRequest {
+    pub room_id: RoomId,
+    pub file_id: FileId,
+}
+
+pub struct GetFileResponse {
+    pub file: File,
 }
 
 pub struct GetFilesRequest {
     pub room_id: RoomId,
+    pub sort_by: FileSortBy,
+    pub order: SortOrder,
+    pub filter: FileFilter,
+    pub cursor: Option<FilesCursor>,
+    pub limit: usize,
 }
 
 pub struct GetFilesResponse {
-    pub files: HashMap<FileId, File>,
+    pub files: Vec<File>,
+    pub next_cursor: Option<FilesCursor>,
+}
+
+pub struct RestoreFileVersionRequest {
+    pub room_id: RoomId,
+    pub file_id: FileId,
+    pub file_version: FileVersionNumber,
+    pub file_source_client_id: ClientId,
+    pub file_uploaded_at: NaiveDateTime,
+    pub file_max_versions: usize,
+}
+
+pub struct RestoreFileVersionResponse {
+    pub file: File,
+}
+
+pub struct DeleteFileRequest {
+    pub room_id: RoomId,
+    pub file_id: FileId,
+}
+
+pub struct DeleteFileResponse {
+    pub file: File,
 }
 
+pub struct DeleteExpiredFilesRequest {
+    pub expired_before: NaiveDateTime,
+}
+
+/// Room and id of every deleted file
+pub type DeleteExpiredFilesResponse = Vec<(RoomId, FileId)>;
+
+pub struct TakeFileDownloadRequest {
+    pub room_id: RoomId,
+    pub file_id: FileId,
+}
+
+pub struct TakeFileDownloadResponse {
+    /// File after the download is counted
+    pub file: File,
+    /// It was the last download, the file is removed
+    pub removed: bool,
+}
+
+pub struct AddBlobRequest {
+    pub room_id: RoomId,
+    /// Stored as is, compressed by the caller
+    pub content: Vec<u8>,
+    pub compression: BlobCompression,
+    /// Bytes of the content before compression
+    pub size: usize,
+    pub sha256: Vec<u8>,
+}
+
+pub struct AddBlobResponse {
+    pub blob: Blob,
+}
+
+pub struct GetBlobChunkRequest {
+    pub room_id: RoomId,
+    pub blob_id: BlobId,
+    /// Chunks are numbered from 0
+    pub index: u32,
+}
+
+/// Absent after the last chunk
+pub type GetBlobChunkResponse = Option<Vec<u8>>;
+
+pub struct DeleteBlobRequest {
+    pub room_id: RoomId,
+    pub blob_id: BlobId,
+}
+
+pub type DeleteBlobResponse = ();
+
 pub struct GetRoomCredentialsRequest {
     pub room_id: RoomId,
 }
@@ -78,3 +237,34 @@ pub struct GetRoomCredentialsRequest {
 pub struct GetRoomCredentialsResponse {
     pub room_cred: RoomCredentials,
 }
+
+pub struct GetRoomSettingsRequest {
+    pub room_id: RoomId,
+}
+
+pub struct GetRoomSettingsResponse {
+    pub room_settings: RoomSettings,
+}
+
+pub struct AddRoomPasswordRequest {
+    pub room_id: RoomId,
+    pub room_password: RoomPassword,
+    /// Passwords which expire before that are removed
+    pub expired_before: NaiveDateTime,
+    /// Invite password is not added if the room already has that many
+    /// unexpired ones
+    pub max_invites: usize,
+}
+
+/// `false` if the room has too many invites
+pub type AddRoomPasswordResponse = bool;
+
+pub struct DeleteRoomPasswordRequest {
+    pub room_id: RoomId,
+    /// Password is identified by its hash
+    pub hash: String,
+}
+
+/// `false` if there was no such password, e.g. one-off password was
+/// already used concurrently
+pub type DeleteRoomPasswordResponse = bool;
@@ -1,21 +1,221 @@
 pub use crate::port::auth::repo::ClientId;
 
 use chrono::NaiveDateTime;
-use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 
-pub type RoomId = u64;
+/// Room identifier, either a number or a word code like
+/// `brave-otter-apple-42`. Codes are kept as numbers with the highest bit
+/// set, so both kinds share fixed size repo keys. Always serialized as a
+/// string to be usable in REST paths
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub struct RoomId(u64);
+
+const CODE_FLAG: u64 = 1 << 63;
+const CODE_WORDS: usize = 3;
+const CODE_WORD_BITS: u32 = 11;
+const CODE_NUMBER_BITS: u32 = 7;
+const CODE_MAX_NUMBER: u8 = 99;
+
+impl RoomId {
+    pub const fn new(id: u64) -> Self {
+        Self(id & !CODE_FLAG)
+    }
+
+    /// `words` are indices in BIP-39 English word list
+    pub fn from_code(words: [u16; CODE_WORDS], number: u8) -> Self {
+        let mut id = 0;
+        for word in words.iter() {
+            id = (id << CODE_WORD_BITS) | (*word as u64 & ((1 << CODE_WORD_BITS) - 1));
+        }
+        id = (id << CODE_NUMBER_BITS) | number.min(CODE_MAX_NUMBER) as u64;
+
+        Self(id | CODE_FLAG)
+    }
+
+    pub fn is_code(self) -> bool {
+        self.0 & CODE_FLAG != 0
+    }
+
+    pub fn to_ne_bytes(self) -> [u8; 8] {
+        self.0.to_ne_bytes()
+    }
+
+    /// Inverse of `to_ne_bytes`, e.g. for room ids in repo keys
+    pub fn from_ne_bytes(bytes: [u8; 8]) -> Self {
+        Self(u64::from_ne_bytes(bytes))
+    }
+
+    pub fn to_be_bytes(self) -> [u8; 8] {
+        self.0.to_be_bytes()
+    }
+}
+
+impl fmt::Display for RoomId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.is_code() {
+            return write!(f, "{}", self.0);
+        }
+
+        let word_list = bip39::Language::English.word_list();
+        let mut rest = self.0 & !CODE_FLAG;
+        let number = rest & ((1 << CODE_NUMBER_BITS) - 1);
+        rest >>= CODE_NUMBER_BITS;
+
+        let mut words = [""; CODE_WORDS];
+        for word in words.iter_mut().rev() {
+            *word = word_list[(rest & ((1 << CODE_WORD_BITS) - 1)) as usize];
+            rest >>= CODE_WORD_BITS;
+        }
+
+        write!(f, "{}-{}", words.join("-"), number)
+    }
+}
+
+impl FromStr for RoomId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(id) = s.parse::<u64>() {
+            if id & CODE_FLAG != 0 {
+                return Err(anyhow::anyhow!("room id is out of range"));
+            }
+            return Ok(Self(id));
+        }
+
+        let parts: Vec<&str> = s.split('-').collect();
+        if parts.len() != CODE_WORDS + 1 {
+            return Err(anyhow::anyhow!("invalid room id '{}'", s));
+        }
+
+        let mut words = [0; CODE_WORDS];
+        for (word, part) in words.iter_mut().zip(&parts) {
+            *word = bip39::Language::English
+                .find_word(&part.to_lowercase())
+                .ok_or_else(|| anyhow::anyhow!("invalid room id '{}'", s))?;
+        }
+
+        let number = parts[CODE_WORDS]
+            .parse::<u8>()
+            .ok()
+            .filter(|n| *n <= CODE_MAX_NUMBER)
+            .ok_or_else(|| anyhow::anyhow!("invalid room id '{}'", s))?;
+
+        Ok(Self::from_code(words, number))
+    }
+}
+
+impl serde::Serialize for RoomId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for RoomId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Numeric ids are still accepted from JSON, while bincode (repo
+        // models) is not self-describing and keeps them as strings
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(RoomIdVisitor)
+        } else {
+            deserializer.deserialize_str(RoomIdVisitor)
+        }
+    }
+}
+
+impl RoomId {
+    /// For deserializers without `deserialize_any` support, like actix path
+    /// segments
+    pub fn deserialize_str<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(RoomIdVisitor)
+    }
+}
+
+struct RoomIdVisitor;
+
+impl<'de> serde::de::Visitor<'de> for RoomIdVisitor {
+    type Value = RoomId;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("room id string or number")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        if v & CODE_FLAG != 0 {
+            return Err(E::custom("room id is out of range"));
+        }
+        Ok(RoomId(v))
+    }
+}
+
 pub type FileId = Uuid;
+pub type FileVersionNumber = u64;
+pub type BlobId = Uuid;
 
 #[derive(Debug, Hash, Eq, PartialEq, Clone)]
 pub enum RoomPasswordFeature {
+    /// Owner credential returned by room creation
+    Master,
+    /// Removed on first use
     OneOff,
-    Expiring { expires_in: NaiveDateTime },
+    Expiring {
+        expires_in: NaiveDateTime,
+    },
+    /// Password of QR codes, removed on first use and rejected after
+    /// `expires_in`
+    Invite {
+        expires_in: NaiveDateTime,
+    },
+    /// TOTP-like code derived from `secret`, rotates every `step` seconds.
+    /// Password hash is not used
+    Rolling {
+        secret: Vec<u8>,
+        step: i64,
+        grace: i64,
+        digits: u32,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct RoomPassword {
+    /// Argon2id hash in PHC string format
+    pub hash: String,
+    /// Short keyed digest of the password, narrows down hashes to verify
+    pub lookup_tag: u8,
+    /// Passphrase is matched ignoring case and separators
+    pub passphrase: bool,
+    pub feature: RoomPasswordFeature,
+}
+
+impl RoomPasswordFeature {
+    pub fn expires_in(&self) -> Option<NaiveDateTime> {
+        match self {
+            RoomPasswordFeature::Expiring { expires_in }
+            | RoomPasswordFeature::Invite { expires_in } => Some(*expires_in),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct RoomCredentials {
-    pub passwords: HashMap<String, RoomPasswordFeature>,
+    /// Random key of password lookup tags
+    pub lookup_key: Vec<u8>,
+    pub passwords: Vec<RoomPassword>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RoomSettings {
+    /// End-to-end encrypted room, file names and mime types are opaque
+    /// ciphertexts which the server never parses
+    pub e2ee: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -25,4 +225,71 @@ pub struct File {
     pub size: usize,
     pub mime_type: String,
     pub source_client_id: ClientId,
+    pub expires_at: Option<NaiveDateTime>,
+    pub uploaded_at: NaiveDateTime,
+    pub version: FileVersionNumber,
+    pub blob: Option<Blob>,
+    /// File is removed with the last download, unlimited if absent
+    pub downloads_left: Option<u32>,
+    pub versions: Vec<FileVersion>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileVersion {
+    pub number: FileVersionNumber,
+    pub size: usize,
+    pub mime_type: String,
+    pub source_client_id: ClientId,
+    pub uploaded_at: NaiveDateTime,
+    /// Absent if only metadata of the file was added
+    pub blob: Option<Blob>,
+}
+
+/// Content of file version, stored apart from the file in chunks. Versions
+/// restored from each other share it
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Blob {
+    pub id: BlobId,
+    /// Bytes of the content, before compression
+    pub size: usize,
+    /// Bytes in chunks
+    pub stored_size: usize,
+    /// Of the content, before compression
+    pub sha256: Vec<u8>,
+    pub compression: BlobCompression,
+}
+
+/// How content is compressed in the chunks, decided per blob
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BlobCompression {
+    None,
+    Zstd,
+}
+
+/// Opaque position in the sorted file listing
+pub type FilesCursor = Vec<u8>;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FileSortBy {
+    Name,
+    Size,
+    UploadedAt,
+    SourceClientId,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    pub mime_type_prefix: Option<String>,
+    pub source_client_id: Option<ClientId>,
+    pub name_contains: Option<String>,
+    /// Files of a directory, e.g. `photos/`
+    pub name_prefix: Option<String>,
+    /// Files which expire at or before that are skipped
+    pub unexpired_at: Option<NaiveDateTime>,
 }
@@ -3,11 +3,19 @@ pub mod models;
 pub use models::*;
 
 use crate::port::ServiceResult;
-use std::collections::HashMap;
+
+use chrono::NaiveDateTime;
 
 #[async_trait::async_trait]
 pub trait RoomService: Send + Sync {
+    /// Page of the client app which joins a room by invite link
+    fn join_url(&self) -> &str;
+    /// Bytes, larger content is rejected by `add_file`
+    fn max_file_size(&self) -> usize;
+
     async fn create_room(&self, req: CreateRoomRequest) -> ServiceResult<CreateRoomResponse>;
+    /// Removes room with all its files, its data key is erased
+    async fn delete_room(&self, req: DeleteRoomRequest) -> ServiceResult<DeleteRoomResponse>;
     async fn connect_room(&self, req: ConnectRoomRequest) -> ServiceResult<ConnectRoomResponse>;
     async fn disconnect_room(
         &self,
@@ -15,15 +23,65 @@ pub trait RoomService: Send + Sync {
     ) -> ServiceResult<DisconnectRoomResponse>;
     async fn add_file(&self, req: AddFileRequest) -> ServiceResult<AddFileResponse>;
     async fn get_files(&self, req: GetFilesRequest) -> ServiceResult<GetFilesResponse>;
+    async fn get_file_content(
+        &self,
+        req: GetFileContentRequest,
+    ) -> ServiceResult<GetFileContentResponse>;
+    /// Files to put into archive, files without stored content are skipped
+    async fn get_archive_files(
+        &self,
+        req: GetArchiveFilesRequest,
+    ) -> ServiceResult<GetArchiveFilesResponse>;
+    /// Limits of archive unpacked into the room by the adapter, e2ee room
+    /// cannot unpack archives as their content is ciphertext
+    async fn get_archive_limits(
+        &self,
+        req: GetArchiveLimitsRequest,
+    ) -> ServiceResult<GetArchiveLimitsResponse>;
+    async fn get_file_versions(
+        &self,
+        req: GetFileVersionsRequest,
+    ) -> ServiceResult<GetFileVersionsResponse>;
+    async fn restore_file_version(
+        &self,
+        req: RestoreFileVersionRequest,
+    ) -> ServiceResult<RestoreFileVersionResponse>;
+    async fn get_join_code(&self, req: GetJoinCodeRequest) -> ServiceResult<GetJoinCodeResponse>;
+    async fn create_invite(&self, req: CreateInviteRequest) -> ServiceResult<CreateInviteResponse>;
+
+    /// Frees ids of rooms deleted long enough ago, called periodically
+    async fn delete_expired(
+        &self,
+        req: DeleteExpiredRequest,
+    ) -> ServiceResult<DeleteExpiredResponse>;
+}
+
+/// Reads file content chunk by chunk
+#[async_trait::async_trait]
+pub trait FileContentReader: Send {
+    /// Absent after the last chunk
+    async fn next_chunk(&mut self) -> ServiceResult<Option<Vec<u8>>>;
 }
 
-pub struct CreateRoomRequest {}
+pub struct CreateRoomRequest {
+    pub room_settings: RoomSettings,
+    /// Add rolling join code besides master password
+    pub join_code: bool,
+}
 
 pub struct CreateRoomResponse {
     pub room_id: RoomId,
-    pub room_cred: RoomCredentials,
+    /// Plaintext master password, only hash of it is stored
+    pub master_password: String,
+    pub room_settings: RoomSettings,
+}
+
+pub struct DeleteRoomRequest {
+    pub room_id: RoomId,
 }
 
+pub type DeleteRoomResponse = ();
+
 pub struct ConnectRoomRequest {
     pub room_id: RoomId,
     pub client_id: ClientId,
@@ -41,19 +99,131 @@ pub type DisconnectRoomResponse = ();
 pub struct AddFileRequest {
     pub room_id: RoomId,
     pub file_name: String,
-    pub file_size: usize,
+    pub file_content: FileContent,
     pub file_mime_type: String,
     pub file_source_client_id: ClientId,
+    pub file_expires_at: Option<NaiveDateTime>,
+    /// File is deleted after that many downloads of its content
+    pub file_max_downloads: Option<u32>,
 }
 
 pub struct AddFileResponse {
     pub file: File,
+    /// Metadata stripped from uploaded image, see `privacy` config
+    pub removed_metadata: Vec<ImageMetadata>,
+    /// Expired file of the same name, it is deleted instead of getting
+    /// the new version
+    pub expired_file_id: Option<FileId>,
 }
 
 pub struct GetFilesRequest {
     pub room_id: RoomId,
+    pub sort_by: FileSortBy,
+    pub order: SortOrder,
+    pub filter: FileFilter,
+    pub cursor: Option<FilesCursor>,
+    pub limit: usize,
 }
 
 pub struct GetFilesResponse {
-    pub files: HashMap<FileId, File>,
+    pub files: Vec<File>,
+    pub next_cursor: Option<FilesCursor>,
+}
+
+pub struct GetFileContentRequest {
+    pub room_id: RoomId,
+    pub file_id: FileId,
+    /// Current version if absent
+    pub file_version: Option<FileVersionNumber>,
+    /// Content is sent in one of them if it is stored compressed, decoded
+    /// otherwise
+    pub accept_encodings: Vec<ContentEncoding>,
+}
+
+pub struct GetFileContentResponse {
+    pub file: File,
+    pub version: FileVersion,
+    /// Name and mime type of the file are ciphertexts then
+    pub e2ee: bool,
+    pub content: Box<dyn FileContentReader>,
+    /// Absent if content is not encoded
+    pub content_encoding: Option<ContentEncoding>,
+    /// Bytes of content as it is sent, absent if it is not known in advance
+    pub content_size: Option<usize>,
+    /// It was the last allowed download, the file is deleted
+    pub file_removed: bool,
+}
+
+pub struct GetArchiveFilesRequest {
+    pub room_id: RoomId,
+    pub selection: ArchiveSelection,
+}
+
+pub enum ArchiveSelection {
+    Files(Vec<FileId>),
+    /// Files which names start with `directory/`, all files if it is empty
+    Directory(String),
+}
+
+pub struct GetArchiveFilesResponse {
+    pub files: Vec<File>,
+    /// Names of the files are ciphertexts then
+    pub e2ee: bool,
+}
+
+pub struct GetArchiveLimitsRequest {
+    pub room_id: RoomId,
+}
+
+pub struct GetArchiveLimitsResponse {
+    pub limits: ArchiveLimits,
+}
+
+pub struct GetFileVersionsRequest {
+    pub room_id: RoomId,
+    pub file_id: FileId,
+}
+
+pub struct GetFileVersionsResponse {
+    pub versions: Vec<FileVersion>,
+}
+
+pub struct RestoreFileVersionRequest {
+    pub room_id: RoomId,
+    pub file_id: FileId,
+    pub file_version: FileVersionNumber,
+    pub client_id: ClientId,
+}
+
+pub struct RestoreFileVersionResponse {
+    pub file: File,
+}
+
+pub struct GetJoinCodeRequest {
+    pub room_id: RoomId,
+}
+
+pub struct GetJoinCodeResponse {
+    /// Absent if room has no rolling join code
+    pub join_code: Option<JoinCode>,
+}
+
+pub struct CreateInviteRequest {
+    pub room_id: RoomId,
+}
+
+pub struct CreateInviteResponse {
+    /// One-off password, only hash of it is stored
+    pub room_password: String,
+    /// `room.join_url` with room id and password in fragment
+    pub join_url: String,
+    pub expires_at: NaiveDateTime,
+}
+
+pub struct DeleteExpiredRequest {
+    pub now: NaiveDateTime,
+}
+
+pub struct DeleteExpiredResponse {
+    pub removed_files: Vec<(RoomId, FileId)>,
 }
@@ -1,21 +1,33 @@
 pub use crate::port::auth::service::ClientId;
+pub use crate::port::room::repo::RoomId;
 
 use chrono::NaiveDateTime;
-use std::collections::HashMap;
 use uuid::Uuid;
 
-pub type RoomId = u64;
 pub type FileId = Uuid;
+pub type FileVersionNumber = u64;
 
 #[derive(Debug, Hash, Eq, PartialEq)]
 pub enum RoomPasswordFeature {
+    Master,
     OneOff,
     Expiring { expires_in: NaiveDateTime },
+    Invite { expires_in: NaiveDateTime },
+    Rolling { step: i64 },
 }
 
+/// Current rolling join code of a room
 #[derive(Debug)]
-pub struct RoomCredentials {
-    pub passwords: HashMap<String, RoomPasswordFeature>,
+pub struct JoinCode {
+    pub code: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Default)]
+pub struct RoomSettings {
+    /// End-to-end encrypted room, file names and mime types are opaque
+    /// ciphertexts which the server never parses
+    pub e2ee: bool,
 }
 
 #[derive(Debug)]
@@ -25,4 +37,86 @@ pub struct File {
     pub size: usize,
     pub mime_type: String,
     pub source_client_id: ClientId,
+    pub expires_at: Option<NaiveDateTime>,
+    pub uploaded_at: NaiveDateTime,
+    pub version: FileVersionNumber,
+    /// Absent if the file has no stored content
+    pub sha256: Option<Vec<u8>>,
+    /// Unlimited if absent
+    pub downloads_left: Option<u32>,
+    pub versions: Vec<FileVersion>,
+}
+
+#[derive(Debug)]
+pub struct FileVersion {
+    pub number: FileVersionNumber,
+    pub size: usize,
+    pub mime_type: String,
+    pub source_client_id: ClientId,
+    pub uploaded_at: NaiveDateTime,
+    pub sha256: Option<Vec<u8>>,
+}
+
+/// Content of added file
+#[derive(Debug)]
+pub enum FileContent {
+    /// Only metadata is added, content is exchanged by clients
+    Absent {
+        size: usize,
+    },
+    Bytes(Vec<u8>),
+}
+
+/// Encoding of downloaded content
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ContentEncoding {
+    Zstd,
+    Gzip,
+}
+
+/// Limits of archive unpacked into the room, see `room.archive` config
+#[derive(Debug, Clone)]
+pub struct ArchiveLimits {
+    pub max_size: usize,
+    pub max_total_size: u64,
+    pub max_ratio: u64,
+    pub max_entries: usize,
+    /// Bytes, `room.max_file_size`
+    pub max_entry_size: usize,
+}
+
+/// Kind of metadata removed from uploaded image
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub enum ImageMetadata {
+    Exif,
+    /// Location in EXIF, reported besides `Exif`
+    Gps,
+    Xmp,
+    Iptc,
+    /// Text comments of JPEG and text chunks of PNG
+    Comment,
+}
+
+/// Opaque position in the sorted file listing
+pub type FilesCursor = Vec<u8>;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FileSortBy {
+    Name,
+    Size,
+    UploadedAt,
+    SourceClientId,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    pub mime_type_prefix: Option<String>,
+    pub source_client_id: Option<ClientId>,
+    pub name_contains: Option<String>,
 }
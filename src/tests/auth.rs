@@ -2,12 +2,25 @@ use crate::adapter::auth::rest as auth_rest;
 use crate::adapter::room::rest as room_rest;
 use crate::tests::utils::*;
 
-use crate::adapter::auth::rest::{ACCESS_TOKEN_HEADER_NAME, REFRESH_TOKEN_COOKIE_NAME};
+use crate::adapter::auth::rest::{Decode, Encode};
+use crate::adapter::auth::rest::{
+    ACCESS_TOKEN_COOKIE_NAME, ACCESS_TOKEN_HEADER_NAME, ANONYMOUS_CLIENT_COOKIE_NAME,
+    CSRF_TOKEN_COOKIE_NAME, CSRF_TOKEN_HEADER_NAME, DEVICE_ID_COOKIE_NAME, DPOP_HEADER_NAME,
+    REFRESH_TOKEN_COOKIE_NAME,
+};
+use crate::config::{Config, Environment, JwtAlgorithm, JwtKey};
+use crate::infra::jwt::{Jwk, JwtKeyring};
 use crate::infra::rest::ApiResult;
+use crate::port::auth::service as auth_service;
+use crate::port::room::service as room_service;
+use actix_web::dev::Service;
 use actix_web::{test, App, HttpResponse};
+use chrono::Utc;
 use http_api_problem::HttpApiProblem;
 use rand::distributions::Alphanumeric;
 use rand::Rng;
+use ring::signature::{self, KeyPair};
+use sha2::{Digest, Sha256};
 
 #[actix_rt::test]
 async fn test_login() -> anyhow::Result<()> {
@@ -105,8 +118,1387 @@ async fn test_bad_login() -> anyhow::Result<()> {
 }
 
 #[actix_rt::test]
-async fn test_logout() -> anyhow::Result<()> {
+async fn test_login_throttle() -> anyhow::Result<()> {
+    let mut cfg = Config::default();
+    cfg.auth.login_throttle.ip_free_attempts = 3;
+    cfg.auth.login_throttle.backoff_base = 60;
+
+    let state = new_state(cfg);
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+    assert_eq!(
+        create_room_resp.status(),
+        http::StatusCode::OK,
+        "status code"
+    );
+
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        actix_web::test::read_body_json(create_room_resp).await;
+
+    let attacker_addr = "10.0.0.1:40000".parse()?;
+    let invalid_password = format!("{}x", create_room_resp_body.master_password);
+
+    for (i, expected) in [
+        http::StatusCode::UNAUTHORIZED,
+        http::StatusCode::UNAUTHORIZED,
+        http::StatusCode::TOO_MANY_REQUESTS,
+    ]
+    .iter()
+    .enumerate()
+    {
+        let login_req = test::TestRequest::post()
+            .uri("/v1/auth/login")
+            .peer_addr(attacker_addr)
+            .set_json(&auth_rest::LoginRequest {
+                fingerprint: "123".to_string(),
+                room_id: create_room_resp_body.room_id,
+                room_password: invalid_password.clone(),
+            })
+            .to_request();
+        let login_resp = test::call_service(&mut app, login_req).await;
+
+        assert_eq!(login_resp.status(), *expected, "attempt #{} status code", i);
+    }
+
+    // Locked client is rejected even with valid password
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .peer_addr(attacker_addr)
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id: create_room_resp_body.room_id,
+            room_password: create_room_resp_body.master_password.clone(),
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::TOO_MANY_REQUESTS,
+        "locked status code"
+    );
+
+    let retry_after: i64 = login_resp
+        .headers()
+        .get(actix_web::http::header::RETRY_AFTER)
+        .expect("no Retry-After header")
+        .to_str()?
+        .parse()?;
+    assert!(
+        retry_after > 0 && retry_after <= 60,
+        "Retry-After {}",
+        retry_after
+    );
+
+    // Other clients are not affected
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .peer_addr("10.0.0.2:40000".parse()?)
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id: create_room_resp_body.room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(login_resp.status(), http::StatusCode::OK, "status code");
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_login_throttle_ipv4_mapped() -> anyhow::Result<()> {
+    let mut cfg = Config::default();
+    cfg.auth.login_throttle.ip_free_attempts = 3;
+    cfg.auth.login_throttle.backoff_base = 60;
+
+    let state = new_state(cfg);
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        test::read_response_json(&mut app, create_room_req).await;
+    let invalid_password = format!("{}x", create_room_resp_body.master_password);
+
+    // IPv4-mapped IPv6 address is the same client as plain IPv4 one
+    for (addr, expected) in [
+        ("10.0.0.1:40000", http::StatusCode::UNAUTHORIZED),
+        ("[::ffff:10.0.0.1]:40000", http::StatusCode::UNAUTHORIZED),
+        ("10.0.0.1:40001", http::StatusCode::TOO_MANY_REQUESTS),
+        (
+            "[::ffff:10.0.0.1]:40001",
+            http::StatusCode::TOO_MANY_REQUESTS,
+        ),
+    ]
+    .iter()
+    {
+        let login_req = test::TestRequest::post()
+            .uri("/v1/auth/login")
+            .peer_addr(addr.parse()?)
+            .set_json(&auth_rest::LoginRequest {
+                fingerprint: "123".to_string(),
+                room_id: create_room_resp_body.room_id,
+                room_password: invalid_password.clone(),
+            })
+            .to_request();
+        let login_resp = test::call_service(&mut app, login_req).await;
+
+        assert_eq!(login_resp.status(), *expected, "{} status code", addr);
+    }
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_login_throttle_trusted_proxy() -> anyhow::Result<()> {
+    let mut cfg = Config::default();
+    cfg.auth.login_throttle.ip_free_attempts = 3;
+    cfg.auth.login_throttle.backoff_base = 60;
+    cfg.server.trusted_proxies = vec!["10.0.0.100".parse()?];
+
+    let state = new_state(cfg);
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        test::read_response_json(&mut app, create_room_req).await;
+    let invalid_password = format!("{}x", create_room_resp_body.master_password);
+
+    let login = |peer_addr: &str, header: (&str, &str), room_password: &str| {
+        Ok::<_, anyhow::Error>(
+            test::TestRequest::post()
+                .uri("/v1/auth/login")
+                .peer_addr(peer_addr.parse()?)
+                .header(header.0, header.1)
+                .set_json(&auth_rest::LoginRequest {
+                    fingerprint: "123".to_string(),
+                    room_id: create_room_resp_body.room_id,
+                    room_password: room_password.to_string(),
+                })
+                .to_request(),
+        )
+    };
+
+    // Client behind the proxy is throttled by its own address
+    for (header, expected) in [
+        (
+            ("x-forwarded-for", "10.0.0.1"),
+            http::StatusCode::UNAUTHORIZED,
+        ),
+        (
+            ("forwarded", "for=10.0.0.1"),
+            http::StatusCode::UNAUTHORIZED,
+        ),
+        (
+            ("x-forwarded-for", "10.0.0.1"),
+            http::StatusCode::TOO_MANY_REQUESTS,
+        ),
+    ]
+    .iter()
+    {
+        let login_req = login("10.0.0.100:40000", *header, &invalid_password)?;
+        let login_resp = test::call_service(&mut app, login_req).await;
+
+        assert_eq!(login_resp.status(), *expected, "{:?} status code", header);
+    }
+
+    // Other clients behind the same proxy are not affected
+    let login_req = login(
+        "10.0.0.100:40000",
+        ("x-forwarded-for", "10.0.0.2"),
+        &create_room_resp_body.master_password,
+    )?;
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(login_resp.status(), http::StatusCode::OK, "status code");
+
+    // Untrusted peer can not escape the lock with forged header
+    let login_req = login(
+        "10.0.0.1:40000",
+        ("x-forwarded-for", "10.0.0.3"),
+        &create_room_resp_body.master_password,
+    )?;
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::TOO_MANY_REQUESTS,
+        "forged header status code"
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_delete_expired_login_attempts() -> anyhow::Result<()> {
+    let mut cfg = Config::default();
+    cfg.auth.login_throttle.ip_free_attempts = 2;
+    cfg.auth.login_throttle.backoff_base = 60;
+
+    let state = new_state(cfg.clone());
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        test::read_response_json(&mut app, create_room_req).await;
+    let invalid_password = format!("{}x", create_room_resp_body.master_password);
+
+    let login = |room_password: &str| {
+        test::TestRequest::post()
+            .uri("/v1/auth/login")
+            .peer_addr("10.0.0.1:40000".parse().unwrap())
+            .set_json(&auth_rest::LoginRequest {
+                fingerprint: "123".to_string(),
+                room_id: create_room_resp_body.room_id,
+                room_password: room_password.to_string(),
+            })
+            .to_request()
+    };
+
+    for (i, expected) in [
+        http::StatusCode::UNAUTHORIZED,
+        http::StatusCode::TOO_MANY_REQUESTS,
+    ]
+    .iter()
+    .enumerate()
+    {
+        let login_resp = test::call_service(&mut app, login(&invalid_password)).await;
+        assert_eq!(login_resp.status(), *expected, "attempt #{} status code", i);
+    }
+
+    // Recent failures are kept
+    state
+        .auth_service
+        .delete_expired(auth_service::DeleteExpiredRequest {
+            now: Utc::now().naive_utc(),
+        })
+        .await?;
+
+    let login_resp =
+        test::call_service(&mut app, login(&create_room_resp_body.master_password)).await;
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::TOO_MANY_REQUESTS,
+        "locked status code"
+    );
+
+    let throttle = &cfg.auth.login_throttle;
+    let ttl = throttle.reset_after.max(throttle.backoff_max);
+    state
+        .auth_service
+        .delete_expired(auth_service::DeleteExpiredRequest {
+            now: Utc::now().naive_utc() + chrono::Duration::seconds(ttl + 1),
+        })
+        .await?;
+
+    let login_resp =
+        test::call_service(&mut app, login(&create_room_resp_body.master_password)).await;
+    assert_eq!(login_resp.status(), http::StatusCode::OK, "status code");
+
+    Ok(())
+}
+
+// Room lock is relayed to room websockets, which need running actix system
+#[test]
+fn test_login_throttle_room() -> anyhow::Result<()> {
+    actix::System::new("test").block_on(async {
+        let mut cfg = Config::default();
+        cfg.auth.login_throttle.ip_free_attempts = 3;
+        cfg.auth.login_throttle.room_free_attempts = 2;
+        cfg.auth.login_throttle.backoff_base = 60;
+
+        let state = new_state(cfg);
+        let mut app = actix_web::test::init_service(
+            App::new()
+                .data(state.clone())
+                .configure(room_rest::service_config)
+                .configure(auth_rest::service_config),
+        )
+        .await;
+
+        let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+        let create_room_resp_body: room_rest::CreateRoomResponse =
+            test::read_response_json(&mut app, create_room_req).await;
+        let room_id = create_room_resp_body.room_id;
+        let invalid_password = format!("{}x", create_room_resp_body.master_password);
+
+        // Member logs in before the lock, its device becomes known to the room
+        let login_req = test::TestRequest::post()
+            .uri("/v1/auth/login")
+            .peer_addr("10.0.0.4:40000".parse()?)
+            .set_json(&auth_rest::LoginRequest {
+                fingerprint: "123".to_string(),
+                room_id,
+                room_password: create_room_resp_body.master_password.clone(),
+            })
+            .to_request();
+        let login_resp = test::call_service(&mut app, login_req).await;
+
+        assert_eq!(
+            login_resp.status(),
+            http::StatusCode::OK,
+            "member login status code"
+        );
+
+        let device_cookie = login_resp
+            .headers()
+            .get_all(http::header::SET_COOKIE)
+            .map(|v| v.to_str().unwrap().to_owned())
+            .find(|c| c.contains(DEVICE_ID_COOKIE_NAME))
+            .expect("(login) cookie device id");
+        let device_cookie = actix_web::cookie::Cookie::parse(device_cookie)?;
+
+        // Unknown room looks like wrong password and is counted as failure
+        let unknown_room_id = crate::port::room::repo::RoomId::from_code([0, 0, 0], 0);
+        let mut bodies = vec![];
+        for (login_room_id, expected) in [
+            (room_id, http::StatusCode::UNAUTHORIZED),
+            (unknown_room_id, http::StatusCode::UNAUTHORIZED),
+            (unknown_room_id, http::StatusCode::TOO_MANY_REQUESTS),
+        ]
+        .iter()
+        {
+            let login_req = test::TestRequest::post()
+                .uri("/v1/auth/login")
+                .peer_addr("10.0.0.1:40000".parse()?)
+                .set_json(&auth_rest::LoginRequest {
+                    fingerprint: "123".to_string(),
+                    room_id: *login_room_id,
+                    room_password: invalid_password.clone(),
+                })
+                .to_request();
+            let login_resp = test::call_service(&mut app, login_req).await;
+
+            assert_eq!(login_resp.status(), *expected, "status code");
+            bodies.push(test::read_body(login_resp).await);
+        }
+        assert_eq!(bodies[0], bodies[1], "unknown room error");
+
+        // Another client locks the room
+        let login_req = test::TestRequest::post()
+            .uri("/v1/auth/login")
+            .peer_addr("10.0.0.2:40000".parse()?)
+            .set_json(&auth_rest::LoginRequest {
+                fingerprint: "123".to_string(),
+                room_id,
+                room_password: invalid_password.clone(),
+            })
+            .to_request();
+        let login_resp = test::call_service(&mut app, login_req).await;
+
+        assert_eq!(
+            login_resp.status(),
+            http::StatusCode::TOO_MANY_REQUESTS,
+            "room lock status code"
+        );
+
+        // Room lock stops every client, even with right password, except of
+        // known devices
+        for (device_cookie, expected) in [
+            (None, http::StatusCode::TOO_MANY_REQUESTS),
+            (Some(device_cookie), http::StatusCode::OK),
+        ]
+        .iter()
+        {
+            let mut login_req = test::TestRequest::post()
+                .uri("/v1/auth/login")
+                .peer_addr("10.0.0.3:40000".parse()?)
+                .set_json(&auth_rest::LoginRequest {
+                    fingerprint: "123".to_string(),
+                    room_id,
+                    room_password: create_room_resp_body.master_password.clone(),
+                });
+            if let Some(cookie) = device_cookie {
+                login_req = login_req.cookie(cookie.clone());
+            }
+            let login_resp = test::call_service(&mut app, login_req.to_request()).await;
+
+            assert_eq!(
+                login_resp.status(),
+                *expected,
+                "known device {} status code",
+                device_cookie.is_some()
+            );
+        }
+
+        Ok(())
+    })
+}
+
+#[actix_rt::test]
+async fn test_login_passphrase() -> anyhow::Result<()> {
+    let mut cfg = Config::default();
+    cfg.room.password.mode = crate::config::PasswordMode::Passphrase;
+
+    let state = new_state(cfg);
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+    assert_eq!(
+        create_room_resp.status(),
+        http::StatusCode::OK,
+        "status code"
+    );
+
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        actix_web::test::read_body_json(create_room_resp).await;
+
+    let words: Vec<&str> = create_room_resp_body.master_password.split('-').collect();
+    assert_eq!(words.len(), 3, "passphrase words");
+
+    // Case and separators do not matter
+    let spoken_password = format!(" {}  {} {}", words[0].to_uppercase(), words[1], words[2]);
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id: create_room_resp_body.room_id,
+            room_password: spoken_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(login_resp.status(), http::StatusCode::OK, "status code");
+
+    // But words do
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id: create_room_resp_body.room_id,
+            room_password: words[..2].join("-"),
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::UNAUTHORIZED,
+        "status code"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_passphrase_separator_config() {
+    let parse = |separator: &str| {
+        serde_yaml::from_str::<crate::config::Passphrase>(&format!(
+            "{{ words: 3, separator: \"{}\", capitalize: false }}",
+            separator
+        ))
+    };
+
+    // Logins split passphrases by anything but letters and digits
+    for separator in &["-", " ", "_", ". "] {
+        assert!(parse(separator).is_ok(), "separator '{}'", separator);
+    }
+    for separator in &["", "x", "1", "-a-"] {
+        assert!(parse(separator).is_err(), "separator '{}'", separator);
+    }
+}
+
+#[actix_rt::test]
+async fn test_login_join_code() -> anyhow::Result<()> {
     let state = new_default_state();
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post()
+        .uri("/v1/rooms")
+        .set_json(&room_rest::CreateRoomBodyRequest {
+            join_code: true,
+            ..Default::default()
+        })
+        .to_request();
+    let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+    assert_eq!(
+        create_room_resp.status(),
+        http::StatusCode::OK,
+        "status code"
+    );
+
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        actix_web::test::read_body_json(create_room_resp).await;
+
+    // Host screen receives it over websocket
+    let join_code = state
+        .room_service
+        .get_join_code(room_service::GetJoinCodeRequest {
+            room_id: create_room_resp_body.room_id,
+        })
+        .await?
+        .join_code
+        .expect("no join code");
+
+    assert_eq!(join_code.code.len(), 6, "join code digits");
+    assert!(
+        join_code.expires_at > Utc::now().naive_utc(),
+        "join code expired"
+    );
+
+    let invalid_code = format!("{:06}", (join_code.code.parse::<u32>()? + 1) % 1_000_000);
+
+    for (code, expected) in [
+        (invalid_code, http::StatusCode::UNAUTHORIZED),
+        (join_code.code, http::StatusCode::OK),
+    ]
+    .iter()
+    {
+        let login_req = test::TestRequest::post()
+            .uri("/v1/auth/login")
+            .set_json(&auth_rest::LoginRequest {
+                fingerprint: "123".to_string(),
+                room_id: create_room_resp_body.room_id,
+                room_password: code.clone(),
+            })
+            .to_request();
+        let login_resp = test::call_service(&mut app, login_req).await;
+
+        assert_eq!(login_resp.status(), *expected, "code {} status code", code);
+    }
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_login_with_invite() -> anyhow::Result<()> {
+    let state = new_default_state();
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+    assert_eq!(
+        create_room_resp.status(),
+        http::StatusCode::OK,
+        "create room status code"
+    );
+
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        actix_web::test::read_body_json(create_room_resp).await;
+    let room_id = create_room_resp_body.room_id;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::OK,
+        "owner login status code"
+    );
+
+    let owner_cookie = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME))
+        .expect("(login) cookie refresh token");
+    let owner_cookie = actix_web::cookie::Cookie::parse(owner_cookie)?;
+
+    let owner_token = actix_web::test::read_body_json::<auth_rest::LoginResponse, _>(login_resp)
+        .await
+        .access_token;
+
+    // Expiration is limited by config
+    let create_invite_req = test::TestRequest::post()
+        .uri(&format!("/v1/rooms/{}/invites", room_id))
+        .header(ACCESS_TOKEN_HEADER_NAME, owner_token.clone())
+        .cookie(owner_cookie.clone())
+        .set_json(&room_rest::CreateInviteBodyRequest {
+            expires_in: Some(Config::default().auth.invite.max_expires + 1),
+            ..Default::default()
+        })
+        .to_request();
+    let create_invite_resp = test::call_service(&mut app, create_invite_req).await;
+
+    assert_eq!(
+        create_invite_resp.status(),
+        http::StatusCode::BAD_REQUEST,
+        "create invite status code"
+    );
+
+    let create_invite_req = test::TestRequest::post()
+        .uri(&format!("/v1/rooms/{}/invites", room_id))
+        .header(ACCESS_TOKEN_HEADER_NAME, owner_token)
+        .cookie(owner_cookie)
+        .set_json(&room_rest::CreateInviteBodyRequest {
+            max_uses: Some(2),
+            ..Default::default()
+        })
+        .to_request();
+    let create_invite_resp = test::call_service(&mut app, create_invite_req).await;
+
+    assert_eq!(
+        create_invite_resp.status(),
+        http::StatusCode::OK,
+        "create invite status code"
+    );
+
+    let invite: room_rest::CreateInviteResponse =
+        actix_web::test::read_body_json(create_invite_resp).await;
+    assert!(
+        invite
+            .join_url
+            .ends_with(&format!("#token={}", invite.token)),
+        "join url has no token"
+    );
+
+    let mut forged_token = invite.token.clone();
+    forged_token.pop();
+
+    for (token, expected) in [
+        (forged_token, http::StatusCode::UNAUTHORIZED),
+        (invite.token.clone(), http::StatusCode::OK),
+        (invite.token.clone(), http::StatusCode::OK),
+        (invite.token.clone(), http::StatusCode::UNAUTHORIZED),
+    ]
+    .iter()
+    {
+        let login_req = test::TestRequest::post()
+            .uri("/v1/auth/login-with-invite")
+            .set_json(&auth_rest::LoginWithInviteRequest {
+                fingerprint: "123".to_string(),
+                token: token.clone(),
+            })
+            .to_request();
+        let login_resp = test::call_service(&mut app, login_req).await;
+
+        assert_eq!(login_resp.status(), *expected, "invite login status code");
+    }
+
+    Ok(())
+}
+
+// Logout closes room websockets, which need running actix system
+#[test]
+fn test_anonymous_access() -> anyhow::Result<()> {
+    actix::System::new("test").block_on(async {
+        let mut cfg = Config::default();
+        cfg.auth.enabled = false;
+
+        let state = new_state(cfg);
+        let mut app = actix_web::test::init_service(
+            App::new()
+                .data(state.clone())
+                .wrap(
+                    auth_rest::JwtAuth::default()
+                        .exclude_regex(".*/auth/login")
+                        .exclude_regex((".*/rooms$", http::Method::POST)),
+                )
+                .configure(room_rest::service_config)
+                .configure(auth_rest::service_config),
+        )
+        .await;
+
+        let client_cookie = |resp: &actix_web::dev::ServiceResponse| {
+            resp.headers()
+                .get_all(http::header::SET_COOKIE)
+                .map(|v| v.to_str().unwrap().to_owned())
+                .find(|c| c.contains(ANONYMOUS_CLIENT_COOKIE_NAME))
+                .map(|c| actix_web::cookie::Cookie::parse(c).unwrap())
+        };
+
+        // Creator gets client id without login and owns the room
+        let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+        let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+        assert_eq!(
+            create_room_resp.status(),
+            http::StatusCode::OK,
+            "create room status code"
+        );
+
+        let owner_cookie = client_cookie(&create_room_resp).expect("cookie client id");
+        let create_room_resp_body: room_rest::CreateRoomResponse =
+            actix_web::test::read_body_json(create_room_resp).await;
+        let room_id = create_room_resp_body.room_id;
+
+        let qr_req = test::TestRequest::post()
+            .uri(&format!("/v1/rooms/{}/qr", room_id))
+            .cookie(owner_cookie.clone())
+            .to_request();
+        let qr_resp = test::call_service(&mut app, qr_req).await;
+
+        assert_eq!(
+            qr_resp.status(),
+            http::StatusCode::OK,
+            "owner qr status code"
+        );
+        assert!(client_cookie(&qr_resp).is_none(), "client id is reissued");
+
+        // Known client id does not identify the client, only its secret does
+        let owner_client_id = auth_rest::AnonymousClientToken::parse(owner_cookie.value())
+            .expect("client token")
+            .client_id();
+        for value in [
+            owner_client_id.to_string(),
+            base64::encode_config(owner_client_id.as_bytes(), base64::URL_SAFE_NO_PAD),
+        ]
+        .iter()
+        {
+            let qr_req = test::TestRequest::post()
+                .uri(&format!("/v1/rooms/{}/qr", room_id))
+                .cookie(actix_web::cookie::Cookie::new(
+                    ANONYMOUS_CLIENT_COOKIE_NAME,
+                    value.clone(),
+                ))
+                .to_request();
+            let err = app
+                .call(qr_req)
+                .await
+                .expect_err("forged client is authorized");
+
+            assert_eq!(
+                err.as_response_error().status_code(),
+                http::StatusCode::UNAUTHORIZED,
+                "forged client qr status code"
+            );
+        }
+
+        // Client which neither created nor joined the room is rejected
+        let connect_req = test::TestRequest::post()
+            .uri(&format!("/v1/rooms/{}/connect", room_id))
+            .to_request();
+        let err = app
+            .call(connect_req)
+            .await
+            .expect_err("unbound client is authorized");
+
+        assert_eq!(
+            err.as_response_error().status_code(),
+            http::StatusCode::UNAUTHORIZED,
+            "unbound connect status code"
+        );
+
+        // Client joins with room password and is not an owner
+        let invite = state
+            .room_service
+            .create_invite(room_service::CreateInviteRequest { room_id })
+            .await?;
+
+        let login_req = test::TestRequest::post()
+            .uri("/v1/auth/login")
+            .set_json(&auth_rest::LoginRequest {
+                fingerprint: "123".to_string(),
+                room_id,
+                room_password: invite.room_password,
+            })
+            .to_request();
+        let login_resp = test::call_service(&mut app, login_req).await;
+
+        assert_eq!(
+            login_resp.status(),
+            http::StatusCode::OK,
+            "member login status code"
+        );
+
+        let member_cookie = client_cookie(&login_resp).expect("cookie client id");
+
+        let connect_req = test::TestRequest::post()
+            .uri(&format!("/v1/rooms/{}/connect", room_id))
+            .cookie(member_cookie.clone())
+            .to_request();
+        let connect_resp = test::call_service(&mut app, connect_req).await;
+
+        assert_eq!(
+            connect_resp.status(),
+            http::StatusCode::OK,
+            "member connect status code"
+        );
+
+        let qr_req = test::TestRequest::post()
+            .uri(&format!("/v1/rooms/{}/qr", room_id))
+            .cookie(member_cookie)
+            .to_request();
+        let qr_resp = test::call_service(&mut app, qr_req).await;
+
+        assert_eq!(
+            qr_resp.status(),
+            http::StatusCode::FORBIDDEN,
+            "member qr status code"
+        );
+
+        // Owner of one room has no access to another one
+        let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+        let create_room_resp = test::call_service(&mut app, create_room_req).await;
+        let other_room_id =
+            actix_web::test::read_body_json::<room_rest::CreateRoomResponse, _>(create_room_resp)
+                .await
+                .room_id;
+
+        let qr_req = test::TestRequest::post()
+            .uri(&format!("/v1/rooms/{}/qr", other_room_id))
+            .cookie(owner_cookie.clone())
+            .to_request();
+        let qr_resp = test::call_service(&mut app, qr_req).await;
+
+        assert_eq!(
+            qr_resp.status(),
+            http::StatusCode::UNAUTHORIZED,
+            "other room qr status code"
+        );
+
+        // Logout unbinds the client
+        let logout_req = test::TestRequest::post()
+            .uri("/v1/auth/logout")
+            .cookie(owner_cookie.clone())
+            .to_request();
+        let logout_resp = test::call_service(&mut app, logout_req).await;
+
+        assert_eq!(
+            logout_resp.status(),
+            http::StatusCode::OK,
+            "logout status code"
+        );
+
+        let qr_req = test::TestRequest::post()
+            .uri(&format!("/v1/rooms/{}/qr", room_id))
+            .cookie(owner_cookie)
+            .to_request();
+        let err = app
+            .call(qr_req)
+            .await
+            .expect_err("logged out client is authorized");
+
+        assert_eq!(
+            err.as_response_error().status_code(),
+            http::StatusCode::UNAUTHORIZED,
+            "logged out qr status code"
+        );
+
+        Ok(())
+    })
+}
+
+#[actix_rt::test]
+async fn test_jwt_keyring() -> anyhow::Result<()> {
+    let es256_key = |kid: &str, active: bool| -> anyhow::Result<JwtKey> {
+        let pkcs8 = ring::signature::EcdsaKeyPair::generate_pkcs8(
+            &ring::signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+            &ring::rand::SystemRandom::new(),
+        )
+        .map_err(|_| anyhow::anyhow!("generate key error"))?;
+        let key = pem::encode(&pem::Pem {
+            tag: "PRIVATE KEY".to_owned(),
+            contents: pkcs8.as_ref().to_vec(),
+        });
+
+        Ok(JwtKey {
+            kid: kid.to_owned(),
+            alg: JwtAlgorithm::ES256,
+            key,
+            active,
+        })
+    };
+
+    let ed25519_key = |kid: &str, active: bool| -> anyhow::Result<JwtKey> {
+        let pkcs8 =
+            ring::signature::Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new())
+                .map_err(|_| anyhow::anyhow!("generate key error"))?;
+        let key = pem::encode(&pem::Pem {
+            tag: "PRIVATE KEY".to_owned(),
+            contents: pkcs8.as_ref().to_vec(),
+        });
+
+        Ok(JwtKey {
+            kid: kid.to_owned(),
+            alg: JwtAlgorithm::EdDSA,
+            key,
+            active,
+        })
+    };
+
+    let old_key = ed25519_key("old", false)?;
+    let new_key = es256_key("new", true)?;
+
+    let mut cfg = Config::default();
+    cfg.auth.keys = vec![old_key.clone(), new_key.clone()];
+
+    let state = new_state(cfg.clone());
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login")
+                    .exclude_regex((".*/rooms$", http::Method::POST))
+                    .exclude_regex("^/.well-known/"),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config)
+            .configure(auth_rest::well_known_config),
+    )
+    .await;
+
+    // Both keys are published
+    let jwks_req = test::TestRequest::get()
+        .uri("/.well-known/jwks.json")
+        .to_request();
+    let jwks_resp: auth_rest::JwksResponse = test::read_response_json(&mut app, jwks_req).await;
+
+    assert_eq!(jwks_resp.keys.len(), 2, "jwks keys");
+    assert!(
+        jwks_resp.keys.iter().any(|k| matches!(
+            k,
+            Jwk::Okp { kid, alg, crv, x, .. }
+                if kid == "old" && alg == "EdDSA" && crv == "Ed25519" && x.len() == 43
+        )),
+        "jwks ed25519 key"
+    );
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        test::read_response_json(&mut app, create_room_req).await;
+    let room_id = create_room_resp_body.room_id;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::OK,
+        "login status code"
+    );
+
+    let cookie = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME))
+        .expect("(login) cookie refresh token");
+    let cookie = actix_web::cookie::Cookie::parse(cookie)?;
+
+    let access_token = actix_web::test::read_body_json::<auth_rest::LoginResponse, _>(login_resp)
+        .await
+        .access_token;
+
+    // Active key signs
+    let header = jsonwebtoken::decode_header(&access_token)?;
+    assert_eq!(header.kid.as_deref(), Some("new"), "kid");
+    assert_eq!(header.alg, jsonwebtoken::Algorithm::ES256, "alg");
+
+    // Token of rotated key still works
+    let old_keyring = {
+        let mut cfg = cfg.clone();
+        cfg.auth.keys = vec![
+            JwtKey {
+                active: true,
+                ..old_key
+            },
+            JwtKey {
+                active: false,
+                ..new_key
+            },
+        ];
+        JwtKeyring::new(&cfg.auth)?
+    };
+    let claims = auth_rest::AccessTokenDecoded::decode(&state.jwt_keyring, &access_token)?;
+    let old_access_token = claims.encode(&old_keyring)?;
+    assert_eq!(
+        jsonwebtoken::decode_header(&old_access_token)?
+            .kid
+            .as_deref(),
+        Some("old"),
+        "old kid"
+    );
+
+    // Shared secret is not accepted anymore
+    let secret_access_token = claims.encode(&JwtKeyring::new(&Config::default().auth)?)?;
+
+    for (token, expected) in [
+        (old_access_token, http::StatusCode::OK),
+        (secret_access_token, http::StatusCode::BAD_REQUEST),
+    ]
+    .iter()
+    {
+        let connect_req = test::TestRequest::post()
+            .uri(&format!("/v1/rooms/{}/connect", room_id))
+            .header(ACCESS_TOKEN_HEADER_NAME, token.clone())
+            .cookie(cookie.clone())
+            .to_request();
+        let status = match app.call(connect_req).await {
+            Ok(res) => res.status(),
+            Err(err) => err.as_response_error().status_code(),
+        };
+
+        assert_eq!(status, *expected, "connect status code");
+    }
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_jwt_claims() -> anyhow::Result<()> {
+    let state = new_default_state();
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        test::read_response_json(&mut app, create_room_req).await;
+    let room_id = create_room_resp_body.room_id;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::OK,
+        "login status code"
+    );
+
+    let cookie = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME))
+        .expect("(login) cookie refresh token");
+    let cookie = actix_web::cookie::Cookie::parse(cookie)?;
+
+    let access_token = actix_web::test::read_body_json::<auth_rest::LoginResponse, _>(login_resp)
+        .await
+        .access_token;
+
+    let mut validation = jsonwebtoken::Validation::default();
+    validation.insecure_disable_signature_validation();
+    let claims = jsonwebtoken::decode::<serde_json::Value>(
+        &access_token,
+        &jsonwebtoken::DecodingKey::from_secret(&[]),
+        &validation,
+    )?
+    .claims;
+    for claim in ["iss", "aud", "iat", "nbf", "jti", "exp"].iter() {
+        assert!(claims.get(claim).is_some(), "no {} claim", claim);
+    }
+    assert_eq!(claims["typ"], "access", "typ claim");
+
+    // Token for another audience
+    let other_aud_access_token = {
+        let mut cfg = Config::default();
+        cfg.auth.audience = "other".to_owned();
+        auth_rest::AccessTokenDecoded::decode(&state.jwt_keyring, &access_token)?
+            .encode(&JwtKeyring::new(&cfg.auth)?)?
+    };
+
+    for (token, expected, name) in [
+        (access_token, http::StatusCode::OK, "access"),
+        // Refresh token is not accepted in place of access token
+        (
+            cookie.value().to_owned(),
+            http::StatusCode::BAD_REQUEST,
+            "refresh",
+        ),
+        (
+            other_aud_access_token,
+            http::StatusCode::BAD_REQUEST,
+            "other audience",
+        ),
+    ]
+    .iter()
+    {
+        let connect_req = test::TestRequest::post()
+            .uri(&format!("/v1/rooms/{}/connect", room_id))
+            .header(ACCESS_TOKEN_HEADER_NAME, token.clone())
+            .cookie(cookie.clone())
+            .to_request();
+        let status = match app.call(connect_req).await {
+            Ok(res) => res.status(),
+            Err(err) => err.as_response_error().status_code(),
+        };
+
+        assert_eq!(status, *expected, "{} token status code", name);
+    }
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_dpop() -> anyhow::Result<()> {
+    let state = new_default_state();
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login$")
+                    .exclude_regex(".*/auth/refresh-tokens$")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let rng = ring::rand::SystemRandom::new();
+    let pkcs8 =
+        signature::EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+            .map_err(|err| anyhow::anyhow!("{}", err))?;
+    let key_pair = signature::EcdsaKeyPair::from_pkcs8(
+        &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+        pkcs8.as_ref(),
+    )
+    .map_err(|err| anyhow::anyhow!("{}", err))?;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        test::read_response_json(&mut app, create_room_req).await;
+    let room_id = create_room_resp_body.room_id;
+
+    let login_proof = dpop_proof(&key_pair, "POST", "/v1/auth/login", None);
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .header(DPOP_HEADER_NAME, login_proof.clone())
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: Default::default(),
+            room_id,
+            room_password: create_room_resp_body.master_password.clone(),
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::OK,
+        "login status code"
+    );
+
+    let cookie = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME))
+        .expect("(login) cookie refresh token");
+    let cookie = actix_web::cookie::Cookie::parse(cookie)?;
+
+    let access_token = actix_web::test::read_body_json::<auth_rest::LoginResponse, _>(login_resp)
+        .await
+        .access_token;
+
+    let claims = auth_rest::AccessTokenDecoded::decode(&state.jwt_keyring, &access_token)?;
+    assert!(claims.cnf.is_some(), "access token is bound");
+
+    // Proof is accepted only once
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .header(DPOP_HEADER_NAME, login_proof)
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: Default::default(),
+            room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::UNAUTHORIZED,
+        "replayed login proof status code"
+    );
+
+    let connect_path = format!("/v1/rooms/{}/connect", room_id);
+    let connect_proof = dpop_proof(&key_pair, "POST", &connect_path, Some(&access_token));
+    let other_key_pair = {
+        let pkcs8 = signature::EcdsaKeyPair::generate_pkcs8(
+            &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+            &rng,
+        )
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+        signature::EcdsaKeyPair::from_pkcs8(
+            &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+            pkcs8.as_ref(),
+        )
+        .map_err(|err| anyhow::anyhow!("{}", err))?
+    };
+
+    for (proof, expected, name) in [
+        (None, http::StatusCode::UNAUTHORIZED, "no proof"),
+        (
+            Some(dpop_proof(
+                &key_pair,
+                "POST",
+                "/v1/rooms",
+                Some(&access_token),
+            )),
+            http::StatusCode::UNAUTHORIZED,
+            "proof for another url",
+        ),
+        (
+            Some(dpop_proof(&key_pair, "POST", &connect_path, None)),
+            http::StatusCode::UNAUTHORIZED,
+            "proof without access token hash",
+        ),
+        (
+            Some(dpop_proof(
+                &other_key_pair,
+                "POST",
+                &connect_path,
+                Some(&access_token),
+            )),
+            http::StatusCode::UNAUTHORIZED,
+            "proof by another key",
+        ),
+        (
+            Some(connect_proof.clone()),
+            http::StatusCode::OK,
+            "valid proof",
+        ),
+        (
+            Some(connect_proof),
+            http::StatusCode::UNAUTHORIZED,
+            "replayed proof",
+        ),
+    ]
+    .iter()
+    {
+        let mut connect_req = test::TestRequest::post()
+            .uri(&connect_path)
+            .header(ACCESS_TOKEN_HEADER_NAME, format!("DPoP {}", access_token))
+            .cookie(cookie.clone());
+        if let Some(proof) = proof {
+            connect_req = connect_req.header(DPOP_HEADER_NAME, proof.clone());
+        }
+        let status = match app.call(connect_req.to_request()).await {
+            Ok(res) => res.status(),
+            Err(err) => err.as_response_error().status_code(),
+        };
+
+        assert_eq!(status, *expected, "{} status code", name);
+    }
+
+    // Refreshed token is bound to the same key
+    let refresh_req = test::TestRequest::post()
+        .uri("/v1/auth/refresh-tokens")
+        .header(ACCESS_TOKEN_HEADER_NAME, format!("DPoP {}", access_token))
+        .header(
+            DPOP_HEADER_NAME,
+            dpop_proof(
+                &key_pair,
+                "POST",
+                "/v1/auth/refresh-tokens",
+                Some(&access_token),
+            ),
+        )
+        .cookie(cookie)
+        .set_json(&auth_rest::RefreshTokensRequest {
+            fingerprint: Default::default(),
+        })
+        .to_request();
+    let refresh_resp = test::call_service(&mut app, refresh_req).await;
+
+    assert_eq!(
+        refresh_resp.status(),
+        http::StatusCode::OK,
+        "refresh status code"
+    );
+
+    let refreshed_access_token =
+        actix_web::test::read_body_json::<auth_rest::RefreshTokensResponse, _>(refresh_resp)
+            .await
+            .access_token;
+    let refreshed_claims =
+        auth_rest::AccessTokenDecoded::decode(&state.jwt_keyring, &refreshed_access_token)?;
+    assert_eq!(
+        refreshed_claims.cnf.map(|cnf| cnf.jkt),
+        claims.cnf.map(|cnf| cnf.jkt),
+        "refreshed token key thumbprint"
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_dpop_replay_cache_flood() -> anyhow::Result<()> {
+    let mut cfg = Config::default();
+    cfg.auth.dpop.max_login_proofs = 3;
+    cfg.auth.dpop.max_proofs_per_key = 2;
+
+    let state = new_state(cfg);
     let mut app = actix_web::test::init_service(
         App::new()
             .data(state.clone())
@@ -120,23 +1512,183 @@ async fn test_logout() -> anyhow::Result<()> {
     )
     .await;
 
+    let rng = ring::rand::SystemRandom::new();
+    let new_key_pair = || {
+        let pkcs8 = signature::EcdsaKeyPair::generate_pkcs8(
+            &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+            &rng,
+        )
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+        signature::EcdsaKeyPair::from_pkcs8(
+            &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+            pkcs8.as_ref(),
+        )
+        .map_err(|err| anyhow::anyhow!("{}", err))
+    };
+
     let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
-    let create_room_resp = test::call_service(&mut app, create_room_req).await;
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        test::read_response_json(&mut app, create_room_req).await;
+    let room_id = create_room_resp_body.room_id;
+
+    let login_req = |key_pair: &signature::EcdsaKeyPair| {
+        test::TestRequest::post()
+            .uri("/v1/auth/login")
+            .header(
+                DPOP_HEADER_NAME,
+                dpop_proof(key_pair, "POST", "/v1/auth/login", None),
+            )
+            .set_json(&auth_rest::LoginRequest {
+                fingerprint: Default::default(),
+                room_id,
+                room_password: create_room_resp_body.master_password.clone(),
+            })
+            .to_request()
+    };
+
+    let key_pair = new_key_pair()?;
+    let login_resp = test::call_service(&mut app, login_req(&key_pair)).await;
 
     assert_eq!(
-        create_room_resp.status(),
+        login_resp.status(),
         http::StatusCode::OK,
-        "create room status code"
+        "login status code"
+    );
+
+    let cookie = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME))
+        .expect("(login) cookie refresh token");
+    let cookie = actix_web::cookie::Cookie::parse(cookie)?;
+
+    let access_token = actix_web::test::read_body_json::<auth_rest::LoginResponse, _>(login_resp)
+        .await
+        .access_token;
+
+    // Logins with foreign keys fill the login cache
+    for (i, expected) in [
+        http::StatusCode::OK,
+        http::StatusCode::OK,
+        http::StatusCode::UNAUTHORIZED,
+    ]
+    .iter()
+    .enumerate()
+    {
+        let login_resp = test::call_service(&mut app, login_req(&new_key_pair()?)).await;
+
+        assert_eq!(
+            login_resp.status(),
+            *expected,
+            "foreign login #{} status code",
+            i
+        );
+    }
+
+    // Existing session still works, within the limit of its key
+    let files_path = format!("/v1/rooms/{}/files", room_id);
+    let other_key_pair = new_key_pair()?;
+    for (key_pair, expected, name) in [
+        (
+            &other_key_pair,
+            http::StatusCode::UNAUTHORIZED,
+            "foreign key",
+        ),
+        (&key_pair, http::StatusCode::OK, "first proof"),
+        (&key_pair, http::StatusCode::OK, "second proof"),
+        (&key_pair, http::StatusCode::UNAUTHORIZED, "over key limit"),
+    ]
+    .iter()
+    {
+        let files_req = test::TestRequest::get()
+            .uri(&files_path)
+            .header(ACCESS_TOKEN_HEADER_NAME, format!("DPoP {}", access_token))
+            .header(
+                DPOP_HEADER_NAME,
+                dpop_proof(key_pair, "GET", &files_path, Some(&access_token)),
+            )
+            .cookie(cookie.clone())
+            .to_request();
+        let status = match app.call(files_req).await {
+            Ok(res) => res.status(),
+            Err(err) => err.as_response_error().status_code(),
+        };
+
+        assert_eq!(status, *expected, "{} status code", name);
+    }
+
+    Ok(())
+}
+
+/// Proof of test request, `path` is prefixed with default host of test server
+fn dpop_proof(
+    key_pair: &signature::EcdsaKeyPair,
+    method: &str,
+    path: &str,
+    access_token: Option<&str>,
+) -> String {
+    let b64 = |input: &[u8]| base64::encode_config(input, base64::URL_SAFE_NO_PAD);
+
+    let point = key_pair.public_key().as_ref();
+    let (x, y) = point[1..].split_at(32);
+    let header = serde_json::json!({
+        "typ": "dpop+jwt",
+        "alg": "ES256",
+        "jwk": { "kty": "EC", "crv": "P-256", "x": b64(x), "y": b64(y) },
+    });
+
+    let mut claims = serde_json::json!({
+        "jti": uuid::Uuid::new_v4().to_string(),
+        "htm": method,
+        "htu": format!("http://localhost:8080{}", path),
+        "iat": Utc::now().timestamp(),
+    });
+    if let Some(access_token) = access_token {
+        claims["ath"] = b64(&Sha256::digest(access_token.as_bytes())).into();
+    }
+
+    let message = format!(
+        "{}.{}",
+        b64(header.to_string().as_bytes()),
+        b64(claims.to_string().as_bytes())
     );
+    let sig = key_pair
+        .sign(&ring::rand::SystemRandom::new(), message.as_bytes())
+        .expect("dpop proof signature");
+
+    format!("{}.{}", message, b64(sig.as_ref()))
+}
+
+#[actix_rt::test]
+async fn test_access_token_cookie() -> anyhow::Result<()> {
+    let mut cfg = Config::default();
+    cfg.auth.access_token_cookie = true;
+
+    let state = new_state(cfg);
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login$")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
 
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
     let create_room_resp_body: room_rest::CreateRoomResponse =
-        actix_web::test::read_body_json(create_room_resp).await;
+        test::read_response_json(&mut app, create_room_req).await;
+    let room_id = create_room_resp_body.room_id;
 
     let login_req = test::TestRequest::post()
         .uri("/v1/auth/login")
         .set_json(&auth_rest::LoginRequest {
             fingerprint: "123".to_string(),
-            room_id: create_room_resp_body.room_id,
+            room_id,
             room_password: create_room_resp_body.master_password,
         })
         .to_request();
@@ -148,41 +1700,305 @@ async fn test_logout() -> anyhow::Result<()> {
         "login status code"
     );
 
-    let cookies: Vec<String> = login_resp
+    let cookies = login_resp
         .headers()
         .get_all(http::header::SET_COOKIE)
-        .map(|v| v.to_str().unwrap().to_owned())
-        .collect();
-
-    let cookie = cookies
-        .into_iter()
-        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
-    assert!(cookie.is_some(), "cookie refresh token");
-
-    let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+        .map(|v| actix_web::cookie::Cookie::parse_encoded(v.to_str().unwrap().to_owned()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let find_cookie = |name: &str| {
+        cookies
+            .iter()
+            .find(|c| c.name() == name)
+            .cloned()
+            .unwrap_or_else(|| panic!("(login) cookie {}", name))
+    };
+    let refresh_cookie = find_cookie(REFRESH_TOKEN_COOKIE_NAME);
+    let access_cookie = find_cookie(ACCESS_TOKEN_COOKIE_NAME);
+    let csrf_cookie = find_cookie(CSRF_TOKEN_COOKIE_NAME);
 
-    let login_resp_body: auth_rest::LoginResponse =
-        actix_web::test::read_body_json(login_resp).await;
+    assert_eq!(
+        access_cookie.http_only(),
+        Some(true),
+        "access cookie http only"
+    );
+    assert_eq!(
+        access_cookie.same_site(),
+        Some(actix_web::cookie::SameSite::Strict),
+        "access cookie same site"
+    );
+    assert_eq!(access_cookie.path(), Some("/api"), "access cookie path");
 
-    let logout_req = test::TestRequest::post()
-        .uri("/v1/auth/logout")
-        .header(
-            ACCESS_TOKEN_HEADER_NAME,
-            login_resp_body.access_token.clone(),
-        )
-        .cookie(cookie)
+    // Download without header
+    let get_files_req = test::TestRequest::get()
+        .uri(&format!("/v1/rooms/{}/files", room_id))
+        .cookie(access_cookie.clone())
+        .cookie(refresh_cookie.clone())
         .to_request();
-    let logout_res = test::call_service(&mut app, logout_req).await;
+    let get_files_resp = test::call_service(&mut app, get_files_req).await;
 
     assert_eq!(
-        logout_res.status(),
+        get_files_resp.status(),
         http::StatusCode::OK,
-        "logout status code"
+        "get files status code"
     );
 
+    for (csrf_token, expected, name) in [
+        (None, http::StatusCode::FORBIDDEN, "no csrf token"),
+        (
+            Some(uuid::Uuid::new_v4().to_string()),
+            http::StatusCode::FORBIDDEN,
+            "wrong csrf token",
+        ),
+        (
+            Some(csrf_cookie.value().to_owned()),
+            http::StatusCode::OK,
+            "csrf token",
+        ),
+    ]
+    .iter()
+    {
+        let mut connect_req = test::TestRequest::post()
+            .uri(&format!("/v1/rooms/{}/connect", room_id))
+            .cookie(access_cookie.clone())
+            .cookie(refresh_cookie.clone());
+        if let Some(csrf_token) = csrf_token {
+            connect_req = connect_req.header(CSRF_TOKEN_HEADER_NAME, csrf_token.clone());
+        }
+        let status = match app.call(connect_req.to_request()).await {
+            Ok(res) => res.status(),
+            Err(err) => err.as_response_error().status_code(),
+        };
+
+        assert_eq!(status, *expected, "{} status code", name);
+    }
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_auth_origin() -> anyhow::Result<()> {
+    let mut cfg = Config::default();
+    cfg.server.env = Environment::Prod;
+    cfg.auth.allowed_origins = vec!["https://app.example/".to_owned()];
+
+    let state = new_state(cfg);
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        test::read_response_json(&mut app, create_room_req).await;
+
+    for (origin, referer, expected, name) in [
+        (None, None, http::StatusCode::OK, "no origin"),
+        (
+            Some("http://localhost:8080"),
+            None,
+            http::StatusCode::OK,
+            "own origin",
+        ),
+        (
+            Some("https://app.example"),
+            None,
+            http::StatusCode::OK,
+            "allowed origin",
+        ),
+        (
+            Some("https://evil.example"),
+            Some("http://localhost:8080/"),
+            http::StatusCode::FORBIDDEN,
+            "other origin",
+        ),
+        (
+            Some("null"),
+            None,
+            http::StatusCode::FORBIDDEN,
+            "null origin",
+        ),
+        (
+            None,
+            Some("http://localhost:8080/join?room=1"),
+            http::StatusCode::OK,
+            "own referer",
+        ),
+        (
+            None,
+            Some("https://evil.example?page=1"),
+            http::StatusCode::FORBIDDEN,
+            "other referer",
+        ),
+    ]
+    .iter()
+    {
+        let mut login_req =
+            test::TestRequest::post()
+                .uri("/v1/auth/login")
+                .set_json(&auth_rest::LoginRequest {
+                    fingerprint: "123".to_string(),
+                    room_id: create_room_resp_body.room_id,
+                    room_password: create_room_resp_body.master_password.clone(),
+                });
+        if let Some(origin) = origin {
+            login_req = login_req.header(http::header::ORIGIN, *origin);
+        }
+        if let Some(referer) = referer {
+            login_req = login_req.header(http::header::REFERER, *referer);
+        }
+        let login_resp = test::call_service(&mut app, login_req.to_request()).await;
+
+        assert_eq!(login_resp.status(), *expected, "{} status code", name);
+
+        if login_resp.status() != http::StatusCode::OK {
+            continue;
+        }
+
+        // Strict by default in prod
+        let cookie = login_resp
+            .headers()
+            .get_all(http::header::SET_COOKIE)
+            .map(|v| v.to_str().unwrap().to_owned())
+            .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME))
+            .expect("(login) cookie refresh token");
+        let cookie = actix_web::cookie::Cookie::parse(cookie)?;
+
+        assert_eq!(cookie.secure(), Some(true), "refresh cookie secure");
+        assert_eq!(
+            cookie.same_site(),
+            Some(actix_web::cookie::SameSite::Strict),
+            "refresh cookie same site"
+        );
+    }
+
     Ok(())
 }
 
+// Logout closes room websockets, which need running actix system
+#[test]
+fn test_logout() -> anyhow::Result<()> {
+    actix::System::new("test").block_on(async {
+        let state = new_default_state();
+        let mut app = actix_web::test::init_service(
+            App::new()
+                .data(state.clone())
+                .wrap(
+                    auth_rest::JwtAuth::default()
+                        .exclude_regex(".*/auth/login$")
+                        .exclude_regex((".*/rooms$", http::Method::POST)),
+                )
+                .configure(room_rest::service_config)
+                .configure(auth_rest::service_config),
+        )
+        .await;
+
+        let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+        let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+        assert_eq!(
+            create_room_resp.status(),
+            http::StatusCode::OK,
+            "create room status code"
+        );
+
+        let create_room_resp_body: room_rest::CreateRoomResponse =
+            actix_web::test::read_body_json(create_room_resp).await;
+
+        let login_req = test::TestRequest::post()
+            .uri("/v1/auth/login")
+            .set_json(&auth_rest::LoginRequest {
+                fingerprint: "123".to_string(),
+                room_id: create_room_resp_body.room_id,
+                room_password: create_room_resp_body.master_password,
+            })
+            .to_request();
+        let login_resp = test::call_service(&mut app, login_req).await;
+
+        assert_eq!(
+            login_resp.status(),
+            http::StatusCode::OK,
+            "login status code"
+        );
+
+        let cookies: Vec<String> = login_resp
+            .headers()
+            .get_all(http::header::SET_COOKIE)
+            .map(|v| v.to_str().unwrap().to_owned())
+            .collect();
+
+        let cookie = cookies
+            .into_iter()
+            .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
+        assert!(cookie.is_some(), "cookie refresh token");
+
+        let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+
+        let login_resp_body: auth_rest::LoginResponse =
+            actix_web::test::read_body_json(login_resp).await;
+
+        let claims = auth_rest::AccessTokenDecoded::decode(
+            &state.jwt_keyring,
+            &login_resp_body.access_token,
+        )?;
+        let room_id = create_room_resp_body.room_id;
+        let has_session_req = || auth_service::HasSessionRequest {
+            client_id: claims.client_id,
+            room_id,
+        };
+        assert!(
+            state.auth_service.has_session(has_session_req()).await?,
+            "session before logout"
+        );
+
+        let logout_req = test::TestRequest::post()
+            .uri("/v1/auth/logout")
+            .header(
+                ACCESS_TOKEN_HEADER_NAME,
+                login_resp_body.access_token.clone(),
+            )
+            .cookie(cookie.clone())
+            .to_request();
+        let logout_res = test::call_service(&mut app, logout_req).await;
+
+        assert_eq!(
+            logout_res.status(),
+            http::StatusCode::OK,
+            "logout status code"
+        );
+
+        // Websocket connections recheck it
+        assert!(
+            !state.auth_service.has_session(has_session_req()).await?,
+            "session after logout"
+        );
+
+        // Tokens of deleted session are rejected before they expire
+        let connect_req = test::TestRequest::post()
+            .uri(&format!(
+                "/v1/rooms/{}/connect",
+                create_room_resp_body.room_id
+            ))
+            .header(ACCESS_TOKEN_HEADER_NAME, login_resp_body.access_token)
+            .cookie(cookie)
+            .to_request();
+        let connect_err = app
+            .call(connect_req)
+            .await
+            .expect_err("connect after logout");
+
+        assert_eq!(
+            connect_err.as_response_error().status_code(),
+            http::StatusCode::UNAUTHORIZED,
+            "connect after logout status code"
+        );
+
+        Ok(())
+    })
+}
+
 #[actix_rt::test]
 async fn test_refresh_tokens() -> anyhow::Result<()> {
     #[actix_web::post("/v1/with_auth")]
@@ -197,6 +2013,7 @@ async fn test_refresh_tokens() -> anyhow::Result<()> {
             .wrap(
                 auth_rest::JwtAuth::default()
                     .exclude_regex(".*/auth/login$")
+                    .exclude_regex(".*/auth/refresh-tokens$")
                     .exclude_regex((".*/rooms$", http::Method::POST)),
             )
             .configure(room_rest::service_config)
@@ -244,7 +2061,7 @@ async fn test_refresh_tokens() -> anyhow::Result<()> {
         .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
     assert!(cookie.is_some(), "(login) cookie refresh token");
 
-    let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+    let old_cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
 
     let login_resp_body: auth_rest::LoginResponse =
         actix_web::test::read_body_json(login_resp).await;
@@ -255,7 +2072,7 @@ async fn test_refresh_tokens() -> anyhow::Result<()> {
             ACCESS_TOKEN_HEADER_NAME,
             login_resp_body.access_token.clone(),
         )
-        .cookie(cookie)
+        .cookie(old_cookie.clone())
         .set_json(&auth_rest::RefreshTokensRequest {
             fingerprint: "123".to_string(),
         })
@@ -279,7 +2096,7 @@ async fn test_refresh_tokens() -> anyhow::Result<()> {
         .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
     assert!(cookie.is_some(), "(refresh tokens) cookie refresh token");
 
-    let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+    let new_cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
 
     let refresh_tokens_resp_body: auth_rest::RefreshTokensResponse =
         actix_web::test::read_body_json(refresh_tokens_resp).await;
@@ -292,13 +2109,102 @@ async fn test_refresh_tokens() -> anyhow::Result<()> {
                 ACCESS_TOKEN_HEADER_NAME,
                 refresh_tokens_resp_body.access_token.clone(),
             )
-            .cookie(cookie)
+            .cookie(new_cookie.clone())
             .to_request();
         let res = test::call_service(&mut app, req).await;
 
         assert_eq!(res.status(), http::StatusCode::OK, "with auth status code");
     }
 
+    // Request of another tab which still carries rotated refresh token is
+    // rejected, but the session is not revoked
+    let req = test::TestRequest::post()
+        .uri("/v1/with_auth")
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(old_cookie.clone())
+        .to_request();
+    let err = app.call(req).await.expect_err("old refresh token");
+
+    assert_eq!(
+        err.as_response_error().status_code(),
+        http::StatusCode::UNAUTHORIZED,
+        "with auth with old refresh token status code"
+    );
+
+    let req = test::TestRequest::post()
+        .uri("/v1/with_auth")
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            refresh_tokens_resp_body.access_token.clone(),
+        )
+        .cookie(new_cookie.clone())
+        .to_request();
+    let res = test::call_service(&mut app, req).await;
+
+    assert_eq!(
+        res.status(),
+        http::StatusCode::OK,
+        "with auth after old refresh token status code"
+    );
+
+    // Refresh with used refresh token is a reuse, the whole session is revoked
+    let refresh_tokens_req = test::TestRequest::post()
+        .uri("/v1/auth/refresh-tokens")
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(old_cookie)
+        .set_json(&auth_rest::RefreshTokensRequest {
+            fingerprint: "123".to_string(),
+        })
+        .to_request();
+    let refresh_tokens_resp = test::call_service(&mut app, refresh_tokens_req).await;
+
+    assert_eq!(
+        refresh_tokens_resp.status(),
+        http::StatusCode::UNAUTHORIZED,
+        "refresh with old refresh token status code"
+    );
+
+    let req = test::TestRequest::post()
+        .uri("/v1/with_auth")
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            refresh_tokens_resp_body.access_token.clone(),
+        )
+        .cookie(new_cookie.clone())
+        .to_request();
+    let err = app.call(req).await.expect_err("revoked session");
+
+    assert_eq!(
+        err.as_response_error().status_code(),
+        http::StatusCode::UNAUTHORIZED,
+        "with auth after reuse status code"
+    );
+
+    let refresh_tokens_req = test::TestRequest::post()
+        .uri("/v1/auth/refresh-tokens")
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            refresh_tokens_resp_body.access_token,
+        )
+        .cookie(new_cookie)
+        .set_json(&auth_rest::RefreshTokensRequest {
+            fingerprint: "123".to_string(),
+        })
+        .to_request();
+    let refresh_tokens_resp = test::call_service(&mut app, refresh_tokens_req).await;
+
+    assert_eq!(
+        refresh_tokens_resp.status(),
+        http::StatusCode::UNAUTHORIZED,
+        "refresh after reuse status code"
+    );
+
     Ok(())
 }
 
@@ -1,9 +1,14 @@
 use crate::adapter::auth::rest as auth_rest;
 use crate::adapter::room::rest as room_rest;
+use crate::config::Config;
+use crate::infra::crypto::KeyStore;
+use crate::port::room::service as room_service;
 use crate::tests::utils::*;
 
 use crate::adapter::auth::rest::{ACCESS_TOKEN_HEADER_NAME, REFRESH_TOKEN_COOKIE_NAME};
+use actix_web::dev::Service;
 use actix_web::{test, App};
+use chrono::{Duration, Utc};
 
 #[actix_rt::test]
 async fn test_create_room() -> anyhow::Result<()> {
@@ -23,6 +28,20 @@ async fn test_create_room() -> anyhow::Result<()> {
 
     let _: room_rest::CreateRoomResponse = actix_web::test::read_body_json(resp).await;
 
+    let req = test::TestRequest::post()
+        .uri("/v1/rooms")
+        .header("content-type", "application/json")
+        .set_payload(r#"{"e2ee":"yes"}"#)
+        .to_request();
+
+    let resp = test::call_service(&mut app, req).await;
+
+    assert_eq!(
+        resp.status(),
+        http::StatusCode::BAD_REQUEST,
+        "malformed body status code"
+    );
+
     Ok(())
 }
 
@@ -107,8 +126,11 @@ async fn test_connect_room() -> anyhow::Result<()> {
 }
 
 #[actix_rt::test]
-async fn test_disconnect_room() -> anyhow::Result<()> {
-    let state = new_default_state();
+async fn test_room_id_words() -> anyhow::Result<()> {
+    let mut cfg = Config::default();
+    cfg.room.id_strategy = crate::config::RoomIdStrategy::Words;
+
+    let state = new_state(cfg);
     let mut app = actix_web::test::init_service(
         App::new()
             .data(state.clone())
@@ -134,11 +156,16 @@ async fn test_disconnect_room() -> anyhow::Result<()> {
     let create_room_resp_body: room_rest::CreateRoomResponse =
         actix_web::test::read_body_json(create_room_resp).await;
 
+    let room_id = create_room_resp_body.room_id;
+    assert!(room_id.is_code(), "room id is not a code");
+    assert_eq!(room_id.to_string().split('-').count(), 4, "room id words");
+    assert_eq!(room_id.to_string().parse::<room_rest::RoomId>()?, room_id);
+
     let login_req = test::TestRequest::post()
         .uri("/v1/auth/login")
         .set_json(&auth_rest::LoginRequest {
             fingerprint: "123".to_string(),
-            room_id: create_room_resp_body.room_id,
+            room_id,
             room_password: create_room_resp_body.master_password,
         })
         .to_request();
@@ -150,33 +177,21 @@ async fn test_disconnect_room() -> anyhow::Result<()> {
         "login status code"
     );
 
-    let cookies: Vec<String> = login_resp
+    let cookie = login_resp
         .headers()
         .get_all(http::header::SET_COOKIE)
         .map(|v| v.to_str().unwrap().to_owned())
-        .collect();
-
-    let cookie = cookies
-        .into_iter()
-        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
-    assert!(cookie.is_some(), "(login) cookie refresh token");
-
-    let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME))
+        .expect("(login) cookie refresh token");
+    let cookie = actix_web::cookie::Cookie::parse(cookie)?;
 
     let login_resp_body: auth_rest::LoginResponse =
         actix_web::test::read_body_json(login_resp).await;
 
-    // Connect
     let connect_req = test::TestRequest::post()
-        .uri(&format!(
-            "/v1/rooms/{}/connect",
-            create_room_resp_body.room_id
-        ))
-        .header(
-            ACCESS_TOKEN_HEADER_NAME,
-            login_resp_body.access_token.clone(),
-        )
-        .cookie(cookie.clone())
+        .uri(&format!("/v1/rooms/{}/connect", room_id))
+        .header(ACCESS_TOKEN_HEADER_NAME, login_resp_body.access_token)
+        .cookie(cookie)
         .to_request();
     let connect_res = test::call_service(&mut app, connect_req).await;
 
@@ -186,28 +201,36 @@ async fn test_disconnect_room() -> anyhow::Result<()> {
         "connect status code"
     );
 
-    // Disconnect
-    let disconnect_req = test::TestRequest::post()
-        .uri(&format!(
-            "/v1/rooms/{}/disconnect",
-            create_room_resp_body.room_id
-        ))
-        .header(ACCESS_TOKEN_HEADER_NAME, login_resp_body.access_token)
-        .cookie(cookie)
-        .to_request();
-    let disconnect_res = test::call_service(&mut app, disconnect_req).await;
+    Ok(())
+}
+
+#[test]
+fn test_room_id_serde() -> anyhow::Result<()> {
+    let room_id = room_rest::RoomId::new(42);
+    let code_id = room_rest::RoomId::from_code([1, 2, 3], 4);
 
+    // JSON takes both strings and plain numbers
     assert_eq!(
-        disconnect_res.status(),
-        http::StatusCode::OK,
-        "disconnect status code"
+        serde_json::from_str::<room_rest::RoomId>("\"42\"")?,
+        room_id
+    );
+    assert_eq!(serde_json::from_str::<room_rest::RoomId>("42")?, room_id);
+    assert!(
+        serde_json::from_str::<room_rest::RoomId>(&(1u64 << 63).to_string()).is_err(),
+        "number with code flag"
     );
 
+    // Repo models are stored with bincode
+    for id in &[room_id, code_id] {
+        let bytes = bincode::serialize(id)?;
+        assert_eq!(bincode::deserialize::<room_rest::RoomId>(&bytes)?, *id);
+    }
+
     Ok(())
 }
 
 #[actix_rt::test]
-async fn test_add_file() -> anyhow::Result<()> {
+async fn test_room_qr() -> anyhow::Result<()> {
     let state = new_default_state();
     let mut app = actix_web::test::init_service(
         App::new()
@@ -233,12 +256,14 @@ async fn test_add_file() -> anyhow::Result<()> {
 
     let create_room_resp_body: room_rest::CreateRoomResponse =
         actix_web::test::read_body_json(create_room_resp).await;
+    let room_id = create_room_resp_body.room_id;
 
+    // Owner gets QR code
     let login_req = test::TestRequest::post()
         .uri("/v1/auth/login")
         .set_json(&auth_rest::LoginRequest {
             fingerprint: "123".to_string(),
-            room_id: create_room_resp_body.room_id,
+            room_id,
             room_password: create_room_resp_body.master_password,
         })
         .to_request();
@@ -247,72 +272,256 @@ async fn test_add_file() -> anyhow::Result<()> {
     assert_eq!(
         login_resp.status(),
         http::StatusCode::OK,
-        "login status code"
+        "owner login status code"
     );
 
-    let cookies: Vec<String> = login_resp
+    let owner_cookie = login_resp
         .headers()
         .get_all(http::header::SET_COOKIE)
         .map(|v| v.to_str().unwrap().to_owned())
-        .collect();
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME))
+        .expect("(login) cookie refresh token");
+    let owner_cookie = actix_web::cookie::Cookie::parse(owner_cookie)?;
+
+    let owner_token = actix_web::test::read_body_json::<auth_rest::LoginResponse, _>(login_resp)
+        .await
+        .access_token;
+
+    for (query, content_type) in [
+        ("", "image/svg+xml"),
+        ("?format=png&size=128&ec=h", "image/png"),
+    ]
+    .iter()
+    {
+        let qr_req = test::TestRequest::post()
+            .uri(&format!("/v1/rooms/{}/qr{}", room_id, query))
+            .header(ACCESS_TOKEN_HEADER_NAME, owner_token.clone())
+            .cookie(owner_cookie.clone())
+            .to_request();
+        let qr_resp = test::call_service(&mut app, qr_req).await;
+
+        assert_eq!(qr_resp.status(), http::StatusCode::OK, "qr status code");
+        assert_eq!(
+            qr_resp.headers().get(http::header::CONTENT_TYPE).unwrap(),
+            content_type,
+            "qr content type"
+        );
+    }
 
-    let cookie = cookies
-        .into_iter()
-        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
-    assert!(cookie.is_some(), "(login) cookie refresh token");
+    // Invite password works only once
+    let invite = state
+        .room_service
+        .create_invite(room_service::CreateInviteRequest { room_id })
+        .await?;
+    assert!(
+        invite.join_url.contains(&invite.room_password),
+        "join url has no password"
+    );
 
-    let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id,
+            room_password: invite.room_password.clone(),
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
 
-    let login_resp_body: auth_rest::LoginResponse =
-        actix_web::test::read_body_json(login_resp).await;
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::OK,
+        "guest login status code"
+    );
 
-    // Connect
-    let connect_req = test::TestRequest::post()
-        .uri(&format!(
-            "/v1/rooms/{}/connect",
-            create_room_resp_body.room_id
-        ))
-        .header(
-            ACCESS_TOKEN_HEADER_NAME,
-            login_resp_body.access_token.clone(),
-        )
-        .cookie(cookie.clone())
+    let guest_cookie = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME))
+        .expect("(login) cookie refresh token");
+    let guest_cookie = actix_web::cookie::Cookie::parse(guest_cookie)?;
+
+    let guest_token = actix_web::test::read_body_json::<auth_rest::LoginResponse, _>(login_resp)
+        .await
+        .access_token;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id,
+            room_password: invite.room_password,
+        })
         .to_request();
-    let connect_res = test::call_service(&mut app, connect_req).await;
+    let login_resp = test::call_service(&mut app, login_req).await;
 
     assert_eq!(
-        connect_res.status(),
+        login_resp.status(),
+        http::StatusCode::UNAUTHORIZED,
+        "reused login status code"
+    );
+
+    // Guest is not an owner
+    let qr_req = test::TestRequest::post()
+        .uri(&format!("/v1/rooms/{}/qr", room_id))
+        .header(ACCESS_TOKEN_HEADER_NAME, guest_token)
+        .cookie(guest_cookie)
+        .to_request();
+    let qr_resp = test::call_service(&mut app, qr_req).await;
+
+    assert_eq!(
+        qr_resp.status(),
+        http::StatusCode::FORBIDDEN,
+        "qr status code"
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_room_qr_expires() -> anyhow::Result<()> {
+    let mut cfg = Config::default();
+    cfg.room.qr_password_expires = 0;
+
+    let state = new_state(cfg);
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login$")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+    assert_eq!(
+        create_room_resp.status(),
         http::StatusCode::OK,
-        "connect status code"
+        "create room status code"
     );
 
-    // Add file
-    let add_file_req = test::TestRequest::post()
-        .uri(&format!(
-            "/v1/rooms/{}/files",
-            create_room_resp_body.room_id
-        ))
-        .header(ACCESS_TOKEN_HEADER_NAME, login_resp_body.access_token)
-        .cookie(cookie)
-        .set_json(&room_rest::AddFileBodyRequest {
-            name: "file-name.txt".to_string(),
-            size: 1024,
-            mime_type: "text".to_string(),
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        actix_web::test::read_body_json(create_room_resp).await;
+    let room_id = create_room_resp_body.room_id;
+
+    // Expired invite password is rejected even if it was never used
+    let invite = state
+        .room_service
+        .create_invite(room_service::CreateInviteRequest { room_id })
+        .await?;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id,
+            room_password: invite.room_password,
         })
         .to_request();
-    let add_file_res = test::call_service(&mut app, add_file_req).await;
+    let login_resp = test::call_service(&mut app, login_req).await;
 
     assert_eq!(
-        add_file_res.status(),
+        login_resp.status(),
+        http::StatusCode::UNAUTHORIZED,
+        "expired login status code"
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_room_qr_max_invites() -> anyhow::Result<()> {
+    let mut cfg = Config::default();
+    cfg.room.max_active_invites = 2;
+
+    let state = new_state(cfg);
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login$")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+    assert_eq!(
+        create_room_resp.status(),
         http::StatusCode::OK,
-        "add file status code",
+        "create room status code"
     );
 
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        actix_web::test::read_body_json(create_room_resp).await;
+    let room_id = create_room_resp_body.room_id;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    let owner_cookie = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME))
+        .expect("(login) cookie refresh token");
+    let owner_cookie = actix_web::cookie::Cookie::parse(owner_cookie)?;
+
+    let owner_token = actix_web::test::read_body_json::<auth_rest::LoginResponse, _>(login_resp)
+        .await
+        .access_token;
+
+    // QR code is not rendered by GET, every one adds a password
+    let qr_req = test::TestRequest::get()
+        .uri(&format!("/v1/rooms/{}/qr", room_id))
+        .header(ACCESS_TOKEN_HEADER_NAME, owner_token.clone())
+        .cookie(owner_cookie.clone())
+        .to_request();
+    let qr_resp = test::call_service(&mut app, qr_req).await;
+
+    assert_ne!(qr_resp.status(), http::StatusCode::OK, "qr get status code");
+
+    for expected in [
+        http::StatusCode::OK,
+        http::StatusCode::OK,
+        http::StatusCode::BAD_REQUEST,
+    ]
+    .iter()
+    {
+        let qr_req = test::TestRequest::post()
+            .uri(&format!("/v1/rooms/{}/qr", room_id))
+            .header(ACCESS_TOKEN_HEADER_NAME, owner_token.clone())
+            .cookie(owner_cookie.clone())
+            .to_request();
+        let qr_resp = test::call_service(&mut app, qr_req).await;
+
+        assert_eq!(qr_resp.status(), *expected, "qr status code");
+    }
+
     Ok(())
 }
 
 #[actix_rt::test]
-async fn test_get_files() -> anyhow::Result<()> {
+async fn test_disconnect_room() -> anyhow::Result<()> {
     let state = new_default_state();
     let mut app = actix_web::test::init_service(
         App::new()
@@ -391,70 +600,2233 @@ async fn test_get_files() -> anyhow::Result<()> {
         "connect status code"
     );
 
-    // Add files
-    {
-        let reqs = vec![
-            test::TestRequest::post()
-                .uri(&format!(
-                    "/v1/rooms/{}/files",
-                    create_room_resp_body.room_id
-                ))
-                .header(
-                    ACCESS_TOKEN_HEADER_NAME,
-                    login_resp_body.access_token.clone(),
-                )
-                .cookie(cookie.clone())
-                .set_json(&room_rest::AddFileBodyRequest {
-                    name: "file-name.txt".to_string(),
-                    size: 1024,
-                    mime_type: "text/plain".to_string(),
-                })
-                .to_request(),
-            test::TestRequest::post()
-                .uri(&format!(
-                    "/v1/rooms/{}/files",
-                    create_room_resp_body.room_id
-                ))
-                .header(
-                    ACCESS_TOKEN_HEADER_NAME,
-                    login_resp_body.access_token.clone(),
-                )
-                .cookie(cookie.clone())
-                .set_json(&room_rest::AddFileBodyRequest {
-                    name: "file-name.png".to_string(),
-                    size: 1024,
-                    mime_type: "image/png".to_string(),
-                })
-                .to_request(),
-            test::TestRequest::post()
-                .uri(&format!(
-                    "/v1/rooms/{}/files",
-                    create_room_resp_body.room_id
-                ))
-                .header(
-                    ACCESS_TOKEN_HEADER_NAME,
-                    login_resp_body.access_token.clone(),
-                )
-                .cookie(cookie.clone())
-                .set_json(&room_rest::AddFileBodyRequest {
-                    name: "file-name.jpg".to_string(),
-                    size: 1024,
-                    mime_type: "image/jpeg".to_string(),
+    // Disconnect
+    let disconnect_req = test::TestRequest::post()
+        .uri(&format!(
+            "/v1/rooms/{}/disconnect",
+            create_room_resp_body.room_id
+        ))
+        .header(ACCESS_TOKEN_HEADER_NAME, login_resp_body.access_token)
+        .cookie(cookie)
+        .to_request();
+    let disconnect_res = test::call_service(&mut app, disconnect_req).await;
+
+    assert_eq!(
+        disconnect_res.status(),
+        http::StatusCode::OK,
+        "disconnect status code"
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_ws_ticket() -> anyhow::Result<()> {
+    let state = new_default_state();
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login$")
+                    .exclude_regex((".*/rooms$", http::Method::POST))
+                    .exclude_regex((".*/rooms/[^/]+/ws$", http::Method::GET)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let mut rooms = vec![];
+    for _ in 0..2 {
+        let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+        let create_room_resp_body: room_rest::CreateRoomResponse =
+            test::read_response_json(&mut app, create_room_req).await;
+        rooms.push(create_room_resp_body);
+    }
+    let room_id = rooms[0].room_id;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id,
+            room_password: rooms[0].master_password.clone(),
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::OK,
+        "login status code"
+    );
+
+    let cookie = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME))
+        .expect("(login) cookie refresh token");
+    let cookie = actix_web::cookie::Cookie::parse(cookie)?;
+
+    let login_resp_body: auth_rest::LoginResponse =
+        actix_web::test::read_body_json(login_resp).await;
+
+    let mut tickets = vec![];
+    for _ in 0..2 {
+        let ticket_req = test::TestRequest::post()
+            .uri(&format!("/v1/rooms/{}/ws-ticket", room_id))
+            .header(
+                ACCESS_TOKEN_HEADER_NAME,
+                login_resp_body.access_token.clone(),
+            )
+            .cookie(cookie.clone())
+            .to_request();
+        let ticket_resp = test::call_service(&mut app, ticket_req).await;
+
+        assert_eq!(
+            ticket_resp.status(),
+            http::StatusCode::OK,
+            "ws ticket status code"
+        );
+
+        let ticket_resp_body: room_rest::CreateWsTicketResponse =
+            actix_web::test::read_body_json(ticket_resp).await;
+        assert!(
+            ticket_resp_body.expires_at > Utc::now().naive_utc(),
+            "ws ticket expires at"
+        );
+        tickets.push(ticket_resp_body.ticket);
+    }
+
+    for (room_id, ticket, expected, name) in [
+        (
+            room_id,
+            Some(tickets[0]),
+            http::StatusCode::SWITCHING_PROTOCOLS,
+            "ticket",
+        ),
+        // Ticket is single-use
+        (
+            room_id,
+            Some(tickets[0]),
+            http::StatusCode::UNAUTHORIZED,
+            "used ticket",
+        ),
+        (
+            room_id,
+            Some(uuid::Uuid::new_v4()),
+            http::StatusCode::UNAUTHORIZED,
+            "unknown ticket",
+        ),
+        (room_id, None, http::StatusCode::BAD_REQUEST, "no ticket"),
+        (
+            rooms[1].room_id,
+            Some(tickets[1]),
+            http::StatusCode::UNAUTHORIZED,
+            "ticket of another room",
+        ),
+    ]
+    .iter()
+    {
+        let uri = match ticket {
+            Some(ticket) => format!("/v1/rooms/{}/ws?ticket={}", room_id, ticket),
+            None => format!("/v1/rooms/{}/ws", room_id),
+        };
+        let ws_req = test::TestRequest::get()
+            .uri(&uri)
+            .header(http::header::UPGRADE, "websocket")
+            .header(http::header::CONNECTION, "upgrade")
+            .header(http::header::SEC_WEBSOCKET_VERSION, "13")
+            .header(http::header::SEC_WEBSOCKET_KEY, "x3JJHMbDL1EzLkh9GBhXDw==")
+            .to_request();
+        let ws_resp = test::call_service(&mut app, ws_req).await;
+
+        assert_eq!(ws_resp.status(), *expected, "{} status code", name);
+    }
+
+    Ok(())
+}
+
+// Actors need running actix system, which `actix_rt::test` does not start
+#[test]
+fn test_ws_join_code_owner_only() -> anyhow::Result<()> {
+    use futures::future::{select, Either};
+    use futures::StreamExt;
+
+    actix::System::new("test").block_on(async {
+        let state = new_default_state();
+        let mut app = actix_web::test::init_service(
+            App::new()
+                .data(state.clone())
+                .wrap(
+                    auth_rest::JwtAuth::default()
+                        .exclude_regex(".*/auth/login$")
+                        .exclude_regex((".*/rooms$", http::Method::POST))
+                        .exclude_regex((".*/rooms/[^/]+/ws$", http::Method::GET)),
+                )
+                .configure(room_rest::service_config)
+                .configure(auth_rest::service_config),
+        )
+        .await;
+
+        let create_room_req = test::TestRequest::post()
+            .uri("/v1/rooms")
+            .set_json(&room_rest::CreateRoomBodyRequest {
+                join_code: true,
+                ..Default::default()
+            })
+            .to_request();
+        let create_room_resp_body: room_rest::CreateRoomResponse =
+            test::read_response_json(&mut app, create_room_req).await;
+        let room_id = create_room_resp_body.room_id;
+
+        // Owner logs in with master password, guest with join code
+        let join_code = state
+            .room_service
+            .get_join_code(room_service::GetJoinCodeRequest { room_id })
+            .await?
+            .join_code
+            .expect("room join code");
+
+        for (room_password, owner) in [
+            (create_room_resp_body.master_password, true),
+            (join_code.code, false),
+        ]
+        .iter()
+        {
+            let login_req = test::TestRequest::post()
+                .uri("/v1/auth/login")
+                .set_json(&auth_rest::LoginRequest {
+                    fingerprint: "123".to_string(),
+                    room_id,
+                    room_password: room_password.clone(),
                 })
-                .to_request(),
-        ];
+                .to_request();
+            let login_resp = test::call_service(&mut app, login_req).await;
+
+            assert_eq!(
+                login_resp.status(),
+                http::StatusCode::OK,
+                "login status code"
+            );
+
+            let cookie = login_resp
+                .headers()
+                .get_all(http::header::SET_COOKIE)
+                .map(|v| v.to_str().unwrap().to_owned())
+                .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME))
+                .expect("(login) cookie refresh token");
+            let cookie = actix_web::cookie::Cookie::parse(cookie)?;
+
+            let login_resp_body: auth_rest::LoginResponse =
+                actix_web::test::read_body_json(login_resp).await;
+
+            let ticket_req = test::TestRequest::post()
+                .uri(&format!("/v1/rooms/{}/ws-ticket", room_id))
+                .header(ACCESS_TOKEN_HEADER_NAME, login_resp_body.access_token)
+                .cookie(cookie)
+                .to_request();
+            let ticket_resp_body: room_rest::CreateWsTicketResponse =
+                test::read_response_json(&mut app, ticket_req).await;
+
+            let ws_req = test::TestRequest::get()
+                .uri(&format!(
+                    "/v1/rooms/{}/ws?ticket={}",
+                    room_id, ticket_resp_body.ticket
+                ))
+                .header(http::header::UPGRADE, "websocket")
+                .header(http::header::CONNECTION, "upgrade")
+                .header(http::header::SEC_WEBSOCKET_VERSION, "13")
+                .header(http::header::SEC_WEBSOCKET_KEY, "x3JJHMbDL1EzLkh9GBhXDw==")
+                .to_request();
+            // Connection is closed once client payload ends, so it never does
+            let pending: actix_web::dev::PayloadStream = Box::pin(futures::stream::pending());
+            let (ws_req, _) = ws_req.replace_payload(actix_web::dev::Payload::Stream(pending));
+            let mut ws_resp = test::call_service(&mut app, ws_req).await;
+
+            assert_eq!(
+                ws_resp.status(),
+                http::StatusCode::SWITCHING_PROTOCOLS,
+                "ws status code"
+            );
+
+            // Frames written by the connection are the response body
+            let mut body = ws_resp.take_body();
+            let timeout = actix::clock::delay_for(std::time::Duration::from_millis(500));
+            let frame = match select(body.next(), Box::pin(timeout)).await {
+                Either::Left((frame, _)) => frame.and_then(Result::ok),
+                Either::Right(_) => None,
+            };
+            let got_join_code = frame
+                .map(|f| String::from_utf8_lossy(&f).contains("join_code"))
+                .unwrap_or_default();
+
+            assert_eq!(got_join_code, *owner, "join code pushed, owner={}", owner);
+        }
+
+        Ok(())
+    })
+}
+
+#[actix_rt::test]
+async fn test_add_file() -> anyhow::Result<()> {
+    let state = new_default_state();
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login$")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+    assert_eq!(
+        create_room_resp.status(),
+        http::StatusCode::OK,
+        "create room status code"
+    );
+
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        actix_web::test::read_body_json(create_room_resp).await;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id: create_room_resp_body.room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::OK,
+        "login status code"
+    );
+
+    let cookies: Vec<String> = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .collect();
+
+    let cookie = cookies
+        .into_iter()
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
+    assert!(cookie.is_some(), "(login) cookie refresh token");
+
+    let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+
+    let login_resp_body: auth_rest::LoginResponse =
+        actix_web::test::read_body_json(login_resp).await;
+
+    // Connect
+    let connect_req = test::TestRequest::post()
+        .uri(&format!(
+            "/v1/rooms/{}/connect",
+            create_room_resp_body.room_id
+        ))
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(cookie.clone())
+        .to_request();
+    let connect_res = test::call_service(&mut app, connect_req).await;
+
+    assert_eq!(
+        connect_res.status(),
+        http::StatusCode::OK,
+        "connect status code"
+    );
+
+    // Add file
+    let add_file_req = test::TestRequest::post()
+        .uri(&format!(
+            "/v1/rooms/{}/files",
+            create_room_resp_body.room_id
+        ))
+        .header(ACCESS_TOKEN_HEADER_NAME, login_resp_body.access_token)
+        .cookie(cookie)
+        .set_json(&room_rest::AddFileBodyRequest {
+            name: "file-name.txt".to_string(),
+            size: 1024,
+            mime_type: "text".to_string(),
+            expires_at: None,
+            max_downloads: None,
+        })
+        .to_request();
+    let add_file_res = test::call_service(&mut app, add_file_req).await;
+
+    assert_eq!(
+        add_file_res.status(),
+        http::StatusCode::OK,
+        "add file status code",
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_add_file_concurrent() -> anyhow::Result<()> {
+    use futures::executor::block_on;
+    use std::sync::Arc;
+
+    let state = new_default_state();
+
+    let create_room_res = block_on(state.room_service.create_room(
+        room_service::CreateRoomRequest {
+            room_settings: Default::default(),
+            join_code: false,
+        },
+    ))?;
+    let room_id = create_room_res.room_id;
+
+    // Uploads of the same name must end up as versions of one file
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let room_service = Arc::clone(&state.room_service);
+            std::thread::spawn(move || {
+                block_on(room_service.add_file(room_service::AddFileRequest {
+                    room_id,
+                    file_name: "file-name.txt".to_string(),
+                    file_content: room_service::FileContent::Absent { size: 1024 },
+                    file_mime_type: "text/plain".to_string(),
+                    file_source_client_id: uuid::Uuid::new_v4(),
+                    file_expires_at: None,
+                    file_max_downloads: None,
+                }))
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().expect("add file thread panicked")?;
+    }
+
+    let get_files_res = block_on(state.room_service.get_files(room_service::GetFilesRequest {
+        room_id,
+        sort_by: room_service::FileSortBy::Name,
+        order: room_service::SortOrder::Asc,
+        filter: Default::default(),
+        cursor: None,
+        limit: 100,
+    }))?;
+
+    assert_eq!(get_files_res.files.len(), 1, "files amount");
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_get_files() -> anyhow::Result<()> {
+    let state = new_default_state();
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login$")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+    assert_eq!(
+        create_room_resp.status(),
+        http::StatusCode::OK,
+        "create room status code"
+    );
+
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        actix_web::test::read_body_json(create_room_resp).await;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id: create_room_resp_body.room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::OK,
+        "login status code"
+    );
+
+    let cookies: Vec<String> = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .collect();
+
+    let cookie = cookies
+        .into_iter()
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
+    assert!(cookie.is_some(), "(login) cookie refresh token");
+
+    let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+
+    let login_resp_body: auth_rest::LoginResponse =
+        actix_web::test::read_body_json(login_resp).await;
+
+    // Connect
+    let connect_req = test::TestRequest::post()
+        .uri(&format!(
+            "/v1/rooms/{}/connect",
+            create_room_resp_body.room_id
+        ))
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(cookie.clone())
+        .to_request();
+    let connect_res = test::call_service(&mut app, connect_req).await;
+
+    assert_eq!(
+        connect_res.status(),
+        http::StatusCode::OK,
+        "connect status code"
+    );
+
+    // Add files
+    {
+        let reqs = vec![
+            test::TestRequest::post()
+                .uri(&format!(
+                    "/v1/rooms/{}/files",
+                    create_room_resp_body.room_id
+                ))
+                .header(
+                    ACCESS_TOKEN_HEADER_NAME,
+                    login_resp_body.access_token.clone(),
+                )
+                .cookie(cookie.clone())
+                .set_json(&room_rest::AddFileBodyRequest {
+                    name: "file-name.txt".to_string(),
+                    size: 1024,
+                    mime_type: "text/plain".to_string(),
+                    expires_at: None,
+                    max_downloads: None,
+                })
+                .to_request(),
+            test::TestRequest::post()
+                .uri(&format!(
+                    "/v1/rooms/{}/files",
+                    create_room_resp_body.room_id
+                ))
+                .header(
+                    ACCESS_TOKEN_HEADER_NAME,
+                    login_resp_body.access_token.clone(),
+                )
+                .cookie(cookie.clone())
+                .set_json(&room_rest::AddFileBodyRequest {
+                    name: "file-name.png".to_string(),
+                    size: 1024,
+                    mime_type: "image/png".to_string(),
+                    expires_at: None,
+                    max_downloads: None,
+                })
+                .to_request(),
+            test::TestRequest::post()
+                .uri(&format!(
+                    "/v1/rooms/{}/files",
+                    create_room_resp_body.room_id
+                ))
+                .header(
+                    ACCESS_TOKEN_HEADER_NAME,
+                    login_resp_body.access_token.clone(),
+                )
+                .cookie(cookie.clone())
+                .set_json(&room_rest::AddFileBodyRequest {
+                    name: "file-name.jpg".to_string(),
+                    size: 1024,
+                    mime_type: "image/jpeg".to_string(),
+                    expires_at: None,
+                    max_downloads: None,
+                })
+                .to_request(),
+        ];
+
+        for req in reqs {
+            let res = test::call_service(&mut app, req).await;
+
+            assert_eq!(res.status(), http::StatusCode::OK, "add file status code",);
+        }
+    }
+
+    // Get files
+    let get_files_req = test::TestRequest::get()
+        .uri(&format!(
+            "/v1/rooms/{}/files",
+            create_room_resp_body.room_id
+        ))
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(cookie.clone())
+        .to_request();
+    let get_files_res = test::call_service(&mut app, get_files_req).await;
+
+    assert_eq!(
+        get_files_res.status(),
+        http::StatusCode::OK,
+        "get files status code"
+    );
+
+    let get_files_res_body: room_rest::GetFilesResponse =
+        actix_web::test::read_body_json(get_files_res).await;
+
+    assert_eq!(get_files_res_body.files.len(), 3, "files amount");
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_get_files_expired() -> anyhow::Result<()> {
+    let state = new_default_state();
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login$")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+    assert_eq!(
+        create_room_resp.status(),
+        http::StatusCode::OK,
+        "create room status code"
+    );
+
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        actix_web::test::read_body_json(create_room_resp).await;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id: create_room_resp_body.room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::OK,
+        "login status code"
+    );
+
+    let cookies: Vec<String> = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .collect();
+
+    let cookie = cookies
+        .into_iter()
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
+    assert!(cookie.is_some(), "(login) cookie refresh token");
+
+    let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+
+    let login_resp_body: auth_rest::LoginResponse =
+        actix_web::test::read_body_json(login_resp).await;
+
+    // Add files
+    {
+        let reqs = vec![
+            test::TestRequest::post()
+                .uri(&format!(
+                    "/v1/rooms/{}/files",
+                    create_room_resp_body.room_id
+                ))
+                .header(
+                    ACCESS_TOKEN_HEADER_NAME,
+                    login_resp_body.access_token.clone(),
+                )
+                .cookie(cookie.clone())
+                .set_json(&room_rest::AddFileBodyRequest {
+                    name: "file-name.txt".to_string(),
+                    size: 1024,
+                    mime_type: "text/plain".to_string(),
+                    expires_at: Some((Utc::now() + Duration::seconds(1)).naive_utc()),
+                    max_downloads: None,
+                })
+                .to_request(),
+            test::TestRequest::post()
+                .uri(&format!(
+                    "/v1/rooms/{}/files",
+                    create_room_resp_body.room_id
+                ))
+                .header(
+                    ACCESS_TOKEN_HEADER_NAME,
+                    login_resp_body.access_token.clone(),
+                )
+                .cookie(cookie.clone())
+                .set_json(&room_rest::AddFileBodyRequest {
+                    name: "file-name.png".to_string(),
+                    size: 1024,
+                    mime_type: "image/png".to_string(),
+                    expires_at: Some((Utc::now() + Duration::minutes(1)).naive_utc()),
+                    max_downloads: None,
+                })
+                .to_request(),
+        ];
+
+        for req in reqs {
+            let res = test::call_service(&mut app, req).await;
+
+            assert_eq!(res.status(), http::StatusCode::OK, "add file status code",);
+        }
+    }
+
+    // File in the past is rejected
+    let add_file_req = test::TestRequest::post()
+        .uri(&format!(
+            "/v1/rooms/{}/files",
+            create_room_resp_body.room_id
+        ))
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(cookie.clone())
+        .set_json(&room_rest::AddFileBodyRequest {
+            name: "file-name.gif".to_string(),
+            size: 1024,
+            mime_type: "image/gif".to_string(),
+            expires_at: Some((Utc::now() - Duration::minutes(1)).naive_utc()),
+            max_downloads: None,
+        })
+        .to_request();
+    let add_file_res = test::call_service(&mut app, add_file_req).await;
+
+    assert_eq!(
+        add_file_res.status(),
+        http::StatusCode::BAD_REQUEST,
+        "add file expired in the past status code"
+    );
+
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+
+    // Expired file comes first in that order, page is still full
+    let get_files_req = test::TestRequest::get()
+        .uri(&format!(
+            "/v1/rooms/{}/files?sort_by=name&order=desc&limit=1",
+            create_room_resp_body.room_id
+        ))
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(cookie.clone())
+        .to_request();
+    let get_files_res_body: room_rest::GetFilesResponse =
+        test::read_response_json(&mut app, get_files_req).await;
+
+    assert_eq!(get_files_res_body.files.len(), 1, "page files amount");
+    assert_eq!(
+        get_files_res_body.files[0].name, "file-name.png",
+        "page skips expired file"
+    );
+    assert!(get_files_res_body.next_cursor.is_none(), "page next cursor");
+
+    // Get files
+    let get_files_req = test::TestRequest::get()
+        .uri(&format!(
+            "/v1/rooms/{}/files",
+            create_room_resp_body.room_id
+        ))
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(cookie.clone())
+        .to_request();
+    let get_files_res = test::call_service(&mut app, get_files_req).await;
+
+    assert_eq!(
+        get_files_res.status(),
+        http::StatusCode::OK,
+        "get files status code"
+    );
+
+    let get_files_res_body: room_rest::GetFilesResponse =
+        actix_web::test::read_body_json(get_files_res).await;
+
+    assert_eq!(get_files_res_body.files.len(), 1, "files amount");
+    assert!(
+        get_files_res_body
+            .files
+            .iter()
+            .all(|f| f.name == "file-name.png"),
+        "expired file skipped"
+    );
+
+    // Janitor deletes expired file
+    let delete_expired_res = state
+        .room_service
+        .delete_expired(room_service::DeleteExpiredRequest {
+            now: Utc::now().naive_utc(),
+        })
+        .await?;
+
+    assert_eq!(
+        delete_expired_res.removed_files.len(),
+        1,
+        "deleted expired files amount"
+    );
+    assert_eq!(
+        delete_expired_res.removed_files[0].0, create_room_resp_body.room_id,
+        "deleted expired file room"
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_file_versions() -> anyhow::Result<()> {
+    let state = new_default_state();
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login$")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+    assert_eq!(
+        create_room_resp.status(),
+        http::StatusCode::OK,
+        "create room status code"
+    );
+
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        actix_web::test::read_body_json(create_room_resp).await;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id: create_room_resp_body.room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::OK,
+        "login status code"
+    );
+
+    let cookies: Vec<String> = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .collect();
+
+    let cookie = cookies
+        .into_iter()
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
+    assert!(cookie.is_some(), "(login) cookie refresh token");
+
+    let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+
+    let login_resp_body: auth_rest::LoginResponse =
+        actix_web::test::read_body_json(login_resp).await;
+
+    // Add the same file twice
+    let mut files = vec![];
+    for size in &[1024, 2048] {
+        let add_file_req = test::TestRequest::post()
+            .uri(&format!(
+                "/v1/rooms/{}/files",
+                create_room_resp_body.room_id
+            ))
+            .header(
+                ACCESS_TOKEN_HEADER_NAME,
+                login_resp_body.access_token.clone(),
+            )
+            .cookie(cookie.clone())
+            .set_json(&room_rest::AddFileBodyRequest {
+                name: "report.pdf".to_string(),
+                size: *size,
+                mime_type: "application/pdf".to_string(),
+                expires_at: None,
+                max_downloads: None,
+            })
+            .to_request();
+        let add_file_res = test::call_service(&mut app, add_file_req).await;
+
+        assert_eq!(
+            add_file_res.status(),
+            http::StatusCode::OK,
+            "add file status code",
+        );
+
+        let add_file_res_body: room_rest::AddFileResponse =
+            actix_web::test::read_body_json(add_file_res).await;
+        files.push(add_file_res_body.file);
+    }
+
+    assert_eq!(files[0].id, files[1].id, "file id");
+    assert_eq!(files[1].version, 2, "file version");
+    assert_eq!(files[1].size, 2048, "file size");
+
+    // Get versions
+    let get_versions_req = test::TestRequest::get()
+        .uri(&format!(
+            "/v1/rooms/{}/files/{}/versions",
+            create_room_resp_body.room_id, files[1].id
+        ))
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(cookie.clone())
+        .to_request();
+    let get_versions_res = test::call_service(&mut app, get_versions_req).await;
+
+    assert_eq!(
+        get_versions_res.status(),
+        http::StatusCode::OK,
+        "get file versions status code"
+    );
+
+    let get_versions_res_body: room_rest::GetFileVersionsResponse =
+        actix_web::test::read_body_json(get_versions_res).await;
+
+    assert_eq!(get_versions_res_body.versions.len(), 2, "versions amount");
+
+    // Restore first version
+    let restore_req = test::TestRequest::post()
+        .uri(&format!(
+            "/v1/rooms/{}/files/{}/versions/{}/restore",
+            create_room_resp_body.room_id, files[1].id, 1
+        ))
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(cookie.clone())
+        .to_request();
+    let restore_res = test::call_service(&mut app, restore_req).await;
+
+    assert_eq!(
+        restore_res.status(),
+        http::StatusCode::OK,
+        "restore file version status code"
+    );
+
+    let restore_res_body: room_rest::RestoreFileVersionResponse =
+        actix_web::test::read_body_json(restore_res).await;
+
+    assert_eq!(restore_res_body.file.version, 3, "restored file version");
+    assert_eq!(restore_res_body.file.size, 1024, "restored file size");
+
+    // Restore unknown version
+    let restore_req = test::TestRequest::post()
+        .uri(&format!(
+            "/v1/rooms/{}/files/{}/versions/{}/restore",
+            create_room_resp_body.room_id, files[1].id, 42
+        ))
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(cookie.clone())
+        .to_request();
+    let restore_res = test::call_service(&mut app, restore_req).await;
+
+    assert_eq!(
+        restore_res.status(),
+        http::StatusCode::NOT_FOUND,
+        "restore unknown file version status code"
+    );
+
+    // Get versions of unknown file
+    let get_versions_req = test::TestRequest::get()
+        .uri(&format!(
+            "/v1/rooms/{}/files/{}/versions",
+            create_room_resp_body.room_id,
+            uuid::Uuid::new_v4()
+        ))
+        .header(ACCESS_TOKEN_HEADER_NAME, login_resp_body.access_token)
+        .cookie(cookie)
+        .to_request();
+    let get_versions_res = test::call_service(&mut app, get_versions_req).await;
+
+    assert_eq!(
+        get_versions_res.status(),
+        http::StatusCode::NOT_FOUND,
+        "get unknown file versions status code"
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_file_versions_expired() -> anyhow::Result<()> {
+    let state = new_default_state();
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login$")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+    assert_eq!(
+        create_room_resp.status(),
+        http::StatusCode::OK,
+        "create room status code"
+    );
+
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        actix_web::test::read_body_json(create_room_resp).await;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id: create_room_resp_body.room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::OK,
+        "login status code"
+    );
+
+    let cookies: Vec<String> = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .collect();
+
+    let cookie = cookies
+        .into_iter()
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
+    assert!(cookie.is_some(), "(login) cookie refresh token");
+
+    let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+
+    let login_resp_body: auth_rest::LoginResponse =
+        actix_web::test::read_body_json(login_resp).await;
+
+    // Add file which expires shortly
+    let add_file_req = test::TestRequest::post()
+        .uri(&format!(
+            "/v1/rooms/{}/files",
+            create_room_resp_body.room_id
+        ))
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(cookie.clone())
+        .set_json(&room_rest::AddFileBodyRequest {
+            name: "report.pdf".to_string(),
+            size: 1024,
+            mime_type: "application/pdf".to_string(),
+            expires_at: Some(Utc::now().naive_utc() + Duration::seconds(1)),
+            max_downloads: None,
+        })
+        .to_request();
+    let add_file_res = test::call_service(&mut app, add_file_req).await;
+
+    assert_eq!(
+        add_file_res.status(),
+        http::StatusCode::OK,
+        "add file status code",
+    );
+
+    let add_file_res_body: room_rest::AddFileResponse =
+        actix_web::test::read_body_json(add_file_res).await;
+    let file_id = add_file_res_body.file.id;
+
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+
+    // Restore version of expired file
+    let restore_req = test::TestRequest::post()
+        .uri(&format!(
+            "/v1/rooms/{}/files/{}/versions/{}/restore",
+            create_room_resp_body.room_id, file_id, 1
+        ))
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(cookie.clone())
+        .to_request();
+    let restore_res = test::call_service(&mut app, restore_req).await;
+
+    assert_eq!(
+        restore_res.status(),
+        http::StatusCode::NOT_FOUND,
+        "restore expired file version status code"
+    );
+
+    // Get versions of expired file
+    let get_versions_req = test::TestRequest::get()
+        .uri(&format!(
+            "/v1/rooms/{}/files/{}/versions",
+            create_room_resp_body.room_id, file_id
+        ))
+        .header(ACCESS_TOKEN_HEADER_NAME, login_resp_body.access_token)
+        .cookie(cookie)
+        .to_request();
+    let get_versions_res = test::call_service(&mut app, get_versions_req).await;
+
+    assert_eq!(
+        get_versions_res.status(),
+        http::StatusCode::NOT_FOUND,
+        "get expired file versions status code"
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_file_content() -> anyhow::Result<()> {
+    let mut cfg = Config::default();
+    cfg.room.max_file_versions = 2;
+    cfg.room.max_file_size = 16;
+    let state = new_state(cfg);
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login$")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+    assert_eq!(
+        create_room_resp.status(),
+        http::StatusCode::OK,
+        "create room status code"
+    );
+
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        actix_web::test::read_body_json(create_room_resp).await;
+    let room_id = create_room_resp_body.room_id;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::OK,
+        "login status code"
+    );
+
+    let cookie = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
+    assert!(cookie.is_some(), "(login) cookie refresh token");
+
+    let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+
+    let login_resp_body: auth_rest::LoginResponse =
+        actix_web::test::read_body_json(login_resp).await;
+    let access_token = login_resp_body.access_token;
+
+    // Upload two versions of the same name
+    let mut files = vec![];
+    for content in &["first", "second"] {
+        let upload_req = test::TestRequest::post()
+            .uri(&format!(
+                "/v1/rooms/{}/files/upload?name=r%C3%A9sum%C3%A9.txt",
+                room_id
+            ))
+            .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+            .cookie(cookie.clone())
+            .header(http::header::CONTENT_TYPE, "text/plain")
+            .set_payload(*content)
+            .to_request();
+        let upload_res = test::call_service(&mut app, upload_req).await;
+
+        assert_eq!(
+            upload_res.status(),
+            http::StatusCode::OK,
+            "upload file status code"
+        );
+
+        let upload_res_body: room_rest::UploadFileResponse =
+            actix_web::test::read_body_json(upload_res).await;
+        files.push(upload_res_body.file);
+    }
+
+    assert_eq!(files[1].version, 2, "file version");
+    assert_eq!(files[1].size, 6, "file size");
+    assert_eq!(files[1].mime_type, "text/plain", "file mime type");
+    assert_eq!(
+        files[1].sha256.as_deref(),
+        Some("16367aacb67a4a017c8da8ab95682ccb390863780f7114dda0a0e0c55644c7c4"),
+        "file sha256"
+    );
+
+    // Current version
+    let get_content_req = test::TestRequest::get()
+        .uri(&format!(
+            "/v1/rooms/{}/files/{}/content",
+            room_id, files[1].id
+        ))
+        .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+        .cookie(cookie.clone())
+        .to_request();
+    let get_content_res = test::call_service(&mut app, get_content_req).await;
+
+    assert_eq!(
+        get_content_res.status(),
+        http::StatusCode::OK,
+        "get file content status code"
+    );
+    let header = |name| {
+        get_content_res
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_owned())
+    };
+    assert_eq!(
+        header(http::header::CONTENT_DISPOSITION).as_deref(),
+        Some("attachment; filename*=UTF-8''r%C3%A9sum%C3%A9.txt"),
+        "content disposition"
+    );
+    assert_eq!(
+        header(http::header::X_CONTENT_TYPE_OPTIONS).as_deref(),
+        Some("nosniff"),
+        "content type options"
+    );
+    assert_eq!(
+        test::read_body(get_content_res).await.as_ref(),
+        b"second",
+        "file content"
+    );
+
+    // Restored version shares content of the first one, which stays when
+    // the first version is dropped
+    let restore_req = test::TestRequest::post()
+        .uri(&format!(
+            "/v1/rooms/{}/files/{}/versions/1/restore",
+            room_id, files[1].id
+        ))
+        .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+        .cookie(cookie.clone())
+        .to_request();
+    let restore_res = test::call_service(&mut app, restore_req).await;
+
+    assert_eq!(
+        restore_res.status(),
+        http::StatusCode::OK,
+        "restore file version status code"
+    );
+
+    for (version, content) in &[(2, &b"second"[..]), (3, &b"first"[..])] {
+        let get_content_req = test::TestRequest::get()
+            .uri(&format!(
+                "/v1/rooms/{}/files/{}/content?version={}",
+                room_id, files[1].id, version
+            ))
+            .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+            .cookie(cookie.clone())
+            .to_request();
+        let get_content_res = test::call_service(&mut app, get_content_req).await;
+
+        assert_eq!(
+            get_content_res.status(),
+            http::StatusCode::OK,
+            "get file version content status code"
+        );
+        assert_eq!(
+            test::read_body(get_content_res).await.as_ref(),
+            *content,
+            "file version content"
+        );
+    }
+
+    // Dropped version
+    let get_content_req = test::TestRequest::get()
+        .uri(&format!(
+            "/v1/rooms/{}/files/{}/content?version=1",
+            room_id, files[1].id
+        ))
+        .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+        .cookie(cookie.clone())
+        .to_request();
+    let get_content_res = test::call_service(&mut app, get_content_req).await;
+
+    assert_eq!(
+        get_content_res.status(),
+        http::StatusCode::NOT_FOUND,
+        "get dropped version content status code"
+    );
+
+    // File without content
+    let add_file_req = test::TestRequest::post()
+        .uri(&format!("/v1/rooms/{}/files", room_id))
+        .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+        .cookie(cookie.clone())
+        .set_json(&room_rest::AddFileBodyRequest {
+            name: "metadata.txt".to_string(),
+            size: 1024,
+            mime_type: "text/plain".to_string(),
+            expires_at: None,
+            max_downloads: None,
+        })
+        .to_request();
+    let add_file_res = test::call_service(&mut app, add_file_req).await;
+
+    assert_eq!(
+        add_file_res.status(),
+        http::StatusCode::OK,
+        "add file status code"
+    );
+
+    let add_file_res_body: room_rest::AddFileResponse =
+        actix_web::test::read_body_json(add_file_res).await;
+
+    let get_content_req = test::TestRequest::get()
+        .uri(&format!(
+            "/v1/rooms/{}/files/{}/content",
+            room_id, add_file_res_body.file.id
+        ))
+        .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+        .cookie(cookie.clone())
+        .to_request();
+    let get_content_res = test::call_service(&mut app, get_content_req).await;
+
+    assert_eq!(
+        get_content_res.status(),
+        http::StatusCode::NOT_FOUND,
+        "get absent content status code"
+    );
+
+    // Too large
+    let upload_req = test::TestRequest::post()
+        .uri(&format!(
+            "/v1/rooms/{}/files/upload?name=large.bin",
+            room_id
+        ))
+        .header(ACCESS_TOKEN_HEADER_NAME, access_token)
+        .cookie(cookie.clone())
+        .set_payload(vec![0u8; 17])
+        .to_request();
+    let upload_res = test::call_service(&mut app, upload_req).await;
+
+    assert_eq!(
+        upload_res.status(),
+        http::StatusCode::PAYLOAD_TOO_LARGE,
+        "upload large file status code"
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_upload_strips_image_metadata() -> anyhow::Result<()> {
+    let mut cfg = Config::default();
+    cfg.privacy.strip_image_metadata = true;
+    let state = new_state(cfg);
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login$")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp = test::call_service(&mut app, create_room_req).await;
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        actix_web::test::read_body_json(create_room_resp).await;
+    let room_id = create_room_resp_body.room_id;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+    let cookie = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
+    assert!(cookie.is_some(), "(login) cookie refresh token");
+
+    let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+
+    let login_resp_body: auth_rest::LoginResponse =
+        actix_web::test::read_body_json(login_resp).await;
+
+    // PNG with author text and EXIF, chunk CRCs are not checked
+    let chunk = |kind: &[u8], contents: &[u8]| {
+        let mut chunk = (contents.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(contents);
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    };
+    let image = [
+        b"\x89PNG\r\n\x1a\n".to_vec(),
+        chunk(b"IHDR", &[0; 13]),
+        chunk(b"tEXt", b"Author\0me"),
+        chunk(b"eXIf", b"MM\0*\0\0\0\x08\0\0"),
+        chunk(b"IDAT", &[1, 2, 3]),
+        chunk(b"IEND", &[]),
+    ]
+    .concat();
+    let stripped = [
+        b"\x89PNG\r\n\x1a\n".to_vec(),
+        chunk(b"IHDR", &[0; 13]),
+        chunk(b"IDAT", &[1, 2, 3]),
+        chunk(b"IEND", &[]),
+    ]
+    .concat();
+
+    let upload_req = test::TestRequest::post()
+        .uri(&format!(
+            "/v1/rooms/{}/files/upload?name=photo.png",
+            room_id
+        ))
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(cookie.clone())
+        .header(http::header::CONTENT_TYPE, "image/png")
+        .set_payload(image)
+        .to_request();
+    let upload_res = test::call_service(&mut app, upload_req).await;
+
+    assert_eq!(
+        upload_res.status(),
+        http::StatusCode::OK,
+        "upload image status code"
+    );
+
+    let upload_res_body: serde_json::Value = actix_web::test::read_body_json(upload_res).await;
+    assert_eq!(
+        upload_res_body["removed_metadata"],
+        serde_json::json!(["exif", "comment"]),
+        "removed metadata"
+    );
+    assert_eq!(
+        upload_res_body["file"]["size"],
+        stripped.len(),
+        "stripped image size"
+    );
+
+    let get_content_req = test::TestRequest::get()
+        .uri(&format!(
+            "/v1/rooms/{}/files/{}/content",
+            room_id,
+            upload_res_body["file"]["id"].as_str().unwrap_or_default()
+        ))
+        .header(ACCESS_TOKEN_HEADER_NAME, login_resp_body.access_token)
+        .cookie(cookie)
+        .to_request();
+    let get_content_res = test::call_service(&mut app, get_content_req).await;
+
+    assert_eq!(
+        get_content_res.headers().get(http::header::CONTENT_TYPE),
+        Some(&http::HeaderValue::from_static("image/png")),
+        "content type"
+    );
+    assert_eq!(
+        test::read_body(get_content_res).await.as_ref(),
+        stripped.as_slice(),
+        "stripped image"
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_file_content_compression() -> anyhow::Result<()> {
+    use std::io::Read;
+
+    let state = new_default_state();
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login$")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        test::read_response_json(&mut app, create_room_req).await;
+    let room_id = create_room_resp_body.room_id;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::OK,
+        "login status code"
+    );
+
+    let cookie = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
+    assert!(cookie.is_some(), "(login) cookie refresh token");
+
+    let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+
+    let login_resp_body: auth_rest::LoginResponse =
+        actix_web::test::read_body_json(login_resp).await;
+    let access_token = login_resp_body.access_token;
+
+    // Larger than a stored chunk, so it is decoded chunk by chunk
+    let log = (0..10_000)
+        .map(|i| format!("2021-03-04 05:06:07 INFO request {} done\n", i))
+        .collect::<String>();
+    let upload_req = test::TestRequest::post()
+        .uri(&format!("/v1/rooms/{}/files/upload?name=app.log", room_id))
+        .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+        .cookie(cookie.clone())
+        .set_payload(log.clone())
+        .to_request();
+    let upload_res_body: room_rest::UploadFileResponse =
+        test::read_response_json(&mut app, upload_req).await;
+
+    assert_eq!(upload_res_body.file.size, log.len(), "logical file size");
+
+    let file_id = upload_res_body.file.id;
+    let cases = vec![
+        (None, None),
+        (Some("zstd"), Some("zstd")),
+        (Some("gzip, deflate"), Some("gzip")),
+        (Some("br, zstd;q=0"), None),
+    ];
+    for (accept_encoding, expected_encoding) in cases {
+        let mut get_content_req = test::TestRequest::get()
+            .uri(&format!("/v1/rooms/{}/files/{}/content", room_id, file_id))
+            .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+            .cookie(cookie.clone());
+        if let Some(accept_encoding) = accept_encoding {
+            get_content_req =
+                get_content_req.header(http::header::ACCEPT_ENCODING, accept_encoding);
+        }
+        let get_content_res = test::call_service(&mut app, get_content_req.to_request()).await;
+
+        assert_eq!(
+            get_content_res
+                .headers()
+                .get(http::header::CONTENT_ENCODING)
+                .and_then(|v| v.to_str().ok()),
+            expected_encoding,
+            "content encoding, accept encoding={:?}",
+            accept_encoding
+        );
+
+        let body = test::read_body(get_content_res).await;
+        let content = match expected_encoding {
+            None => String::from_utf8(body.to_vec())?,
+            Some("zstd") => {
+                assert!(body.len() < log.len(), "zstd content is stored compressed");
+                String::from_utf8(zstd::decode_all(body.as_ref())?)?
+            }
+            _ => {
+                let mut content = String::new();
+                flate2::read::GzDecoder::new(body.as_ref()).read_to_string(&mut content)?;
+                content
+            }
+        };
+        assert!(
+            content == log,
+            "content, accept encoding={:?}",
+            accept_encoding
+        );
+    }
+
+    // Content which does not compress is stored as is
+    let random: Vec<u8> = (0..4096).map(|_| rand::random()).collect();
+    let upload_req = test::TestRequest::post()
+        .uri(&format!(
+            "/v1/rooms/{}/files/upload?name=random.bin",
+            room_id
+        ))
+        .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+        .cookie(cookie.clone())
+        .set_payload(random.clone())
+        .to_request();
+    let upload_res_body: room_rest::UploadFileResponse =
+        test::read_response_json(&mut app, upload_req).await;
+
+    let get_content_req = test::TestRequest::get()
+        .uri(&format!(
+            "/v1/rooms/{}/files/{}/content",
+            room_id, upload_res_body.file.id
+        ))
+        .header(ACCESS_TOKEN_HEADER_NAME, access_token)
+        .cookie(cookie)
+        .header(http::header::ACCEPT_ENCODING, "zstd, gzip")
+        .to_request();
+    let get_content_res = test::call_service(&mut app, get_content_req).await;
+
+    assert!(
+        get_content_res
+            .headers()
+            .get(http::header::CONTENT_ENCODING)
+            .is_none(),
+        "incompressible content encoding"
+    );
+    assert_eq!(
+        test::read_body(get_content_res).await.as_ref(),
+        random.as_slice(),
+        "incompressible content"
+    );
+
+    Ok(())
+}
+
+// Last download notifies room over ws hub, which needs running actix system
+#[test]
+fn test_file_max_downloads() -> anyhow::Result<()> {
+    actix::System::new("test").block_on(async {
+        let state = new_default_state();
+        let mut app = actix_web::test::init_service(
+            App::new()
+                .data(state.clone())
+                .wrap(
+                    auth_rest::JwtAuth::default()
+                        .exclude_regex(".*/auth/login$")
+                        .exclude_regex((".*/rooms$", http::Method::POST)),
+                )
+                .configure(room_rest::service_config)
+                .configure(auth_rest::service_config),
+        )
+        .await;
+
+        let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+        let create_room_resp_body: room_rest::CreateRoomResponse =
+            test::read_response_json(&mut app, create_room_req).await;
+        let room_id = create_room_resp_body.room_id;
+
+        let login_req = test::TestRequest::post()
+            .uri("/v1/auth/login")
+            .set_json(&auth_rest::LoginRequest {
+                fingerprint: "123".to_string(),
+                room_id,
+                room_password: create_room_resp_body.master_password,
+            })
+            .to_request();
+        let login_resp = test::call_service(&mut app, login_req).await;
+
+        assert_eq!(
+            login_resp.status(),
+            http::StatusCode::OK,
+            "login status code"
+        );
+
+        let cookie = login_resp
+            .headers()
+            .get_all(http::header::SET_COOKIE)
+            .map(|v| v.to_str().unwrap().to_owned())
+            .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
+        assert!(cookie.is_some(), "(login) cookie refresh token");
+
+        let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+
+        let login_resp_body: auth_rest::LoginResponse =
+            actix_web::test::read_body_json(login_resp).await;
+        let access_token = login_resp_body.access_token;
+
+        // Zero downloads is rejected
+        let upload_req = test::TestRequest::post()
+            .uri(&format!(
+                "/v1/rooms/{}/files/upload?name=once.txt&max_downloads=0",
+                room_id
+            ))
+            .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+            .cookie(cookie.clone())
+            .set_payload("secret")
+            .to_request();
+        let upload_res = test::call_service(&mut app, upload_req).await;
+
+        assert_eq!(
+            upload_res.status(),
+            http::StatusCode::BAD_REQUEST,
+            "upload file with zero downloads status code"
+        );
+
+        let upload_req = test::TestRequest::post()
+            .uri(&format!(
+                "/v1/rooms/{}/files/upload?name=once.txt&max_downloads=2",
+                room_id
+            ))
+            .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+            .cookie(cookie.clone())
+            .set_payload("secret")
+            .to_request();
+        let upload_res_body: room_rest::UploadFileResponse =
+            test::read_response_json(&mut app, upload_req).await;
+        let file_id = upload_res_body.file.id;
+
+        assert_eq!(
+            upload_res_body.file.downloads_left,
+            Some(2),
+            "file downloads left"
+        );
+
+        // Content is served on every allowed download, the last one included
+        for downloads_left in &[1, 0] {
+            let get_content_req = test::TestRequest::get()
+                .uri(&format!("/v1/rooms/{}/files/{}/content", room_id, file_id))
+                .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+                .cookie(cookie.clone())
+                .to_request();
+            let get_content_res = test::call_service(&mut app, get_content_req).await;
+
+            assert_eq!(
+                get_content_res.status(),
+                http::StatusCode::OK,
+                "get file content status code, downloads left={}",
+                downloads_left
+            );
+            assert_eq!(
+                test::read_body(get_content_res).await.as_ref(),
+                b"secret",
+                "file content, downloads left={}",
+                downloads_left
+            );
+        }
+
+        // File is gone after the last download
+        let get_content_req = test::TestRequest::get()
+            .uri(&format!("/v1/rooms/{}/files/{}/content", room_id, file_id))
+            .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+            .cookie(cookie.clone())
+            .to_request();
+        let get_content_res = test::call_service(&mut app, get_content_req).await;
+
+        assert_eq!(
+            get_content_res.status(),
+            http::StatusCode::NOT_FOUND,
+            "get content of used up file status code"
+        );
+
+        let get_files_req = test::TestRequest::get()
+            .uri(&format!("/v1/rooms/{}/files", room_id))
+            .header(ACCESS_TOKEN_HEADER_NAME, access_token)
+            .cookie(cookie)
+            .to_request();
+        let get_files_res_body: room_rest::GetFilesResponse =
+            test::read_response_json(&mut app, get_files_req).await;
+
+        assert!(get_files_res_body.files.is_empty(), "used up file removed");
+
+        Ok(())
+    })
+}
+
+#[actix_rt::test]
+async fn test_files_zip() -> anyhow::Result<()> {
+    use std::io::Read;
+
+    let state = new_default_state();
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login$")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        test::read_response_json(&mut app, create_room_req).await;
+    let room_id = create_room_resp_body.room_id;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::OK,
+        "login status code"
+    );
+
+    let cookie = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
+    assert!(cookie.is_some(), "(login) cookie refresh token");
+
+    let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+
+    let login_resp_body: auth_rest::LoginResponse =
+        actix_web::test::read_body_json(login_resp).await;
+    let access_token = login_resp_body.access_token;
+
+    let mut file_ids = vec![];
+    for (name, content) in &[
+        ("photos/a.txt", "first"),
+        ("photos/b.txt", "second"),
+        ("photos.txt", "third"),
+        ("..%2Fescape.txt", "fourth"),
+    ] {
+        let upload_req = test::TestRequest::post()
+            .uri(&format!("/v1/rooms/{}/files/upload?name={}", room_id, name))
+            .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+            .cookie(cookie.clone())
+            .set_payload(*content)
+            .to_request();
+        let upload_res_body: room_rest::UploadFileResponse =
+            test::read_response_json(&mut app, upload_req).await;
+        file_ids.push(upload_res_body.file.id);
+    }
+
+    let cases = vec![
+        (
+            room_rest::GetFilesZipBodyRequest {
+                file_ids: None,
+                directory: Some("photos".to_string()),
+            },
+            "photos.zip",
+            vec![("photos/a.txt", "first"), ("photos/b.txt", "second")],
+        ),
+        (
+            room_rest::GetFilesZipBodyRequest {
+                file_ids: Some(vec![file_ids[3], file_ids[0], file_ids[0]]),
+                directory: None,
+            },
+            "files.zip",
+            vec![("escape.txt", "fourth"), ("photos/a.txt", "first")],
+        ),
+    ];
+    for (req_body, archive_name, expected) in cases {
+        let zip_req = test::TestRequest::post()
+            .uri(&format!("/v1/rooms/{}/files/zip", room_id))
+            .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+            .cookie(cookie.clone())
+            .set_json(&req_body)
+            .to_request();
+        let zip_res = test::call_service(&mut app, zip_req).await;
+
+        assert_eq!(zip_res.status(), http::StatusCode::OK, "zip status code");
+        assert_eq!(
+            zip_res
+                .headers()
+                .get(http::header::CONTENT_DISPOSITION)
+                .and_then(|v| v.to_str().ok()),
+            Some(format!("attachment; filename*=UTF-8''{}", archive_name).as_str()),
+            "zip content disposition"
+        );
+
+        let body = test::read_body(zip_res).await;
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(body.to_vec()))?;
+        let mut entries = vec![];
+        for i in 0..archive.len() {
+            let mut entry = archive.by_index(i)?;
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            entries.push((entry.name().to_owned(), content));
+        }
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(n, c)| (n.to_owned(), c.to_owned()))
+            .collect();
+        assert_eq!(entries, expected, "zip entries");
+    }
+
+    // Files and directory at once
+    let zip_req = test::TestRequest::post()
+        .uri(&format!("/v1/rooms/{}/files/zip", room_id))
+        .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+        .cookie(cookie.clone())
+        .set_json(&room_rest::GetFilesZipBodyRequest {
+            file_ids: Some(vec![file_ids[0]]),
+            directory: Some("photos".to_string()),
+        })
+        .to_request();
+    let zip_res = test::call_service(&mut app, zip_req).await;
+
+    assert_eq!(
+        zip_res.status(),
+        http::StatusCode::BAD_REQUEST,
+        "zip of files and directory status code"
+    );
+
+    // Unknown file
+    let zip_req = test::TestRequest::post()
+        .uri(&format!("/v1/rooms/{}/files/zip", room_id))
+        .header(ACCESS_TOKEN_HEADER_NAME, access_token)
+        .cookie(cookie)
+        .set_json(&room_rest::GetFilesZipBodyRequest {
+            file_ids: Some(vec![uuid::Uuid::new_v4()]),
+            directory: None,
+        })
+        .to_request();
+    let zip_res = test::call_service(&mut app, zip_req).await;
+
+    assert_eq!(
+        zip_res.status(),
+        http::StatusCode::NOT_FOUND,
+        "zip of unknown file status code"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_upload_archive() -> anyhow::Result<()> {
+    use std::io::Write;
+
+    actix::System::new("test").block_on(async {
+        let state = new_default_state();
+        let mut app = actix_web::test::init_service(
+            App::new()
+                .data(state.clone())
+                .wrap(
+                    auth_rest::JwtAuth::default()
+                        .exclude_regex(".*/auth/login$")
+                        .exclude_regex((".*/rooms$", http::Method::POST)),
+                )
+                .configure(room_rest::service_config)
+                .configure(auth_rest::service_config),
+        )
+        .await;
+
+        let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+        let create_room_resp_body: room_rest::CreateRoomResponse =
+            test::read_response_json(&mut app, create_room_req).await;
+        let room_id = create_room_resp_body.room_id;
+
+        let login_req = test::TestRequest::post()
+            .uri("/v1/auth/login")
+            .set_json(&auth_rest::LoginRequest {
+                fingerprint: "123".to_string(),
+                room_id,
+                room_password: create_room_resp_body.master_password,
+            })
+            .to_request();
+        let login_resp = test::call_service(&mut app, login_req).await;
+
+        assert_eq!(
+            login_resp.status(),
+            http::StatusCode::OK,
+            "login status code"
+        );
+
+        let cookie = login_resp
+            .headers()
+            .get_all(http::header::SET_COOKIE)
+            .map(|v| v.to_str().unwrap().to_owned())
+            .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
+        assert!(cookie.is_some(), "(login) cookie refresh token");
+
+        let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+
+        let login_resp_body: auth_rest::LoginResponse =
+            actix_web::test::read_body_json(login_resp).await;
+        let access_token = login_resp_body.access_token;
+
+        let options = zip::write::FileOptions::default();
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        writer.start_file("a.txt", options)?;
+        writer.write_all(b"first")?;
+        writer.add_directory("docs/", options)?;
+        writer.start_file("docs/b.json", options)?;
+        writer.write_all(b"{}")?;
+        writer.add_symlink("docs/link", "/etc/passwd", options)?;
+        let archive = writer.finish()?.into_inner();
+
+        let upload_req = test::TestRequest::post()
+            .uri(&format!(
+                "/v1/rooms/{}/files/archive?directory=backup",
+                room_id
+            ))
+            .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+            .cookie(cookie.clone())
+            .set_payload(archive)
+            .to_request();
+        let upload_res = test::call_service(&mut app, upload_req).await;
+
+        assert_eq!(
+            upload_res.status(),
+            http::StatusCode::OK,
+            "upload archive status code"
+        );
+
+        let upload_res_body: room_rest::UploadArchiveResponse =
+            test::read_body_json(upload_res).await;
+        let files: Vec<_> = upload_res_body
+            .files
+            .iter()
+            .map(|f| (f.name.as_str(), f.size, f.mime_type.as_str()))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                ("backup/a.txt", 5, "text/plain"),
+                ("backup/docs/b.json", 2, "application/json")
+            ],
+            "unpacked files"
+        );
+        assert_eq!(
+            upload_res_body.skipped,
+            ["backup/docs/link"],
+            "skipped entries"
+        );
+
+        // Entry escaping the archive rejects all of it
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        writer.start_file("c.txt", options)?;
+        writer.start_file("../../evil.sh", options)?;
+        let archive = writer.finish()?.into_inner();
+
+        let upload_req = test::TestRequest::post()
+            .uri(&format!("/v1/rooms/{}/files/archive", room_id))
+            .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+            .cookie(cookie.clone())
+            .set_payload(archive)
+            .to_request();
+        let upload_res = test::call_service(&mut app, upload_req).await;
+
+        assert_eq!(
+            upload_res.status(),
+            http::StatusCode::BAD_REQUEST,
+            "upload archive with traversal status code"
+        );
+
+        let upload_req = test::TestRequest::post()
+            .uri(&format!("/v1/rooms/{}/files/archive", room_id))
+            .header(ACCESS_TOKEN_HEADER_NAME, access_token.clone())
+            .cookie(cookie.clone())
+            .set_payload("not an archive")
+            .to_request();
+        let upload_res = test::call_service(&mut app, upload_req).await;
+
+        assert_eq!(
+            upload_res.status(),
+            http::StatusCode::BAD_REQUEST,
+            "upload not an archive status code"
+        );
+
+        let get_files_req = test::TestRequest::get()
+            .uri(&format!("/v1/rooms/{}/files", room_id))
+            .header(ACCESS_TOKEN_HEADER_NAME, access_token)
+            .cookie(cookie)
+            .to_request();
+        let get_files_res_body: room_rest::GetFilesResponse =
+            test::read_response_json(&mut app, get_files_req).await;
+
+        assert_eq!(
+            get_files_res_body.files.len(),
+            2,
+            "nothing added from rejected archives"
+        );
+
+        Ok(())
+    })
+}
+
+#[actix_rt::test]
+async fn test_get_files_paginated() -> anyhow::Result<()> {
+    let state = new_default_state();
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login$")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+    let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+    assert_eq!(
+        create_room_resp.status(),
+        http::StatusCode::OK,
+        "create room status code"
+    );
+
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        actix_web::test::read_body_json(create_room_resp).await;
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id: create_room_resp_body.room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::OK,
+        "login status code"
+    );
 
-        for req in reqs {
-            let res = test::call_service(&mut app, req).await;
+    let cookies: Vec<String> = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .collect();
 
-            assert_eq!(res.status(), http::StatusCode::OK, "add file status code",);
-        }
+    let cookie = cookies
+        .into_iter()
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
+    assert!(cookie.is_some(), "(login) cookie refresh token");
+
+    let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+
+    let login_resp_body: auth_rest::LoginResponse =
+        actix_web::test::read_body_json(login_resp).await;
+
+    // Add files
+    let files = vec![
+        ("file-name.txt", 3072, "text/plain"),
+        ("file-name.png", 1024, "image/png"),
+        ("file-name.jpg", 2048, "image/jpeg"),
+    ];
+    for (name, size, mime_type) in files {
+        let add_file_req = test::TestRequest::post()
+            .uri(&format!(
+                "/v1/rooms/{}/files",
+                create_room_resp_body.room_id
+            ))
+            .header(
+                ACCESS_TOKEN_HEADER_NAME,
+                login_resp_body.access_token.clone(),
+            )
+            .cookie(cookie.clone())
+            .set_json(&room_rest::AddFileBodyRequest {
+                name: name.to_string(),
+                size,
+                mime_type: mime_type.to_string(),
+                expires_at: None,
+                max_downloads: None,
+            })
+            .to_request();
+        let add_file_res = test::call_service(&mut app, add_file_req).await;
+
+        assert_eq!(
+            add_file_res.status(),
+            http::StatusCode::OK,
+            "add file status code",
+        );
     }
 
-    // Get files
+    // First page
     let get_files_req = test::TestRequest::get()
         .uri(&format!(
-            "/v1/rooms/{}/files",
+            "/v1/rooms/{}/files?sort_by=size&order=desc&limit=2",
             create_room_resp_body.room_id
         ))
         .header(
@@ -474,7 +2846,600 @@ async fn test_get_files() -> anyhow::Result<()> {
     let get_files_res_body: room_rest::GetFilesResponse =
         actix_web::test::read_body_json(get_files_res).await;
 
-    assert_eq!(get_files_res_body.files.len(), 3, "files amount");
+    let sizes: Vec<usize> = get_files_res_body.files.iter().map(|f| f.size).collect();
+    assert_eq!(sizes, vec![3072, 2048], "first page files");
+    assert!(get_files_res_body.next_cursor.is_some(), "next cursor");
+
+    // Second page
+    let get_files_req = test::TestRequest::get()
+        .uri(&format!(
+            "/v1/rooms/{}/files?sort_by=size&order=desc&limit=2&cursor={}",
+            create_room_resp_body.room_id,
+            get_files_res_body.next_cursor.unwrap()
+        ))
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(cookie.clone())
+        .to_request();
+    let get_files_res = test::call_service(&mut app, get_files_req).await;
+
+    assert_eq!(
+        get_files_res.status(),
+        http::StatusCode::OK,
+        "get files status code"
+    );
+
+    let get_files_res_body: room_rest::GetFilesResponse =
+        actix_web::test::read_body_json(get_files_res).await;
+
+    let sizes: Vec<usize> = get_files_res_body.files.iter().map(|f| f.size).collect();
+    assert_eq!(sizes, vec![1024], "second page files");
+    assert!(get_files_res_body.next_cursor.is_none(), "no next cursor");
+
+    // Filter by mime type
+    let get_files_req = test::TestRequest::get()
+        .uri(&format!(
+            "/v1/rooms/{}/files?mime_type=image/",
+            create_room_resp_body.room_id
+        ))
+        .header(ACCESS_TOKEN_HEADER_NAME, login_resp_body.access_token)
+        .cookie(cookie)
+        .to_request();
+    let get_files_res = test::call_service(&mut app, get_files_req).await;
+
+    assert_eq!(
+        get_files_res.status(),
+        http::StatusCode::OK,
+        "get files status code"
+    );
+
+    let get_files_res_body: room_rest::GetFilesResponse =
+        actix_web::test::read_body_json(get_files_res).await;
+
+    let names: Vec<String> = get_files_res_body
+        .files
+        .into_iter()
+        .map(|f| f.name)
+        .collect();
+    assert_eq!(
+        names,
+        vec!["file-name.jpg".to_string(), "file-name.png".to_string()],
+        "filtered files"
+    );
+
+    Ok(())
+}
+
+// Room deletion closes its websockets, which need running actix system
+#[test]
+fn test_get_files_encrypted() -> anyhow::Result<()> {
+    actix::System::new("test").block_on(async {
+        let mut cfg = Config::default();
+        cfg.room.max_rooms = 2;
+        cfg.encryption.enabled = true;
+        cfg.encryption.master_key = Some(base64::encode([7u8; 32]));
+
+        let state = new_state(cfg);
+        let mut app = actix_web::test::init_service(
+            App::new()
+                .data(state.clone())
+                .wrap(
+                    auth_rest::JwtAuth::default()
+                        .exclude_regex(".*/auth/login$")
+                        .exclude_regex((".*/rooms$", http::Method::POST)),
+                )
+                .configure(room_rest::service_config)
+                .configure(auth_rest::service_config),
+        )
+        .await;
+
+        let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+        let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+        assert_eq!(
+            create_room_resp.status(),
+            http::StatusCode::OK,
+            "create room status code"
+        );
+
+        let create_room_resp_body: room_rest::CreateRoomResponse =
+            actix_web::test::read_body_json(create_room_resp).await;
+
+        let login_req = test::TestRequest::post()
+            .uri("/v1/auth/login")
+            .set_json(&auth_rest::LoginRequest {
+                fingerprint: "123".to_string(),
+                room_id: create_room_resp_body.room_id,
+                room_password: create_room_resp_body.master_password,
+            })
+            .to_request();
+        let login_resp = test::call_service(&mut app, login_req).await;
+
+        assert_eq!(
+            login_resp.status(),
+            http::StatusCode::OK,
+            "login status code"
+        );
+
+        let cookies: Vec<String> = login_resp
+            .headers()
+            .get_all(http::header::SET_COOKIE)
+            .map(|v| v.to_str().unwrap().to_owned())
+            .collect();
+
+        let cookie = cookies
+            .into_iter()
+            .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
+        assert!(cookie.is_some(), "(login) cookie refresh token");
+
+        let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+
+        let login_resp_body: auth_rest::LoginResponse =
+            actix_web::test::read_body_json(login_resp).await;
+
+        // Add files, the same name is found by its tag and gets a new version
+        for name in &["file-name.txt", "file-name.txt", "another.txt"] {
+            let add_file_req = test::TestRequest::post()
+                .uri(&format!(
+                    "/v1/rooms/{}/files",
+                    create_room_resp_body.room_id
+                ))
+                .header(
+                    ACCESS_TOKEN_HEADER_NAME,
+                    login_resp_body.access_token.clone(),
+                )
+                .cookie(cookie.clone())
+                .set_json(&room_rest::AddFileBodyRequest {
+                    name: name.to_string(),
+                    size: 1024,
+                    mime_type: "text/plain".to_string(),
+                    expires_at: None,
+                    max_downloads: None,
+                })
+                .to_request();
+            let add_file_res = test::call_service(&mut app, add_file_req).await;
+
+            assert_eq!(
+                add_file_res.status(),
+                http::StatusCode::OK,
+                "add file status code",
+            );
+        }
+
+        // Get files page by page, sorted without indexes
+        let mut names = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let mut uri = format!(
+                "/v1/rooms/{}/files?sort_by=name&limit=1",
+                create_room_resp_body.room_id
+            );
+            if let Some(cursor) = &cursor {
+                uri.push_str(&format!("&cursor={}", cursor));
+            }
+
+            let get_files_req = test::TestRequest::get()
+                .uri(&uri)
+                .header(
+                    ACCESS_TOKEN_HEADER_NAME,
+                    login_resp_body.access_token.clone(),
+                )
+                .cookie(cookie.clone())
+                .to_request();
+            let get_files_res = test::call_service(&mut app, get_files_req).await;
+
+            assert_eq!(
+                get_files_res.status(),
+                http::StatusCode::OK,
+                "get files status code"
+            );
+
+            let get_files_res_body: room_rest::GetFilesResponse =
+                actix_web::test::read_body_json(get_files_res).await;
+
+            assert_eq!(get_files_res_body.files.len(), 1, "files amount");
+            names.push(get_files_res_body.files[0].name.clone());
+
+            cursor = get_files_res_body.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(
+            names,
+            vec!["another.txt".to_string(), "file-name.txt".to_string()],
+            "sorted files"
+        );
+
+        // Delete room, its data key is erased
+        let delete_room_req = test::TestRequest::delete()
+            .uri(&format!("/v1/rooms/{}", create_room_resp_body.room_id))
+            .header(
+                ACCESS_TOKEN_HEADER_NAME,
+                login_resp_body.access_token.clone(),
+            )
+            .cookie(cookie.clone())
+            .to_request();
+        let delete_room_res = test::call_service(&mut app, delete_room_req).await;
+
+        assert_eq!(
+            delete_room_res.status(),
+            http::StatusCode::OK,
+            "delete room status code"
+        );
+
+        // Sessions of the room are revoked with it
+        let get_files_req = test::TestRequest::get()
+            .uri(&format!(
+                "/v1/rooms/{}/files",
+                create_room_resp_body.room_id
+            ))
+            .header(ACCESS_TOKEN_HEADER_NAME, login_resp_body.access_token)
+            .cookie(cookie)
+            .to_request();
+        let get_files_err = app
+            .call(get_files_req)
+            .await
+            .expect_err("deleted room get files");
+
+        assert_eq!(
+            get_files_err.as_response_error().status_code(),
+            http::StatusCode::UNAUTHORIZED,
+            "deleted room get files status code"
+        );
+
+        // Id of deleted room is not given out again, even after wraparound
+        let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+        let create_room_resp_body_2: room_rest::CreateRoomResponse =
+            test::read_response_json(&mut app, create_room_req).await;
+
+        assert_ne!(
+            create_room_resp_body_2.room_id, create_room_resp_body.room_id,
+            "room id of deleted room"
+        );
+
+        let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+        let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+        assert_eq!(
+            create_room_resp.status(),
+            http::StatusCode::SERVICE_UNAVAILABLE,
+            "no free room id status code"
+        );
+
+        // Id is given out again once tombstone expires
+        let tombstone_expires = Config::default().room.tombstone_expires;
+        state
+            .room_service
+            .delete_expired(room_service::DeleteExpiredRequest {
+                now: Utc::now().naive_utc() + Duration::seconds(tombstone_expires + 1),
+            })
+            .await?;
+
+        let create_room_req = test::TestRequest::post().uri("/v1/rooms").to_request();
+        let create_room_resp_body_3: room_rest::CreateRoomResponse =
+            test::read_response_json(&mut app, create_room_req).await;
+
+        assert_eq!(
+            create_room_resp_body_3.room_id, create_room_resp_body.room_id,
+            "room id of expired tombstone"
+        );
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_key_store() -> anyhow::Result<()> {
+    let mut cfg = Config::default();
+    cfg.encryption.enabled = true;
+    cfg.encryption.master_key = Some(base64::encode([7u8; 32]));
+
+    let sled_db = sled::Config::default().temporary(true).open()?;
+    let key_store = KeyStore::new(sled_db, &cfg.encryption)?;
+
+    // Key is never created implicitly
+    assert!(key_store.get_cipher(b"room/1").is_err(), "missing key");
+
+    let sealed = key_store.create_key(b"room/1")?.seal(b"value", b"aad")?;
+    assert_eq!(
+        key_store.get_cipher(b"room/1")?.open(&sealed, b"aad")?,
+        b"value",
+        "opened value"
+    );
+
+    key_store.erase(b"room/1")?;
+    assert!(key_store.get_cipher(b"room/1").is_err(), "erased key");
+
+    // Next owner of the scope gets a new key
+    let cipher = key_store.create_key(b"room/1")?;
+    assert!(cipher.open(&sealed, b"aad").is_err(), "value of erased key");
+
+    // Long-lived scope keeps its key
+    let sealed = key_store
+        .get_or_create_key(b"auth")?
+        .seal(b"value", b"aad")?;
+    assert_eq!(
+        key_store
+            .get_or_create_key(b"auth")?
+            .open(&sealed, b"aad")?,
+        b"value",
+        "opened long-lived value"
+    );
+
+    Ok(())
+}
+
+#[actix_rt::test]
+async fn test_e2ee_room() -> anyhow::Result<()> {
+    let state = new_default_state();
+    let mut app = actix_web::test::init_service(
+        App::new()
+            .data(state.clone())
+            .wrap(
+                auth_rest::JwtAuth::default()
+                    .exclude_regex(".*/auth/login$")
+                    .exclude_regex((".*/rooms$", http::Method::POST)),
+            )
+            .configure(room_rest::service_config)
+            .configure(auth_rest::service_config),
+    )
+    .await;
+
+    let create_room_req = test::TestRequest::post()
+        .uri("/v1/rooms")
+        .set_json(&room_rest::CreateRoomBodyRequest {
+            e2ee: true,
+            ..Default::default()
+        })
+        .to_request();
+    let create_room_resp = test::call_service(&mut app, create_room_req).await;
+
+    assert_eq!(
+        create_room_resp.status(),
+        http::StatusCode::OK,
+        "create room status code"
+    );
+
+    let create_room_resp_body: room_rest::CreateRoomResponse =
+        actix_web::test::read_body_json(create_room_resp).await;
+
+    assert!(create_room_resp_body.e2ee, "e2ee room");
+
+    let login_req = test::TestRequest::post()
+        .uri("/v1/auth/login")
+        .set_json(&auth_rest::LoginRequest {
+            fingerprint: "123".to_string(),
+            room_id: create_room_resp_body.room_id,
+            room_password: create_room_resp_body.master_password,
+        })
+        .to_request();
+    let login_resp = test::call_service(&mut app, login_req).await;
+
+    assert_eq!(
+        login_resp.status(),
+        http::StatusCode::OK,
+        "login status code"
+    );
+
+    let cookies: Vec<String> = login_resp
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .map(|v| v.to_str().unwrap().to_owned())
+        .collect();
+
+    let cookie = cookies
+        .into_iter()
+        .find(|c| c.contains(REFRESH_TOKEN_COOKIE_NAME));
+    assert!(cookie.is_some(), "(login) cookie refresh token");
+
+    let cookie = actix_web::cookie::Cookie::parse(cookie.unwrap())?;
+
+    let login_resp_body: auth_rest::LoginResponse =
+        actix_web::test::read_body_json(login_resp).await;
+
+    // Filter by content is not allowed
+    let get_files_req = test::TestRequest::get()
+        .uri(&format!(
+            "/v1/rooms/{}/files?mime_type=image/",
+            create_room_resp_body.room_id
+        ))
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(cookie.clone())
+        .to_request();
+    let get_files_res = test::call_service(&mut app, get_files_req).await;
+
+    assert_eq!(
+        get_files_res.status(),
+        http::StatusCode::BAD_REQUEST,
+        "get filtered files status code"
+    );
+
+    // Archive content is ciphertext, it cannot be unpacked
+    let upload_req = test::TestRequest::post()
+        .uri(&format!(
+            "/v1/rooms/{}/files/archive",
+            create_room_resp_body.room_id
+        ))
+        .header(
+            ACCESS_TOKEN_HEADER_NAME,
+            login_resp_body.access_token.clone(),
+        )
+        .cookie(cookie.clone())
+        .set_payload("ciphertext")
+        .to_request();
+    let upload_res = test::call_service(&mut app, upload_req).await;
+
+    assert_eq!(
+        upload_res.status(),
+        http::StatusCode::BAD_REQUEST,
+        "upload archive status code"
+    );
+
+    // Plain listing works
+    let get_files_req = test::TestRequest::get()
+        .uri(&format!(
+            "/v1/rooms/{}/files",
+            create_room_resp_body.room_id
+        ))
+        .header(ACCESS_TOKEN_HEADER_NAME, login_resp_body.access_token)
+        .cookie(cookie)
+        .to_request();
+    let get_files_res = test::call_service(&mut app, get_files_req).await;
+
+    assert_eq!(
+        get_files_res.status(),
+        http::StatusCode::OK,
+        "get files status code"
+    );
 
     Ok(())
 }
+
+// Actors need running actix system, which `actix_rt::test` does not start
+#[test]
+fn test_ws_hub_connections() -> anyhow::Result<()> {
+    use actix::prelude::*;
+    use room_rest::ws::{Broadcast, Join, Kick, Leave, Relay, WsHub};
+
+    /// Counts received messages
+    #[derive(Default)]
+    struct Recorder(usize);
+
+    impl Actor for Recorder {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<room_rest::WsServerMessage> for Recorder {
+        type Result = ();
+
+        fn handle(&mut self, _msg: room_rest::WsServerMessage, _ctx: &mut Self::Context) {
+            self.0 += 1;
+        }
+    }
+
+    #[derive(Message)]
+    #[rtype(result = "usize")]
+    struct Received;
+
+    impl Handler<Received> for Recorder {
+        type Result = usize;
+
+        fn handle(&mut self, _msg: Received, _ctx: &mut Self::Context) -> usize {
+            self.0
+        }
+    }
+
+    actix::System::new("test").block_on(async {
+        let hub = WsHub::from_registry();
+        let room_id = room_rest::RoomId::new(1);
+        let client_id = uuid::Uuid::new_v4();
+
+        // Two tabs of the same client
+        let (first, second) = (Recorder::default().start(), Recorder::default().start());
+        let (first_id, second_id) = (uuid::Uuid::new_v4(), uuid::Uuid::new_v4());
+        for (conn_id, addr) in [(first_id, &first), (second_id, &second)].iter() {
+            hub.send(Join {
+                conn_id: *conn_id,
+                room_id,
+                client_id,
+                owner: true,
+                addr: (*addr).clone().recipient(),
+            })
+            .await?;
+        }
+
+        // Member which is not an owner
+        let member = Recorder::default().start();
+        hub.send(Join {
+            conn_id: uuid::Uuid::new_v4(),
+            room_id,
+            client_id: uuid::Uuid::new_v4(),
+            owner: false,
+            addr: member.clone().recipient(),
+        })
+        .await?;
+
+        // First tab is closed
+        hub.send(Leave {
+            conn_id: first_id,
+            room_id,
+        })
+        .await?;
+
+        hub.send(Relay {
+            room_id,
+            from: uuid::Uuid::nil(),
+            to: Some(client_id),
+            owners_only: false,
+            msg: room_rest::WsServerMessage::LoginLocked { retry_after: 1 },
+        })
+        .await?;
+
+        hub.send(Relay {
+            room_id,
+            from: uuid::Uuid::nil(),
+            to: None,
+            owners_only: true,
+            msg: room_rest::WsServerMessage::LoginLocked { retry_after: 1 },
+        })
+        .await?;
+
+        // Messages of the hub are ahead of these in mailboxes
+        assert_eq!(first.send(Received).await?, 0, "closed connection messages");
+        assert_eq!(second.send(Received).await?, 2, "open connection messages");
+        assert_eq!(member.send(Received).await?, 0, "member messages");
+
+        // File events go to every connection of the room
+        hub.send(Broadcast {
+            room_id,
+            msg: room_rest::WsServerMessage::FileRemoved {
+                file_id: uuid::Uuid::new_v4(),
+            },
+        })
+        .await?;
+
+        assert_eq!(second.send(Received).await?, 3, "broadcast messages");
+        assert_eq!(member.send(Received).await?, 1, "broadcast member messages");
+
+        // Logged out client is told to close and gets nothing after that
+        hub.send(Kick {
+            room_id,
+            client_id: Some(client_id),
+        })
+        .await?;
+
+        hub.send(Relay {
+            room_id,
+            from: uuid::Uuid::nil(),
+            to: None,
+            owners_only: false,
+            msg: room_rest::WsServerMessage::LoginLocked { retry_after: 1 },
+        })
+        .await?;
+
+        assert_eq!(
+            second.send(Received).await?,
+            4,
+            "kicked connection messages"
+        );
+        assert_eq!(member.send(Received).await?, 2, "member messages");
+
+        // Room deletion closes all connections
+        hub.send(Kick {
+            room_id,
+            client_id: None,
+        })
+        .await?;
+
+        assert_eq!(member.send(Received).await?, 3, "kicked member messages");
+
+        Ok(())
+    })
+}
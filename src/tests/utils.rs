@@ -9,11 +9,20 @@ use crate::config::Config;
 use crate::domain::auth::AuthServiceImpl;
 use crate::domain::example::ExampleServiceImpl;
 use crate::domain::room::RoomServiceImpl;
+use crate::infra::client_ip::ClientIpResolver;
+use crate::infra::cookie::CookiePolicy;
+use crate::infra::crypto::KeyStore;
+use crate::infra::dpop::DpopVerifier;
+use crate::infra::jwt::JwtKeyring;
 use crate::infra::state::State;
 
 #[allow(dead_code)]
 pub fn new_default_state() -> State {
-    let cfg = Config::default();
+    new_state(Config::default())
+}
+
+#[allow(dead_code)]
+pub fn new_state(cfg: Config) -> State {
     let tmp_file_path = env::temp_dir().join(format!("ezspot-test-{}", Uuid::new_v4()));
     let sled_db = sled::Config::default()
         .temporary(true)
@@ -24,19 +33,35 @@ pub fn new_default_state() -> State {
     let example_repo = Arc::new(ExampleRepoSled::new(sled_db.clone()).expect("example repo init"));
     let example_service = Arc::new(ExampleServiceImpl::new(example_repo));
 
-    let room_repo = Arc::new(RoomRepoSled::new(sled_db.clone()).expect("room repo init"));
+    let key_store =
+        Arc::new(KeyStore::new(sled_db.clone(), &cfg.encryption).expect("key store init"));
+
+    let room_repo = Arc::new(
+        RoomRepoSled::new(sled_db.clone(), Arc::clone(&key_store)).expect("room repo init"),
+    );
     let room_service = Arc::new(RoomServiceImpl::new(
         cfg.room.clone(),
+        cfg.privacy.clone(),
         Arc::clone(&room_repo),
     ));
 
-    let auth_repo =
-        Arc::new(AuthRepoSled::new(sled_db, Arc::clone(&room_repo)).expect("auth repo init"));
+    let auth_repo = Arc::new(
+        AuthRepoSled::new(sled_db, key_store, Arc::clone(&room_repo)).expect("auth repo init"),
+    );
     let auth_service = Arc::new(AuthServiceImpl::new(cfg.auth.clone(), auth_repo));
+    let jwt_keyring = Arc::new(JwtKeyring::new(&cfg.auth).expect("jwt keyring init"));
+    let dpop_verifier = Arc::new(DpopVerifier::new(&cfg.auth));
+    let cookie_policy =
+        Arc::new(CookiePolicy::new(&cfg.auth, &cfg.server.env).expect("cookie policy init"));
+    let client_ip_resolver = Arc::new(ClientIpResolver::new(&cfg.server));
 
     State {
         example_service,
         auth_service,
         room_service,
+        jwt_keyring,
+        dpop_verifier,
+        cookie_policy,
+        client_ip_resolver,
     }
 }